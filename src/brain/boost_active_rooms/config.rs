@@ -20,6 +20,12 @@ pub struct BoostActiveRoomsConfig {
     /// temperature of a room we were boosting.
     #[serde_as(as = "DurationSeconds")]
     interfere_change_leave_alone_time: Duration,
+    /// How far (in degrees) the boost we want to apply has to be from the boost currently applied
+    /// before it is re-applied.
+    reapply_min_difference: f32,
+    /// Only boost rooms while we are heating (heat is going to the radiators or wiser is calling for heat),
+    /// so that boosts don't open valves when there is nothing to heat the radiators.
+    only_while_heating: bool,
     /// Individual room boost entries
     parts: Vec<BoostActiveRoom>,
 }
@@ -40,6 +46,14 @@ impl BoostActiveRoomsConfig {
     pub fn get_interfere_change_leave_alone_time(&self) -> &Duration {
         &self.interfere_change_leave_alone_time
     }
+
+    pub fn get_reapply_min_difference(&self) -> f32 {
+        self.reapply_min_difference
+    }
+
+    pub fn only_while_heating(&self) -> bool {
+        self.only_while_heating
+    }
 }
 
 impl Default for BoostActiveRoomsConfig {
@@ -47,6 +61,8 @@ impl Default for BoostActiveRoomsConfig {
         Self {
             interefere_off_leave_alone_time: Duration::from_secs(60 * 60),
             interfere_change_leave_alone_time: Duration::from_secs(60 * 60),
+            reapply_min_difference: 0.3,
+            only_while_heating: false,
             parts: Vec::default(),
         }
     }
@@ -106,6 +122,8 @@ mod test {
                 },
             ],
             interfere_change_leave_alone_time: Duration::from_secs(60 * 60),
+            reapply_min_difference: 0.3,
+            only_while_heating: false,
             interefere_off_leave_alone_time: Duration::from_secs(60 * 60),
         };
 
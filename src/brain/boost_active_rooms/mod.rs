@@ -1,11 +1,14 @@
 use crate::brain::boost_active_rooms::config::BoostActiveRoomsConfig;
+use crate::brain::python_like::config::unnamed_rooms::UnnamedRoomPolicy;
 use crate::brain::python_like::control::devices::Device;
 use crate::io::wiser::hub::{WiserHub, WiserRoomData};
 use crate::io::wiser::WiserManager;
+use crate::time_util::mytime::TimeProvider;
 use chrono::Duration as CDuration;
 use chrono::{DateTime, Utc};
 use itertools::Itertools;
 use log::{debug, info, trace, warn};
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::error::Error;
 use std::fmt::{Display, Formatter};
@@ -93,6 +96,11 @@ impl AppliedBoosts {
         return self.room_temps.get(room_name);
     }
 
+    /// Every boost we have applied, by room name.
+    pub fn get_applied_boosts(&self) -> &HashMap<String, AppliedBoost> {
+        &self.room_temps
+    }
+
     pub fn mark_leave_alone_for(&mut self, room_name: String, until: DateTime<Utc>) {
         self.leave_alone_until.insert(room_name, until);
     }
@@ -105,14 +113,24 @@ impl AppliedBoosts {
     }
 }
 
+/// Boost the rooms of the active devices, cancelling any boosts we applied that are no
+/// longer wanted. If configured to only boost while heating, and we aren't, every boost is cancelled.
 pub async fn update_boosted_rooms(
     state: &mut AppliedBoosts,
     config: &BoostActiveRoomsConfig,
+    unnamed_rooms: UnnamedRoomPolicy,
     active_devices: Vec<Device>,
+    heating: bool,
     wiser: &dyn WiserManager,
+    time_provider: &impl TimeProvider,
 ) -> Result<(), Box<dyn Error>> {
-    // TODO: Should be extracted out and use TimeProvider
-    let now = Utc::now();
+    let now = time_provider.get_utc_time();
+    let active_devices = if config.only_while_heating() && !heating {
+        debug!("Not heating, so not boosting any rooms");
+        vec![]
+    } else {
+        active_devices
+    };
     debug!(
         "Active Devices: {}",
         active_devices
@@ -149,13 +167,10 @@ pub async fn update_boosted_rooms(
 
     let mut ignored = Vec::new();
 
-    for room in wiser_data.iter() {
-        let room_name = room.get_name();
-        if room_name.is_none() {
-            warn!("Failed to get room name from id: {}", room.get_id());
-            continue;
-        }
-        let room_name = room_name.unwrap();
+    let named_rooms = get_named_rooms(&wiser_data, unnamed_rooms);
+
+    for (room_name, room) in named_rooms.iter() {
+        let room_name: &str = room_name;
 
         if !state.can_touch(room_name, &now) {
             ignored.push(room_name);
@@ -201,7 +216,7 @@ pub async fn update_boosted_rooms(
                         temp,
                         applied_boost
                     );
-                    if (should_set_to - temp).abs() > 0.3 {
+                    if (should_set_to - temp).abs() > config.get_reapply_min_difference() {
                         info!("Significant difference between what we applied and what we should be applying now, increasing.");
                         apply_boost(
                             room,
@@ -287,6 +302,24 @@ pub async fn update_boosted_rooms(
     Ok(())
 }
 
+/// Get the rooms along with the name to boost them by, skipping any
+/// rooms without a name if that is the policy.
+fn get_named_rooms(
+    rooms: &[WiserRoomData],
+    unnamed_rooms: UnnamedRoomPolicy,
+) -> Vec<(Cow<'_, str>, &WiserRoomData)> {
+    rooms
+        .iter()
+        .filter_map(|room| match unnamed_rooms.get_room_name(room) {
+            Some(name) => Some((name, room)),
+            None => {
+                warn!("Skipping room with no name, id: {}", room.get_id());
+                None
+            }
+        })
+        .collect()
+}
+
 fn mark_interference(
     room_name: &str,
     ignore_duration: &Duration,
@@ -327,3 +360,269 @@ async fn apply_boost(
     state.mark_applied(room_name.to_string(), temp, time);
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::io::wiser::hub::{RetrieveDataError, WiserData, FROM_SCHEDULE_ORIGIN};
+    use crate::time_util::mytime::DummyTimeProvider;
+    use async_trait::async_trait;
+    use chrono::TimeZone;
+    use std::sync::Mutex;
+
+    /// A wiser with a single room "Kitchen" scheduled at 20.0, that keeps track of the boosts applied.
+    struct BoostWiser {
+        now: Mutex<DateTime<Utc>>,
+        boost: Mutex<Option<(f32, DateTime<Utc>)>>,
+        boosts_applied: Mutex<usize>,
+    }
+
+    impl BoostWiser {
+        fn new() -> Self {
+            Self {
+                now: Mutex::new(Utc::now()),
+                boost: Mutex::new(None),
+                boosts_applied: Mutex::new(0),
+            }
+        }
+
+        fn boosts_applied(&self) -> usize {
+            *self.boosts_applied.lock().unwrap()
+        }
+    }
+
+    #[async_trait]
+    impl WiserManager for BoostWiser {
+        async fn get_heating_turn_off_time(&self) -> Option<DateTime<Utc>> {
+            None
+        }
+
+        async fn get_heating_on(&self) -> Result<bool, ()> {
+            Ok(false)
+        }
+
+        fn get_wiser_hub(&self) -> &dyn WiserHub {
+            self
+        }
+    }
+
+    #[async_trait]
+    impl WiserHub for BoostWiser {
+        async fn get_data(&self) -> Result<WiserData, RetrieveDataError> {
+            Err(RetrieveDataError::Other("Not supported".into()))
+        }
+
+        async fn get_room_data(&self) -> Result<Vec<WiserRoomData>, RetrieveDataError> {
+            let room = match *self.boost.lock().unwrap() {
+                None => WiserRoomData::new(1, None, None, None, FROM_SCHEDULE_ORIGIN.into(), 190, 200, Some("Kitchen".into())),
+                Some((temp, end)) => WiserRoomData::new(
+                    1,
+                    Some("Manual".into()),
+                    Some(end.timestamp()),
+                    Some((temp * 10.0).round() as i32),
+                    "FromBoost".into(),
+                    190,
+                    200,
+                    Some("Kitchen".into()),
+                ),
+            };
+            Ok(vec![room])
+        }
+
+        async fn cancel_boost(&self, _room_id: usize, _originator: String) -> Result<(), Box<dyn Error>> {
+            *self.boost.lock().unwrap() = None;
+            Ok(())
+        }
+
+        async fn set_boost(
+            &self,
+            _room_id: usize,
+            duration_minutes: usize,
+            temp: f32,
+            _originator: String,
+        ) -> Result<(f32, DateTime<Utc>), Box<dyn Error>> {
+            let end = *self.now.lock().unwrap() + CDuration::minutes(duration_minutes as i64);
+            *self.boost.lock().unwrap() = Some((temp, end));
+            *self.boosts_applied.lock().unwrap() += 1;
+            Ok((temp, end))
+        }
+    }
+
+    fn kitchen_config(increase: f32) -> BoostActiveRoomsConfig {
+        toml::from_str(&format!(
+            r#"
+reapply_min_difference = 0.5
+
+[[parts]]
+room = "Kitchen"
+device = "MyComputer"
+increase = {increase:.1}
+"#
+        ))
+        .expect("Invalid config")
+    }
+
+    async fn update_at(state: &mut AppliedBoosts, wiser: &BoostWiser, time: DateTime<Utc>) {
+        update_with_config_at(state, wiser, time, &kitchen_config(1.0)).await
+    }
+
+    async fn update_with_config_at(
+        state: &mut AppliedBoosts,
+        wiser: &BoostWiser,
+        time: DateTime<Utc>,
+        config: &BoostActiveRoomsConfig,
+    ) {
+        update_while_heating_at(state, wiser, time, config, false).await
+    }
+
+    async fn update_while_heating_at(
+        state: &mut AppliedBoosts,
+        wiser: &BoostWiser,
+        time: DateTime<Utc>,
+        config: &BoostActiveRoomsConfig,
+        heating: bool,
+    ) {
+        *wiser.now.lock().unwrap() = time;
+        let devices = vec![Device::new("MyComputer".into())];
+        update_boosted_rooms(
+            state,
+            config,
+            UnnamedRoomPolicy::Skip,
+            devices,
+            heating,
+            wiser,
+            &DummyTimeProvider::new(time),
+        )
+        .await
+        .expect("Should update boosted rooms");
+    }
+
+    #[tokio::test]
+    async fn test_boost_renewed() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 10, 12, 0, 0).unwrap();
+        let wiser = BoostWiser::new();
+        let mut state = AppliedBoosts::new();
+
+        update_at(&mut state, &wiser, start).await;
+        assert_eq!(wiser.boosts_applied(), 1);
+
+        // Plenty of boost remaining.
+        update_at(&mut state, &wiser, start + CDuration::minutes(29)).await;
+        assert_eq!(wiser.boosts_applied(), 1);
+
+        // Less than BOOST_RENEW_MINUTES remaining.
+        update_at(&mut state, &wiser, start + CDuration::minutes(31)).await;
+        assert_eq!(wiser.boosts_applied(), 2);
+
+        // Renewed, so nothing to do until nearer the new end.
+        update_at(&mut state, &wiser, start + CDuration::minutes(60)).await;
+        assert_eq!(wiser.boosts_applied(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_reapply_below_min_difference() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 10, 12, 0, 0).unwrap();
+        let wiser = BoostWiser::new();
+        let mut state = AppliedBoosts::new();
+
+        update_at(&mut state, &wiser, start).await;
+        assert_eq!(wiser.boosts_applied(), 1);
+
+        // Wants 21.4, has 21.0
+        update_with_config_at(&mut state, &wiser, start + CDuration::minutes(1), &kitchen_config(1.4)).await;
+        assert_eq!(wiser.boosts_applied(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_reapply_above_min_difference() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 10, 12, 0, 0).unwrap();
+        let wiser = BoostWiser::new();
+        let mut state = AppliedBoosts::new();
+
+        update_at(&mut state, &wiser, start).await;
+        assert_eq!(wiser.boosts_applied(), 1);
+
+        // Wants 21.6, has 21.0
+        update_with_config_at(&mut state, &wiser, start + CDuration::minutes(1), &kitchen_config(1.6)).await;
+        assert_eq!(wiser.boosts_applied(), 2);
+        assert_eq!(wiser.boost.lock().unwrap().map(|(temp, _)| temp), Some(21.6));
+    }
+
+    #[tokio::test]
+    async fn test_leave_alone_expires() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 10, 12, 0, 0).unwrap();
+        let wiser = BoostWiser::new();
+        let mut state = AppliedBoosts::new();
+
+        update_at(&mut state, &wiser, start).await;
+        assert_eq!(wiser.boosts_applied(), 1);
+
+        // Someone else turns off the boost.
+        *wiser.boost.lock().unwrap() = None;
+        update_at(&mut state, &wiser, start + CDuration::minutes(5)).await;
+        assert_eq!(wiser.boosts_applied(), 1);
+
+        // Still within the leave alone time.
+        update_at(&mut state, &wiser, start + CDuration::minutes(64)).await;
+        assert_eq!(wiser.boosts_applied(), 1);
+
+        // Leave alone time has expired, so boost again.
+        update_at(&mut state, &wiser, start + CDuration::minutes(66)).await;
+        assert_eq!(wiser.boosts_applied(), 2);
+    }
+
+    fn rooms() -> Vec<WiserRoomData> {
+        vec![
+            WiserRoomData::new(1, None, None, None, "FromSchedule".into(), 180, 200, Some("Kitchen".into())),
+            WiserRoomData::new(7, None, None, None, "FromSchedule".into(), 180, 200, None),
+        ]
+    }
+
+    #[test]
+    fn test_unnamed_room_skipped() {
+        let rooms = rooms();
+        let named = get_named_rooms(&rooms, UnnamedRoomPolicy::Skip);
+        assert_eq!(named.len(), 1);
+        assert_eq!(named[0].0, "Kitchen");
+    }
+
+    #[test]
+    fn test_unnamed_room_uses_id() {
+        let rooms = rooms();
+        let named = get_named_rooms(&rooms, UnnamedRoomPolicy::UseId);
+        assert_eq!(named.len(), 2);
+        assert_eq!(named[1].0, "7");
+        assert_eq!(named[1].1.get_id(), 7);
+    }
+
+    #[tokio::test]
+    async fn test_only_while_heating() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 10, 12, 0, 0).unwrap();
+        let config: BoostActiveRoomsConfig = toml::from_str(r#"
+only_while_heating = true
+
+[[parts]]
+room = "Kitchen"
+device = "MyComputer"
+increase = 1.0
+"#).expect("Invalid config");
+        let wiser = BoostWiser::new();
+        let mut state = AppliedBoosts::new();
+
+        update_while_heating_at(&mut state, &wiser, start, &config, false).await;
+        assert_eq!(wiser.boosts_applied(), 0, "Shouldn't boost while not heating");
+
+        update_while_heating_at(&mut state, &wiser, start + CDuration::minutes(1), &config, true).await;
+        assert_eq!(wiser.boosts_applied(), 1, "Should boost while heating");
+        assert!(state.get_applied_boost("Kitchen").is_some());
+
+        update_while_heating_at(&mut state, &wiser, start + CDuration::minutes(2), &config, false).await;
+        assert!(state.get_applied_boost("Kitchen").is_none(), "Should cancel the boost once no longer heating");
+        assert!(wiser.boost.lock().unwrap().is_none());
+
+        // Without the option, boost regardless.
+        let wiser = BoostWiser::new();
+        update_while_heating_at(&mut AppliedBoosts::new(), &wiser, start, &kitchen_config(1.0), false).await;
+        assert_eq!(wiser.boosts_applied(), 1);
+    }
+}
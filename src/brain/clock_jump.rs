@@ -0,0 +1,79 @@
+use chrono::{DateTime, Utc};
+use std::time::{Duration, Instant};
+
+/// Spots the wall clock jumping (e.g. NTP correcting the time after boot) by comparing
+/// how far it moved between loops with how far the monotonic clock moved.
+#[derive(Default)]
+pub struct ClockJumpDetector {
+    last: Option<(Instant, DateTime<Utc>)>,
+}
+
+impl ClockJumpDetector {
+    /// Record the time of this loop, returning how far the wall clock jumped (positive for
+    /// forwards, negative for backwards) since the last loop, if it jumped by more than the threshold.
+    pub fn check(
+        &mut self,
+        instant: Instant,
+        now: DateTime<Utc>,
+        threshold: &Duration,
+    ) -> Option<chrono::Duration> {
+        let last = self.last.replace((instant, now));
+        let (last_instant, last_now) = last?;
+
+        let expected = chrono::Duration::from_std(instant.saturating_duration_since(last_instant)).ok()?;
+        let jump = (now - last_now) - expected;
+        let threshold = chrono::Duration::from_std(*threshold).ok()?;
+        if jump > threshold || jump < -threshold {
+            Some(jump)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::time_util::test_utils::utc_datetime;
+
+    const THRESHOLD: Duration = Duration::from_secs(15 * 60);
+
+    #[test]
+    fn test_steady() {
+        let mut detector = ClockJumpDetector::default();
+        let instant = Instant::now();
+        let now = utc_datetime(2024, 1, 5, 12, 0, 0);
+
+        assert_eq!(detector.check(instant, now, &THRESHOLD), None, "Nothing to compare to");
+        for i in 1..=5 {
+            let elapsed = Duration::from_secs(60 * i);
+            // A little drift either way is fine.
+            let drift = chrono::Duration::seconds(if i % 2 == 0 { 2 } else { -2 });
+            let now = now + chrono::Duration::from_std(elapsed).unwrap() + drift;
+            assert_eq!(detector.check(instant + elapsed, now, &THRESHOLD), None);
+        }
+    }
+
+    #[test]
+    fn test_jumps() {
+        let mut detector = ClockJumpDetector::default();
+        let instant = Instant::now();
+        let now = utc_datetime(2024, 1, 5, 12, 0, 0);
+        detector.check(instant, now, &THRESHOLD);
+
+        // Forwards an hour, while only 10 seconds really passed.
+        let instant = instant + Duration::from_secs(10);
+        let now = now + chrono::Duration::hours(1) + chrono::Duration::seconds(10);
+        assert_eq!(detector.check(instant, now, &THRESHOLD), Some(chrono::Duration::hours(1)));
+
+        // Back to normal.
+        let instant = instant + Duration::from_secs(10);
+        let now = now + chrono::Duration::seconds(10);
+        assert_eq!(detector.check(instant, now, &THRESHOLD), None);
+
+        // Backwards 2 hours.
+        let instant = instant + Duration::from_secs(10);
+        let now = now - chrono::Duration::hours(2) + chrono::Duration::seconds(10);
+        assert_eq!(detector.check(instant, now, &THRESHOLD), Some(chrono::Duration::hours(-2)));
+    }
+}
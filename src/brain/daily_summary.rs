@@ -0,0 +1,179 @@
+use chrono::{DateTime, Local, NaiveTime, TimeZone, Utc};
+use itertools::Itertools;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// What was running at the last loop, which the time until the next loop is counted against.
+#[derive(Debug, Clone, PartialEq)]
+struct ActivityState {
+    time: DateTime<Utc>,
+    mode: Option<String>,
+    heat_pump_on: bool,
+    immersion_heater_on: bool,
+}
+
+/// Counts up what has been running, so a summary of each day's activity can be logged
+/// without having to go to the database.
+#[derive(Debug, Default)]
+pub struct DailyActivity {
+    since: Option<DateTime<Utc>>,
+    last: Option<ActivityState>,
+    heat_pump_on_time: Duration,
+    heat_pump_cycles: u32,
+    immersion_heater_on_time: Duration,
+    mode_times: BTreeMap<String, Duration>,
+}
+
+impl DailyActivity {
+    /// Record what is running as of this loop, counting the time since the last loop against
+    /// what was running then. Returns the summary (starting counting again) if it is due,
+    /// at summary_time each day (local time).
+    pub fn record(
+        &mut self,
+        now: DateTime<Utc>,
+        mode: Option<&str>,
+        heat_pump_on: bool,
+        immersion_heater_on: bool,
+        summary_time: Option<NaiveTime>,
+    ) -> Option<String> {
+        let since = *self.since.get_or_insert(now);
+        let current = ActivityState {
+            time: now,
+            mode: mode.map(str::to_owned),
+            heat_pump_on,
+            immersion_heater_on,
+        };
+        match self.last.replace(current) {
+            Some(last) => {
+                let elapsed = (now - last.time).to_std().unwrap_or_default();
+                if last.heat_pump_on {
+                    self.heat_pump_on_time += elapsed;
+                }
+                if last.immersion_heater_on {
+                    self.immersion_heater_on_time += elapsed;
+                }
+                if let Some(mode) = last.mode {
+                    *self.mode_times.entry(mode).or_default() += elapsed;
+                }
+                if heat_pump_on && !last.heat_pump_on {
+                    self.heat_pump_cycles += 1;
+                }
+            }
+            None if heat_pump_on => self.heat_pump_cycles += 1,
+            None => {}
+        }
+
+        let due = next_summary_due(since, summary_time?);
+        if now < due {
+            return None;
+        }
+        let summary = self.format_summary(since);
+        self.reset(now);
+        Some(summary)
+    }
+
+    fn reset(&mut self, now: DateTime<Utc>) {
+        *self = Self {
+            since: Some(now),
+            last: self.last.take(),
+            ..Self::default()
+        };
+    }
+
+    fn format_summary(&self, since: DateTime<Utc>) -> String {
+        let modes = self.mode_times.iter()
+            .filter(|(_, time)| !time.is_zero())
+            .map(|(mode, time)| format!("{} {}", mode, format_duration(time)))
+            .join(", ");
+        format!(
+            "Daily summary since {}: heat pump on for {} in {} cycle(s), immersion heater on for {}. Modes: {}",
+            since.with_timezone(&Local).format("%Y-%m-%d %H:%M"),
+            format_duration(&self.heat_pump_on_time),
+            self.heat_pump_cycles,
+            format_duration(&self.immersion_heater_on_time),
+            if modes.is_empty() { "none" } else { &modes },
+        )
+    }
+}
+
+/// The first time after since that is the summary time (local time).
+fn next_summary_due(since: DateTime<Utc>, summary_time: NaiveTime) -> DateTime<Utc> {
+    let mut date = since.with_timezone(&Local).date_naive();
+    loop {
+        if let Some(due) = Local.from_local_datetime(&date.and_time(summary_time)).earliest() {
+            let due = due.with_timezone(&Utc);
+            if due > since {
+                return due;
+            }
+        }
+        date = date.succ_opt().expect("Should be a next day");
+    }
+}
+
+/// Formats as hours and minutes, e.g. 3h 05m
+fn format_duration(duration: &Duration) -> String {
+    let minutes = duration.as_secs() / 60;
+    format!("{}h {:02}m", minutes / 60, minutes % 60)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::time_util::test_utils::{time, utc_datetime};
+
+    #[test]
+    fn test_format_summary() {
+        let activity = DailyActivity {
+            since: None,
+            last: None,
+            heat_pump_on_time: Duration::from_secs(3 * 3600 + 20 * 60 + 59),
+            heat_pump_cycles: 4,
+            immersion_heater_on_time: Duration::from_secs(60 * 60),
+            mode_times: BTreeMap::from([
+                ("Off".to_owned(), Duration::from_secs(20 * 3600)),
+                ("On".to_owned(), Duration::from_secs(3 * 3600 + 5 * 60)),
+                ("Circulate".to_owned(), Duration::ZERO),
+            ]),
+        };
+
+        assert_eq!(
+            activity.format_summary(utc_datetime(2024, 1, 5, 6, 0, 0)),
+            "Daily summary since 2024-01-05 06:00: heat pump on for 3h 20m in 4 cycle(s), immersion heater on for 1h 00m. Modes: Off 20h 00m, On 3h 05m"
+        );
+        assert!(DailyActivity::default().format_summary(utc_datetime(2024, 1, 5, 6, 0, 0)).ends_with("Modes: none"));
+    }
+
+    #[test]
+    fn test_record() {
+        let mut activity = DailyActivity::default();
+        let start = utc_datetime(2024, 1, 5, 6, 0, 0);
+        let mins = chrono::Duration::minutes;
+        let summary_time = Some(time(6, 0, 0));
+
+        assert_eq!(activity.record(start, Some("Off"), false, true, summary_time), None);
+        assert_eq!(activity.record(start + mins(30), Some("On"), true, false, summary_time), None);
+        assert_eq!(activity.record(start + mins(90), Some("Circulate"), false, false, summary_time), None);
+        assert_eq!(activity.record(start + mins(100), Some("On"), true, false, summary_time), None);
+        assert_eq!(activity.record(start + mins(110), Some("Off"), false, false, summary_time), None);
+
+        assert_eq!(activity.heat_pump_cycles, 2);
+        assert_eq!(activity.heat_pump_on_time, Duration::from_secs(70 * 60));
+        assert_eq!(activity.immersion_heater_on_time, Duration::from_secs(30 * 60));
+        assert_eq!(activity.mode_times.get("On"), Some(&Duration::from_secs(70 * 60)));
+
+        let summary = activity.record(start + chrono::Duration::days(1), Some("Off"), false, false, summary_time)
+            .expect("Should be due");
+        assert!(summary.contains("heat pump on for 1h 10m in 2 cycle(s)"), "{}", summary);
+        assert!(summary.contains("Off 22h 40m"), "{}", summary);
+
+        // Starts again.
+        assert_eq!(activity.heat_pump_cycles, 0);
+        assert_eq!(activity.record(start + chrono::Duration::days(1) + mins(10), Some("Off"), false, false, summary_time), None);
+        assert_eq!(activity.mode_times.get("Off"), Some(&Duration::from_secs(10 * 60)));
+
+        // Never due unless configured.
+        let mut activity = DailyActivity::default();
+        assert_eq!(activity.record(start, None, false, false, None), None);
+        assert_eq!(activity.record(start + chrono::Duration::days(2), None, false, false, None), None);
+    }
+}
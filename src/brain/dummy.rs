@@ -1,3 +1,4 @@
+use chrono::{DateTime, Utc};
 use tokio::runtime::Runtime;
 use crate::brain::{Brain, BrainFailure};
 use crate::io::IOBundle;
@@ -17,4 +18,20 @@ impl Brain for Dummy {
     }
 
     fn reload_config(&mut self) {}
+
+    fn toggle_maintenance(&mut self) {}
+
+    fn set_maintenance(&mut self, _maintenance: bool) {}
+
+    fn set_away(&mut self, _away: bool) {}
+
+    fn reboot_wiser(&mut self) {}
+
+    fn force_mode(&mut self, _mode: &str) -> Result<(), String> {
+        Err("The dummy brain has no modes".to_owned())
+    }
+
+    fn dump_state(&self, _now: DateTime<Utc>) -> Result<String, String> {
+        Err("The dummy brain has no state to dump".to_owned())
+    }
 }
\ No newline at end of file
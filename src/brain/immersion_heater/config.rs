@@ -1,26 +1,161 @@
 use crate::brain::modes::heating_mode::PossibleTemperatureContainer;
 use crate::io::temperatures::Sensor;
 use crate::math::model::{LinearModel, Model};
-use chrono::{NaiveTime, Timelike};
+use crate::time_util::timeslot::ZonedSlot;
+use chrono::{DateTime, NaiveTime, Timelike, Utc};
+use crate::log_rate_limit::rate_limited;
+use crate::temp_format::fmt_temp;
 use log::error;
 use serde::{Deserialize, Deserializer};
+use serde_with::serde_as;
+use serde_with::DurationSeconds;
 use std::collections::HashMap;
 use std::ops::RangeInclusive;
+use std::time::Duration;
 
+#[serde_as]
 #[derive(Deserialize, Clone, Debug, PartialEq, Default)]
 #[serde(deny_unknown_fields)]
 pub struct ImmersionHeaterModelConfig {
     parts: Vec<ImmersionHeaterModelPart>,
+    /// Never run the immersion heater when the top or bottom of the tank is above this temperature,
+    /// whatever the model says, since the heat pump may be heating the tank at the same time.
+    #[serde(default)]
+    max_tank_temp: Option<f32>,
+    /// The minimum time (in seconds) between turning the immersion heater on or off, to save the
+    /// relay if the model is oscillating. Turning off for being above max_tank_temp ignores this.
+    #[serde_as(as = "Option<DurationSeconds>")]
+    #[serde(default)]
+    min_switch_interval: Option<Duration>,
+    /// Keep the immersion heater off while the heat pump is heating the tank,
+    /// as it would be wasteful to do both. Like max_tank_temp, this ignores min_switch_interval.
+    #[serde(default)]
+    suppress_while_heating_tank: bool,
+    /// If any are given, only ever run the immersion heater within one of these windows.
+    #[serde(default)]
+    allowed: Vec<ZonedSlot>,
+    /// Never run the immersion heater within any of these windows (e.g. peak tariff hours),
+    /// whatever the model says. Like max_tank_temp, this ignores min_switch_interval.
+    #[serde(default)]
+    forbidden: Vec<ZonedSlot>,
+    /// Ignore any reading below this, as it must be from a faulty sensor and would keep
+    /// the immersion heater on for far too long.
+    #[serde(default)]
+    min_plausible_temp: Option<f32>,
 }
 
 impl ImmersionHeaterModelConfig {
     #[cfg(test)]
     pub fn new(parts: Vec<ImmersionHeaterModelPart>) -> Self {
-        Self { parts }
+        Self {
+            parts,
+            max_tank_temp: None,
+            min_switch_interval: None,
+            suppress_while_heating_tank: false,
+            allowed: vec![],
+            forbidden: vec![],
+            min_plausible_temp: None,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn with_min_plausible_temp(mut self, min_plausible_temp: f32) -> Self {
+        self.min_plausible_temp = Some(min_plausible_temp);
+        self
+    }
+
+    #[cfg(test)]
+    pub fn with_allowed(mut self, slot: ZonedSlot) -> Self {
+        self.allowed.push(slot);
+        self
+    }
+
+    #[cfg(test)]
+    pub fn with_forbidden(mut self, slot: ZonedSlot) -> Self {
+        self.forbidden.push(slot);
+        self
+    }
+
+    #[cfg(test)]
+    pub fn with_suppress_while_heating_tank(mut self) -> Self {
+        self.suppress_while_heating_tank = true;
+        self
+    }
+
+    #[cfg(test)]
+    pub fn with_max_tank_temp(mut self, max_tank_temp: f32) -> Self {
+        self.max_tank_temp = Some(max_tank_temp);
+        self
+    }
+
+    #[cfg(test)]
+    pub fn with_min_switch_interval(mut self, min_switch_interval: Duration) -> Self {
+        self.min_switch_interval = Some(min_switch_interval);
+        self
     }
 
     pub fn combine(&mut self, mut other: Self) {
-        self.parts.append(&mut other.parts)
+        self.parts.append(&mut other.parts);
+        self.max_tank_temp = match (self.max_tank_temp, other.max_tank_temp) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        self.min_switch_interval = self.min_switch_interval.max(other.min_switch_interval);
+        self.suppress_while_heating_tank |= other.suppress_while_heating_tank;
+        self.allowed.append(&mut other.allowed);
+        self.forbidden.append(&mut other.forbidden);
+        self.min_plausible_temp = match (self.min_plausible_temp, other.min_plausible_temp) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            (a, b) => a.or(b),
+        };
+    }
+
+    /// The same model, with the temperatures of every part lowered by the given amount.
+    pub fn lowered_by(&self, amount: f32) -> Self {
+        Self {
+            parts: self.parts.iter()
+                .map(|part| ImmersionHeaterModelPart {
+                    model: part.model.lowered_by(amount),
+                    ..part.clone()
+                })
+                .collect(),
+            ..self.clone()
+        }
+    }
+
+    pub fn suppress_while_heating_tank(&self) -> bool {
+        self.suppress_while_heating_tank
+    }
+
+    pub fn get_min_switch_interval(&self) -> Option<&Duration> {
+        self.min_switch_interval.as_ref()
+    }
+
+    /// Get the first tank sensor (and its temperature) that is above the max tank temperature, if any.
+    pub fn get_above_max_tank_temp(
+        &self,
+        temps: &impl PossibleTemperatureContainer,
+    ) -> Option<(Sensor, f32)> {
+        let max = self.max_tank_temp?;
+        [Sensor::TKTP, Sensor::TKBT]
+            .iter()
+            .filter_map(|sensor| temps.get_sensor_temp(sensor).map(|temp| (sensor.clone(), *temp)))
+            .find(|(_sensor, temp)| *temp > max)
+    }
+
+    /// Why the immersion heater isn't allowed to run at the given time, if it isn't.
+    pub fn get_blocked_reason(&self, now: &DateTime<Utc>) -> Option<String> {
+        if let Some(slot) = self.forbidden.iter().find(|slot| slot.contains(now)) {
+            return Some(format!("within forbidden window {}", slot));
+        }
+        if !self.allowed.is_empty() && !self.allowed.iter().any(|slot| slot.contains(now)) {
+            return Some("outside of all allowed windows".to_owned());
+        }
+        None
+    }
+
+    pub fn get_sensors(&self) -> impl Iterator<Item = &Sensor> {
+        self.parts.iter().map(|part| part.get_sensor())
     }
 
     pub fn should_be_on(
@@ -33,6 +168,15 @@ impl ImmersionHeaterModelConfig {
         for part in &self.parts {
             if let Some(recommended) = part.recommended_temp(time) {
                 match temps.get_sensor_temp(part.get_sensor()) {
+                    Some(temp) if self.min_plausible_temp.is_some_and(|min| *temp < min) => {
+                        rate_limited!(
+                            warn,
+                            "ih_implausible_temp",
+                            "Ignoring {} of {} for the immersion heater as it is implausibly low, the sensor may be faulty",
+                            part.sensor,
+                            fmt_temp(*temp)
+                        );
+                    }
                     Some(temp) => {
                         if *temp < recommended {
                             map.entry(part.get_sensor().clone())
@@ -266,6 +410,33 @@ mod test {
         ];
 
         assert_eq!(model.parts, parts);
+        assert_eq!(model.max_tank_temp, None);
+    }
+
+    #[test]
+    fn check_min_plausible_temp() {
+        let part = ImmersionHeaterModelPart::from_time_points(
+            (time(01, 00, 00), 40.0),
+            (time(04, 00, 00), 40.0),
+            Sensor::TKBT,
+        );
+        let model = ImmersionHeaterModelConfig::new(vec![part]).with_min_plausible_temp(5.0);
+
+        let temps = HashMap::from([(Sensor::TKBT, 20.0)]);
+        assert_eq!(model.should_be_on(&temps, time(02, 00, 00)), Some((Sensor::TKBT, 40.0)), "Sensible reading");
+
+        let temps = HashMap::from([(Sensor::TKBT, -127.0)]);
+        assert_eq!(model.should_be_on(&temps, time(02, 00, 00)), None, "Implausible reading");
+    }
+
+    #[test]
+    fn check_combine_max_tank_temp() {
+        let mut model: ImmersionHeaterModelConfig = toml::from_str("max_tank_temp = 60.0\nparts = []").unwrap();
+        model.combine(ImmersionHeaterModelConfig::new(vec![]));
+        assert_eq!(model.max_tank_temp, Some(60.0));
+
+        model.combine(ImmersionHeaterModelConfig::new(vec![]).with_max_tank_temp(55.0));
+        assert_eq!(model.max_tank_temp, Some(55.0), "Lowest max should win");
     }
 }
 
@@ -1,35 +1,96 @@
 use crate::brain::immersion_heater::config::ImmersionHeaterModelConfig;
 use crate::brain::modes::heating_mode::PossibleTemperatureContainer;
+use crate::brain::python_like::control::heating_control::HeatPumpMode;
 use crate::brain::python_like::control::misc_control::ImmersionHeaterControl;
 use crate::brain::BrainFailure;
+use crate::temp_format::fmt_temp;
 use crate::time_util::mytime::TimeProvider;
 use log::{debug, info};
+use std::time::Instant;
 
 pub mod config;
 
+/// Turn the immersion heater on or off according to the model.
+/// heat_pump_mode is the current mode of the heat pump, if known.
+/// last_switch is when we last turned it on or off, to limit how often we do, and now is the
+/// current instant to compare it to.
 pub fn follow_ih_model(
     time_provider: &impl TimeProvider,
     temps: &impl PossibleTemperatureContainer,
     immersion_heater_control: &mut dyn ImmersionHeaterControl,
+    heat_pump_mode: Option<HeatPumpMode>,
     model: &ImmersionHeaterModelConfig,
+    last_switch: &mut Option<Instant>,
+    now: Instant,
 ) -> Result<(), BrainFailure> {
     let currently_on = immersion_heater_control.try_get_immersion_heater()?;
+    if let Some((sensor, temp)) = model.get_above_max_tank_temp(temps) {
+        if currently_on {
+            info!(
+                "Turning off immersion heater as {} is {}, above the max tank temp",
+                sensor, fmt_temp(temp)
+            );
+            immersion_heater_control.try_set_immersion_heater(false)?;
+            *last_switch = Some(now);
+        } else {
+            debug!("Not using immersion heater as {} is {}, above the max tank temp", sensor, fmt_temp(temp));
+        }
+        return Ok(());
+    }
+    if let Some(mode) = heat_pump_mode.filter(|mode| model.suppress_while_heating_tank() && mode.is_heating_tank()) {
+        if currently_on {
+            info!("Turning off immersion heater as the heat pump is heating the tank ({:?})", mode);
+            immersion_heater_control.try_set_immersion_heater(false)?;
+            *last_switch = Some(now);
+        } else {
+            debug!("Not using immersion heater as the heat pump is heating the tank ({:?})", mode);
+        }
+        return Ok(());
+    }
+    if let Some(reason) = model.get_blocked_reason(&time_provider.get_utc_time()) {
+        if currently_on {
+            info!("Turning off immersion heater as it is {}", reason);
+            immersion_heater_control.try_set_immersion_heater(false)?;
+            *last_switch = Some(now);
+        } else {
+            debug!("Not using immersion heater as it is {}", reason);
+        }
+        return Ok(());
+    }
     let recommendation = model.should_be_on(temps, time_provider.get_local_time().time());
-    if let Some((sensor, recommend_temp)) = recommendation {
+    if let Some((sensor, recommend_temp)) = &recommendation {
         debug!(
-            "Hope for temp {}: {:.2}, currently {:.2} at this time",
+            "Hope for temp {}: {}, currently {} at this time",
             sensor,
-            recommend_temp,
-            temps.get_sensor_temp(&sensor).copied().unwrap_or(-10000.0)
+            fmt_temp(*recommend_temp),
+            fmt_temp(temps.get_sensor_temp(sensor).copied().unwrap_or(-10000.0))
         );
-        if !currently_on {
-            info!("Turning on immersion heater");
-            immersion_heater_control.try_set_immersion_heater(true)?;
+    }
+
+    let should_be_on = recommendation.is_some();
+    if should_be_on == currently_on {
+        return Ok(());
+    }
+
+    if let (Some(min_interval), Some(last)) = (model.get_min_switch_interval(), *last_switch) {
+        let since_last = now.saturating_duration_since(last);
+        if since_last < *min_interval {
+            debug!(
+                "Keeping immersion heater {} as it was only switched {}s ago",
+                if currently_on { "on" } else { "off" },
+                since_last.as_secs()
+            );
+            return Ok(());
         }
-    } else if currently_on {
+    }
+
+    if should_be_on {
+        info!("Turning on immersion heater");
+    } else {
         info!("Turning off immersion heater");
-        immersion_heater_control.try_set_immersion_heater(false)?;
     }
+    immersion_heater_control.try_set_immersion_heater(should_be_on)?;
+    *last_switch = Some(now);
     Ok(())
 }
 
@@ -41,7 +102,7 @@ mod test {
     use crate::brain::python_like::control::misc_control::MiscControls;
     use crate::io::dummy::DummyAllOutputs;
     use crate::time_util::mytime::DummyTimeProvider;
-    use crate::time_util::test_utils::{date, time};
+    use crate::time_util::test_utils::{date, time, utc_time_slot};
     use crate::Sensor;
     use chrono::{TimeZone, Utc};
     use std::collections::HashMap;
@@ -57,7 +118,7 @@ mod test {
         let mut dummy = DummyAllOutputs::default();
         let datetime = Utc.from_utc_datetime(&date(2022, 10, 03).and_time(time(02, 30, 00)));
         let time_provider = DummyTimeProvider::new(datetime);
-        follow_ih_model(&time_provider, &temps, dummy.as_ih(), &model).unwrap();
+        follow_ih_model(&time_provider, &temps, dummy.as_ih(), None, &model, &mut None, Instant::now()).unwrap();
 
         assert!(
             !dummy.try_get_immersion_heater().unwrap(),
@@ -81,11 +142,161 @@ mod test {
         let mut dummy = DummyAllOutputs::default();
         let time_provider = DummyTimeProvider::new(datetime);
 
-        follow_ih_model(&time_provider, &temps, dummy.as_ih(), &model).unwrap();
+        follow_ih_model(&time_provider, &temps, dummy.as_ih(), None, &model, &mut None, Instant::now()).unwrap();
 
         assert!(
             dummy.try_get_immersion_heater().unwrap(),
             "Immersion heater should have been turned on."
         );
     }
+
+    fn run_with_max_tank_temp(tktp: f32, tkbt: f32, currently_on: bool) -> bool {
+        let model_part = ImmersionHeaterModelPart::from_time_points(
+            (time(00, 30, 00), 70.0),
+            (time(04, 30, 00), 70.0),
+            Sensor::TKBT,
+        );
+        let model = ImmersionHeaterModelConfig::new(vec![model_part]).with_max_tank_temp(55.0);
+        let datetime = Utc.from_utc_datetime(&date(2022, 01, 18).and_time(time(02, 30, 00)));
+        let mut temps = HashMap::new();
+        temps.insert(Sensor::TKTP, tktp);
+        temps.insert(Sensor::TKBT, tkbt);
+
+        let mut dummy = DummyAllOutputs::default();
+        dummy.try_set_immersion_heater(currently_on).unwrap();
+        let time_provider = DummyTimeProvider::new(datetime);
+
+        follow_ih_model(&time_provider, &temps, dummy.as_ih(), None, &model, &mut None, Instant::now()).unwrap();
+        dummy.try_get_immersion_heater().unwrap()
+    }
+
+    #[test]
+    fn check_max_tank_temp() {
+        assert!(run_with_max_tank_temp(54.9, 40.0, false), "Should turn on just below the max");
+        assert!(run_with_max_tank_temp(55.0, 40.0, false), "Should turn on at the max");
+        assert!(!run_with_max_tank_temp(55.1, 40.0, false), "Should not turn on above the max");
+        assert!(!run_with_max_tank_temp(50.0, 56.0, false), "TKBT above max should also prevent it");
+        assert!(!run_with_max_tank_temp(55.1, 40.0, true), "Should turn off above the max");
+        assert!(run_with_max_tank_temp(54.0, 40.0, true), "Should stay on below the max");
+    }
+
+    #[test]
+    fn check_min_switch_interval() {
+        let model_part = ImmersionHeaterModelPart::from_time_points(
+            (time(00, 30, 00), 30.0),
+            (time(04, 30, 00), 30.0),
+            Sensor::TKBT,
+        );
+        let model = ImmersionHeaterModelConfig::new(vec![model_part])
+            .with_min_switch_interval(std::time::Duration::from_secs(5 * 60));
+        let start = Utc.from_utc_datetime(&date(2022, 01, 18).and_time(time(02, 30, 00)));
+        let start_instant = Instant::now();
+
+        let mut dummy = DummyAllOutputs::default();
+        let mut last_switch = None;
+        let mut run = |minutes: i64, tkbt: f32| {
+            let temps = HashMap::from([(Sensor::TKTP, 40.0), (Sensor::TKBT, tkbt)]);
+            let time_provider = DummyTimeProvider::new(start + chrono::Duration::minutes(minutes));
+            let now = start_instant + std::time::Duration::from_secs(minutes as u64 * 60);
+            follow_ih_model(&time_provider, &temps, dummy.as_ih(), None, &model, &mut last_switch, now).unwrap();
+            dummy.try_get_immersion_heater().unwrap()
+        };
+
+        assert!(run(0, 29.0), "Nothing to wait for the first time");
+        assert!(run(1, 31.0), "Should stay on within the interval");
+        assert!(run(4, 31.0), "Should stay on within the interval");
+        assert!(!run(5, 31.0), "Should turn off after the interval");
+        assert!(!run(6, 29.0), "Should stay off within the interval");
+        assert!(!run(7, 31.0), "Should stay off");
+        assert!(!run(9, 29.0), "Should stay off within the interval");
+        assert!(run(10, 29.0), "Should turn on after the interval");
+    }
+
+    #[test]
+    fn check_max_tank_temp_ignores_min_switch_interval() {
+        let model_part = ImmersionHeaterModelPart::from_time_points(
+            (time(00, 30, 00), 70.0),
+            (time(04, 30, 00), 70.0),
+            Sensor::TKBT,
+        );
+        let model = ImmersionHeaterModelConfig::new(vec![model_part])
+            .with_max_tank_temp(55.0)
+            .with_min_switch_interval(std::time::Duration::from_secs(5 * 60));
+        let datetime = Utc.from_utc_datetime(&date(2022, 01, 18).and_time(time(02, 30, 00)));
+        let time_provider = DummyTimeProvider::new(datetime);
+
+        let mut dummy = DummyAllOutputs::default();
+        let mut last_switch = Some(Instant::now());
+        dummy.try_set_immersion_heater(true).unwrap();
+
+        let temps = HashMap::from([(Sensor::TKTP, 56.0), (Sensor::TKBT, 40.0)]);
+        follow_ih_model(&time_provider, &temps, dummy.as_ih(), None, &model, &mut last_switch, Instant::now()).unwrap();
+        assert!(!dummy.try_get_immersion_heater().unwrap(), "Should turn off above the max regardless");
+    }
+
+    #[test]
+    fn check_suppress_while_heating_tank() {
+        let model_part = ImmersionHeaterModelPart::from_time_points(
+            (time(00, 30, 00), 40.0),
+            (time(04, 30, 00), 40.0),
+            Sensor::TKBT,
+        );
+        let datetime = Utc.from_utc_datetime(&date(2022, 01, 18).and_time(time(02, 30, 00)));
+        let time_provider = DummyTimeProvider::new(datetime);
+        let temps = HashMap::from([(Sensor::TKTP, 40.0), (Sensor::TKBT, 30.0)]);
+
+        let run = |model: &ImmersionHeaterModelConfig, mode: Option<HeatPumpMode>, currently_on: bool| {
+            let mut dummy = DummyAllOutputs::default();
+            dummy.try_set_immersion_heater(currently_on).unwrap();
+            follow_ih_model(&time_provider, &temps, dummy.as_ih(), mode, model, &mut None, Instant::now()).unwrap();
+            dummy.try_get_immersion_heater().unwrap()
+        };
+
+        let suppressing = ImmersionHeaterModelConfig::new(vec![model_part.clone()]).with_suppress_while_heating_tank();
+        let not_suppressing = ImmersionHeaterModelConfig::new(vec![model_part]);
+        for (mode, heating_tank) in [
+            (HeatPumpMode::HotWaterOnly, true),
+            (HeatPumpMode::MostlyHotWater, true),
+            (HeatPumpMode::HeatingOnly, false),
+            (HeatPumpMode::BoostedHeating, false),
+            (HeatPumpMode::DrainTank, false),
+            (HeatPumpMode::Off, false),
+        ] {
+            assert_eq!(run(&suppressing, Some(mode.clone()), false), !heating_tank, "Turning on in {:?}", mode);
+            assert_eq!(run(&suppressing, Some(mode.clone()), true), !heating_tank, "Staying on in {:?}", mode);
+            assert!(run(&not_suppressing, Some(mode.clone()), false), "Not suppressing in {:?}", mode);
+        }
+        assert!(run(&suppressing, None, false), "Unknown heat pump mode shouldn't suppress");
+    }
+
+    #[test]
+    fn check_time_windows() {
+        let model_part = ImmersionHeaterModelPart::from_time_points(
+            (time(00, 00, 00), 50.0),
+            (time(23, 59, 59), 50.0),
+            Sensor::TKBT,
+        );
+        let run = |model: &ImmersionHeaterModelConfig, hour: u32, currently_on: bool| {
+            let temps = HashMap::from([(Sensor::TKTP, 40.0), (Sensor::TKBT, 30.0)]);
+            let datetime = Utc.from_utc_datetime(&date(2022, 01, 18).and_time(time(hour, 30, 00)));
+            let mut dummy = DummyAllOutputs::default();
+            dummy.try_set_immersion_heater(currently_on).unwrap();
+            let time_provider = DummyTimeProvider::new(datetime);
+            follow_ih_model(&time_provider, &temps, dummy.as_ih(), None, model, &mut None, Instant::now()).unwrap();
+            dummy.try_get_immersion_heater().unwrap()
+        };
+
+        let forbidden = ImmersionHeaterModelConfig::new(vec![model_part.clone()])
+            .with_forbidden(utc_time_slot(16, 0, 0, 19, 0, 0))
+            .with_min_switch_interval(std::time::Duration::from_secs(60 * 60));
+        assert!(run(&forbidden, 2, false), "Model recommends on outside of the forbidden window");
+        assert!(!run(&forbidden, 17, false), "Shouldn't turn on within the forbidden window");
+        assert!(!run(&forbidden, 17, true), "Should turn off within the forbidden window");
+
+        let allowed = ImmersionHeaterModelConfig::new(vec![model_part])
+            .with_allowed(utc_time_slot(0, 0, 0, 6, 0, 0));
+        assert!(run(&allowed, 2, false), "Should turn on within the allowed window");
+        assert!(!run(&allowed, 12, false), "Shouldn't turn on outside of the allowed window");
+        assert!(!run(&allowed, 12, true), "Should turn off outside of the allowed window");
+    }
 }
@@ -0,0 +1,39 @@
+use crate::io::temperatures::Sensor;
+use serde::Deserialize;
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MissingSensorsConfig {
+    /// Sensors that should always be present in the readings, even if they
+    /// haven't been seen since starting up.
+    expected: Vec<Sensor>,
+    /// How many consecutive loops a sensor has to be missing for before alerting.
+    alert_after_loops: usize,
+}
+
+impl MissingSensorsConfig {
+    #[cfg(test)]
+    pub fn new(expected: Vec<Sensor>, alert_after_loops: usize) -> Self {
+        Self {
+            expected,
+            alert_after_loops,
+        }
+    }
+
+    pub fn get_expected(&self) -> &[Sensor] {
+        &self.expected
+    }
+
+    pub fn get_alert_after_loops(&self) -> usize {
+        self.alert_after_loops
+    }
+}
+
+impl Default for MissingSensorsConfig {
+    fn default() -> Self {
+        Self {
+            expected: vec![],
+            alert_after_loops: 6,
+        }
+    }
+}
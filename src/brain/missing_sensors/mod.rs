@@ -0,0 +1,91 @@
+use crate::brain::missing_sensors::config::MissingSensorsConfig;
+use crate::io::temperatures::Sensor;
+use log::{error, info};
+use std::collections::{HashMap, HashSet};
+
+pub mod config;
+
+/// Keeps track of which sensors have been seen, and for how many loops
+/// any of them have been missing from the readings.
+#[derive(Default)]
+pub struct MissingSensorTracker {
+    seen: HashSet<Sensor>,
+    missing_loops: HashMap<Sensor, usize>,
+}
+
+impl MissingSensorTracker {
+    /// Update with the latest readings.
+    /// Returns the sensors that have just been missing for long enough to alert about.
+    pub fn update(
+        &mut self,
+        temps: &HashMap<Sensor, f32>,
+        config: &MissingSensorsConfig,
+    ) -> Vec<Sensor> {
+        self.seen.extend(temps.keys().cloned());
+
+        let expected: HashSet<&Sensor> = self.seen.iter()
+            .chain(config.get_expected())
+            .collect();
+
+        let mut alerts = vec![];
+        for sensor in expected {
+            if temps.contains_key(sensor) {
+                if let Some(loops) = self.missing_loops.remove(sensor) {
+                    if loops >= config.get_alert_after_loops() {
+                        info!("Sensor {} has reappeared after {} loops", sensor, loops);
+                    }
+                }
+                continue;
+            }
+            let loops = self.missing_loops.entry(sensor.clone()).or_insert(0);
+            *loops += 1;
+            if *loops == config.get_alert_after_loops() {
+                alerts.push(sensor.clone());
+            }
+        }
+
+        alerts.sort();
+        for sensor in &alerts {
+            error!("Sensor {} has been missing for {} consecutive loops - is it faulty?", sensor, config.get_alert_after_loops());
+        }
+        alerts
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_alert_after_threshold() {
+        let config = MissingSensorsConfig::new(vec![], 3);
+        let mut tracker = MissingSensorTracker::default();
+
+        let all = HashMap::from([(Sensor::TKBT, 40.0), (Sensor::HPRT, 30.0)]);
+        let dropped = HashMap::from([(Sensor::HPRT, 30.0)]);
+
+        assert!(tracker.update(&all, &config).is_empty());
+        assert!(tracker.update(&dropped, &config).is_empty());
+        assert!(tracker.update(&dropped, &config).is_empty());
+        assert_eq!(tracker.update(&dropped, &config), vec![Sensor::TKBT]);
+        // Only alert once.
+        assert!(tracker.update(&dropped, &config).is_empty());
+
+        // Coming back resets the count.
+        assert!(tracker.update(&all, &config).is_empty());
+        assert!(tracker.update(&dropped, &config).is_empty());
+        assert!(tracker.update(&dropped, &config).is_empty());
+        assert_eq!(tracker.update(&dropped, &config), vec![Sensor::TKBT]);
+    }
+
+    #[test]
+    fn test_expected_never_seen() {
+        let config = MissingSensorsConfig::new(vec![Sensor::HXOR, Sensor::HXIF], 2);
+        let mut tracker = MissingSensorTracker::default();
+
+        let temps = HashMap::from([(Sensor::TKBT, 40.0)]);
+
+        assert!(tracker.update(&temps, &config).is_empty());
+        assert_eq!(tracker.update(&temps, &config), vec![Sensor::HXOR, Sensor::HXIF]);
+    }
+}
@@ -1,6 +1,7 @@
 use crate::io::IOBundle;
 use crate::time_util::mytime::TimeProvider;
 use backtrace::Backtrace;
+use chrono::{DateTime, Utc};
 use std::fmt::{Display, Formatter};
 use tokio::runtime::Runtime;
 
@@ -8,8 +9,13 @@ pub mod dummy;
 pub mod python_like;
 
 mod boost_active_rooms;
+mod clock_jump;
+mod daily_summary;
 mod immersion_heater;
-mod modes;
+mod missing_sensors;
+pub mod modes;
+mod pump_exercise;
+mod trend;
 
 #[derive(Debug)]
 pub struct BrainFailure {
@@ -65,6 +71,25 @@ pub trait Brain {
     ) -> Result<(), BrainFailure>;
 
     fn reload_config(&mut self);
+
+    /// Toggle maintenance mode, where everything is held off but readings are still logged.
+    fn toggle_maintenance(&mut self);
+
+    /// Enter or leave maintenance mode, see toggle_maintenance.
+    fn set_maintenance(&mut self, maintenance: bool);
+
+    /// Turn away mode on or off, where the targets are lowered to save energy while nobody is home.
+    fn set_away(&mut self, away: bool);
+
+    /// Cut the wiser's power for a while on the next run, to force it to reboot.
+    fn reboot_wiser(&mut self);
+
+    /// Switch into the mode with the given name on the next run, regardless of what
+    /// would normally be chosen. It will then carry on as normal from that mode.
+    fn force_mode(&mut self, mode: &str) -> Result<(), String>;
+
+    /// The current internal state as JSON, for debugging.
+    fn dump_state(&self, now: DateTime<Utc>) -> Result<String, String>;
 }
 
 impl CorrectiveActions {
@@ -1,15 +1,49 @@
+use crate::brain::modes::heating_mode::HeatingMode;
 use crate::brain::modes::{InfoCache, Intention, Mode};
 use crate::brain::python_like::control::heating_control::HeatPumpMode;
+use crate::io::temperatures::Sensor;
+use crate::temp_format::fmt_temp;
 use crate::time_util::mytime::TimeProvider;
 use crate::{expect_available, BrainFailure, IOBundle, PythonBrainConfig};
 use core::option::Option::{None, Some};
 use log::{error, info};
+use std::time::Instant;
 use tokio::runtime::Runtime;
 
 use super::working_temp::{find_working_temp_action, CurrentHeatDirection, WorkingTempAction};
 
 #[derive(Debug, PartialEq, Default)]
-pub struct CirculateMode {}
+pub struct CirculateMode {
+    /// TKBT (if available) as of the last update, if it had reached the bottom of the working range.
+    bottom_reached_tkbt: Option<Option<f32>>,
+    /// When turning on was first wanted after reaching the bottom of the working range, and TKBT
+    /// at the time, while waiting to confirm it isn't just a brief dip.
+    finish_wanted_since: Option<(Instant, Option<f32>)>,
+}
+
+impl CirculateMode {
+    /// Whether to go ahead with ending circulation to turn the heat pump on, only doing so once
+    /// the bottom of the working range has been reached for long enough, rather than being a brief
+    /// dip in TKBT. Stays circulating (None) until then.
+    pub fn confirm_turning_on(&mut self, config: &PythonBrainConfig, next_mode: Option<HeatingMode>, now: Instant) -> Option<HeatingMode> {
+        let (Some(HeatingMode::TurningOn(_)), Some(tkbt)) = (&next_mode, self.bottom_reached_tkbt) else {
+            return next_mode;
+        };
+        let confirm = config.hp_circulation.circulate_finish_confirm;
+        let (since, tkbt_at_start) = *self.finish_wanted_since.get_or_insert((now, tkbt));
+        let elapsed = now.saturating_duration_since(since);
+        if elapsed >= confirm {
+            return next_mode;
+        }
+        info!("Reached bottom of working range (TKBT {}, was {}), waiting {}s to confirm before turning on.",
+            fmt_reading(tkbt), fmt_reading(tkbt_at_start), (confirm - elapsed).as_secs());
+        None
+    }
+}
+
+fn fmt_reading(temp: Option<f32>) -> String {
+    temp.map_or_else(|| "missing".to_owned(), |temp| fmt_temp(temp).to_string())
+}
 
 impl Mode for CirculateMode {
     fn enter(
@@ -29,8 +63,9 @@ impl Mode for CirculateMode {
         config: &PythonBrainConfig,
         info_cache: &mut InfoCache,
         io_bundle: &mut IOBundle,
-        _time: &impl TimeProvider,
+        time: &impl TimeProvider,
     ) -> Result<Intention, BrainFailure> {
+        self.bottom_reached_tkbt = None;
         if !info_cache.heating_on() {
             return Ok(Intention::finish());
         }
@@ -42,19 +77,45 @@ impl Mode for CirculateMode {
             }
         };
         let range = info_cache.get_working_temp_range();
-        match find_working_temp_action(
-            &temps,
+        let action = find_working_temp_action(
+            &info_cache.circulate_temps(&temps),
             &range,
             &config.hp_circulation,
             CurrentHeatDirection::Falling,
             None, None,
-        ) {
+        );
+
+        // Cooling to a target replaces stopping at the bottom of the working range.
+        let cool_to = config.get_circulate_cool_to(&time.get_utc_time())
+            .and_then(|target| match temps.get(target.get_target_sensor()) {
+                Some(temp) => Some((target, *temp)),
+                None => {
+                    error!("Missing {} sensor to cool to {}, using the working range instead.", target.get_target_sensor(), target);
+                    None
+                }
+            });
+        if let (Some((target, temp)), Ok(WorkingTempAction::Cool { circulate: true } | WorkingTempAction::Heat { .. })) = (cool_to, &action) {
+            if temp <= target.get_target_temp() {
+                info!("Reached cool to target of {}, ending circulation.", target);
+                return Ok(Intention::finish());
+            }
+            return Ok(Intention::YieldHeatUps);
+        }
+
+        if !matches!(action, Ok(WorkingTempAction::Heat { .. })) {
+            if let Some((_, tkbt)) = self.finish_wanted_since.take() {
+                info!("Back above the bottom of the working range (TKBT was {}), carrying on circulating.", fmt_reading(tkbt));
+            }
+        }
+
+        match action {
             Ok(WorkingTempAction::Cool { circulate: true }) => Ok(Intention::YieldHeatUps),
             Ok(WorkingTempAction::Cool { circulate: false }) => {
                 info!("TKBT too cold, would be heating the tank. Ending circulation.");
                 Ok(Intention::finish())
             }
             Ok(WorkingTempAction::Heat { .. }) => {
+                self.bottom_reached_tkbt = Some(temps.get(&Sensor::TKBT).copied());
                 info!("Reached bottom of working range, ending circulation.");
                 Ok(Intention::Finish)
             }
@@ -68,3 +129,140 @@ impl Mode for CirculateMode {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::brain::modes::working_temp::{WorkingRange, WorkingTemperatureRange};
+    use crate::brain::modes::HeatingState;
+    use crate::io::dummy_io_bundle::new_dummy_io;
+    use crate::io::temperatures::Sensor;
+    use crate::time_util::mytime::DummyTimeProvider;
+    use crate::brain::modes::turning_on::TurningOnMode;
+    use crate::time_util::test_utils::utc_datetime;
+    use std::time::Duration;
+
+    const COOL_TO_CONFIG_STR: &str = r#"
+[[circulate_cool_to]]
+slot = { type = "Utc", start = "02:00:00", end = "04:00:00" }
+target = { sensor = "TKBT", temp = 30.0 }
+"#;
+
+    /// Update circulate with the heat exchanger below the working range (30-40)
+    fn update_below_range(config: &PythonBrainConfig, hour: u32, tkbt: f32) -> Intention {
+        let (mut io_bundle, mut io_handle) = new_dummy_io();
+        let range = WorkingRange::from_temp_only(WorkingTemperatureRange::from_min_max(30.0, 40.0));
+        let mut info_cache = InfoCache::create(HeatingState::ON, range);
+        let rt = Runtime::new().unwrap();
+        let time_provider = DummyTimeProvider::new(utc_datetime(2023, 11, 14, hour, 0, 0));
+
+        io_handle.send_temp(Sensor::HXIF, 28.0);
+        io_handle.send_temp(Sensor::HXIR, 28.0);
+        io_handle.send_temp(Sensor::HXOR, 28.0);
+        io_handle.send_temp(Sensor::HXOF, 28.0);
+        io_handle.send_temp(Sensor::HPRT, 28.0);
+        io_handle.send_temp(Sensor::TKFL, 28.0);
+        io_handle.send_temp(Sensor::HPFL, 28.0);
+        io_handle.send_temp(Sensor::TKBT, tkbt);
+
+        CirculateMode::default()
+            .update(&rt, config, &mut info_cache, &mut io_bundle, &time_provider)
+            .expect("Should succeed")
+    }
+
+    #[test]
+    fn test_stops_at_working_minimum() {
+        let config = PythonBrainConfig::default();
+        assert_eq!(update_below_range(&config, 3, 35.0), Intention::Finish);
+
+        let config: PythonBrainConfig = toml::from_str(COOL_TO_CONFIG_STR).unwrap();
+        assert_eq!(update_below_range(&config, 12, 35.0), Intention::Finish, "Outside of the slot");
+    }
+
+    #[test]
+    fn test_cools_to_target() {
+        let config: PythonBrainConfig = toml::from_str(COOL_TO_CONFIG_STR).unwrap();
+
+        assert_eq!(update_below_range(&config, 3, 35.0), Intention::YieldHeatUps);
+        assert_eq!(update_below_range(&config, 3, 30.5), Intention::YieldHeatUps);
+        assert_eq!(update_below_range(&config, 3, 30.0), Intention::Finish);
+    }
+
+    const FINISH_CONFIRM_CONFIG_STR: &str = r#"
+[hp_circulation]
+circulate_finish_confirm = 120
+"#;
+
+    /// Update the same circulate mode at the given minute, with the heat exchanger at hx,
+    /// returning the next mode if finishing would turn the heat pump on.
+    fn update_at(mode: &mut CirculateMode, config: &PythonBrainConfig, start: Instant, minute: u64, hx: f32, tkbt: f32) -> Option<HeatingMode> {
+        let (mut io_bundle, mut io_handle) = new_dummy_io();
+        let range = WorkingRange::from_temp_only(WorkingTemperatureRange::from_min_max(30.0, 40.0));
+        let mut info_cache = InfoCache::create(HeatingState::ON, range);
+        let rt = Runtime::new().unwrap();
+        let time_provider = DummyTimeProvider::new(utc_datetime(2023, 11, 14, 12, minute as u32, 0));
+
+        for sensor in [Sensor::HXIF, Sensor::HXIR, Sensor::HXOR, Sensor::HXOF, Sensor::HPRT, Sensor::TKFL, Sensor::HPFL] {
+            io_handle.send_temp(sensor, hx);
+        }
+        io_handle.send_temp(Sensor::TKBT, tkbt);
+
+        let intention = mode.update(&rt, config, &mut info_cache, &mut io_bundle, &time_provider)
+            .expect("Should succeed");
+        if intention == Intention::YieldHeatUps {
+            return None;
+        }
+        assert_eq!(intention, Intention::Finish);
+        let turning_on = HeatingMode::TurningOn(TurningOnMode::new(start));
+        mode.confirm_turning_on(config, Some(turning_on), start + Duration::from_secs(minute * 60))
+    }
+
+    #[test]
+    fn test_brief_dip_keeps_circulating() {
+        let config: PythonBrainConfig = toml::from_str(FINISH_CONFIRM_CONFIG_STR).unwrap();
+        let mut mode = CirculateMode::default();
+        let start = Instant::now();
+
+        assert_eq!(update_at(&mut mode, &config, start, 0, 36.0, 38.0), None);
+        assert_eq!(update_at(&mut mode, &config, start, 1, 28.0, 30.0), None, "Dipped, waiting to confirm");
+        assert_eq!(update_at(&mut mode, &config, start, 2, 36.0, 38.0), None);
+        assert_eq!(mode.finish_wanted_since, None, "Should have forgotten the dip");
+        assert_eq!(update_at(&mut mode, &config, start, 3, 28.0, 30.0), None, "Waiting to confirm from the start again");
+        assert_eq!(update_at(&mut mode, &config, start, 4, 28.0, 30.0), None);
+    }
+
+    #[test]
+    fn test_sustained_drop_finishes() {
+        let config: PythonBrainConfig = toml::from_str(FINISH_CONFIRM_CONFIG_STR).unwrap();
+        let mut mode = CirculateMode::default();
+        let start = Instant::now();
+
+        assert_eq!(update_at(&mut mode, &config, start, 0, 36.0, 38.0), None);
+        assert_eq!(update_at(&mut mode, &config, start, 1, 28.0, 30.0), None);
+        assert_eq!(update_at(&mut mode, &config, start, 2, 28.0, 30.0), None);
+        let next = update_at(&mut mode, &config, start, 3, 28.0, 30.0);
+        assert!(matches!(next, Some(HeatingMode::TurningOn(_))), "Got {:?}", next);
+
+        let mut mode = CirculateMode::default();
+        let next = update_at(&mut mode, &PythonBrainConfig::default(), start, 0, 28.0, 30.0);
+        assert!(matches!(next, Some(HeatingMode::TurningOn(_))), "No confirmation by default, got {:?}", next);
+    }
+
+    #[test]
+    fn test_confirm_only_turning_on() {
+        let config: PythonBrainConfig = toml::from_str(FINISH_CONFIRM_CONFIG_STR).unwrap();
+        let mut mode = CirculateMode::default();
+        let now = Instant::now();
+        mode.bottom_reached_tkbt = Some(None);
+
+        assert_eq!(mode.confirm_turning_on(&config, Some(HeatingMode::off()), now), Some(HeatingMode::off()));
+        assert_eq!(mode.finish_wanted_since, None);
+        let turning_on = HeatingMode::TurningOn(TurningOnMode::new(now));
+        assert_eq!(mode.confirm_turning_on(&config, Some(turning_on), now), None, "Waiting even with TKBT missing");
+
+        // Only once the bottom of the working range has been reached.
+        mode.bottom_reached_tkbt = None;
+        let turning_on = HeatingMode::TurningOn(TurningOnMode::new(now));
+        assert!(mode.confirm_turning_on(&config, Some(turning_on), now).is_some());
+    }
+}
@@ -2,27 +2,41 @@ use crate::brain::modes::working_temp::{
     find_working_temp_action, CurrentHeatDirection, WorkingTempAction,
 };
 use crate::brain::modes::{InfoCache, Intention, Mode};
+use crate::brain::python_like::config::demand_priority::DemandPriority;
+use crate::brain::python_like::config::overrun_config::SlotEnd;
 use crate::brain::python_like::config::PythonBrainConfig;
 use crate::brain::python_like::control::heating_control::HeatPumpMode;
+use crate::brain::modes::heating_mode::TargetTemperature;
+use crate::brain::trend::TemperatureTrends;
 use crate::brain::BrainFailure;
 use crate::expect_available;
 use crate::io::IOBundle;
 use crate::io::temperatures::Sensor;
+use crate::temp_format::fmt_temp;
 use crate::time_util::mytime::TimeProvider;
 use crate::time_util::timeslot::ZonedSlot;
 use chrono::{DateTime, SecondsFormat, Utc};
+use crate::log_rate_limit::rate_limited;
 use log::{debug, error, info, warn};
+use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::runtime::Runtime;
 
 use super::working_temp::MixedState;
 use super::{allow_dhw_mixed, AllowDhwMixed};
 use super::heating_mode::HeatingMode;
 use super::mixed::MixedMode;
+use super::on::OnMode;
 
 #[derive(Debug, PartialEq)]
 pub struct DhwOnlyMode {
+    /// The readings while in this mode, to estimate how long until the target is reached.
+    trends: TemperatureTrends,
+    /// What the slot we are heating for wants to heat up to, as of the last update.
+    target: Option<TargetTemperature>,
+    /// When the target sensor went missing, if it is.
+    target_missing_since: Option<Instant>,
 }
 
 impl Mode for DhwOnlyMode {
@@ -44,38 +58,66 @@ impl Mode for DhwOnlyMode {
         io_bundle: &mut IOBundle,
         time: &impl TimeProvider,
     ) -> Result<Intention, BrainFailure> {
-        let temps = match rt.block_on(info_cache.get_temps(io_bundle.temperature_manager())) {
+        let mut temps = match rt.block_on(info_cache.get_temps(io_bundle.temperature_manager())) {
             Err(err) => {
                 error!("Temperatures not available, stopping overrun {err}");
                 return Ok(Intention::off_now());
             },
             Ok(temps) => temps,
         };
+        config.missing_overrun_sensor.apply_fallbacks(&mut temps);
 
         let now = time.get_utc_time();
+        self.trends.update(now, &temps);
 
         let heating_control = expect_available!(io_bundle.heating_control())?;
         let (_hp_on, hp_duration) = heating_control.get_heat_pump_on_with_time()?;
         let short_duration = hp_duration < Duration::from_secs(60 * 10);
 
+        // Moving onto a different slot would be starting another overrun straight after this one.
+        let only_sensor = match (config.get_min_overrun_gap(), &self.target) {
+            (Some(_), Some(target)) => Some(target.get_target_sensor().clone()),
+            _ => None,
+        };
         let slot = config.get_overrun_during().find_matching_slot(&now, &temps,
-            |temps, temp| temp < temps.max || (short_duration && temp < temps.extra.unwrap_or(temps.max))
+            |temps, temp| only_sensor.as_ref().is_none_or(|sensor| temps.sensor == *sensor)
+                && (temp < temps.max || (short_duration && temp < temps.extra.unwrap_or(temps.max)))
         );
 
         let Some(slot) = slot else {
+            if self.should_hold_for_missing_target(config, &temps, now, Instant::now()) {
+                return Ok(Intention::KeepState);
+            }
+            if let Some(min_runtime) = config.get_min_hp_runtime() {
+                if min_runtime.should_keep_heating(hp_duration, &temps) {
+                    debug!("Heat pump not on for its minimum runtime, heating until {}", min_runtime.get_safety_cut_off());
+                    return Ok(Intention::KeepState);
+                }
+            }
+            if self.slot_has_ended(config, now) && config.get_overrun_during().at_slot_end == SlotEnd::ContinueIfWiser {
+                if info_cache.heating_on() {
+                    let cp_on = heating_control.try_get_heat_circulation_pump()?;
+                    return Ok(Intention::SwitchForce(HeatingMode::On(OnMode::create(cp_on)))
+                        .because("Hot water slot ended with wiser calling for heat"));
+                }
+                return Ok(Intention::off_now().because("Hot water slot ended with wiser not calling for heat"));
+            }
             info!("No longer matches a DHW slot");
             return Ok(Intention::finish());
         };
+        self.target = Some(TargetTemperature::new(slot.temps.sensor.clone(), slot.temps.max));
+        self.target_missing_since = None;
 
         if info_cache.heating_on() {
             let allow_dhw_mixed = allow_dhw_mixed(&temps, slot, false);
 
             if matches!(allow_dhw_mixed, AllowDhwMixed::Force) {
-                return Ok(Intention::SwitchForce(HeatingMode::Mixed(MixedMode::new())))
+                return Ok(Intention::SwitchForce(HeatingMode::Mixed(MixedMode::new()))
+                    .because("Hot water slot needs mixed mode while wiser is calling for heat"))
             }
 
             match find_working_temp_action(
-                &temps,
+                &info_cache.circulate_temps(&temps),
                 &info_cache.get_working_temp_range(),
                 &config.hp_circulation,
                 CurrentHeatDirection::Falling,
@@ -88,9 +130,16 @@ impl Mode for DhwOnlyMode {
                 Ok(WorkingTempAction::Heat { mixed_state }) => {
                     match allow_dhw_mixed {
                         AllowDhwMixed::Error  => return Ok(Intention::off_now()),
+                        AllowDhwMixed::Can if config.demand_priority == DemandPriority::DhwFirst => {
+                            debug!("Hot water prioritised over heating, continuing.");
+                        }
+                        AllowDhwMixed::Can if config.demand_priority == DemandPriority::HeatingFirst => {
+                            return Ok(Intention::finish().because("Heating prioritised over hot water"));
+                        }
                         AllowDhwMixed::Can    => {
                             if mixed_state == MixedState::MixedHeating {
-                                return Ok(Intention::SwitchForce(HeatingMode::Mixed(MixedMode::new())))
+                                return Ok(Intention::SwitchForce(HeatingMode::Mixed(MixedMode::new()))
+                                    .because("Heating wanted during a hot water slot"))
                             }
                             return Ok(Intention::finish());
                         }
@@ -110,13 +159,13 @@ impl Mode for DhwOnlyMode {
             match heating_control.try_get_heat_pump()? {
                 HeatPumpMode::MostlyHotWater => {
                     if diff <= bypass.stop_hp_drop {
-                        info!("Bypass no longer required as HPFL-HPRT={diff:.1}");
+                        info!("Bypass no longer required as HPFL-HPRT={}", fmt_temp(diff));
                         heating_control.set_heat_pump(HeatPumpMode::HotWaterOnly, None)?;
                     }
                 },
                 HeatPumpMode::HotWaterOnly => {
                     if diff >= bypass.start_hp_drop {
-                        info!("Bypass required as HPFL-HPRT={diff:.1}");
+                        info!("Bypass required as HPFL-HPRT={}", fmt_temp(diff));
                         heating_control.set_heat_pump(HeatPumpMode::MostlyHotWater, None)?;
                     }
                 },
@@ -164,7 +213,65 @@ impl Display for HeatUpEnd {
 
 impl DhwOnlyMode {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            trends: TemperatureTrends::default(),
+            target: None,
+            target_missing_since: None,
+        }
+    }
+
+    /// Whether the time ran out on the slot we were heating for, rather than it reaching its target.
+    fn slot_has_ended(&self, config: &PythonBrainConfig, now: DateTime<Utc>) -> bool {
+        match &self.target {
+            Some(target) => !config.get_overrun_during().has_current_slot_for(&now, target.get_target_sensor()),
+            None => false,
+        }
+    }
+
+    /// Whether to carry on heating as the sensor we were heating up has gone missing,
+    /// but we haven't yet waited hold_secs for it to come back. The wait is timed from instant.
+    fn should_hold_for_missing_target(
+        &mut self,
+        config: &PythonBrainConfig,
+        temps: &HashMap<Sensor, f32>,
+        now: DateTime<Utc>,
+        instant: Instant,
+    ) -> bool {
+        let sensor = match &self.target {
+            Some(target) if !temps.contains_key(target.get_target_sensor()) => target.get_target_sensor(),
+            _ => {
+                self.target_missing_since = None;
+                return false;
+            }
+        };
+        let hold = config.missing_overrun_sensor.hold_secs;
+        if hold.is_zero() || !config.get_overrun_during().has_current_slot_for(&now, sensor) {
+            return false;
+        }
+        let since = *self.target_missing_since.get_or_insert(instant);
+        let missing_for = instant.saturating_duration_since(since);
+        if missing_for < hold {
+            rate_limited!(warn, "overrun_sensor_missing", "{} missing for {}s, waiting up to {}s for it to come back",
+                sensor, missing_for.as_secs(), hold.as_secs());
+            return true;
+        }
+        error!("{} missing for {}s, giving up on the overrun", sensor, missing_for.as_secs());
+        false
+    }
+
+    /// Estimate how many minutes until the target is reached, going by how fast the target
+    /// sensor has been warming up. None (unknown) if it isn't warming or we don't know yet.
+    pub fn estimate_minutes_to_target(&self) -> Option<f32> {
+        let target = self.target.as_ref()?;
+        let sensor = target.get_target_sensor();
+        let remaining = target.get_target_temp() - self.trends.get_latest(sensor)?;
+        if remaining <= 0.0 {
+            return Some(0.0);
+        }
+        match self.trends.get_trend(sensor)? {
+            rate if rate > 0.0 => Some(remaining / rate),
+            _ => None,
+        }
     }
 }
 
@@ -172,6 +279,7 @@ impl DhwOnlyMode {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::brain::modes::heating_mode::handle_intention;
     use crate::brain::modes::working_temp::{WorkingRange, WorkingTemperatureRange};
     use crate::brain::modes::{HeatingState, InfoCache, Intention, Mode};
     use crate::brain::python_like::config::PythonBrainConfig;
@@ -361,4 +469,190 @@ mod test {
 
         Ok(())
     }
+
+    fn update_with_temps(mode: &mut DhwOnlyMode, config: &PythonBrainConfig, temps: HashMap<Sensor, f32>, now: DateTime<Utc>) -> Result<Intention, BrainFailure> {
+        update_with_wiser(mode, config, HeatingState::OFF, temps, now)
+    }
+
+    fn update_with_wiser(mode: &mut DhwOnlyMode, config: &PythonBrainConfig, wiser: HeatingState, temps: HashMap<Sensor, f32>, now: DateTime<Utc>) -> Result<Intention, BrainFailure> {
+        let rt = Runtime::new().unwrap();
+        let (mut io_bundle, mut io_handle) = new_dummy_io();
+        io_handle.send_temps(TModifyState::SetTemps(temps));
+        let mut info_cache = InfoCache::create(
+            wiser,
+            WorkingRange::from_temp_only(WorkingTemperatureRange::from_min_max(40.0, 50.0)),
+        );
+        mode.update(&rt, config, &mut info_cache, &mut io_bundle, &DummyTimeProvider::new(now))
+    }
+
+    #[test]
+    fn test_missing_target_sensor_fallback() -> Result<(), BrainFailure> {
+        let mut config: PythonBrainConfig = toml::from_str("missing_overrun_sensor.fallbacks = { TKBT = \"TKBTM\" }")
+            .expect("Invalid config string");
+        config._add_dhw_slot(DhwBap::_new(utc_time_slot(10, 0, 0, 12, 0, 0), Sensor::TKBT, 10.0, 40.0));
+        let mut default_config = PythonBrainConfig::default();
+        default_config._add_dhw_slot(DhwBap::_new(utc_time_slot(10, 0, 0, 12, 0, 0), Sensor::TKBT, 10.0, 40.0));
+        let now = utc_datetime(2022, 2, 13, 11, 0, 0);
+
+        let temps = || HashMap::from([(Sensor::from("TKBTM"), 35.0)]);
+        let intention = update_with_temps(&mut DhwOnlyMode::new(), &config, temps(), now)?;
+        assert_eq!(intention, Intention::KeepState, "Should heat using the fallback");
+
+        let intention = update_with_temps(&mut DhwOnlyMode::new(), &default_config, temps(), now)?;
+        assert_eq!(intention, Intention::Finish, "No fallback by default");
+
+        let hot = HashMap::from([(Sensor::from("TKBTM"), 45.0)]);
+        let intention = update_with_temps(&mut DhwOnlyMode::new(), &config, hot, now)?;
+        assert_eq!(intention, Intention::Finish, "Fallback reached the target");
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_target_sensor_hold() -> Result<(), BrainFailure> {
+        let mut config: PythonBrainConfig = toml::from_str("missing_overrun_sensor.hold_secs = 120")
+            .expect("Invalid config string");
+        config._add_dhw_slot(DhwBap::_new(utc_time_slot(10, 0, 0, 12, 0, 0), Sensor::TKBT, 10.0, 40.0));
+        let start = utc_datetime(2022, 2, 13, 11, 0, 0);
+        let missing = || HashMap::from([(Sensor::TKTP, 50.0)]);
+
+        let mut mode = DhwOnlyMode::new();
+        assert_eq!(update_with_temps(&mut mode, &config, HashMap::from([(Sensor::TKBT, 35.0)]), start)?, Intention::KeepState);
+        assert_eq!(update_with_temps(&mut mode, &config, missing(), start)?, Intention::KeepState, "Waiting for TKBT");
+
+        let start_instant = Instant::now();
+        let mut hold = |temps: HashMap<Sensor, f32>, secs: u64| mode.should_hold_for_missing_target(
+            &config,
+            &temps,
+            start + chrono::Duration::seconds(secs as i64),
+            start_instant + Duration::from_secs(secs),
+        );
+        assert!(hold(missing(), 0), "Waiting for TKBT");
+        assert!(hold(missing(), 100), "Still waiting");
+        // Coming back starts the wait again.
+        assert!(!hold(HashMap::from([(Sensor::TKBT, 36.0)]), 110));
+        assert!(hold(missing(), 120));
+        assert!(hold(missing(), 230));
+        assert!(!hold(missing(), 240), "Given up");
+
+        // Not held by default.
+        let mut default_config = PythonBrainConfig::default();
+        default_config._add_dhw_slot(DhwBap::_new(utc_time_slot(10, 0, 0, 12, 0, 0), Sensor::TKBT, 10.0, 40.0));
+        let mut mode = DhwOnlyMode::new();
+        assert_eq!(update_with_temps(&mut mode, &default_config, HashMap::from([(Sensor::TKBT, 35.0)]), start)?, Intention::KeepState);
+        assert_eq!(update_with_temps(&mut mode, &default_config, missing(), start + chrono::Duration::seconds(10))?, Intention::Finish);
+        Ok(())
+    }
+
+    #[test]
+    fn test_demand_priority_heating_first() -> Result<(), BrainFailure> {
+        let rt = Runtime::new().unwrap();
+        let time = DummyTimeProvider::new(utc_datetime(2022, 2, 13, 11, 0, 0));
+
+        let update = |priority: &str| -> Result<Option<HeatingMode>, BrainFailure> {
+            let mut config: PythonBrainConfig = toml::from_str(&format!("demand_priority = \"{}\"", priority))
+                .expect("Invalid config string");
+            config._add_dhw_slot(DhwBap::_new(utc_time_slot(10, 0, 0, 12, 0, 0), Sensor::TKBT, 10.0, 40.0));
+
+            let (mut io_bundle, mut handle) = new_dummy_io();
+            let mut info_cache = InfoCache::create(
+                HeatingState::ON,
+                WorkingRange::from_temp_only(WorkingTemperatureRange::from_min_max(40.0, 50.0)),
+            );
+            // Above the slot minimum, with the heating wanting heat.
+            handle.send_temps(TModifyState::SetTemps(HashMap::from([
+                (Sensor::TKBT, 35.0),
+                (Sensor::TKFL, 35.0),
+                (Sensor::HPFL, 45.0),
+                (Sensor::HXIF, 30.0),
+                (Sensor::HXIR, 30.0),
+                (Sensor::HXOR, 30.0),
+                (Sensor::HPRT, 30.0),
+            ])));
+
+            let mut mode = DhwOnlyMode::new();
+            mode.enter(&config, &rt, &mut io_bundle)?;
+            let intention = mode.update(&rt, &config, &mut info_cache, &mut io_bundle, &time)?;
+            assert!(!matches!(intention, Intention::SwitchForce(HeatingMode::Mixed(_))), "Shouldn't go mixed, was: {:?}", intention);
+            handle_intention(intention, Some(&HeatingMode::DhwOnly(mode)), &mut info_cache, &mut io_bundle, &config, &rt, &time.get_utc_time())
+        };
+
+        let next = update("HeatingFirst")?;
+        assert!(matches!(next, Some(HeatingMode::On(_))), "HeatingFirst should heat, was: {:?}", next);
+
+        let next = update("DhwFirst")?;
+        assert!(next.is_none(), "DhwFirst should carry on heating the tank, was: {:?}", next);
+        Ok(())
+    }
+
+    #[test]
+    fn test_slot_end() -> Result<(), BrainFailure> {
+        let mut config: PythonBrainConfig = toml::from_str("overrun_during.slots = []\noverrun_during.at_slot_end = \"ContinueIfWiser\"")
+            .expect("Invalid config string");
+        config._add_dhw_slot(DhwBap::_new(utc_time_slot(10, 0, 0, 12, 0, 0), Sensor::TKBT, 10.0, 40.0));
+        let mut default_config = PythonBrainConfig::default();
+        default_config._add_dhw_slot(DhwBap::_new(utc_time_slot(10, 0, 0, 12, 0, 0), Sensor::TKBT, 10.0, 40.0));
+        let during = utc_datetime(2022, 2, 13, 11, 0, 0);
+        let after = utc_datetime(2022, 2, 13, 12, 0, 1);
+        let temps = || HashMap::from([(Sensor::TKBT, 35.0)]);
+
+        let update = |config: &PythonBrainConfig, wiser: HeatingState| -> Result<Intention, BrainFailure> {
+            let mut mode = DhwOnlyMode::new();
+            assert_eq!(update_with_wiser(&mut mode, config, wiser, temps(), during)?, Intention::KeepState);
+            update_with_wiser(&mut mode, config, wiser, temps(), after)
+        };
+
+        let intention = update(&config, HeatingState::ON)?;
+        assert!(matches!(intention, Intention::Explained(ref next, _) if matches!(**next, Intention::SwitchForce(HeatingMode::On(_)))),
+            "Should carry on heating as the wiser is on, was: {:?}", intention);
+        let intention = update(&config, HeatingState::OFF)?;
+        assert!(matches!(intention, Intention::Explained(ref next, _) if **next == Intention::off_now()),
+            "Should turn off as the wiser is off, was: {:?}", intention);
+
+        assert_eq!(update(&default_config, HeatingState::ON)?, Intention::Finish);
+        assert_eq!(update(&default_config, HeatingState::OFF)?, Intention::Finish);
+
+        // Reaching the target isn't the slot ending.
+        let mut mode = DhwOnlyMode::new();
+        assert_eq!(update_with_wiser(&mut mode, &config, HeatingState::ON, temps(), during)?, Intention::KeepState);
+        let hot = HashMap::from([(Sensor::TKBT, 45.0)]);
+        assert_eq!(update_with_wiser(&mut mode, &config, HeatingState::ON, hot, during)?, Intention::Finish);
+        Ok(())
+    }
+
+    #[test]
+    fn test_estimate_minutes_to_target() -> Result<(), BrainFailure> {
+        let utc_slot = utc_time_slot(12, 0, 0, 13, 0, 0);
+        let mut config = PythonBrainConfig::default();
+        config._add_dhw_slot(DhwBap::_new(utc_slot, Sensor::TKBT, 30.0, 45.0));
+
+        let mut mode = DhwOnlyMode::new();
+        let rt = Runtime::new().unwrap();
+        let (mut io_bundle, mut handle) = new_dummy_io();
+        mode.enter(&config, &rt, &mut io_bundle)?;
+        assert_eq!(mode.estimate_minutes_to_target(), None, "Nothing known yet");
+
+        let mut update_at = |mode: &mut DhwOnlyMode, minute: u32, tkbt: f32| {
+            handle.send_temp(Sensor::TKBT, tkbt);
+            let mut info_cache = InfoCache::create(
+                HeatingState::OFF,
+                WorkingRange::from_temp_only(WorkingTemperatureRange::from_min_max(40.0, 50.0)),
+            );
+            let time = DummyTimeProvider::new(utc_datetime(2023, 6, 12, 12, minute, 0));
+            mode.update(&rt, &config, &mut info_cache, &mut io_bundle, &time)
+                .map(|_| mode.estimate_minutes_to_target())
+        };
+
+        assert_eq!(update_at(&mut mode, 10, 35.0)?, None, "Need more than one reading");
+        // Warming at 0.5 degrees per minute.
+        assert_eq!(update_at(&mut mode, 12, 36.0)?, Some(18.0));
+        assert_eq!(update_at(&mut mode, 14, 37.0)?, Some(16.0));
+        assert_eq!(update_at(&mut mode, 16, 40.0)?.map(f32::round), Some(6.0), "Faster, on average 0.8 a minute");
+
+        // Not warming, so no idea when it will get there.
+        let mut mode = DhwOnlyMode::new();
+        assert_eq!(update_at(&mut mode, 10, 38.0)?, None);
+        assert_eq!(update_at(&mut mode, 12, 38.0)?, None, "Flat");
+        assert_eq!(update_at(&mut mode, 14, 37.0)?, None, "Cooling");
+        Ok(())
+    }
 }
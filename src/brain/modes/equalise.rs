@@ -13,7 +13,7 @@ use crate::time_util::mytime::TimeProvider;
 use super::heating_mode::HeatingMode;
 use super::intention::Intention;
 use super::try_circulate::TryCirculateMode;
-use super::working_temp::{find_working_temp_action, CurrentHeatDirection, WorkingTempAction};
+use super::working_temp::{find_working_temp_action, tank_warm_enough_to_drain, CurrentHeatDirection, WorkingTempAction};
 use super::{InfoCache, Mode};
 
 #[derive(PartialEq, Debug)]
@@ -66,23 +66,33 @@ impl Mode for EqualiseMode {
         let temps = rt.block_on(info_cache.get_temps(io_bundle.temperature_manager()));
         if temps.is_err() {
             error!("Failed to get temperatures, sleeping more and will keep checking.");
-            return Ok(Intention::off_now());
+            return Ok(Intention::off_now().because("Failed to get temperatures"));
         }
 
+        let temps = temps.unwrap();
         match find_working_temp_action(
-            &temps.unwrap(),
+            &info_cache.circulate_temps(&temps),
             &working_temp,
             &config.hp_circulation,
             CurrentHeatDirection::Falling,
             None, None,
         ) {
-            Ok(WorkingTempAction::Cool { circulate: true }) => Ok(Intention::SwitchForce(
-                HeatingMode::TryCirculate(TryCirculateMode::new(Instant::now())),
-            )),
+            Ok(WorkingTempAction::Cool { circulate: true }) => {
+                match tank_warm_enough_to_drain(&info_cache.circulate_temps(&temps), &working_temp, &config.hp_circulation) {
+                    Ok(true) => Ok(Intention::SwitchForce(
+                        HeatingMode::TryCirculate(TryCirculateMode::new(Instant::now())),
+                    ).because("Finished equalising, the tank is warm enough to circulate")),
+                    Ok(false) => Ok(Intention::off_now().because("Finished equalising, the tank is not warm enough to drain")),
+                    Err(missing_sensor) => {
+                        error!("Failed to get {} temperature, turning off.", missing_sensor);
+                        Ok(Intention::off_now())
+                    }
+                }
+            }
             Ok(WorkingTempAction::Cool { circulate: false }) => {
                 if self.started.elapsed() > config.hp_circulation.initial_hp_sleep {
                     info!("TKBT too cold, would be heating the tank. Staying off.");
-                    Ok(Intention::off_now())
+                    Ok(Intention::off_now().because("TKBT too cold, would be heating the tank"))
                 }
                 else {
                     info!("Nothing to do - equalising for longer");
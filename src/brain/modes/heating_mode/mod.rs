@@ -3,11 +3,14 @@ use crate::brain::modes::dhw_only::DhwOnlyMode;
 use crate::brain::modes::off::OffMode;
 use crate::brain::modes::on::OnMode;
 use crate::brain::modes::working_temp::{
-    find_working_temp_action, CurrentHeatDirection, WorkingTempAction, MixedState,
+    find_working_temp_action, forecast_hx_drop, tank_warm_enough_to_drain, HxForecast, CurrentHeatDirection, WorkingTempAction, MixedState,
 };
 use crate::brain::modes::equalise::EqualiseMode;
 use crate::brain::modes::{HeatingState, InfoCache, Intention, Mode};
+use crate::brain::python_like::config::demand_priority::DemandPriority;
+use crate::brain::python_like::config::heat_pump_circulation::HeatPumpCirculationConfig;
 use crate::brain::python_like::config::PythonBrainConfig;
+use crate::brain::python_like::config::smoothing::SmoothingConfig;
 use crate::brain::python_like::control::heating_control::HeatPumpMode;
 use crate::brain::python_like::FallbackWorkingRange;
 use crate::brain::BrainFailure;
@@ -15,6 +18,9 @@ use crate::io::robbable::Dispatchable;
 use crate::io::temperatures::Sensor;
 use crate::io::wiser::hub::WiserRoomData;
 use crate::io::wiser::WiserManager;
+use crate::log_rate_limit::rate_limited;
+use crate::logging::ModeLogging;
+use crate::temp_format::fmt_temp;
 use crate::io::IOBundle;
 use crate::python_like::config::overrun_config::OverrunConfig;
 use crate::time_util::mytime::TimeProvider;
@@ -28,6 +34,8 @@ use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
 use std::ops::DerefMut;
 use std::time::{Instant, Duration};
+use tracing::level_filters::LevelFilter;
+use tracing::Level;
 use tokio::runtime::Runtime;
 
 use super::mixed::MixedMode;
@@ -50,6 +58,28 @@ impl PossibleTemperatureContainer for HashMap<Sensor, f32> {
     }
 }
 
+/// The temperatures to decide whether to heat or circulate with, using the smoothed
+/// TKBT (if smoothing it) in place of the raw reading.
+pub struct CirculateTemps<'a, T: PossibleTemperatureContainer> {
+    temps: &'a T,
+    smoothed_tkbt: Option<f32>,
+}
+
+impl<'a, T: PossibleTemperatureContainer> CirculateTemps<'a, T> {
+    pub fn new(temps: &'a T, smoothed_tkbt: Option<f32>) -> Self {
+        Self { temps, smoothed_tkbt }
+    }
+}
+
+impl<T: PossibleTemperatureContainer> PossibleTemperatureContainer for CirculateTemps<'_, T> {
+    fn get_sensor_temp(&self, sensor: &Sensor) -> Option<&f32> {
+        match (sensor, &self.smoothed_tkbt) {
+            (Sensor::TKBT, Some(tkbt)) => Some(tkbt),
+            _ => self.temps.get_sensor_temp(sensor),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Deserialize, PartialEq)]
 pub struct TargetTemperature {
     sensor: Sensor,
@@ -101,6 +131,20 @@ pub struct SharedData {
     pub fallback_working_range: FallbackWorkingRange,
     pub entered_state: Instant,
     pub last_wiser_state: HeatingState,
+    /// Whether we have been into PreCirculate since the heat pump was last heating.
+    pre_circulated_since_heating: bool,
+    /// When we last came out of a hot water overrun.
+    last_overrun_finished: Option<DateTime<Utc>>,
+    /// When we last finished circulating.
+    last_circulate_finished: Option<DateTime<Utc>>,
+    /// The smoothed value of TKBT, if smoothing it.
+    smoothed_tkbt: Option<f32>,
+    /// When we started heating continuously (in On or Mixed), if we are.
+    on_session_started: Option<Instant>,
+    /// The HX forecast made when deciding to circulate from rest, if diagnosing the forecast.
+    pending_hx_forecast: Option<HxForecast>,
+    /// When the heat pump last failed to start (HPRT didn't rise while turning on).
+    last_turning_on_fault: Option<Instant>,
 }
 
 impl SharedData {
@@ -110,6 +154,128 @@ impl SharedData {
             fallback_working_range: working_range,
             entered_state: Instant::now(),
             last_wiser_state: HeatingState::OFF,
+            pre_circulated_since_heating: true,
+            last_overrun_finished: None,
+            last_circulate_finished: None,
+            smoothed_tkbt: None,
+            on_session_started: None,
+            pending_hx_forecast: None,
+            last_turning_on_fault: None,
+        }
+    }
+
+    /// Keep track of how long we have been heating for, returning whether that is longer than
+    /// the maximum (if there is one).
+    pub fn on_session_too_long(&mut self, mode: &HeatingMode, max: Option<&Duration>, now: Instant) -> bool {
+        match mode {
+            HeatingMode::On(_) | HeatingMode::Mixed(_) => {
+                let started = *self.on_session_started.get_or_insert(now);
+                max.is_some_and(|max| now.saturating_duration_since(started) >= *max)
+            }
+            _ => {
+                self.on_session_started = None;
+                false
+            }
+        }
+    }
+
+    /// Smooth the latest TKBT reading, starting again if it is missing.
+    pub fn smooth_tkbt(&mut self, config: &SmoothingConfig, raw: Option<f32>) -> Option<f32> {
+        self.smoothed_tkbt = raw.map(|raw| config.smooth(self.smoothed_tkbt, raw));
+        self.smoothed_tkbt
+    }
+
+    #[cfg(test)]
+    pub fn get_smoothed_tkbt(&self) -> Option<f32> {
+        self.smoothed_tkbt
+    }
+
+    /// Diagnose the HX forecast used to decide to circulate from rest. When going from Off into
+    /// TryCirculate, remember the forecast. When leaving TryCirculate, i.e. once it has been
+    /// circulating, log how far off it was and return the error (see `HxForecast::error`).
+    pub fn check_hx_forecast(
+        &mut self,
+        from: Option<&HeatingMode>,
+        to: &HeatingMode,
+        temps: &impl PossibleTemperatureContainer,
+        config: &HeatPumpCirculationConfig,
+    ) -> Option<f32> {
+        match (from, to) {
+            (None | Some(HeatingMode::Off(_)), HeatingMode::TryCirculate(_)) => {
+                self.pending_hx_forecast = forecast_hx_drop(temps, config).ok();
+                None
+            }
+            (Some(HeatingMode::TryCirculate(_)), _) => {
+                let forecast = self.pending_hx_forecast.take()?;
+                let hxia_now = forecast_hx_drop(temps, config).ok()?.hxia;
+                let error = forecast.error(hxia_now);
+                info!(
+                    target: "forecast",
+                    "HXIA was {}, expected drop {}, actual drop {} (error {:.1}), HXOR was {}",
+                    fmt_temp(forecast.hxia), fmt_temp(forecast.expected_drop), fmt_temp(forecast.hxia - hxia_now),
+                    error, fmt_temp(forecast.hxor)
+                );
+                Some(error)
+            }
+            _ => None,
+        }
+    }
+
+    /// Keep track of when the last overrun or circulation finished, if we are leaving one.
+    pub fn notify_leaving_mode(&mut self, mode: &HeatingMode, now: DateTime<Utc>) {
+        match mode {
+            HeatingMode::DhwOnly(_) => self.last_overrun_finished = Some(now),
+            HeatingMode::Circulate(_) => self.last_circulate_finished = Some(now),
+            _ => {}
+        }
+    }
+
+    pub fn get_last_overrun_finished(&self) -> Option<DateTime<Utc>> {
+        self.last_overrun_finished
+    }
+
+    /// Note that the heat pump failed to start, so that we don't try again for a while.
+    pub fn notify_turning_on_fault(&mut self, backoff: Duration, now: Instant) {
+        error!("Heat pump failed to start, not turning it on again for {}s", backoff.as_secs());
+        self.last_turning_on_fault = Some(now);
+    }
+
+    /// Stay off instead of going into TurningOn within backoff of the heat pump failing to start,
+    /// so that a faulty heat pump isn't restarted over and over.
+    pub fn apply_turning_on_lockout(
+        &self,
+        next_mode: Option<HeatingMode>,
+        backoff: Duration,
+        info_cache: &mut InfoCache,
+        now: Instant,
+    ) -> Option<HeatingMode> {
+        match (next_mode, self.last_turning_on_fault) {
+            (Some(HeatingMode::TurningOn(_)), Some(fault)) if now.saturating_duration_since(fault) < backoff => {
+                rate_limited!(warn, "turning_on_lockout", "Heat pump failed to start {}s ago, staying off until {}s have passed",
+                    now.saturating_duration_since(fault).as_secs(), backoff.as_secs());
+                info_cache.set_mode_reason("Heat pump failed to start recently, staying off");
+                Some(HeatingMode::off())
+            }
+            (next_mode, _) => next_mode,
+        }
+    }
+
+    pub fn get_last_circulate_finished(&self) -> Option<DateTime<Utc>> {
+        self.last_circulate_finished
+    }
+
+    /// Keep track of whether we have pre-circulated since last heating, marking the
+    /// next mode as the first PreCirculate since heating if it is.
+    pub fn notify_next_mode(&mut self, next_mode: &mut HeatingMode) {
+        match next_mode {
+            HeatingMode::On(_) | HeatingMode::Mixed(_) => self.pre_circulated_since_heating = false,
+            HeatingMode::PreCirculate(mode) => {
+                if !self.pre_circulated_since_heating {
+                    mode.set_first();
+                }
+                self.pre_circulated_since_heating = true;
+            }
+            _ => {}
         }
     }
 
@@ -150,6 +316,9 @@ pub enum HeatingMode {
     DhwOnly(DhwOnlyMode),
 }
 
+/// The reason for going or staying off when the wiser isn't calling for heat.
+pub const WISER_OFF_REASON: &str = "Wiser not calling for heat";
+
 const OFF_ENTRY_PREFERENCE:           EntryPreferences = EntryPreferences::new(false, false);
 const TURNING_ON_ENTRY_PREFERENCE:    EntryPreferences = EntryPreferences::new(true, true);
 const ON_ENTRY_PREFERENCE:            EntryPreferences = EntryPreferences::new(true, true);
@@ -164,11 +333,14 @@ pub fn get_working_temp_fn(
     wiser: &dyn WiserManager,
     config: &PythonBrainConfig,
     runtime: &Runtime,
+    outdoor_temp: Option<f32>,
 ) -> WorkingRange {
     working_temp::get_working_temperature_range_from_wiser_data(
         fallback,
         get_wiser_room_data(wiser, runtime),
         &config.working_temp_model,
+        config.unnamed_rooms,
+        outdoor_temp,
     )
 }
 
@@ -177,8 +349,8 @@ fn get_wiser_room_data(
     rt: &Runtime,
 ) -> Result<Vec<WiserRoomData>, RetrieveDataError> {
     let wiser_data = rt.block_on(wiser.get_wiser_hub().get_room_data());
-    if wiser_data.is_err() {
-        warn!(target: "wiser", "Failed to retrieve wiser data {:?}", wiser_data.as_ref().unwrap_err());
+    if let Err(e) = &wiser_data {
+        rate_limited!(warn, target: "wiser", "wiser_room_data", "Failed to retrieve wiser data {:?}", e);
     }
     wiser_data
 }
@@ -188,35 +360,108 @@ impl HeatingMode {
         HeatingMode::Off(OffMode::default())
     }
 
+    /// A fresh mode with the given name, as returned by name().
+    pub fn from_name(name: &str) -> Option<Self> {
+        let mode = match name {
+            "Off"          => HeatingMode::off(),
+            "TurningOn"    => HeatingMode::TurningOn(TurningOnMode::new(Instant::now())),
+            "On"           => HeatingMode::On(OnMode::default()),
+            "Mixed"        => HeatingMode::Mixed(MixedMode::new()),
+            "PreCirculate" => HeatingMode::PreCirculate(PreCirculateMode::start()),
+            "Equalise"     => HeatingMode::Equalise(EqualiseMode::start()),
+            "TryCirculate" => HeatingMode::TryCirculate(TryCirculateMode::start()),
+            "Circulate"    => HeatingMode::Circulate(CirculateMode::default()),
+            "DhwOnly"      => HeatingMode::DhwOnly(DhwOnlyMode::new()),
+            _ => return None,
+        };
+        Some(mode)
+    }
+
+    /// The name of the mode, without any of its state.
+    pub fn name(&self) -> &'static str {
+        match self {
+            HeatingMode::Off(_) => "Off",
+            HeatingMode::TurningOn(_) => "TurningOn",
+            HeatingMode::On(_) => "On",
+            HeatingMode::Mixed(_) => "Mixed",
+            HeatingMode::PreCirculate(_) => "PreCirculate",
+            HeatingMode::Equalise(_) => "Equalise",
+            HeatingMode::TryCirculate(_) => "TryCirculate",
+            HeatingMode::Circulate(_) => "Circulate",
+            HeatingMode::DhwOnly(_) => "DhwOnly",
+        }
+    }
+
     pub fn update(
         &mut self,
-        _shared_data: &mut SharedData,
+        shared_data: &mut SharedData,
         rt: &Runtime,
         config: &PythonBrainConfig,
         io_bundle: &mut IOBundle,
         info_cache: &mut InfoCache,
         time_provider: &impl TimeProvider,
     ) -> Result<Option<HeatingMode>, BrainFailure> {
-        let intention = match self {
-            HeatingMode::Off(mode)          => mode.update(rt, config, info_cache, io_bundle, time_provider)?,
-            HeatingMode::TurningOn(mode)    => mode.update(rt, config, info_cache, io_bundle, time_provider)?,
-            HeatingMode::On(mode)           => mode.update(rt, config, info_cache, io_bundle, time_provider)?,
-            HeatingMode::PreCirculate(mode) => mode.update(rt, config, info_cache, io_bundle, time_provider)?,
-            HeatingMode::Equalise(mode)     => mode.update(rt, config, info_cache, io_bundle, time_provider)?,
-            HeatingMode::Circulate(mode)    => mode.update(rt, config, info_cache, io_bundle, time_provider)?,
-            HeatingMode::DhwOnly(mode)      => mode.update(rt, config, info_cache, io_bundle, time_provider)?,
-            HeatingMode::Mixed(mode)        => mode.update(rt, config, info_cache, io_bundle, time_provider)?,
-            HeatingMode::TryCirculate(mode) => mode.update(rt, config, info_cache, io_bundle, time_provider)?,
+        let logging = ModeLogging::enter(self.name(), config.get_mode_log_level(self.name()));
+        let intention = if shared_data.on_session_too_long(self, config.get_max_on_session(), Instant::now()) {
+            warn!("Heating for longer than the maximum on session, re-evaluating");
+            Intention::SwitchForce(HeatingMode::PreCirculate(PreCirculateMode::start()))
+                .because("Heating for longer than the maximum on session")
+        } else if !info_cache.heating_on() && !config.is_mode_interruptible(self.name()) && !self.min_action_complete(config) {
+            debug!("Wiser is off, but finishing what {} started first", self.name());
+            Intention::KeepState.because("Not interruptible until its minimum action is complete")
+        } else {
+            match self {
+                HeatingMode::Off(mode)          => mode.update(rt, config, info_cache, io_bundle, time_provider)?,
+                HeatingMode::TurningOn(mode)    => mode.update(rt, config, info_cache, io_bundle, time_provider)?,
+                HeatingMode::On(mode)           => mode.update(rt, config, info_cache, io_bundle, time_provider)?,
+                HeatingMode::PreCirculate(mode) => mode.update(rt, config, info_cache, io_bundle, time_provider)?,
+                HeatingMode::Equalise(mode)     => mode.update(rt, config, info_cache, io_bundle, time_provider)?,
+                HeatingMode::Circulate(mode)    => mode.update(rt, config, info_cache, io_bundle, time_provider)?,
+                HeatingMode::DhwOnly(mode)      => mode.update(rt, config, info_cache, io_bundle, time_provider)?,
+                HeatingMode::Mixed(mode)        => mode.update(rt, config, info_cache, io_bundle, time_provider)?,
+                HeatingMode::TryCirculate(mode) => mode.update(rt, config, info_cache, io_bundle, time_provider)?,
+            }
         };
+        // The intention is logged at its own level, not limited by the mode's.
+        drop(logging);
+        log_intention(config.get_intention_log_level(), self.name(), &intention);
 
-        handle_intention(
+        if let HeatingMode::TurningOn(mode) = self {
+            if mode.had_hprt_fault() {
+                shared_data.notify_turning_on_fault(config.turning_on_fault_backoff, Instant::now());
+            }
+        }
+
+        let next_mode = handle_intention(
             intention,
+            Some(self),
             info_cache,
             io_bundle,
             config,
             rt,
             &time_provider.get_utc_time(),
-        )
+        )?;
+        let next_mode = match self {
+            HeatingMode::Circulate(mode) => mode.confirm_turning_on(config, next_mode, Instant::now()),
+            _ => next_mode,
+        };
+        Ok(shared_data.apply_turning_on_lockout(next_mode, config.turning_on_fault_backoff, info_cache, Instant::now()))
+    }
+
+    /// Whether this mode has a minimum it needs to do for it to have been worthwhile,
+    /// so whether it being interruptible makes any difference.
+    pub fn has_min_action(&self) -> bool {
+        matches!(self, HeatingMode::TryCirculate(_))
+    }
+
+    /// Whether this mode has done the minimum it needs to for it to have been worthwhile, so that
+    /// it can be interrupted by the wiser turning off if configured not to be interruptible.
+    /// Modes without a minimum action always have.
+    fn min_action_complete(&self, config: &PythonBrainConfig) -> bool {
+        match self {
+            HeatingMode::TryCirculate(mode) => mode.sample_complete(config),
+            _ => true,
+        }
     }
 
     pub fn enter(
@@ -362,9 +607,9 @@ fn get_heatup_while_off(
     if let Some(bap) = slot {
         if let Some(t) = temps.get_sensor_temp(&bap.temps.sensor) {
             info!(
-                "{} is {:.2} which is below the minimum for this time. (From {:?})",
+                "{} is {} which is below the minimum for this time. (From {:?})",
                 bap.temps.sensor,
-                t,
+                fmt_temp(*t),
                 bap
             );
         } else {
@@ -375,8 +620,49 @@ fn get_heatup_while_off(
     None
 }
 
+/// Whether an overrun finished too recently (within min_overrun_gap) to start another one,
+/// or we are still within post_circulate_dhw_cooldown of circulating.
+fn too_soon_for_overrun(info_cache: &InfoCache, config: &PythonBrainConfig, current_mode: Option<&HeatingMode>, now: &DateTime<Utc>) -> bool {
+    let cooldown = config.post_circulate_dhw_cooldown;
+    if !cooldown.is_zero() {
+        if matches!(current_mode, Some(HeatingMode::Circulate(_))) {
+            debug!("Just finished circulating, not heating the hot water for {}s", cooldown.as_secs());
+            return true;
+        }
+        if let Some(finished) = info_cache.get_last_circulate_finished() {
+            let since = (*now - finished).to_std().unwrap_or_default();
+            if since < cooldown {
+                debug!("Finished circulating {}s ago, not heating the hot water until {}s have passed", since.as_secs(), cooldown.as_secs());
+                return true;
+            }
+        }
+    }
+    let (Some(gap), Some(finished)) = (config.get_min_overrun_gap(), info_cache.get_last_overrun_finished()) else {
+        return false;
+    };
+    let since = (*now - finished).to_std().unwrap_or_default();
+    if since < *gap {
+        debug!("Last overrun finished {}s ago, not starting another until {}s have passed", since.as_secs(), gap.as_secs());
+        return true;
+    }
+    false
+}
+
+/// Log the intention a mode returned, at the given level, which tracing's macros can't take directly.
+fn log_intention(level: LevelFilter, mode: &str, intention: &Intention) {
+    match level.into_level() {
+        Some(Level::ERROR) => tracing::error!("{} intention: {:?}", mode, intention),
+        Some(Level::WARN) => tracing::warn!("{} intention: {:?}", mode, intention),
+        Some(Level::INFO) => tracing::info!("{} intention: {:?}", mode, intention),
+        Some(Level::DEBUG) => tracing::debug!("{} intention: {:?}", mode, intention),
+        Some(Level::TRACE) => tracing::trace!("{} intention: {:?}", mode, intention),
+        None => {}
+    }
+}
+
 pub fn handle_intention(
     intention: Intention,
+    current_mode: Option<&HeatingMode>,
     info_cache: &mut InfoCache,
     io_bundle: &mut IOBundle,
     config: &PythonBrainConfig,
@@ -390,26 +676,35 @@ pub fn handle_intention(
             debug!("Force switching to mode: {:?}", mode);
             Ok(Some(mode))
         }
-        Intention::Finish => handle_finish_mode(info_cache, io_bundle, config, rt, now),
+        Intention::Finish => handle_finish_mode(current_mode, info_cache, io_bundle, config, rt, now),
+        Intention::Explained(intention, reason) => {
+            debug!("Intention reason: {}", reason);
+            info_cache.set_mode_reason(reason);
+            handle_intention(*intention, current_mode, info_cache, io_bundle, config, rt, now)
+        }
         Intention::YieldHeatUps => {
             // Check for heat ups.
             let temps = match rt.block_on(info_cache.get_temps(io_bundle.temperature_manager())) {
                 Ok(temps) => temps,
                 Err(e) => {
-                    error!("Failed to get temperatures to check for overruns: {}, but might be ok in the current mode, not changing.", e);
+                    rate_limited!(error, "overrun_temps", "Failed to get temperatures to check for overruns: {}, but might be ok in the current mode, not changing.", e);
                     return Ok(None);
                 }
             };
-            Ok(get_heatup_while_off(
-                now,
-                config.get_overrun_during(),
-                &temps,
-            ))
+            if too_soon_for_overrun(info_cache, config, current_mode, now) {
+                return Ok(None);
+            }
+            let heatup = get_heatup_while_off(now, config.get_overrun_during(), &config.missing_overrun_sensor.overrun_temps(&temps));
+            if heatup.is_some() {
+                info_cache.set_mode_reason("Below the minimum temperature of a hot water slot");
+            }
+            Ok(heatup)
         }
     }
 }
 
 pub fn handle_finish_mode(
+    current_mode: Option<&HeatingMode>,
     info_cache: &mut InfoCache,
     io_bundle: &mut IOBundle,
     config: &PythonBrainConfig,
@@ -417,7 +712,7 @@ pub fn handle_finish_mode(
     now: &DateTime<Utc>,
 ) -> Result<Option<HeatingMode>, BrainFailure> {
     let heating_control = expect_available!(io_bundle.heating_control())?;
-    let wiser_state = info_cache.heating_state();
+    let wiser_state = *info_cache.heating_state();
     let (hp_on, hp_duration) = heating_control.get_heat_pump_on_with_time()?;
     let cp_on = heating_control.try_get_heat_circulation_pump()?;
     debug!(
@@ -433,12 +728,19 @@ pub fn handle_finish_mode(
                 Ok(temps) => temps,
                 Err(err) => {
                     error!("Failed to get temperatures, turning off: {}", err);
+                    info_cache.set_mode_reason("Failed to get temperatures");
                     return Ok(Some(HeatingMode::off()));
                 }
             };
 
-            if let Some(heatupto) = get_heatup_while_off(now, config.get_overrun_during(), &temps) {
+            let heatupto = if too_soon_for_overrun(info_cache, config, current_mode, now) {
+                None
+            } else {
+                get_heatup_while_off(now, config.get_overrun_during(), &config.missing_overrun_sensor.overrun_temps(&temps))
+            };
+            if let Some(heatupto) = heatupto {
                 info!("Below minimum for a HeatUpTo, entering despite wiser calling for heat.");
+                info_cache.set_mode_reason("Below the minimum temperature of a hot water slot, despite wiser calling for heat");
                 return Ok(Some(heatupto));
             }
 
@@ -450,7 +752,7 @@ pub fn handle_finish_mode(
             };
 
             let working_temp_action = find_working_temp_action(
-                &temps,
+                &info_cache.circulate_temps(&temps),
                 &working_temp,
                 &config.hp_circulation,
                 CurrentHeatDirection::Climbing,
@@ -460,47 +762,93 @@ pub fn handle_finish_mode(
 
             let heating_mode = match working_temp_action {
                 Ok(WorkingTempAction::Heat { mixed_state }) => {
-                    if matches!(mixed_state, MixedState::MixedHeating) {
-                        // Use "extra" when considering MixedMode
-                        let slot = config.get_overrun_during().find_matching_slot(now, &temps,
-                            |temps, temp| temp < temps.extra.unwrap_or(temps.max));
-                        if let Some(overrun) = slot {
-                            debug!("Applicable overrun: {overrun} while heating is nearly at top of working range. Will use mixed mode.");
-                            return Ok(Some(HeatingMode::Mixed(MixedMode::new())));
+                    match config.demand_priority {
+                        DemandPriority::Balanced => {
+                            if matches!(mixed_state, MixedState::MixedHeating) {
+                                // Use "extra" when considering MixedMode
+                                let slot = config.get_overrun_during().find_matching_slot(now, &config.missing_overrun_sensor.overrun_temps(&temps),
+                                    |temps, temp| temp < temps.extra.unwrap_or(temps.max));
+                                if let Some(overrun) = slot {
+                                    debug!("Applicable overrun: {overrun} while heating is nearly at top of working range. Will use mixed mode.");
+                                    info_cache.set_mode_reason("Near the top of the working range with a hot water slot applicable");
+                                    return Ok(Some(HeatingMode::Mixed(MixedMode::new())));
+                                }
+                            }
                         }
+                        DemandPriority::DhwFirst => {
+                            let slot = config.get_overrun_during().find_matching_slot(now, &config.missing_overrun_sensor.overrun_temps(&temps),
+                                |temps, temp| temp < temps.max);
+                            if let Some(overrun) = slot {
+                                debug!("Applicable overrun: {overrun} while heating is wanted. Prioritising hot water.");
+                                info_cache.set_mode_reason("Hot water prioritised over heating");
+                                return Ok(Some(HeatingMode::DhwOnly(DhwOnlyMode::new())));
+                            }
+                        }
+                        DemandPriority::HeatingFirst => {}
                     }
+                    info_cache.set_mode_reason("Wiser calling for heat and below the working range");
                     Ok(Some(HeatingMode::On(OnMode::create(cp_on))))
                 }
                 Ok(WorkingTempAction::Cool { circulate }) => {
-                    let slot = config.get_overrun_during().find_matching_slot(now, &temps,
+                    let slot = config.get_overrun_during().find_matching_slot(now, &config.missing_overrun_sensor.overrun_temps(&temps),
                         |temps, temp| temp < temps.max);
                     if let Some(slot) = slot {
                         debug!("Overrun: {slot:?} would apply, going into overrun instead of circulating.");
+                        info_cache.set_mode_reason("Above the working range but a hot water slot applies");
                         return Ok(Some(HeatingMode::DhwOnly(DhwOnlyMode::new())));
                     }
 
                     if !circulate {
                         info!("Avoiding circulate but going into pre-circulate before deciding what to do");
+                        info_cache.set_mode_reason("Above the working range but TKBT is too cold to circulate");
                         return Ok(Some(HeatingMode::PreCirculate(PreCirculateMode::start())));
                     }
 
+                    if let Some(rise) = info_cache.get_trend(&Sensor::TKBT) {
+                        if rise > config.hp_circulation.circulate_max_tkbt_rise {
+                            info!("TKBT rising at {:.2}/min, going into pre-circulate rather than circulating straight away", rise);
+                            info_cache.set_mode_reason("Above the working range but TKBT is still rising");
+                            return Ok(Some(HeatingMode::PreCirculate(PreCirculateMode::start())));
+                        }
+                    }
+
+                    match tank_warm_enough_to_drain(&info_cache.circulate_temps(&temps), &working_temp, &config.hp_circulation) {
+                        Ok(true) => {}
+                        Ok(false) => {
+                            info_cache.set_mode_reason("Above the working range but the tank is not warm enough to drain");
+                            return Ok(Some(HeatingMode::off()));
+                        }
+                        Err(missing_sensor) => {
+                            error!("Missing {missing_sensor} sensor - turning off");
+                            info_cache.set_mode_reason(format!("Missing {} sensor", missing_sensor));
+                            return Ok(Some(HeatingMode::off()));
+                        }
+                    }
+
                     let hxor = match temps.get_sensor_temp(&Sensor::HXOR) {
                         Some(temp) => temp,
                         None => {
                             error!("Missing HXOR sensor - turning off");
+                            info_cache.set_mode_reason("Missing HXOR sensor");
                             return Ok(Some(HeatingMode::off()));
                         }
                     };
 
-                    if *hxor > config.hp_circulation.pre_circulate_temp_required
-                    {
-                        info!("Hot enough to pre-circulate straight away");
-                        return Ok(Some(HeatingMode::PreCirculate(PreCirculateMode::start())));
+                    if hot_enough_to_pre_circulate(*hxor, &config.hp_circulation, current_mode) {
+                        if config.hp_circulation.skip_pre_circulate {
+                            debug!("Hot enough to pre-circulate, but configured to skip it");
+                        } else {
+                            info!("Hot enough to pre-circulate straight away");
+                            info_cache.set_mode_reason("Above the working range and HXOR hot enough to pre-circulate");
+                            return Ok(Some(HeatingMode::PreCirculate(PreCirculateMode::start())));
+                        }
                     }
 
+                    info_cache.set_mode_reason("Above the working range, trying circulation");
                     Ok(Some(HeatingMode::TryCirculate(TryCirculateMode::start())))
                 }
                 Err(missing_sensor) => {
+                    info_cache.set_mode_reason(format!("Missing {} sensor", missing_sensor));
                     error!(
                                 "Could not determine whether to circulate due to missing sensor: {}. Turning off.",
                                 missing_sensor
@@ -516,69 +864,167 @@ pub fn handle_finish_mode(
             let temps = rt.block_on(info_cache.get_temps(io_bundle.temperature_manager()));
             if let Err(err) = temps {
                 error!("Failed to retrieve temperatures: '{}', turning off", err);
+                info_cache.set_mode_reason("Failed to get temperatures");
                 return Ok(Some(HeatingMode::off()));
             }
 
-            let slot = config.get_overrun_during().find_matching_slot(now, &temps.unwrap(),
-                |temps, temp| temp < temps.max || (hp_duration < Duration::from_secs(60 * 10) && temp < temps.extra.unwrap_or(temps.max))
-            );
+            let temps = temps.unwrap();
+
+            // Finishing an overrun counts as it having finished, so don't chain straight into another.
+            let chaining = config.get_min_overrun_gap().is_some() && matches!(current_mode, Some(HeatingMode::DhwOnly(_)));
+            let slot = if chaining || too_soon_for_overrun(info_cache, config, current_mode, now) {
+                None
+            } else {
+                config.get_overrun_during().find_matching_slot(now, &config.missing_overrun_sensor.overrun_temps(&temps),
+                    |temps, temp| temp < temps.max || (hp_duration < Duration::from_secs(60 * 10) && temp < temps.extra.unwrap_or(temps.max))
+                )
+            };
             if let Some(slot) = slot {
+                info_cache.set_mode_reason("Wiser not calling for heat, but a hot water slot applies");
                 return Ok(Some(HeatingMode::DhwOnly(DhwOnlyMode::new())));
             }
-            Ok(Some(HeatingMode::off()))
-        }
-        // WISER ON, HP OFF
-        (true, false) => {
-            let temps = rt.block_on(info_cache.get_temps(io_bundle.temperature_manager()));
-            if let Err(err) = temps {
-                error!("Failed to retrieve temperatures: {}, staying off", err);
-                return Ok(Some(HeatingMode::off()));
-            }
-            match find_working_temp_action(
-                &temps.unwrap(),
-                &info_cache.get_working_temp_range(),
-                &config.hp_circulation,
-                CurrentHeatDirection::None,
-                None, None,
-            ) {
-                Ok(WorkingTempAction::Heat { .. }) => {
-                    info!("Call for heat: turning on");
-                    Ok(Some(HeatingMode::TurningOn(TurningOnMode::new(
-                        Instant::now(),
-                    ))))
-                }
-                Ok(WorkingTempAction::Cool { circulate: true }) => {
-                    info!("Circulation recommended - will try.");
-                    Ok(Some(HeatingMode::TryCirculate(TryCirculateMode::new(
-                        Instant::now(),
-                    ))))
-                }
-                Ok(WorkingTempAction::Cool { circulate: false }) => {
-                    info!("TKBT too cold, would be heating the tank. Idle recommended, doing pre-circulate");
-                    Ok(Some(HeatingMode::PreCirculate(PreCirculateMode::start())))
-                }
-                Err(missing_sensor) => {
-                    error!("Missing sensor: {}", missing_sensor);
-                    Ok(Some(HeatingMode::off()))
+            if let Some(min_runtime) = config.get_min_hp_runtime() {
+                if min_runtime.should_keep_heating(hp_duration, &temps) {
+                    info!("Heat pump not on for its minimum runtime, heating the tank until {}", min_runtime.get_safety_cut_off());
+                    info_cache.set_mode_reason("Wiser not calling for heat, but the heat pump hasn't run for its minimum runtime");
+                    return Ok(Some(HeatingMode::DhwOnly(DhwOnlyMode::new())));
                 }
             }
+            info_cache.set_mode_reason(WISER_OFF_REASON);
+            Ok(Some(HeatingMode::off()))
         }
-        // WISER OFF, HP OFF
-        (false, false) => {
-            // Check if should go into HeatUpTo.
+        // WISER ON/OFF, HP OFF
+        (_, false) => {
             let temps = match rt.block_on(info_cache.get_temps(io_bundle.temperature_manager())) {
                 Ok(temps) => temps,
                 Err(err) => {
-                    error!("Failed to get temperatures, turning off: {}", err);
+                    error!("Failed to get temperatures, staying off: {}", err);
+                    info_cache.set_mode_reason("Failed to get temperatures");
+                    info_cache.set_off_reason("Failed to get temperatures");
                     return Ok(Some(HeatingMode::off()));
                 }
             };
+            let (mut mode, mut off_reason) = decide_mode_from_off(
+                &temps,
+                info_cache.get_smoothed_tkbt(),
+                &info_cache.get_working_temp_range(),
+                &wiser_state,
+                config,
+                now,
+                hp_duration >= config.hp_circulation.cold_start_after,
+            );
+            if matches!(mode, HeatingMode::DhwOnly(_)) && too_soon_for_overrun(info_cache, config, current_mode, now) {
+                mode = HeatingMode::off();
+                off_reason = Some(format!("{}, and too soon after the last overrun for another", WISER_OFF_REASON));
+            }
+            if let Some(off_reason) = off_reason {
+                info_cache.set_off_reason(off_reason);
+            }
+            info_cache.set_mode_reason(format!("Heat pump off, {} based on the current temperatures", mode.name()));
+            Ok(Some(mode))
+        }
+    }
+}
+
+/// Whether HXOR is hot enough to go straight into pre-circulate rather than trying to circulate.
+/// Between pre_circulate_temp_min and pre_circulate_temp_required, stick with pre-circulate only
+/// if we are currently pre-circulating or circulating.
+pub fn hot_enough_to_pre_circulate(
+    hxor: f32,
+    config: &HeatPumpCirculationConfig,
+    current_mode: Option<&HeatingMode>,
+) -> bool {
+    if hxor > config.pre_circulate_temp_required {
+        return true;
+    }
+    if hxor <= config.get_pre_circulate_temp_min() {
+        return false;
+    }
+    matches!(
+        current_mode,
+        Some(HeatingMode::PreCirculate(_) | HeatingMode::Circulate(_))
+    )
+}
+
+/// If configured, check HPRT is available and sane before committing to turning on.
+fn check_hprt_before_turning_on(
+    temps: &impl PossibleTemperatureContainer,
+    config: &PythonBrainConfig,
+) -> Result<(), String> {
+    let range = match &config.require_hprt_to_turn_on {
+        Some(range) => range,
+        None => return Ok(()),
+    };
+    match temps.get_sensor_temp(&Sensor::HPRT) {
+        Some(hprt) if range.contains(*hprt) => Ok(()),
+        Some(hprt) => Err(format!("HPRT {} is outside of the sane range {}", fmt_temp(*hprt), range)),
+        None => Err("HPRT is missing".to_owned()),
+    }
+}
+
+/// Decide which mode to go into next when the heat pump is off, based purely on
+/// the given temperatures, working range, wiser state, overrun config and time.
+/// smoothed_tkbt is used in place of TKBT when deciding whether to heat or circulate, if given.
+/// cold_start is whether the heat pump has been idle for long enough to start cold.
+/// Also gives the reason for staying off, if that is the decision.
+pub fn decide_mode_from_off(
+    temps: &impl PossibleTemperatureContainer,
+    smoothed_tkbt: Option<f32>,
+    working_range: &WorkingRange,
+    wiser_state: &HeatingState,
+    config: &PythonBrainConfig,
+    now: &DateTime<Utc>,
+    cold_start: bool,
+) -> (HeatingMode, Option<String>) {
+    if !wiser_state.is_on() {
+        // Check if should go into HeatUpTo.
+        if let Some(overrun) = get_heatup_while_off(now, config.get_overrun_during(), &config.missing_overrun_sensor.overrun_temps(temps)) {
+            debug!("Found overrun: {:?}.", overrun);
+            return (overrun, None);
+        }
+        return (HeatingMode::off(), Some(WISER_OFF_REASON.to_owned()));
+    }
 
-            if let Some(overrun) = get_heatup_while_off(now, config.get_overrun_during(), &temps) {
-                debug!("Found overrun: {:?}.", overrun);
-                return Ok(Some(overrun));
+    let circulate_temps = CirculateTemps::new(temps, smoothed_tkbt);
+    let heat_direction = if cold_start { CurrentHeatDirection::ColdStart } else { CurrentHeatDirection::None };
+    match find_working_temp_action(
+        &circulate_temps,
+        working_range,
+        &config.hp_circulation,
+        heat_direction,
+        None, None,
+    ) {
+        Ok(WorkingTempAction::Heat { .. }) => {
+            if let Err(e) = check_hprt_before_turning_on(temps, config) {
+                warn!("Call for heat, but staying off: {}", e);
+                return (HeatingMode::off(), Some(format!("Call for heat, but {}", e)));
             }
-            Ok(Some(HeatingMode::off()))
+            info!("Call for heat: turning on");
+            (HeatingMode::TurningOn(TurningOnMode::new(Instant::now())), None)
+        }
+        Ok(WorkingTempAction::Cool { circulate: true }) => {
+            match tank_warm_enough_to_drain(&circulate_temps, working_range, &config.hp_circulation) {
+                Ok(true) => {
+                    info!("Circulation recommended - will try.");
+                    (HeatingMode::TryCirculate(TryCirculateMode::new(Instant::now())), None)
+                }
+                Ok(false) => (
+                    HeatingMode::off(),
+                    Some(format!("Warm enough for the working range {}, but TKBT isn't worth circulating", working_range)),
+                ),
+                Err(missing_sensor) => {
+                    error!("Missing sensor: {}", missing_sensor);
+                    (HeatingMode::off(), Some(format!("Missing {} sensor", missing_sensor)))
+                }
+            }
+        }
+        Ok(WorkingTempAction::Cool { circulate: false }) => {
+            info!("TKBT too cold, would be heating the tank. Idle recommended, doing pre-circulate");
+            (HeatingMode::PreCirculate(PreCirculateMode::start()), None)
+        }
+        Err(missing_sensor) => {
+            error!("Missing sensor: {}", missing_sensor);
+            (HeatingMode::off(), Some(format!("Missing {} sensor", missing_sensor)))
         }
     }
 }
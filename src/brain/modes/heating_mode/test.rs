@@ -1,10 +1,15 @@
 use crate::brain::modes::dhw_only::DhwOnlyMode;
 use crate::brain::modes::working_temp::{Room, WorkingRange, WorkingTemperatureRange};
-use crate::io::dummy_io_bundle::new_dummy_io;
+use crate::io::dummy::{DummyAllOutputs, HeatingControlEvent, RecordingHeatingControl};
+use crate::io::dummy_io_bundle::{new_dummy_io, new_dummy_io_with_heating_control};
 use crate::io::temperatures::dummy::ModifyState;
+use crate::logging::{mode_log_filter, RecordLevels};
 use crate::python_like::control::heating_control::HeatingControl;
-use crate::time_util::mytime::RealTimeProvider;
-use crate::time_util::test_utils::{date, time};
+use crate::time_util::mytime::{DummyTimeProvider, RealTimeProvider};
+use crate::brain::python_like::config::overrun_config::DhwBap;
+use crate::brain::python_like::config::sensor_range::SensorRange;
+use crate::brain::trend::TemperatureTrends;
+use crate::time_util::test_utils::{date, time, utc_datetime, utc_time_slot};
 use crate::{wiser, GPIOState};
 use chrono::{TimeZone, Utc};
 use std::thread::sleep;
@@ -255,6 +260,72 @@ pub fn test_transitions() -> Result<(), BrainFailure> {
     Ok(())
 }
 
+/// A fresh instance of every mode.
+fn all_modes() -> Vec<HeatingMode> {
+    vec![
+        HeatingMode::off(),
+        HeatingMode::TurningOn(TurningOnMode::new(Instant::now())),
+        HeatingMode::On(OnMode::default()),
+        HeatingMode::Mixed(MixedMode::new()),
+        HeatingMode::PreCirculate(PreCirculateMode::start()),
+        HeatingMode::Equalise(EqualiseMode::start()),
+        HeatingMode::TryCirculate(TryCirculateMode::start()),
+        HeatingMode::Circulate(CirculateMode::default()),
+        HeatingMode::DhwOnly(DhwOnlyMode::new()),
+    ]
+}
+
+/// Try every transition between two different modes, checking that nothing fails and that
+/// the state in between (after exiting, before entering) respects the entry preferences of
+/// the mode being entered. This is transition_to() split in two so that state can be seen.
+///
+/// TryCirculate is skipped when checking the state in between, as exit_to() deliberately
+/// leaves everything as it is for it and its enter() sets everything up itself.
+#[test]
+fn test_transition_matrix() -> Result<(), BrainFailure> {
+    let rt = Builder::new_current_thread().enable_time().build().unwrap();
+    let config = PythonBrainConfig::default();
+
+    let count = all_modes().len();
+    for (from_index, to_index) in (0..count).flat_map(|from| (0..count).map(move |to| (from, to))) {
+        if from_index == to_index {
+            continue;
+        }
+        let mut from = all_modes().swap_remove(from_index);
+        let mut to = all_modes().swap_remove(to_index);
+        let transition_msg = format!("{} -> {}", from.name(), to.name());
+
+        let (mut io_bundle, _io_handle) = new_dummy_io();
+        from.enter(&config, &rt, &mut io_bundle)?;
+
+        let entry_preferences = to.get_entry_preferences().clone();
+        from.exit_to(&to, &mut io_bundle)?;
+        if !matches!(to, HeatingMode::TryCirculate(_)) {
+            let control = expect_present(io_bundle.heating_control());
+            if !entry_preferences.allow_heat_pump_on {
+                assert!(control.try_get_heat_pump()?.is_hp_off(), "HP should be off between {}", transition_msg);
+            }
+            if !entry_preferences.allow_circulation_pump_on {
+                assert!(!control.try_get_heat_circulation_pump()?, "CP should be off between {}", transition_msg);
+            }
+        }
+
+        to.enter(&config, &rt, &mut io_bundle)?;
+    }
+
+    // And transition_to() itself, all the way through every mode.
+    let (mut io_bundle, _io_handle) = new_dummy_io();
+    let mut mode = HeatingMode::off();
+    mode.enter(&config, &rt, &mut io_bundle)?;
+    for next in all_modes().into_iter().skip(1).chain([HeatingMode::off()]) {
+        mode.transition_to(next, &config, &rt, &mut io_bundle)?;
+    }
+    let control = expect_present(io_bundle.heating_control());
+    assert_eq!(control.try_get_heat_pump()?, HeatPumpMode::Off, "Should end up off");
+    assert!(!control.try_get_heat_circulation_pump()?, "Should end up off");
+    Ok(())
+}
+
 #[test]
 pub fn test_circulation_exit() -> Result<(), BrainFailure> {
     let (mut io_bundle, mut handle) = new_dummy_io();
@@ -372,6 +443,7 @@ fn test_intention_change() {
     // Heating off and no overrun.
     let off_result = handle_intention(
         Intention::Finish,
+        None,
         &mut info_cache,
         &mut io_bundle,
         &default_config,
@@ -404,6 +476,7 @@ temps = { sensor = "TKBT", min = 0.0, max = 44.0 }
 
         let overrun_result = handle_intention(
             Intention::Finish,
+            None,
             &mut info_cache,
             &mut io_bundle,
             &overrun_config,
@@ -443,6 +516,7 @@ temps = { sensor = "TKBT", min = 0.0, max = 44.0 }
 
         let overrun_result = handle_intention(
             Intention::Finish,
+            None,
             &mut info_cache,
             &mut io_bundle,
             &overrun_config,
@@ -476,6 +550,7 @@ temps = { sensor = "TKBT", min = 0.0, max = 44.0 }
 
         let turning_on = handle_intention(
             Intention::Finish,
+            None,
             &mut info_cache,
             &mut io_bundle,
             &default_config,
@@ -511,6 +586,7 @@ fn test_intention_basic() {
 
     let switch_off_force = handle_intention(
         Intention::SwitchForce(HeatingMode::off()),
+        None,
         &mut info_cache,
         &mut io_bundle,
         &Default::default(),
@@ -525,6 +601,7 @@ fn test_intention_basic() {
 
     let keep_state = handle_intention(
         Intention::KeepState,
+        None,
         &mut info_cache,
         &mut io_bundle,
         &Default::default(),
@@ -534,3 +611,1004 @@ fn test_intention_basic() {
     .unwrap();
     assert!(keep_state.is_none(), "Keep state should lead to None");
 }
+
+fn off_decision_range() -> WorkingRange {
+    WorkingRange::from_temp_only(WorkingTemperatureRange::from_min_max(30.0, 40.0))
+}
+
+fn off_decision_time() -> DateTime<Utc> {
+    Utc.from_utc_datetime(&date(2022, 03, 12).and_time(time(02, 30, 00)))
+}
+
+#[test]
+fn test_off_decision_straight_to_circulate() {
+    let temps = HashMap::from([
+        (Sensor::HXIF, 25.0),
+        (Sensor::HXIR, 25.0),
+        (Sensor::HXOF, 25.0),
+        (Sensor::HXOR, 25.0),
+        (Sensor::TKBT, 60.0),
+        (Sensor::HPRT, 50.0),
+    ]);
+
+    let (mode, _) = decide_mode_from_off(
+        &temps,
+        None,
+        &off_decision_range(),
+        &HeatingState::ON,
+        &PythonBrainConfig::default(),
+        &off_decision_time(),
+        false,
+    );
+    assert!(matches!(mode, HeatingMode::TryCirculate(_)), "Got {:?}", mode);
+}
+
+#[test]
+fn test_off_decision_small_demand_circulate() {
+    let temps = HashMap::from([
+        (Sensor::HXIF, 40.5),
+        (Sensor::HXIR, 40.5),
+        (Sensor::HXOF, 40.5),
+        (Sensor::HXOR, 40.5),
+        (Sensor::TKBT, 20.0),
+        (Sensor::HPRT, 50.0),
+    ]);
+
+    let (mode, _) = decide_mode_from_off(
+        &temps,
+        None,
+        &off_decision_range(),
+        &HeatingState::ON,
+        &PythonBrainConfig::default(),
+        &off_decision_time(),
+        false,
+    );
+    assert!(matches!(mode, HeatingMode::PreCirculate(_)), "Got {:?}", mode);
+}
+
+#[test]
+fn test_off_decision_turning_on() {
+    let temps = HashMap::from([
+        (Sensor::HXIF, 10.0),
+        (Sensor::HXIR, 10.0),
+        (Sensor::HXOF, 10.0),
+        (Sensor::HXOR, 10.0),
+        (Sensor::TKBT, 10.0),
+        (Sensor::HPRT, 10.0),
+    ]);
+
+    let (mode, _) = decide_mode_from_off(
+        &temps,
+        None,
+        &off_decision_range(),
+        &HeatingState::ON,
+        &PythonBrainConfig::default(),
+        &off_decision_time(),
+        false,
+    );
+    assert!(matches!(mode, HeatingMode::TurningOn(_)), "Got {:?}", mode);
+}
+
+#[test]
+fn test_off_decision_cold_start_after_idle() -> Result<(), BrainFailure> {
+    let config: PythonBrainConfig = toml::from_str(r#"
+[hp_circulation]
+forecast_start_above_percent = 0.1
+cold_start_above_percent = 0.2
+cold_start_after = 3600
+"#).expect("Invalid config string");
+    assert_eq!(config.hp_circulation.cold_start_after, Duration::from_secs(3600));
+    let cold_immediately: PythonBrainConfig = toml::from_str(r#"
+[hp_circulation]
+forecast_start_above_percent = 0.1
+cold_start_above_percent = 0.2
+cold_start_after = 0
+"#).expect("Invalid config string");
+
+    let (mut io_bundle, mut io_handle) = new_dummy_io();
+    let rt = Runtime::new().expect("Failed to create runtime");
+    // 15% of the way up the working range, so between the two thresholds.
+    io_handle.send_temps(ModifyState::SetTemps(HashMap::from([
+        (Sensor::HXIF, 31.5),
+        (Sensor::HXIR, 31.5),
+        (Sensor::HXOF, 31.5),
+        (Sensor::HXOR, 31.5),
+        (Sensor::TKBT, 20.0),
+        (Sensor::HPRT, 50.0),
+    ])));
+    let info_cache = || InfoCache::create(HeatingState::ON, off_decision_range());
+
+    // Just started up, but the heat pump has only just been turned off as far as we know.
+    let next = handle_intention(Intention::finish(), None, &mut info_cache(), &mut io_bundle, &config, &rt, &off_decision_time())?;
+    assert!(matches!(next, Some(HeatingMode::PreCirculate(_))), "Not idle for long enough, got {:?}", next);
+
+    let off = HeatingMode::off();
+    let next = handle_intention(Intention::finish(), Some(&off), &mut info_cache(), &mut io_bundle, &cold_immediately, &rt, &off_decision_time())?;
+    assert!(matches!(next, Some(HeatingMode::TurningOn(_))), "Idle for long enough, got {:?}", next);
+    Ok(())
+}
+
+#[test]
+fn test_off_decision_require_hprt() {
+    let config: PythonBrainConfig = toml::from_str("require_hprt_to_turn_on = { min = 15.0, max = 70.0 }")
+        .expect("Invalid config string");
+    assert_eq!(config.require_hprt_to_turn_on, Some(SensorRange::new(15.0, 70.0)));
+
+    let mut temps = HashMap::from([
+        (Sensor::HXIF, 10.0),
+        (Sensor::HXIR, 10.0),
+        (Sensor::HXOF, 10.0),
+        (Sensor::HXOR, 10.0),
+        (Sensor::TKBT, 10.0),
+        (Sensor::HPRT, 20.0),
+    ]);
+    let decide = |temps: &HashMap<Sensor, f32>| decide_mode_from_off(
+        temps,
+        None,
+        &off_decision_range(),
+        &HeatingState::ON,
+        &config,
+        &off_decision_time(),
+        false,
+    );
+
+    let (mode, _) = decide(&temps);
+    assert!(matches!(mode, HeatingMode::TurningOn(_)), "HPRT present: got {:?}", mode);
+
+    temps.insert(Sensor::HPRT, 10.0);
+    let (mode, _) = decide(&temps);
+    assert!(matches!(mode, HeatingMode::Off(_)), "HPRT out of range: got {:?}", mode);
+
+    // Without the option, any HPRT reading will do.
+    let (mode, _) = decide_mode_from_off(
+        &temps,
+        None,
+        &off_decision_range(),
+        &HeatingState::ON,
+        &PythonBrainConfig::default(),
+        &off_decision_time(),
+        false,
+    );
+    assert!(matches!(mode, HeatingMode::TurningOn(_)), "No option: got {:?}", mode);
+
+    temps.remove(&Sensor::HPRT);
+    let (mode, _) = decide(&temps);
+    assert!(matches!(mode, HeatingMode::Off(_)), "HPRT missing: got {:?}", mode);
+}
+
+#[test]
+fn test_off_decision_overnight_heatup() {
+    let mut config = PythonBrainConfig::default();
+    config._add_dhw_slot(DhwBap::_new(
+        utc_time_slot(01, 00, 00, 04, 30, 00),
+        Sensor::TKBT,
+        40.0,
+        45.0,
+    ));
+    let temps = HashMap::from([(Sensor::TKBT, 35.0)]);
+
+    let (mode, _) = decide_mode_from_off(
+        &temps,
+        None,
+        &off_decision_range(),
+        &HeatingState::OFF,
+        &config,
+        &off_decision_time(),
+        false,
+    );
+    assert!(matches!(mode, HeatingMode::DhwOnly(_)), "Got {:?}", mode);
+
+    // Outside of the slot, so nothing to do.
+    let daytime = Utc.from_utc_datetime(&date(2022, 03, 12).and_time(time(12, 30, 00)));
+    let (mode, _) = decide_mode_from_off(
+        &temps,
+        None,
+        &off_decision_range(),
+        &HeatingState::OFF,
+        &config,
+        &daytime,
+        false,
+    );
+    assert!(matches!(mode, HeatingMode::Off(_)), "Got {:?}", mode);
+}
+
+#[test]
+fn test_off_decision_missing_sensor() {
+    let temps = HashMap::from([(Sensor::TKBT, 35.0)]);
+
+    let (mode, _) = decide_mode_from_off(
+        &temps,
+        None,
+        &off_decision_range(),
+        &HeatingState::ON,
+        &PythonBrainConfig::default(),
+        &off_decision_time(),
+        false,
+    );
+    assert!(matches!(mode, HeatingMode::Off(_)), "Got {:?}", mode);
+}
+
+#[test]
+fn test_off_decision_reason() {
+    let mut config: PythonBrainConfig = toml::from_str("require_hprt_to_turn_on = { min = 15.0, max = 70.0 }")
+        .expect("Invalid config string");
+    let cold = HashMap::from([
+        (Sensor::HXIF, 10.0),
+        (Sensor::HXIR, 10.0),
+        (Sensor::HXOF, 10.0),
+        (Sensor::HXOR, 10.0),
+        (Sensor::TKBT, 10.0),
+        (Sensor::HPRT, 20.0),
+    ]);
+    let reason = |temps: &HashMap<Sensor, f32>, wiser_state: HeatingState, config: &PythonBrainConfig| decide_mode_from_off(
+        temps,
+        None,
+        &off_decision_range(),
+        &wiser_state,
+        config,
+        &off_decision_time(),
+        false,
+    ).1;
+
+    assert_eq!(reason(&cold, HeatingState::ON, &config), None, "Would turn on");
+    assert_eq!(
+        reason(&cold, HeatingState::OFF, &config).as_deref(),
+        Some(WISER_OFF_REASON),
+    );
+
+    let mut hprt_low = cold.clone();
+    hprt_low.insert(Sensor::HPRT, 10.0);
+    assert_eq!(
+        reason(&hprt_low, HeatingState::ON, &config),
+        Some(format!("Call for heat, but HPRT {} is outside of the sane range {}", fmt_temp(10.0), SensorRange::new(15.0, 70.0))),
+    );
+
+    let warm = HashMap::from([
+        (Sensor::HXIF, 25.0),
+        (Sensor::HXIR, 25.0),
+        (Sensor::HXOF, 25.0),
+        (Sensor::HXOR, 25.0),
+        (Sensor::TKBT, 60.0),
+        (Sensor::HPRT, 50.0),
+    ]);
+    assert_eq!(reason(&warm, HeatingState::ON, &config), None, "Would circulate");
+
+    let costly: PythonBrainConfig = toml::from_str("hp_circulation.circulation_cost.threshold = 100.0")
+        .expect("Invalid config string");
+    let costly_reason = reason(&warm, HeatingState::ON, &costly).expect("Not worth circulating");
+    assert!(costly_reason.starts_with("Warm enough for the working range"), "Got {}", costly_reason);
+
+    assert_eq!(
+        reason(&HashMap::from([(Sensor::TKBT, 35.0)]), HeatingState::ON, &config).as_deref(),
+        Some("Missing HXIF sensor"),
+    );
+
+    config._add_dhw_slot(DhwBap::_new(
+        utc_time_slot(01, 00, 00, 04, 30, 00),
+        Sensor::TKBT,
+        40.0,
+        45.0,
+    ));
+    assert_eq!(reason(&cold, HeatingState::OFF, &config), None, "Would heat the hot water");
+}
+
+const MIXED_OVERRUN_CONFIG_STR: &str = r#"
+[[overrun_during.slots]]
+slot = { type = "Utc", start="11:00:00", end="13:00:05" }
+temps = { sensor = "TKBT", min = 0.0, max = 44.0 }
+"#;
+
+fn finish_near_top_of_range(config: &PythonBrainConfig) -> Option<HeatingMode> {
+    let (mut io_bundle, mut io_handle) = new_dummy_io();
+    let rt = Runtime::new().unwrap();
+    let time = Utc.from_utc_datetime(&date(2022, 03, 12).and_time(time(12, 30, 00)));
+
+    let mut info_cache = InfoCache::create(
+        HeatingState::ON,
+        WorkingRange::from_temp_only(WorkingTemperatureRange::from_min_max(30.0, 40.0)),
+    );
+    expect_present(io_bundle.heating_control())
+        .try_set_heat_pump(HeatPumpMode::HeatingOnly)
+        .expect("Should be able to turn on.");
+
+    io_handle.send_steady_temps(&[
+        (Sensor::HXIF, 38.5),
+        (Sensor::HXIR, 38.5),
+        (Sensor::HXOR, 38.5),
+        (Sensor::HPRT, 40.0),
+        (Sensor::TKBT, 40.0),
+    ]);
+
+    handle_intention(
+        Intention::Finish,
+        None,
+        &mut info_cache,
+        &mut io_bundle,
+        config,
+        &rt,
+        &time,
+    )
+    .expect("Should succeed")
+}
+
+#[test]
+fn test_mixed_near_top_of_range() {
+    let config: PythonBrainConfig =
+        toml::from_str(MIXED_OVERRUN_CONFIG_STR).expect("Invalid config string");
+
+    let mode = finish_near_top_of_range(&config);
+    assert!(matches!(mode, Some(HeatingMode::Mixed(_))), "Expected Mixed but got {:?}", mode);
+}
+
+#[test]
+fn test_mixed_disabled() {
+    let config_str = format!("hp_circulation.mixed_enabled = false\n{}", MIXED_OVERRUN_CONFIG_STR);
+    let config: PythonBrainConfig = toml::from_str(&config_str).expect("Invalid config string");
+
+    let mode = finish_near_top_of_range(&config);
+    assert!(matches!(mode, Some(HeatingMode::On(_))), "Expected On but got {:?}", mode);
+}
+
+#[test]
+fn test_demand_priority() {
+    let config_with_priority = |priority: &str| -> PythonBrainConfig {
+        let config_str = format!("demand_priority = \"{}\"\n{}", priority, MIXED_OVERRUN_CONFIG_STR);
+        toml::from_str(&config_str).expect("Invalid config string")
+    };
+
+    let mode = finish_near_top_of_range(&config_with_priority("Balanced"));
+    assert!(matches!(mode, Some(HeatingMode::Mixed(_))), "Balanced: expected Mixed but got {:?}", mode);
+
+    let mode = finish_near_top_of_range(&config_with_priority("HeatingFirst"));
+    assert!(matches!(mode, Some(HeatingMode::On(_))), "HeatingFirst: expected On but got {:?}", mode);
+
+    let mode = finish_near_top_of_range(&config_with_priority("DhwFirst"));
+    assert!(matches!(mode, Some(HeatingMode::DhwOnly(_))), "DhwFirst: expected DhwOnly but got {:?}", mode);
+}
+
+#[test]
+fn test_off_decision_tank_too_cold_to_drain() {
+    let config: PythonBrainConfig = toml::from_str("hp_circulation.drain_tank_min_margin = 8.0")
+        .expect("Invalid config string");
+    let mut temps = HashMap::from([
+        (Sensor::HXIF, 25.0),
+        (Sensor::HXIR, 25.0),
+        (Sensor::HXOF, 25.0),
+        (Sensor::HXOR, 25.0),
+        (Sensor::TKBT, 36.0),
+        (Sensor::HPRT, 50.0),
+    ]);
+
+    let (mode, _) = decide_mode_from_off(
+        &temps,
+        None,
+        &off_decision_range(),
+        &HeatingState::ON,
+        &config,
+        &off_decision_time(),
+        false,
+    );
+    assert!(matches!(mode, HeatingMode::Off(_)), "Got {:?}", mode);
+
+    temps.insert(Sensor::TKBT, 40.0);
+    let (mode, _) = decide_mode_from_off(
+        &temps,
+        None,
+        &off_decision_range(),
+        &HeatingState::ON,
+        &config,
+        &off_decision_time(),
+        false,
+    );
+    assert!(matches!(mode, HeatingMode::TryCirculate(_)), "Got {:?}", mode);
+
+    // The smoothed TKBT is what matters when deciding whether to circulate.
+    let (mode, _) = decide_mode_from_off(
+        &temps,
+        Some(36.0),
+        &off_decision_range(),
+        &HeatingState::ON,
+        &config,
+        &off_decision_time(),
+        false,
+    );
+    assert!(matches!(mode, HeatingMode::Off(_)), "Got {:?}", mode);
+}
+
+#[test]
+fn test_pre_circulate_hysteresis() {
+    let config = PythonBrainConfig::default().hp_circulation;
+    assert_eq!(config.pre_circulate_temp_required, 35.0);
+    assert_eq!(config.get_pre_circulate_temp_min(), 33.0);
+
+    let on = HeatingMode::On(OnMode::default());
+    let pre_circulate = HeatingMode::PreCirculate(PreCirculateMode::start());
+    let try_circulate = HeatingMode::TryCirculate(TryCirculateMode::start());
+    let circulate = HeatingMode::Circulate(CirculateMode::default());
+    let equalise = HeatingMode::Equalise(EqualiseMode::start());
+
+    // Above the required temperature, always pre-circulate.
+    assert!(hot_enough_to_pre_circulate(35.1, &config, Some(&on)));
+    assert!(hot_enough_to_pre_circulate(35.1, &config, Some(&try_circulate)));
+    assert!(hot_enough_to_pre_circulate(35.1, &config, None));
+
+    // In between, depends on what we were already doing.
+    for hxor in [35.0, 34.0, 33.1] {
+        assert!(hot_enough_to_pre_circulate(hxor, &config, Some(&pre_circulate)), "{} pre-circulating", hxor);
+        assert!(hot_enough_to_pre_circulate(hxor, &config, Some(&circulate)), "{} circulating", hxor);
+        assert!(!hot_enough_to_pre_circulate(hxor, &config, Some(&equalise)), "{} equalising", hxor);
+        assert!(!hot_enough_to_pre_circulate(hxor, &config, Some(&try_circulate)), "{} trying to circulate", hxor);
+        assert!(!hot_enough_to_pre_circulate(hxor, &config, Some(&on)), "{} on", hxor);
+        assert!(!hot_enough_to_pre_circulate(hxor, &config, None), "{} no mode", hxor);
+    }
+
+    // At or below the minimum, never pre-circulate.
+    assert!(!hot_enough_to_pre_circulate(33.0, &config, Some(&pre_circulate)));
+    assert!(!hot_enough_to_pre_circulate(30.0, &config, Some(&pre_circulate)));
+}
+
+#[test]
+fn test_pre_circulate_min_capped() {
+    let config: PythonBrainConfig = toml::from_str(
+        "[hp_circulation]\npre_circulate_temp_required = 30.0\npre_circulate_temp_min = 32.0",
+    )
+    .unwrap();
+    assert_eq!(config.hp_circulation.get_pre_circulate_temp_min(), 30.0);
+    let pre_circulate = HeatingMode::PreCirculate(PreCirculateMode::start());
+    assert!(hot_enough_to_pre_circulate(30.1, &config.hp_circulation, Some(&pre_circulate)));
+    assert!(!hot_enough_to_pre_circulate(30.0, &config.hp_circulation, Some(&pre_circulate)));
+}
+
+#[test]
+fn test_explained_intention_reason() {
+    let time = Utc.from_utc_datetime(&date(2022, 03, 12).and_time(time(12, 30, 00)));
+    let (mut io_bundle, _io_handle) = new_dummy_io();
+    let mut info_cache = InfoCache::create(
+        HeatingState::ON,
+        WorkingRange::from_temp_only(WorkingTemperatureRange::from_min_max(30.0, 50.0)),
+    );
+    let rt = Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .expect("Expected to be able to make runtime");
+
+    let intention = Intention::off_now().because("TKBT above working max");
+    assert_eq!(
+        intention,
+        Intention::Explained(Box::new(Intention::off_now()), "TKBT above working max".to_owned())
+    );
+
+    let mode = handle_intention(
+        intention,
+        None,
+        &mut info_cache,
+        &mut io_bundle,
+        &Default::default(),
+        &rt,
+        &time,
+    )
+    .unwrap();
+
+    assert_eq!(mode, Some(HeatingMode::off()));
+    assert_eq!(info_cache.get_mode_reason(), Some("TKBT above working max"));
+}
+
+#[test]
+fn test_defer_circulate_while_tkbt_rising() {
+    let finish_above_range = |trends: TemperatureTrends| {
+        let (mut io_bundle, mut io_handle) = new_dummy_io();
+        let rt = Runtime::new().unwrap();
+        let time = Utc.from_utc_datetime(&date(2022, 03, 12).and_time(time(18, 30, 00)));
+
+        let mut info_cache = InfoCache::create(
+            HeatingState::ON,
+            WorkingRange::from_temp_only(WorkingTemperatureRange::from_min_max(30.0, 40.0)),
+        )
+        .with_trends(trends);
+        expect_present(io_bundle.heating_control())
+            .try_set_heat_pump(HeatPumpMode::HeatingOnly)
+            .expect("Should be able to turn on.");
+
+        io_handle.send_steady_temps(&[
+            (Sensor::HXIF, 45.0),
+            (Sensor::HXIR, 45.0),
+            (Sensor::HXOR, 30.0),
+            (Sensor::HXOF, 45.0),
+            (Sensor::HPRT, 40.0),
+            (Sensor::TKBT, 48.0),
+        ]);
+
+        let mode = handle_intention(
+            Intention::Finish,
+            None,
+            &mut info_cache,
+            &mut io_bundle,
+            &Default::default(),
+            &rt,
+            &time,
+        )
+        .expect("Should succeed");
+        (mode, info_cache.get_mode_reason().map(str::to_owned))
+    };
+
+    let mut trends = TemperatureTrends::default();
+    let start = utc_datetime(2022, 3, 12, 18, 25, 0);
+    for (i, tkbt) in [44.0, 45.0, 46.0, 47.0, 48.0].iter().enumerate() {
+        let time = start + chrono::Duration::minutes(i as i64);
+        trends.update(time, &HashMap::from([(Sensor::TKBT, *tkbt)]));
+    }
+
+    let (mode, reason) = finish_above_range(trends);
+    assert!(matches!(mode, Some(HeatingMode::PreCirculate(_))), "Rising: expected PreCirculate but got {:?}", mode);
+    assert_eq!(reason.as_deref(), Some("Above the working range but TKBT is still rising"));
+
+    let (mode, reason) = finish_above_range(TemperatureTrends::default());
+    assert!(matches!(mode, Some(HeatingMode::TryCirculate(_))), "No trend: expected TryCirculate but got {:?} ({:?})", mode, reason);
+}
+
+#[test]
+fn test_skip_pre_circulate() {
+    let finish_above_range = |hxor: f32, config: &PythonBrainConfig| {
+        let (mut io_bundle, mut io_handle) = new_dummy_io();
+        let rt = Runtime::new().unwrap();
+        let time = utc_datetime(2022, 3, 12, 18, 30, 0);
+
+        let mut info_cache = InfoCache::create(
+            HeatingState::ON,
+            WorkingRange::from_temp_only(WorkingTemperatureRange::from_min_max(30.0, 40.0)),
+        );
+        expect_present(io_bundle.heating_control())
+            .try_set_heat_pump(HeatPumpMode::HeatingOnly)
+            .expect("Should be able to turn on.");
+
+        io_handle.send_steady_temps(&[
+            (Sensor::HXIF, 45.0),
+            (Sensor::HXIR, 45.0),
+            (Sensor::HXOR, hxor),
+            (Sensor::HXOF, 45.0),
+            (Sensor::HPRT, 40.0),
+            (Sensor::TKBT, 48.0),
+        ]);
+
+        handle_intention(Intention::Finish, None, &mut info_cache, &mut io_bundle, config, &rt, &time)
+            .expect("Should succeed")
+    };
+
+    let default_config = PythonBrainConfig::default();
+    let skip_config: PythonBrainConfig = toml::from_str("[hp_circulation]\nskip_pre_circulate = true")
+        .expect("Invalid config string");
+    assert!(!default_config.hp_circulation.skip_pre_circulate);
+
+    let mode = finish_above_range(40.0, &default_config);
+    assert!(matches!(mode, Some(HeatingMode::PreCirculate(_))), "High HXOR: expected PreCirculate but got {:?}", mode);
+    let mode = finish_above_range(40.0, &skip_config);
+    assert!(matches!(mode, Some(HeatingMode::TryCirculate(_))), "High HXOR, skipping: expected TryCirculate but got {:?}", mode);
+
+    let mode = finish_above_range(30.0, &default_config);
+    assert!(matches!(mode, Some(HeatingMode::TryCirculate(_))), "Low HXOR: expected TryCirculate but got {:?}", mode);
+    let mode = finish_above_range(30.0, &skip_config);
+    assert!(matches!(mode, Some(HeatingMode::TryCirculate(_))), "Low HXOR, skipping: expected TryCirculate but got {:?}", mode);
+}
+
+#[test]
+fn test_switch_configuration_ordering() -> Result<(), BrainFailure> {
+    let rt = Builder::new_current_thread().build().unwrap();
+    let config = PythonBrainConfig::default();
+    let (heating_control, log) = RecordingHeatingControl::new(DummyAllOutputs::default());
+    let (mut io_bundle, _io_handle) = new_dummy_io_with_heating_control(heating_control);
+
+    let mut mode = HeatingMode::Circulate(CirculateMode::default());
+    mode.enter(&config, &rt, &mut io_bundle)?;
+    assert_eq!(log.get_events(), vec![
+        HeatingControlEvent::HeatPump(HeatPumpMode::DrainTank),
+        HeatingControlEvent::HeatCirculationPump(true),
+    ]);
+
+    // The heat pump (and so the valves) should be sorted out before the circulation pump is
+    // turned off, and nothing more should be done once we are off.
+    log.clear();
+    mode.transition_to(HeatingMode::off(), &config, &rt, &mut io_bundle)?;
+    assert_eq!(log.get_events(), vec![
+        HeatingControlEvent::HeatPump(HeatPumpMode::Off),
+        HeatingControlEvent::HeatCirculationPump(false),
+    ]);
+
+    // The heat pump should be set up before the circulation pump pushes water through it.
+    log.clear();
+    mode.transition_to(HeatingMode::TurningOn(TurningOnMode::new(Instant::now())), &config, &rt, &mut io_bundle)?;
+    let recorded = log.get_recorded();
+    assert_eq!(recorded.iter().map(|r| r.index).collect::<Vec<_>>(), vec![0, 1]);
+    assert_eq!(recorded[0].event, HeatingControlEvent::HeatPump(HeatPumpMode::HeatingOnly));
+    assert_eq!(recorded[1].event, HeatingControlEvent::HeatCirculationPump(true));
+    assert!(recorded[0].time <= recorded[1].time);
+
+    Ok(())
+}
+
+#[test]
+fn test_first_pre_circulate_since_heating() {
+    let mut shared_data = SharedData::new(FallbackWorkingRange::new(
+        WorkingTemperatureRange::from_min_max(42.0, 45.0),
+    ));
+    let is_first = |mode: &HeatingMode| match mode {
+        HeatingMode::PreCirculate(mode) => mode.is_first(),
+        other => panic!("Expected PreCirculate, got {:?}", other),
+    };
+
+    // Haven't heated yet since starting up.
+    let mut mode = HeatingMode::PreCirculate(PreCirculateMode::start());
+    shared_data.notify_next_mode(&mut mode);
+    assert!(!is_first(&mode));
+
+    shared_data.notify_next_mode(&mut HeatingMode::On(OnMode::default()));
+    let mut mode = HeatingMode::PreCirculate(PreCirculateMode::start());
+    shared_data.notify_next_mode(&mut mode);
+    assert!(is_first(&mode), "First after heating");
+
+    shared_data.notify_next_mode(&mut HeatingMode::Equalise(EqualiseMode::start()));
+    let mut mode = HeatingMode::PreCirculate(PreCirculateMode::start());
+    shared_data.notify_next_mode(&mut mode);
+    assert!(!is_first(&mode), "Not heated since the last one");
+}
+
+#[test]
+fn test_min_overrun_gap() -> Result<(), BrainFailure> {
+    let slots = r#"
+[[overrun_during.slots]]
+slot = { type = "Utc", start="11:00:00", end="13:00:05" }
+temps = { sensor = "TKBT", min = 40.0, max = 44.0 }
+
+[[overrun_during.slots]]
+slot = { type = "Utc", start="11:00:00", end="13:00:05" }
+temps = { sensor = "TKTP", min = 45.0, max = 50.0 }
+"#;
+    let no_gap_config: PythonBrainConfig = toml::from_str(slots).expect("Invalid config string");
+    let config: PythonBrainConfig = toml::from_str(&format!("min_overrun_gap = 1800\n{}", slots))
+        .expect("Invalid config string");
+    assert_eq!(config.get_min_overrun_gap(), Some(&Duration::from_secs(1800)));
+
+    let rt = Runtime::new().expect("Failed to create runtime");
+    let now = utc_datetime(2022, 3, 12, 12, 0, 0);
+    let info_cache = |shared_data: &SharedData| {
+        InfoCache::create(
+            HeatingState::OFF,
+            WorkingRange::from_temp_only(WorkingTemperatureRange::from_min_max(30.0, 50.0)),
+        ).with_last_overrun_finished(shared_data.get_last_overrun_finished())
+    };
+
+    // Returns the next mode once TKBT has been heated up, by which point TKTP has dropped from above its
+    // maximum to below its minimum.
+    let run_overrun = |config: &PythonBrainConfig, shared_data: &mut SharedData| -> Result<Option<HeatingMode>, BrainFailure> {
+        let (mut io_bundle, mut io_handle) = new_dummy_io();
+        let time = DummyTimeProvider::new(now);
+        io_handle.send_temps(ModifyState::SetTemps(HashMap::from([(Sensor::TKBT, 38.0), (Sensor::TKTP, 51.0)])));
+        let next = handle_intention(Intention::finish(), Some(&HeatingMode::off()), &mut info_cache(shared_data), &mut io_bundle, config, &rt, &now)?;
+        let Some(mut mode @ HeatingMode::DhwOnly(_)) = next else {
+            panic!("TKBT is below its minimum so should start an overrun, got {:?}", next);
+        };
+        shared_data.notify_leaving_mode(&HeatingMode::off(), now);
+        mode.enter(config, &rt, &mut io_bundle)?;
+        let next = mode.update(shared_data, &rt, config, &mut io_bundle, &mut info_cache(shared_data), &time)?;
+        assert_eq!(next, None, "Still heating TKBT");
+
+        io_handle.send_temps(ModifyState::SetTemps(HashMap::from([(Sensor::TKBT, 44.5), (Sensor::TKTP, 44.0)])));
+        let next = mode.update(shared_data, &rt, config, &mut io_bundle, &mut info_cache(shared_data), &time)?;
+        if next.is_some() {
+            shared_data.notify_leaving_mode(&mode, now);
+        }
+        Ok(next)
+    };
+    let new_shared_data = || SharedData::new(FallbackWorkingRange::new(WorkingTemperatureRange::from_min_max(42.0, 45.0)));
+
+    let next = run_overrun(&no_gap_config, &mut new_shared_data())?;
+    assert_eq!(next, None, "Without a gap, should carry on heating for TKTP");
+
+    let mut shared_data = new_shared_data();
+    let next = run_overrun(&config, &mut shared_data)?;
+    assert!(matches!(next, Some(HeatingMode::Off(_))), "Should finish rather than move onto TKTP, got {:?}", next);
+    assert_eq!(shared_data.get_last_overrun_finished(), Some(now));
+
+    // Back in off, with TKTP still wanting heating.
+    let (mut io_bundle, mut io_handle) = new_dummy_io();
+    io_handle.send_temps(ModifyState::SetTemps(HashMap::from([(Sensor::TKBT, 44.5), (Sensor::TKTP, 44.0)])));
+    let off = HeatingMode::off();
+    let later = now + chrono::Duration::minutes(10);
+    let next = handle_intention(Intention::finish(), Some(&off), &mut info_cache(&shared_data), &mut io_bundle, &config, &rt, &later)?;
+    assert!(matches!(next, Some(HeatingMode::Off(_))), "Within the gap, got {:?}", next);
+    let next = handle_intention(Intention::YieldHeatUps, Some(&off), &mut info_cache(&shared_data), &mut io_bundle, &config, &rt, &later)?;
+    assert_eq!(next, None, "Within the gap");
+
+    let later = now + chrono::Duration::minutes(31);
+    let next = handle_intention(Intention::finish(), Some(&off), &mut info_cache(&shared_data), &mut io_bundle, &config, &rt, &later)?;
+    assert!(matches!(next, Some(HeatingMode::DhwOnly(_))), "After the gap, got {:?}", next);
+    Ok(())
+}
+
+#[test]
+fn test_overrun_from_off_uses_fallback_sensor() -> Result<(), BrainFailure> {
+    let slots = r#"
+[[overrun_during.slots]]
+slot = { type = "Utc", start="11:00:00", end="13:00:05" }
+temps = { sensor = "TKBT", min = 40.0, max = 44.0 }
+"#;
+    let no_fallback_config: PythonBrainConfig = toml::from_str(slots).expect("Invalid config string");
+    let config: PythonBrainConfig = toml::from_str(&format!("missing_overrun_sensor.fallbacks = {{ TKBT = \"TKBTM\" }}\n{}", slots))
+        .expect("Invalid config string");
+
+    let (mut io_bundle, mut io_handle) = new_dummy_io();
+    let rt = Runtime::new().expect("Failed to create runtime");
+    let now = utc_datetime(2022, 3, 12, 12, 30, 0);
+    let info_cache = || InfoCache::create(
+        HeatingState::OFF,
+        WorkingRange::from_temp_only(WorkingTemperatureRange::from_min_max(30.0, 50.0)),
+    );
+    io_handle.send_temps(ModifyState::SetTemps(HashMap::from([(Sensor::from("TKBTM"), 35.0)])));
+    let off = HeatingMode::off();
+
+    let next = handle_intention(Intention::finish(), Some(&off), &mut info_cache(), &mut io_bundle, &no_fallback_config, &rt, &now)?;
+    assert!(matches!(next, Some(HeatingMode::Off(_))), "TKBT is missing, got {:?}", next);
+
+    let next = handle_intention(Intention::finish(), Some(&off), &mut info_cache(), &mut io_bundle, &config, &rt, &now)?;
+    assert!(matches!(next, Some(HeatingMode::DhwOnly(_))), "Should use TKBTM, got {:?}", next);
+    let next = handle_intention(Intention::YieldHeatUps, Some(&off), &mut info_cache(), &mut io_bundle, &config, &rt, &now)?;
+    assert!(matches!(next, Some(HeatingMode::DhwOnly(_))), "Should use TKBTM, got {:?}", next);
+    Ok(())
+}
+
+#[test]
+fn test_post_circulate_dhw_cooldown() -> Result<(), BrainFailure> {
+    let slots = r#"
+[[overrun_during.slots]]
+slot = { type = "Utc", start="11:00:00", end="13:00:05" }
+temps = { sensor = "TKBT", min = 40.0, max = 44.0 }
+"#;
+    let no_cooldown_config: PythonBrainConfig = toml::from_str(slots).expect("Invalid config string");
+    let config: PythonBrainConfig = toml::from_str(&format!("post_circulate_dhw_cooldown = 1200\n{}", slots))
+        .expect("Invalid config string");
+    assert_eq!(config.post_circulate_dhw_cooldown, Duration::from_secs(1200));
+
+    let (mut io_bundle, mut io_handle) = new_dummy_io();
+    let rt = Runtime::new().expect("Failed to create runtime");
+    let now = utc_datetime(2022, 3, 12, 12, 30, 0);
+    let info_cache = |finished: Option<DateTime<Utc>>| {
+        InfoCache::create(
+            HeatingState::OFF,
+            WorkingRange::from_temp_only(WorkingTemperatureRange::from_min_max(30.0, 50.0)),
+        ).with_last_circulate_finished(finished)
+    };
+
+    // Below the minimum of the slot, so would normally heat the hot water.
+    io_handle.send_temps(ModifyState::SetTemps(HashMap::from([(Sensor::TKBT, 38.0)])));
+    let circulate = HeatingMode::Circulate(CirculateMode::default());
+
+    let next = handle_intention(Intention::finish(), Some(&circulate), &mut info_cache(None), &mut io_bundle, &no_cooldown_config, &rt, &now)?;
+    assert!(matches!(next, Some(HeatingMode::DhwOnly(_))), "Without a cooldown, got {:?}", next);
+
+    let next = handle_intention(Intention::finish(), Some(&circulate), &mut info_cache(None), &mut io_bundle, &config, &rt, &now)?;
+    assert!(matches!(next, Some(HeatingMode::Off(_))), "Just finished circulating, got {:?}", next);
+
+    let off = HeatingMode::off();
+    let just_finished = Some(now - chrono::Duration::minutes(10));
+    let next = handle_intention(Intention::finish(), Some(&off), &mut info_cache(just_finished), &mut io_bundle, &config, &rt, &now)?;
+    assert!(matches!(next, Some(HeatingMode::Off(_))), "Within the cooldown, got {:?}", next);
+    let next = handle_intention(Intention::YieldHeatUps, Some(&off), &mut info_cache(just_finished), &mut io_bundle, &config, &rt, &now)?;
+    assert_eq!(next, None, "Within the cooldown");
+
+    let finished_earlier = Some(now - chrono::Duration::minutes(21));
+    let next = handle_intention(Intention::finish(), Some(&off), &mut info_cache(finished_earlier), &mut io_bundle, &config, &rt, &now)?;
+    assert!(matches!(next, Some(HeatingMode::DhwOnly(_))), "After the cooldown, got {:?}", next);
+    let next = handle_intention(Intention::YieldHeatUps, Some(&off), &mut info_cache(finished_earlier), &mut io_bundle, &config, &rt, &now)?;
+    assert!(matches!(next, Some(HeatingMode::DhwOnly(_))), "After the cooldown, got {:?}", next);
+    Ok(())
+}
+
+#[test]
+fn test_last_overrun_finished() {
+    let mut shared_data = SharedData::new(FallbackWorkingRange::new(
+        WorkingTemperatureRange::from_min_max(42.0, 45.0),
+    ));
+    let now = utc_datetime(2022, 3, 12, 12, 30, 0);
+
+    shared_data.notify_leaving_mode(&HeatingMode::off(), now);
+    assert_eq!(shared_data.get_last_overrun_finished(), None);
+
+    shared_data.notify_leaving_mode(&HeatingMode::DhwOnly(DhwOnlyMode::new()), now);
+    assert_eq!(shared_data.get_last_overrun_finished(), Some(now));
+    assert_eq!(shared_data.get_last_circulate_finished(), None);
+
+    shared_data.notify_leaving_mode(&HeatingMode::Circulate(CirculateMode::default()), now);
+    assert_eq!(shared_data.get_last_circulate_finished(), Some(now));
+}
+
+#[test]
+fn test_hx_forecast_error() {
+    let mut shared_data = SharedData::new(FallbackWorkingRange::new(
+        WorkingTemperatureRange::from_min_max(42.0, 45.0),
+    ));
+    let config = HeatPumpCirculationConfig {
+        forecast_diff_offset: 5.0,
+        forecast_diff_proportion: 0.5,
+        ..HeatPumpCirculationConfig::default()
+    };
+    let hx_temps = |hxia: f32, hxor: f32| HashMap::from([
+        (Sensor::HXIF, hxia + 1.0),
+        (Sensor::HXIR, hxia - 1.0),
+        (Sensor::HXOR, hxor),
+    ]);
+
+    let off = HeatingMode::off();
+    let try_circulate = HeatingMode::TryCirculate(TryCirculateMode::new(Instant::now()));
+    let circulate = HeatingMode::Circulate(CirculateMode::default());
+    let turning_on = HeatingMode::TurningOn(TurningOnMode::new(Instant::now()));
+
+    // Expect (40 - 25 - 5) * 0.5 = 5 drop.
+    assert_eq!(shared_data.check_hx_forecast(Some(&off), &try_circulate, &hx_temps(40.0, 25.0), &config), None, "Nothing to compare to yet");
+    // Dropped by 8 once circulating, so 3 more than expected.
+    assert_eq!(shared_data.check_hx_forecast(Some(&try_circulate), &circulate, &hx_temps(32.0, 30.0), &config), Some(3.0));
+    assert_eq!(shared_data.check_hx_forecast(Some(&try_circulate), &circulate, &hx_temps(32.0, 30.0), &config), None, "Only compared once");
+
+    // Deciding to heat from rest doesn't make a forecast to compare.
+    assert_eq!(shared_data.check_hx_forecast(Some(&off), &turning_on, &hx_temps(40.0, 25.0), &config), None);
+    assert_eq!(shared_data.check_hx_forecast(Some(&try_circulate), &off, &hx_temps(32.0, 30.0), &config), None);
+
+    // At startup, and rising by 1.
+    assert_eq!(shared_data.check_hx_forecast(None, &try_circulate, &hx_temps(32.0, 30.0), &config), None);
+    assert_eq!(shared_data.check_hx_forecast(Some(&try_circulate), &turning_on, &hx_temps(33.0, 30.0), &config), Some(-1.0));
+
+    assert_eq!(shared_data.check_hx_forecast(Some(&off), &try_circulate, &HashMap::new(), &config), None, "Missing sensors");
+    assert_eq!(shared_data.check_hx_forecast(Some(&try_circulate), &circulate, &hx_temps(33.0, 30.0), &config), None, "No forecast made");
+}
+
+#[test]
+fn test_wiser_off_mid_try_circulate() -> Result<(), BrainFailure> {
+    let uninterruptible: PythonBrainConfig = toml::from_str("mode_interruptible = { TryCirculate = false }")
+        .expect("Invalid config string");
+    assert!(!uninterruptible.is_mode_interruptible("TryCirculate"));
+    assert!(uninterruptible.is_mode_interruptible("Circulate"));
+    assert!(toml::from_str::<PythonBrainConfig>("mode_interruptible = { TryCirc = false }").is_err(), "Unknown mode");
+    assert!(toml::from_str::<PythonBrainConfig>("mode_interruptible = { Circulate = false }").is_err(), "No minimum action");
+    let interruptible = PythonBrainConfig::default();
+    assert!(interruptible.is_mode_interruptible("TryCirculate"));
+
+    let (mut io_bundle, mut io_handle) = new_dummy_io();
+    let rt = Runtime::new().expect("Failed to create runtime");
+    let time_provider = RealTimeProvider::default();
+    let mut shared_data = SharedData::new(FallbackWorkingRange::new(interruptible.default_working_range.clone()));
+    for sensor in [Sensor::TKBT, Sensor::HXIF, Sensor::HXIR, Sensor::HXOR, Sensor::HPRT] {
+        io_handle.send_temp(sensor, 45.0);
+    }
+
+    let mut update = |config: &PythonBrainConfig, started: Instant| {
+        let mut mode = HeatingMode::TryCirculate(TryCirculateMode::new(started));
+        let mut info_cache = InfoCache::create(
+            HeatingState::OFF,
+            WorkingRange::from_temp_only(WorkingTemperatureRange::from_min_max(40.0, 50.0)),
+        );
+        mode.update(&mut shared_data, &rt, config, &mut io_bundle, &mut info_cache, &time_provider)
+    };
+
+    let mid_sample = Instant::now();
+    let next = update(&interruptible, mid_sample)?;
+    assert!(next.is_some(), "Should stop straight away when interruptible, got {:?}", next);
+    let next = update(&uninterruptible, mid_sample)?;
+    assert_eq!(next, None, "Should finish the reading first when not interruptible");
+
+    let sampled = Instant::now() - uninterruptible.hp_circulation.sample_tank_time - Duration::from_secs(1);
+    let next = update(&uninterruptible, sampled)?;
+    assert!(next.is_some(), "Should stop once the reading is done, got {:?}", next);
+    Ok(())
+}
+
+#[test]
+fn test_max_on_session() -> Result<(), BrainFailure> {
+    let config: PythonBrainConfig = toml::from_str("max_on_session = 3600").expect("Invalid config string");
+    assert_eq!(config.get_max_on_session(), Some(&Duration::from_secs(3600)));
+    assert_eq!(PythonBrainConfig::default().get_max_on_session(), None);
+
+    let (mut io_bundle, mut io_handle) = new_dummy_io();
+    let rt = Runtime::new().expect("Failed to create runtime");
+    let time_provider = RealTimeProvider::default();
+    let mut shared_data = SharedData::new(FallbackWorkingRange::new(config.default_working_range.clone()));
+
+    // Well below the top of the working range, so would carry on heating.
+    io_handle.send_steady_temps(&[(Sensor::HPRT, 35.0)]);
+    expect_present(io_bundle.heating_control()).try_set_heat_pump(HeatPumpMode::HeatingOnly)?;
+
+    let mut update = |mode: &mut HeatingMode, shared_data: &mut SharedData| {
+        let mut info_cache = InfoCache::create(
+            HeatingState::ON,
+            WorkingRange::from_temp_only(WorkingTemperatureRange::from_min_max(40.0, 50.0)),
+        );
+        mode.update(shared_data, &rt, &config, &mut io_bundle, &mut info_cache, &time_provider)
+    };
+
+    let mut mode = HeatingMode::On(OnMode::default());
+    let next = update(&mut mode, &mut shared_data)?;
+    assert_eq!(next, None, "Should carry on heating");
+
+    shared_data.on_session_started = Some(Instant::now() - Duration::from_secs(3601));
+    let next = update(&mut mode, &mut shared_data)?;
+    assert!(matches!(next, Some(HeatingMode::PreCirculate(_))), "Should re-evaluate after the max on session, got {:?}", next);
+
+    // Starts again after leaving On.
+    let pre_circulate = HeatingMode::PreCirculate(PreCirculateMode::start());
+    assert!(!shared_data.on_session_too_long(&pre_circulate, config.get_max_on_session(), Instant::now()));
+    assert_eq!(shared_data.on_session_started, None);
+    let next = update(&mut mode, &mut shared_data)?;
+    assert_eq!(next, None, "A new session");
+    Ok(())
+}
+
+#[test]
+fn test_log_intention() -> Result<(), BrainFailure> {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let (mut io_bundle, mut io_handle) = new_dummy_io();
+    let rt = Runtime::new().expect("Failed to create runtime");
+    io_handle.send_temp(Sensor::TKBT, 45.0);
+
+    let mut update_off = |config: &PythonBrainConfig| -> Result<Vec<(Level, String)>, BrainFailure> {
+        let recorded = RecordLevels::default();
+        let subscriber = tracing_subscriber::registry()
+            .with(mode_log_filter())
+            .with(recorded.clone());
+        let mut shared_data = SharedData::new(FallbackWorkingRange::new(config.default_working_range.clone()));
+        let mut info_cache = InfoCache::create(
+            HeatingState::OFF,
+            WorkingRange::from_temp_only(WorkingTemperatureRange::from_min_max(40.0, 50.0)),
+        );
+        let mut mode = HeatingMode::off();
+        tracing::subscriber::with_default(subscriber, || {
+            mode.update(&mut shared_data, &rt, config, &mut io_bundle, &mut info_cache, &RealTimeProvider::default())
+        })?;
+        Ok(recorded.events())
+    };
+
+    let messages = update_off(&PythonBrainConfig::default())?;
+    assert!(
+        messages.contains(&(Level::DEBUG, "Off intention: Finish".to_owned())),
+        "Should have logged the intention, got {:?}", messages
+    );
+
+    let config: PythonBrainConfig = toml::from_str("intention_log_level = \"info\"").expect("Invalid config string");
+    let messages = update_off(&config)?;
+    assert!(messages.contains(&(Level::INFO, "Off intention: Finish".to_owned())), "Got {:?}", messages);
+
+    // The mode's own log level doesn't limit the intention.
+    let config: PythonBrainConfig = toml::from_str("mode_log_levels = { Off = \"warn\" }").expect("Invalid config string");
+    let messages = update_off(&config)?;
+    assert!(messages.contains(&(Level::DEBUG, "Off intention: Finish".to_owned())), "Got {:?}", messages);
+
+    let config: PythonBrainConfig = toml::from_str("intention_log_level = \"off\"").expect("Invalid config string");
+    let messages = update_off(&config)?;
+    assert!(!messages.iter().any(|(_, message)| message.contains("intention:")), "Got {:?}", messages);
+    Ok(())
+}
+
+#[test]
+fn test_circulate_confirms_before_turning_on() -> Result<(), BrainFailure> {
+    let config: PythonBrainConfig = toml::from_str("hp_circulation.circulate_finish_confirm = 120")
+        .expect("Invalid config string");
+    let rt = Runtime::new().expect("Failed to create runtime");
+    let time = DummyTimeProvider::new(utc_datetime(2023, 11, 14, 12, 0, 0));
+
+    let update = |config: &PythonBrainConfig| -> Result<Option<HeatingMode>, BrainFailure> {
+        let (mut io_bundle, mut io_handle) = new_dummy_io();
+        for sensor in [Sensor::HXIF, Sensor::HXIR, Sensor::HXOR, Sensor::HXOF, Sensor::HPRT, Sensor::TKFL, Sensor::HPFL] {
+            io_handle.send_temp(sensor, 28.0);
+        }
+        io_handle.send_temp(Sensor::TKBT, 30.0);
+        let mut info_cache = InfoCache::create(
+            HeatingState::ON,
+            WorkingRange::from_temp_only(WorkingTemperatureRange::from_min_max(30.0, 40.0)),
+        );
+        let mut shared_data = SharedData::new(FallbackWorkingRange::new(WorkingTemperatureRange::from_min_max(30.0, 40.0)));
+        HeatingMode::Circulate(CirculateMode::default())
+            .update(&mut shared_data, &rt, config, &mut io_bundle, &mut info_cache, &time)
+    };
+
+    let next = update(&PythonBrainConfig::default())?;
+    assert!(matches!(next, Some(HeatingMode::TurningOn(_))), "Got {:?}", next);
+    assert_eq!(update(&config)?, None, "Should wait to confirm before turning on");
+    Ok(())
+}
@@ -10,6 +10,8 @@ pub enum Intention {
     Finish,
     /// Yield to a heat up if we are below its minimum temperature.
     YieldHeatUps,
+    /// The given intention, along with a human readable reason for it.
+    Explained(Box<Intention>, String),
 }
 
 impl Intention {
@@ -27,4 +29,10 @@ impl Intention {
     pub fn finish() -> Intention {
         Intention::Finish
     }
+
+    /// Attach a human readable reason explaining why this intention was chosen.
+    #[must_use]
+    pub fn because(self, reason: impl Into<String>) -> Intention {
+        Intention::Explained(Box::new(self), reason.into())
+    }
 }
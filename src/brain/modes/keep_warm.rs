@@ -0,0 +1,96 @@
+use std::time::Instant;
+
+use log::info;
+
+use crate::brain::python_like::config::heat_pump_circulation::KeepWarmConfig;
+use crate::temp_format::fmt_temp;
+
+/// Tracks nudging some heat into the hot water tank while heating the house.
+#[derive(Debug, PartialEq, Default)]
+pub struct KeepWarm {
+    /// When we started keeping the hot water warm, if we are.
+    started: Option<Instant>,
+    /// When we last stopped keeping the hot water warm.
+    stopped: Option<Instant>,
+}
+
+impl KeepWarm {
+    /// Whether to nudge some heat into the hot water tank while heating, starting when TKBT
+    /// drops below the floor and returning to just heating once TKBT is back above the floor
+    /// by the margin, or we have been doing so for the max duration.
+    pub fn should_keep_warm(&mut self, config: &KeepWarmConfig, tkbt: Option<f32>, now: Instant) -> bool {
+        let (floor, tkbt) = match (config.floor, tkbt) {
+            (Some(floor), Some(tkbt)) => (floor, tkbt),
+            _ => {
+                self.stop(now);
+                return false;
+            }
+        };
+
+        if let Some(started) = self.started {
+            if tkbt >= floor + config.margin {
+                info!("TKBT back up to {}, no longer keeping hot water warm", fmt_temp(tkbt));
+                self.stop(now);
+                return false;
+            }
+            if now.saturating_duration_since(started) >= config.max_duration {
+                info!("Kept hot water warm for {}s, going back to just heating", config.max_duration.as_secs());
+                self.stop(now);
+                return false;
+            }
+            return true;
+        }
+
+        let waited = self.stopped
+            .is_none_or(|stopped| now.saturating_duration_since(stopped) >= config.max_duration);
+        if tkbt < floor && waited {
+            info!("TKBT dropped to {}, below {}, keeping hot water warm", fmt_temp(tkbt), fmt_temp(floor));
+            self.started = Some(now);
+            return true;
+        }
+        false
+    }
+
+    pub fn stop(&mut self, now: Instant) {
+        if self.started.take().is_some() {
+            self.stopped = Some(now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_keep_warm_returns_to_heating() {
+        let config = KeepWarmConfig {
+            floor: Some(30.0),
+            margin: 2.0,
+            max_duration: Duration::from_secs(600),
+        };
+        let mins = |m: u64| Duration::from_secs(m * 60);
+        let start = Instant::now();
+        let mut keep_warm = KeepWarm::default();
+
+        assert!(keep_warm.should_keep_warm(&config, Some(29.0), start));
+        // Stay keeping warm until above the floor by the margin.
+        assert!(keep_warm.should_keep_warm(&config, Some(31.0), start + mins(1)));
+        assert!(keep_warm.should_keep_warm(&config, Some(31.9), start + mins(2)));
+        assert!(!keep_warm.should_keep_warm(&config, Some(32.0), start + mins(3)));
+        assert!(!keep_warm.should_keep_warm(&config, Some(31.0), start + mins(4)));
+
+        // Wait before starting again.
+        assert!(!keep_warm.should_keep_warm(&config, Some(29.0), start + mins(10)));
+        assert!(keep_warm.should_keep_warm(&config, Some(29.0), start + mins(13)));
+        // Give up after the max duration, even if still cold.
+        assert!(keep_warm.should_keep_warm(&config, Some(29.5), start + mins(22)));
+        assert!(!keep_warm.should_keep_warm(&config, Some(29.5), start + mins(23)));
+        assert!(!keep_warm.should_keep_warm(&config, Some(29.5), start + mins(32)));
+        assert!(keep_warm.should_keep_warm(&config, Some(29.5), start + mins(33)));
+
+        // Missing TKBT goes back to heating.
+        assert!(!keep_warm.should_keep_warm(&config, None, start + mins(34)));
+    }
+}
@@ -1,24 +1,31 @@
 use log::*;
+use std::time::Instant;
 use tokio::runtime::Runtime;
 
 use crate::brain::python_like::config::PythonBrainConfig;
 use crate::brain::python_like::control::heating_control::HeatPumpMode;
 use crate::brain::BrainFailure;
 use crate::expect_available;
+use crate::io::temperatures::Sensor;
 use crate::io::IOBundle;
 use crate::time_util::mytime::TimeProvider;
 
 use super::intention::Intention;
+use super::keep_warm::KeepWarm;
 use super::working_temp::{find_working_temp_action, CurrentHeatDirection, WorkingTempAction, MixedState};
 use super::{InfoCache, Mode, allow_dhw_mixed, AllowDhwMixed};
 
 /// Mode for running both heating and
 #[derive(Debug, PartialEq)]
-pub struct MixedMode {}
+pub struct MixedMode {
+    keep_warm: KeepWarm,
+}
 
 impl MixedMode {
     pub fn new() -> Self {
-        Self { }
+        Self {
+            keep_warm: KeepWarm::default(),
+        }
     }
 }
 
@@ -63,9 +70,15 @@ impl Mode for MixedMode {
         );
 
         let Some(slot) = slot else {
+            let tkbt = temps.get(&Sensor::TKBT).copied();
+            if self.keep_warm.should_keep_warm(&config.hp_circulation.keep_warm, tkbt, Instant::now()) {
+                debug!("No longer matches a DHW slot, but keeping hot water warm");
+                return Ok(Intention::KeepState);
+            }
             info!("No longer matches a DHW slot");
             return Ok(Intention::finish());
         };
+        self.keep_warm.stop(Instant::now());
 
         let allow_dhw_mixed = allow_dhw_mixed(&temps, slot, true);
 
@@ -74,7 +87,7 @@ impl Mode for MixedMode {
         }
 
         match find_working_temp_action(
-            &temps,
+            &info_cache.circulate_temps(&temps),
             &info_cache.get_working_temp_range(),
             &config.hp_circulation,
             CurrentHeatDirection::Climbing,
@@ -278,4 +291,40 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_keep_warm_after_slot() -> Result<(), BrainFailure> {
+        let run = |config: &PythonBrainConfig, mode: &mut MixedMode, tkbt: f32| -> Result<Intention, BrainFailure> {
+            let (mut io_bundle, mut handle) = new_dummy_io();
+            let range = WorkingRange::from_temp_only(WorkingTemperatureRange::from_min_max(20.0, 60.0));
+            let mut info_cache = InfoCache::create(HeatingState::ON, range);
+            let rt = Runtime::new().unwrap();
+            // After the slot has ended.
+            let time_provider = DummyTimeProvider::new(utc_datetime(2023, 11, 14, 16, 0, 0));
+
+            handle.send_temp(Sensor::HXIF, 59.0);
+            handle.send_temp(Sensor::HXIR, 59.0);
+            handle.send_temp(Sensor::HXOR, 59.0);
+            handle.send_temp(Sensor::TKBT, tkbt);
+            handle.send_temp(Sensor::HPRT, 50.0);
+
+            mode.enter(config, &rt, &mut io_bundle)?;
+            mode.update(&rt, config, &mut info_cache, &mut io_bundle, &time_provider)
+        };
+
+        let mut default_config = PythonBrainConfig::default();
+        default_config._add_dhw_slot(DhwBap::_new(utc_time_slot(11,0,0, 15,30,20), Sensor::TKBT, 0.0, 40.0));
+        let mut config: PythonBrainConfig = toml::from_str("hp_circulation.keep_warm.floor = 36.0")
+            .expect("Invalid config string");
+        config._add_dhw_slot(DhwBap::_new(utc_time_slot(11,0,0, 15,30,20), Sensor::TKBT, 0.0, 40.0));
+
+        assert_eq!(run(&default_config, &mut MixedMode::new(), 35.5)?, Intention::Finish, "Not kept warm unless configured");
+        assert_eq!(run(&config, &mut MixedMode::new(), 36.0)?, Intention::Finish, "At the floor");
+
+        let mut mode = MixedMode::new();
+        assert_eq!(run(&config, &mut mode, 35.5)?, Intention::KeepState, "Below the floor");
+        assert_eq!(run(&config, &mut mode, 37.0)?, Intention::KeepState, "Not above the floor by the margin yet");
+        assert_eq!(run(&config, &mut mode, 38.0)?, Intention::Finish, "Above the floor by the margin");
+        Ok(())
+    }
 }
@@ -1,5 +1,7 @@
 use crate::brain::modes::intention::Intention;
+use crate::brain::trend::TemperatureTrends;
 use crate::time_util::mytime::TimeProvider;
+use chrono::{DateTime, Utc};
 use crate::{BrainFailure, IOBundle, PythonBrainConfig, Sensor, TemperatureManager};
 use log::*;
 use std::collections::HashMap;
@@ -7,6 +9,7 @@ use std::fmt::{Display, Formatter};
 use std::sync::atomic::{AtomicBool, Ordering};
 use tokio::runtime::Runtime;
 
+use self::heating_mode::{CirculateTemps, PossibleTemperatureContainer};
 use self::working_temp::WorkingRange;
 
 use super::python_like::config::overrun_config::DhwBap;
@@ -15,6 +18,7 @@ pub mod circulate;
 pub mod dhw_only;
 pub mod mixed;
 pub mod equalise;
+pub mod keep_warm;
 mod off;
 pub mod on;
 pub mod pre_circulate;
@@ -49,6 +53,14 @@ pub struct InfoCache {
     temps: Option<Result<HashMap<Sensor, f32>, String>>,
     working_temp_range: WorkingRange,
     working_temp_range_printed: AtomicBool,
+    /// Why the next mode was chosen, if known.
+    mode_reason: Option<String>,
+    /// Why Off was decided on, if it was.
+    off_reason: Option<String>,
+    trends: TemperatureTrends,
+    last_overrun_finished: Option<DateTime<Utc>>,
+    last_circulate_finished: Option<DateTime<Utc>>,
+    smoothed_tkbt: Option<f32>,
 }
 
 impl InfoCache {
@@ -58,9 +70,57 @@ impl InfoCache {
             temps: None,
             working_temp_range: working_range,
             working_temp_range_printed: AtomicBool::new(false),
+            mode_reason: None,
+            off_reason: None,
+            trends: TemperatureTrends::default(),
+            last_overrun_finished: None,
+            last_circulate_finished: None,
+            smoothed_tkbt: None,
         }
     }
 
+    /// When the last hot water overrun finished, so that another isn't started too soon after.
+    #[must_use]
+    pub fn with_last_overrun_finished(mut self, finished: Option<DateTime<Utc>>) -> Self {
+        self.last_overrun_finished = finished;
+        self
+    }
+
+    pub fn get_last_overrun_finished(&self) -> Option<DateTime<Utc>> {
+        self.last_overrun_finished
+    }
+
+    /// When we last finished circulating, so that the hot water isn't heated straight after.
+    #[must_use]
+    pub fn with_last_circulate_finished(mut self, finished: Option<DateTime<Utc>>) -> Self {
+        self.last_circulate_finished = finished;
+        self
+    }
+
+    pub fn get_last_circulate_finished(&self) -> Option<DateTime<Utc>> {
+        self.last_circulate_finished
+    }
+
+    /// Use the given readings rather than retrieving them when first needed.
+    #[must_use]
+    pub fn with_temps(mut self, temps: Result<HashMap<Sensor, f32>, String>) -> Self {
+        self.temps = Some(temps);
+        self
+    }
+
+    /// Use the given history of readings to work out whether temperatures are rising or falling.
+    #[must_use]
+    pub fn with_trends(mut self, trends: TemperatureTrends) -> Self {
+        self.trends = trends;
+        self
+    }
+
+    /// How fast (in degrees per minute) the sensor is rising (positive) or falling (negative),
+    /// if known.
+    pub fn get_trend(&self, sensor: &Sensor) -> Option<f32> {
+        self.trends.get_trend(sensor)
+    }
+
     pub fn heating_on(&self) -> bool {
         self.heating_state.is_on()
     }
@@ -90,6 +150,39 @@ impl InfoCache {
         self.temps.as_ref().unwrap().clone()
     }
 
+    /// Use a smoothed TKBT when deciding whether to heat or circulate, leaving the
+    /// retrieved temperatures as they are for everything else.
+    pub fn set_smoothed_tkbt(&mut self, smoothed_tkbt: f32) {
+        self.smoothed_tkbt = Some(smoothed_tkbt);
+    }
+
+    pub fn get_smoothed_tkbt(&self) -> Option<f32> {
+        self.smoothed_tkbt
+    }
+
+    /// The temperatures to decide whether to heat or circulate with.
+    pub fn circulate_temps<'a, T: PossibleTemperatureContainer>(&self, temps: &'a T) -> CirculateTemps<'a, T> {
+        CirculateTemps::new(temps, self.smoothed_tkbt)
+    }
+
+    /// Record why the next mode was chosen.
+    pub fn set_mode_reason(&mut self, reason: impl Into<String>) {
+        self.mode_reason = Some(reason.into());
+    }
+
+    pub fn get_mode_reason(&self) -> Option<&str> {
+        self.mode_reason.as_deref()
+    }
+
+    /// Record why we are staying off.
+    pub fn set_off_reason(&mut self, reason: impl Into<String>) {
+        self.off_reason = Some(reason.into());
+    }
+
+    pub fn get_off_reason(&self) -> Option<&str> {
+        self.off_reason.as_deref()
+    }
+
     #[cfg(test)]
     pub fn reset_cache(&mut self) {
         self.temps = None;
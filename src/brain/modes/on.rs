@@ -3,7 +3,9 @@ use std::time::Instant;
 use crate::brain::modes::dhw_only::DhwOnlyMode;
 use crate::brain::modes::heating_mode::HeatingMode;
 use crate::brain::modes::intention::Intention;
+use crate::brain::modes::keep_warm::KeepWarm;
 use crate::brain::modes::{InfoCache, Mode};
+use crate::brain::python_like::config::hot_tank_policy::HotTankPolicy;
 use crate::brain::python_like::config::PythonBrainConfig;
 use crate::brain::python_like::config::overrun_config::DhwTemps;
 use crate::brain::python_like::control::heating_control::HeatPumpMode;
@@ -12,6 +14,7 @@ use crate::expect_available;
 use crate::io::temperatures::Sensor;
 use crate::io::IOBundle;
 use crate::time_util::mytime::TimeProvider;
+use crate::temp_format::fmt_temp;
 use log::{debug, error, info, warn};
 use tokio::runtime::Runtime;
 
@@ -29,21 +32,74 @@ pub struct OnMode {
     // in IoBundle with a function that determines whether the HP is actually on and
     // how long it has been on for.
     started: Instant,
+
+    keep_warm: KeepWarm,
+
+    /// When we started boosting the heating from a hot tank, if we have.
+    hot_tank_boost_started: Option<Instant>,
+
+    /// When HPRT first went above the temperature to start circulating, if it still is.
+    hprt_above_circulate_since: Option<Instant>,
 }
 
 impl OnMode {
     pub fn create(circulation_pump_on: bool) -> Self {
-        Self {
-            circulation_pump_on,
-            started: Instant::now(),
-        }
+        Self::new(circulation_pump_on, Instant::now())
     }
 
     pub fn new(circulation_pump_on: bool, started: Instant) -> Self {
         Self {
             circulation_pump_on, started,
+            keep_warm: KeepWarm::default(),
+            hot_tank_boost_started: None,
+            hprt_above_circulate_since: None,
         }
     }
+
+    /// Whether to boost the heating from a tank that is hotter than the working range rather
+    /// than finishing, which is only tried once, for up to hot_tank_boost_time, and only while
+    /// HXIA is within hot_tank_boost_max_margin of the top of the working range.
+    fn should_boost_from_hot_tank(&mut self, config: &PythonBrainConfig, tkbt: Option<f32>, hxia: Option<f32>, range_max: f32, now: Instant) -> bool {
+        if config.hot_tank_policy != HotTankPolicy::Boost {
+            return false;
+        }
+        match tkbt {
+            Some(tkbt) if tkbt > range_max => {}
+            _ => return false,
+        }
+        match hxia {
+            Some(hxia) if hxia <= range_max + config.hot_tank_boost_max_margin => {}
+            Some(hxia) => {
+                info!("HXIA {} too far above the working range to boost from the hot tank", fmt_temp(hxia));
+                return false;
+            }
+            None => return false,
+        }
+        let started = *self.hot_tank_boost_started.get_or_insert(now);
+        now.saturating_duration_since(started) < config.hot_tank_boost_time
+    }
+
+    /// Whether HPRT has stayed above the temperature to start circulating for long enough
+    /// to start the circulation pump. Dropping back to or below it starts the wait again.
+    fn should_start_circulating(&mut self, config: &PythonBrainConfig, hprt: Option<f32>, now: Instant) -> bool {
+        let threshold = config.get_on_temp_before_circulate();
+        match hprt {
+            Some(hprt) if hprt > threshold => {}
+            _ => {
+                if self.hprt_above_circulate_since.take().is_some() {
+                    debug!("HPRT dropped back to {:?}, not above {}", hprt, fmt_temp(threshold));
+                }
+                return false;
+            }
+        }
+        let since = *self.hprt_above_circulate_since.get_or_insert(now);
+        let waited = now.saturating_duration_since(since);
+        if waited < config.on_circulate_debounce {
+            debug!("HPRT above {} for {}s, waiting for {}s before circulating", fmt_temp(threshold), waited.as_secs(), config.on_circulate_debounce.as_secs());
+            return false;
+        }
+        true
+    }
 }
 
 impl Default for OnMode {
@@ -93,10 +149,8 @@ impl Mode for OnMode {
         let temps = temps.unwrap();
 
         if !info_cache.heating_on() {
-            // Finish mode should pick up any overrun whether considering
-            // minimum run time or not.
-            // TODO: config.get_min_hp_runtime();
-            // min_runtime.get_safety_cut_off().get_target_sensor().clone(),
+            // Finish mode should pick up any overrun, or heating up to
+            // the safety cut off if the minimum runtime hasn't been reached.
             return Ok(Intention::finish());
         }
 
@@ -106,7 +160,7 @@ impl Mode for OnMode {
 
         let heating = expect_available!(io_bundle.heating_control())?;
         match find_working_temp_action(
-            &temps,
+            &info_cache.circulate_temps(&temps),
             &info_cache.get_working_temp_range(),
             &config.hp_circulation,
             CurrentHeatDirection::Climbing,
@@ -118,14 +172,27 @@ impl Mode for OnMode {
                 return Ok(Intention::finish());
             }
             Ok(WorkingTempAction::Heat { mixed_state: MixedState::NotMixed }) => {               
-                heating.set_heat_pump(HeatPumpMode::HeatingOnly, Some("Disabling boost from hot water tank"))?;
+                let tkbt = temps.get(&Sensor::TKBT).copied();
+                if self.keep_warm.should_keep_warm(&config.hp_circulation.keep_warm, tkbt, Instant::now()) {
+                    heating.set_heat_pump(HeatPumpMode::MostlyHotWater, Some("Keeping hot water warm"))?;
+                } else {
+                    heating.set_heat_pump(HeatPumpMode::HeatingOnly, Some("Disabling boost from hot water tank"))?;
+                }
             }
             Ok(WorkingTempAction::Heat { mixed_state: MixedState::BoostedHeating }) => {
+                self.keep_warm.stop(Instant::now());
                 heating.set_heat_pump(HeatPumpMode::BoostedHeating, Some("Enabling boost from hot water tank"))?;
             }
             Ok(WorkingTempAction::Cool { .. }) => {
-                info!("Hit top of working range - should no longer heat");
-                return Ok(Intention::finish());
+                let tkbt = temps.get(&Sensor::TKBT).copied();
+                let hxia = temps.get(&Sensor::HXIF).zip(temps.get(&Sensor::HXIR)).map(|(hxif, hxir)| (hxif + hxir) / 2.0);
+                let range_max = info_cache.get_working_temp_range().get_max();
+                if self.should_boost_from_hot_tank(config, tkbt, hxia, range_max, Instant::now()) {
+                    heating.set_heat_pump(HeatPumpMode::BoostedHeating, Some("Draining the hot tank into the heating before circulating"))?;
+                } else {
+                    info!("Hit top of working range - should no longer heat");
+                    return Ok(Intention::finish());
+                }
             }
             Err(missing_sensor) => {
                 error!(
@@ -136,15 +203,211 @@ impl Mode for OnMode {
             }
         }
         if !self.circulation_pump_on {
-            if let Some(temp) = temps.get(&Sensor::HPRT) {
-                if *temp > config.temp_before_circulate {
-                    info!("Reached min circulation temp.");
-                    let gpio = expect_available!(io_bundle.heating_control())?;
-                    gpio.try_set_heat_circulation_pump(true)?;
-                    self.circulation_pump_on = true;
-                }
+            let hprt = temps.get(&Sensor::HPRT).copied();
+            if self.should_start_circulating(config, hprt, Instant::now()) {
+                info!("Reached min circulation temp.");
+                let gpio = expect_available!(io_bundle.heating_control())?;
+                gpio.try_set_heat_circulation_pump(true)?;
+                self.circulation_pump_on = true;
             }
         }
         Ok(Intention::YieldHeatUps)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::brain::modes::working_temp::{WorkingRange, WorkingTemperatureRange};
+    use crate::brain::modes::HeatingState;
+    use crate::io::dummy_io_bundle::new_dummy_io;
+    use crate::time_util::mytime::RealTimeProvider;
+
+    fn run_on_mode(config: &PythonBrainConfig, hprt: f32) -> Result<bool, BrainFailure> {
+        let rt = Runtime::new().unwrap();
+        let (mut io_bundle, mut handle) = new_dummy_io();
+
+        handle.send_steady_temps(&[(Sensor::HPRT, hprt)]);
+
+        let mut info_cache = InfoCache::create(
+            HeatingState::ON,
+            WorkingRange::from_temp_only(WorkingTemperatureRange::from_min_max(40.0, 50.0)),
+        );
+
+        let mut mode = OnMode::default();
+        mode.enter(config, &rt, &mut io_bundle)?;
+        let intention = mode.update(&rt, config, &mut info_cache, &mut io_bundle, &RealTimeProvider::default())?;
+        assert!(matches!(intention, Intention::YieldHeatUps), "Should have stayed on, got {:?}", intention);
+
+        expect_available!(io_bundle.heating_control())?.try_get_heat_circulation_pump()
+    }
+
+    #[test]
+    fn test_on_threshold() -> Result<(), BrainFailure> {
+        let config: PythonBrainConfig = toml::from_str(r#"
+temp_before_circulate = 40.0
+turning_on_temp_before_circulate = 45.0
+on_temp_before_circulate = 30.0
+"#).expect("Invalid config string");
+
+        assert!(!run_on_mode(&config, 29.0)?, "Below the On threshold");
+        assert!(run_on_mode(&config, 31.0)?, "Above the On threshold, but below the others");
+        Ok(())
+    }
+
+    #[test]
+    fn test_on_threshold_fallback() -> Result<(), BrainFailure> {
+        let config: PythonBrainConfig = toml::from_str("temp_before_circulate = 40.0")
+            .expect("Invalid config string");
+
+        assert!(!run_on_mode(&config, 39.0)?, "Below the fallback threshold");
+        assert!(run_on_mode(&config, 41.0)?, "Above the fallback threshold");
+        Ok(())
+    }
+
+    #[test]
+    fn test_circulate_debounce() {
+        let config: PythonBrainConfig = toml::from_str(r#"
+on_temp_before_circulate = 30.0
+on_circulate_debounce = 60
+"#).expect("Invalid config string");
+        let secs = std::time::Duration::from_secs;
+        let start = Instant::now();
+
+        // Sustained above the threshold.
+        let mut mode = OnMode::default();
+        assert!(!mode.should_start_circulating(&config, Some(31.0), start));
+        assert!(!mode.should_start_circulating(&config, Some(32.0), start + secs(30)));
+        assert!(mode.should_start_circulating(&config, Some(32.0), start + secs(60)));
+
+        // A transient spike, the wait starts again once back above.
+        let mut mode = OnMode::default();
+        assert!(!mode.should_start_circulating(&config, Some(35.0), start));
+        assert!(!mode.should_start_circulating(&config, Some(29.0), start + secs(30)));
+        assert!(!mode.should_start_circulating(&config, Some(31.0), start + secs(60)));
+        assert!(!mode.should_start_circulating(&config, None, start + secs(90)), "Missing HPRT");
+        assert!(!mode.should_start_circulating(&config, Some(31.0), start + secs(120)));
+        assert!(mode.should_start_circulating(&config, Some(31.0), start + secs(180)));
+
+        // No debounce by default.
+        let mut mode = OnMode::default();
+        assert!(mode.should_start_circulating(&PythonBrainConfig::default(), Some(40.0), start));
+    }
+
+    fn heat_pump_mode_with_tkbt(config: &PythonBrainConfig, tkbt: f32) -> Result<HeatPumpMode, BrainFailure> {
+        let rt = Runtime::new().unwrap();
+        let (mut io_bundle, mut handle) = new_dummy_io();
+
+        handle.send_steady_temps(&[(Sensor::TKBT, tkbt), (Sensor::HPRT, 35.0)]);
+
+        let mut info_cache = InfoCache::create(
+            HeatingState::ON,
+            WorkingRange::from_temp_only(WorkingTemperatureRange::from_min_max(40.0, 50.0)),
+        );
+
+        let mut mode = OnMode::default();
+        mode.enter(config, &rt, &mut io_bundle)?;
+        let intention = mode.update(&rt, config, &mut info_cache, &mut io_bundle, &RealTimeProvider::default())?;
+        assert!(matches!(intention, Intention::YieldHeatUps), "Should have stayed on, got {:?}", intention);
+
+        expect_available!(io_bundle.heating_control())?.try_get_heat_pump()
+    }
+
+    #[test]
+    fn test_keep_warm_floor() -> Result<(), BrainFailure> {
+        let config: PythonBrainConfig = toml::from_str("hp_circulation.keep_warm.floor = 30.0")
+            .expect("Invalid config string");
+
+        assert_eq!(heat_pump_mode_with_tkbt(&config, 30.5)?, HeatPumpMode::HeatingOnly);
+        assert_eq!(heat_pump_mode_with_tkbt(&config, 30.0)?, HeatPumpMode::HeatingOnly);
+        assert_eq!(heat_pump_mode_with_tkbt(&config, 29.5)?, HeatPumpMode::MostlyHotWater);
+
+        // Not kept warm unless configured.
+        assert_eq!(heat_pump_mode_with_tkbt(&PythonBrainConfig::default(), 20.0)?, HeatPumpMode::HeatingOnly);
+        Ok(())
+    }
+
+    /// The heating is warm enough, but the tank is hotter still.
+    fn update_with_hot_tank(config: &PythonBrainConfig, tkbt: f32) -> Result<(Intention, HeatPumpMode), BrainFailure> {
+        let rt = Runtime::new().unwrap();
+        let (mut io_bundle, mut handle) = new_dummy_io();
+
+        handle.send_temp(Sensor::TKBT, tkbt);
+        handle.send_temp(Sensor::HXIF, 55.0);
+        handle.send_temp(Sensor::HXIR, 55.0);
+        handle.send_temp(Sensor::HXOR, 55.0);
+        handle.send_temp(Sensor::HXOF, 50.0);
+        handle.send_temp(Sensor::TKFL, 50.0);
+        handle.send_temp(Sensor::HPFL, 50.0);
+        handle.send_temp(Sensor::HPRT, 45.0);
+
+        let mut info_cache = InfoCache::create(
+            HeatingState::ON,
+            WorkingRange::from_temp_only(WorkingTemperatureRange::from_min_max(40.0, 50.0)),
+        );
+
+        let mut mode = OnMode::default();
+        mode.enter(config, &rt, &mut io_bundle)?;
+        let intention = mode.update(&rt, config, &mut info_cache, &mut io_bundle, &RealTimeProvider::default())?;
+        Ok((intention, expect_available!(io_bundle.heating_control())?.try_get_heat_pump()?))
+    }
+
+    #[test]
+    fn test_hot_tank_policy() -> Result<(), BrainFailure> {
+        let boost_config: PythonBrainConfig = toml::from_str("hot_tank_policy = \"Boost\"")
+            .expect("Invalid config string");
+        let default_config = PythonBrainConfig::default();
+        assert_eq!(default_config.hot_tank_policy, HotTankPolicy::Circulate);
+
+        let (intention, hp_mode) = update_with_hot_tank(&default_config, 60.0)?;
+        assert_eq!(intention, Intention::finish(), "Should finish to circulate");
+        assert_eq!(hp_mode, HeatPumpMode::HeatingOnly);
+
+        let (intention, hp_mode) = update_with_hot_tank(&boost_config, 60.0)?;
+        assert_eq!(intention, Intention::YieldHeatUps, "Should stay on to boost from the tank");
+        assert_eq!(hp_mode, HeatPumpMode::BoostedHeating);
+
+        let (intention, _) = update_with_hot_tank(&boost_config, 48.0)?;
+        assert_eq!(intention, Intention::finish(), "Tank isn't above the working range");
+
+        let mut no_mixed_config: PythonBrainConfig = toml::from_str("hot_tank_policy = \"Boost\"")
+            .expect("Invalid config string");
+        no_mixed_config.hp_circulation.mixed_enabled = false;
+        let (intention, hp_mode) = update_with_hot_tank(&no_mixed_config, 60.0)?;
+        assert_eq!(intention, Intention::YieldHeatUps, "Enabled by the policy alone");
+        assert_eq!(hp_mode, HeatPumpMode::BoostedHeating);
+
+        let low_ceiling_config: PythonBrainConfig = toml::from_str("hot_tank_policy = \"Boost\"\nhot_tank_boost_max_margin = 2.0")
+            .expect("Invalid config string");
+        let (intention, _) = update_with_hot_tank(&low_ceiling_config, 60.0)?;
+        assert_eq!(intention, Intention::finish(), "HXIA is already too far above the working range");
+        Ok(())
+    }
+
+    #[test]
+    fn test_hot_tank_boost_time() {
+        let config: PythonBrainConfig = toml::from_str("hot_tank_policy = \"Boost\"\nhot_tank_boost_time = 300")
+            .expect("Invalid config string");
+        let mins = |m: u64| std::time::Duration::from_secs(m * 60);
+        let start = Instant::now();
+        let mut mode = OnMode::default();
+
+        assert!(mode.should_boost_from_hot_tank(&config, Some(55.0), Some(50.0), 50.0, start));
+        assert!(mode.should_boost_from_hot_tank(&config, Some(55.0), Some(50.0), 50.0, start + mins(4)));
+        assert!(!mode.should_boost_from_hot_tank(&config, Some(55.0), Some(50.0), 50.0, start + mins(5)), "Boosted for long enough");
+        assert!(!mode.should_boost_from_hot_tank(&config, Some(55.0), Some(50.0), 50.0, start + mins(20)), "Only tried once");
+        assert!(!OnMode::default().should_boost_from_hot_tank(&config, None, Some(50.0), 50.0, start), "Missing TKBT");
+    }
+
+    #[test]
+    fn test_hot_tank_boost_ceiling() {
+        let config: PythonBrainConfig = toml::from_str("hot_tank_policy = \"Boost\"\nhot_tank_boost_max_margin = 3.0")
+            .expect("Invalid config string");
+        let start = Instant::now();
+        let mut mode = OnMode::default();
+
+        assert!(mode.should_boost_from_hot_tank(&config, Some(60.0), Some(53.0), 50.0, start));
+        assert!(!mode.should_boost_from_hot_tank(&config, Some(60.0), Some(53.5), 50.0, start), "HXIA too hot");
+        assert!(!OnMode::default().should_boost_from_hot_tank(&config, Some(60.0), None, 50.0, start), "Missing HXIA");
+    }
+}
@@ -16,14 +16,25 @@ use super::{InfoCache, Mode};
 #[derive(PartialEq, Debug)]
 pub struct PreCirculateMode {
     started: Instant,
+    /// Whether this is the first PreCirculate since heating, see SharedData.
+    first: bool,
 }
 
 impl PreCirculateMode {
     pub fn start() -> Self {
         Self {
             started: Instant::now(),
+            first: false,
         }
     }
+
+    pub fn set_first(&mut self) {
+        self.first = true;
+    }
+
+    pub fn is_first(&self) -> bool {
+        self.first
+    }
 }
 
 impl Mode for PreCirculateMode {
@@ -34,8 +45,9 @@ impl Mode for PreCirculateMode {
         _io_bundle: &mut crate::io::IOBundle,
     ) -> Result<(), BrainFailure> {
         info!(
-            "Waiting {}s in PreCirculate",
-            config.hp_circulation.initial_hp_sleep.as_secs()
+            "Waiting {}s in PreCirculate{}",
+            config.hp_circulation.get_pre_circulate_sleep(self.is_first()).as_secs(),
+            if self.is_first() { " (first since heating)" } else { "" }
         );
 
         Ok(())
@@ -55,13 +67,81 @@ impl Mode for PreCirculateMode {
 
         // TODO: Check working range each time.
 
-        if self.started.elapsed() > config.hp_circulation.initial_hp_sleep {
+        if self.started.elapsed() > config.hp_circulation.get_pre_circulate_sleep(self.is_first()) {
             Ok(Intention::SwitchForce(
                 HeatingMode::Equalise(EqualiseMode::start()),
-            ))
+            ).because("Finished waiting in pre-circulate"))
         }
         else {
             Ok(Intention::YieldHeatUps)
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::brain::modes::working_temp::{WorkingRange, WorkingTemperatureRange};
+    use crate::brain::modes::HeatingState;
+    use crate::io::dummy_io_bundle::new_dummy_io;
+    use crate::time_util::mytime::DummyTimeProvider;
+    use crate::time_util::test_utils::utc_datetime;
+    use std::time::Duration;
+
+    #[test]
+    fn test_equalise_after_waiting() -> Result<(), BrainFailure> {
+        let config = PythonBrainConfig::default();
+        let (mut io_bundle, _handle) = new_dummy_io();
+        let range = WorkingRange::from_temp_only(WorkingTemperatureRange::from_min_max(30.0, 40.0));
+        let mut info_cache = InfoCache::create(HeatingState::ON, range);
+        let rt = Runtime::new().unwrap();
+        let time_provider = DummyTimeProvider::new(utc_datetime(2023, 11, 14, 12, 0, 0));
+
+        let mut mode = PreCirculateMode {
+            started: Instant::now() - config.hp_circulation.initial_hp_sleep - std::time::Duration::from_secs(1),
+            first: false,
+        };
+        let intention = mode.update(&rt, &config, &mut info_cache, &mut io_bundle, &time_provider)?;
+
+        match intention {
+            Intention::Explained(inner, reason) => {
+                assert_eq!(reason, "Finished waiting in pre-circulate");
+                assert!(
+                    matches!(*inner, Intention::SwitchForce(HeatingMode::Equalise(_))),
+                    "Expected to switch to equalise, got {:?}",
+                    inner
+                );
+            }
+            other => panic!("Expected an explained intention, got {:?}", other),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_first_sleep() -> Result<(), BrainFailure> {
+        let config: PythonBrainConfig = toml::from_str(r#"
+            [hp_circulation]
+            initial_hp_sleep = 300
+            first_initial_hp_sleep = 600
+        "#).expect("Invalid config string");
+        assert_eq!(config.hp_circulation.get_pre_circulate_sleep(true), Duration::from_secs(600));
+        assert_eq!(config.hp_circulation.get_pre_circulate_sleep(false), Duration::from_secs(300));
+        assert_eq!(PythonBrainConfig::default().hp_circulation.get_pre_circulate_sleep(true), Duration::from_secs(5 * 60));
+
+        let (mut io_bundle, _handle) = new_dummy_io();
+        let range = WorkingRange::from_temp_only(WorkingTemperatureRange::from_min_max(30.0, 40.0));
+        let mut info_cache = InfoCache::create(HeatingState::ON, range);
+        let rt = Runtime::new().unwrap();
+        let time_provider = DummyTimeProvider::new(utc_datetime(2023, 11, 14, 12, 0, 0));
+        let waited = Instant::now() - Duration::from_secs(400);
+
+        let mut subsequent = PreCirculateMode { started: waited, first: false };
+        let intention = subsequent.update(&rt, &config, &mut info_cache, &mut io_bundle, &time_provider)?;
+        assert!(matches!(intention, Intention::Explained(..)), "Subsequent should have finished waiting, got {:?}", intention);
+
+        let mut first = PreCirculateMode { started: waited, first: true };
+        let intention = first.update(&rt, &config, &mut info_cache, &mut io_bundle, &time_provider)?;
+        assert!(matches!(intention, Intention::YieldHeatUps), "First should still be waiting, got {:?}", intention);
+        Ok(())
+    }
+}
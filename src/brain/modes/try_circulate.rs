@@ -14,7 +14,7 @@ use crate::python_like::control::heating_control::HeatPumpMode;
 use crate::time_util::mytime::TimeProvider;
 
 use super::intention::Intention;
-use super::working_temp::{find_working_temp_action, CurrentHeatDirection, WorkingTempAction, MixedState};
+use super::working_temp::{find_working_temp_action, tank_warm_enough_to_drain, CurrentHeatDirection, WorkingTempAction, MixedState};
 use super::{InfoCache, Mode};
 
 #[derive(Debug, PartialEq)]
@@ -32,6 +32,11 @@ impl TryCirculateMode {
             started: Instant::now(),
         }
     }
+
+    /// Whether we have circulated for long enough to get a reading.
+    pub fn sample_complete(&self, config: &PythonBrainConfig) -> bool {
+        self.started.elapsed() > config.hp_circulation.sample_tank_time
+    }
 }
 
 impl Mode for TryCirculateMode {
@@ -73,9 +78,9 @@ impl Mode for TryCirculateMode {
             }
         };
 
-        if self.started.elapsed() > config.hp_circulation.sample_tank_time {
+        if self.sample_complete(config) {
             return match find_working_temp_action(
-                &temps,
+                &info_cache.circulate_temps(&temps),
                 &info_cache.get_working_temp_range(),
                 &config.hp_circulation,
                 CurrentHeatDirection::Falling,
@@ -86,13 +91,25 @@ impl Mode for TryCirculateMode {
                     info!("End of try period, heating is recommended.");
                     Ok(Intention::SwitchForce(HeatingMode::TurningOn(
                         TurningOnMode::new(Instant::now()),
-                    )))
+                    )).because("End of try period, heating is recommended"))
                 }
                 Ok(WorkingTempAction::Cool { circulate: true }) => {
-                    info!("End of try period, deciding to circulate");
-                    Ok(Intention::SwitchForce(HeatingMode::Circulate(
-                        CirculateMode::default(),
-                    )))
+                    match tank_warm_enough_to_drain(&info_cache.circulate_temps(&temps), &info_cache.get_working_temp_range(), &config.hp_circulation) {
+                        Ok(true) => {
+                            info!("End of try period, deciding to circulate");
+                            Ok(Intention::SwitchForce(HeatingMode::Circulate(
+                                CirculateMode::default(),
+                            )).because("End of try period, circulating is recommended"))
+                        }
+                        Ok(false) => Ok(Intention::off_now().because("End of try period, the tank is not warm enough to drain")),
+                        Err(missing_sensor) => {
+                            error!(
+                                "Missing {} sensor to decide whether to circulate, stopping",
+                                missing_sensor
+                            );
+                            Ok(Intention::off_now())
+                        }
+                    }
                 }
                 Ok(WorkingTempAction::Cool { circulate: false }) => {
                     info!("TKBT too cold, would be heating the tank. End of try period, want to cool but not circulate. Finishing mode.");
@@ -109,7 +126,7 @@ impl Mode for TryCirculateMode {
         }
 
         match find_working_temp_action(
-            &temps,
+            &info_cache.circulate_temps(&temps),
             &info_cache.get_working_temp_range(),
             &config.hp_circulation,
             CurrentHeatDirection::None,
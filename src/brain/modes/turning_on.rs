@@ -1,13 +1,14 @@
 use std::time::Instant;
 
 use crate::brain::python_like::control::heating_control::HeatPumpMode;
+use crate::temp_format::fmt_temp;
 use log::*;
 use tokio::runtime::Runtime;
 
 use crate::{
     brain::{python_like::config::PythonBrainConfig, BrainFailure},
     expect_available,
-    io::IOBundle,
+    io::{temperatures::Sensor, IOBundle},
     time_util::mytime::TimeProvider,
 };
 
@@ -16,23 +17,59 @@ use super::{intention::Intention, InfoCache, Mode, working_temp::{find_working_t
 #[derive(Debug, PartialEq)]
 pub struct TurningOnMode {
     started: Instant,
+    /// The first HPRT reading while turning on, to check it rises.
+    start_hprt: Option<f32>,
+    /// Whether we gave up because HPRT didn't rise, so the heat pump is probably faulty.
+    hprt_fault: bool,
 }
 
 impl TurningOnMode {
     pub fn new(begun: Instant) -> Self {
-        Self { started: begun }
+        Self { started: begun, start_hprt: None, hprt_fault: false }
+    }
+
+    /// Whether we gave up turning on because HPRT didn't rise.
+    pub fn had_hprt_fault(&self) -> bool {
+        self.hprt_fault
+    }
+
+    /// Check HPRT has risen enough since we started turning on, if configured.
+    fn hprt_rose_enough(&self, config: &PythonBrainConfig, hprt: Option<f32>) -> bool {
+        let min_rise = match config.turning_on_min_hprt_rise {
+            Some(min_rise) => min_rise,
+            None => return true,
+        };
+        match (self.start_hprt, hprt) {
+            (Some(start), Some(now)) if now - start < min_rise => {
+                warn!("HPRT only went from {} to {} while turning on, needed to rise by {} - possible hardware fault.", fmt_temp(start), fmt_temp(now), fmt_temp(min_rise));
+                false
+            }
+            (Some(_), Some(_)) => true,
+            (None, _) => {
+                warn!("Never got a starting HPRT reading, unable to check it rose while turning on.");
+                true
+            }
+            (Some(_), None) => {
+                warn!("Missing HPRT, unable to check it rose while turning on.");
+                true
+            }
+        }
     }
 }
 
 impl Mode for TurningOnMode {
     fn enter(
         &mut self,
-        _config: &PythonBrainConfig,
+        config: &PythonBrainConfig,
         _runtime: &Runtime,
         io_bundle: &mut IOBundle,
     ) -> Result<(), BrainFailure> {
         let heating = expect_available!(io_bundle.heating_control())?;
         heating.set_heat_pump(HeatPumpMode::HeatingOnly, Some("Turning on HP when entering mode."))?;
+        if config.turning_on_temp_before_circulate.is_some() {
+            // Wait for HPRT to get warm enough in update.
+            return Ok(());
+        }
         heating.set_heat_circulation_pump(true, Some("Turning on CP when entering mode."))
     }
 
@@ -52,6 +89,13 @@ impl Mode for TurningOnMode {
         }
 
         if self.started.elapsed() > config.hp_enable_time {
+            let hprt = rt.block_on(info_cache.get_temps(io_bundle.temperature_manager()))
+                .ok()
+                .and_then(|temps| temps.get(&Sensor::HPRT).copied());
+            if !self.hprt_rose_enough(config, hprt) {
+                self.hprt_fault = true;
+                return Ok(Intention::off_now().because("HPRT didn't rise while turning on"));
+            }
             return Ok(Intention::finish());
         }
 
@@ -63,13 +107,17 @@ impl Mode for TurningOnMode {
             }
         };
 
+        if self.start_hprt.is_none() {
+            self.start_hprt = temps.get(&Sensor::HPRT).copied();
+        }
+
         let slot = config.get_overrun_during().find_matching_slot(&time.get_utc_time(), &temps,
             |_temps, _temp| true
         );
 
         let heating = expect_available!(io_bundle.heating_control())?;
         match find_working_temp_action(
-            &temps,
+            &info_cache.circulate_temps(&temps),
             &info_cache.get_working_temp_range(),
             &config.hp_circulation,
             CurrentHeatDirection::None,
@@ -84,6 +132,144 @@ impl Mode for TurningOnMode {
             }
         }
 
+        if let Some(temp_before_circulate) = config.turning_on_temp_before_circulate {
+            if let Some(temp) = temps.get(&Sensor::HPRT) {
+                if *temp > temp_before_circulate {
+                    heating.set_heat_circulation_pump(true, Some("Reached min circulation temp while turning on."))?;
+                }
+            }
+        }
+
         Ok(Intention::KeepState)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::brain::modes::heating_mode::{HeatingMode, SharedData};
+    use crate::brain::modes::working_temp::{WorkingRange, WorkingTemperatureRange};
+    use crate::brain::modes::HeatingState;
+    use crate::brain::python_like::FallbackWorkingRange;
+    use crate::io::dummy_io_bundle::new_dummy_io;
+    use crate::time_util::mytime::RealTimeProvider;
+
+    #[test]
+    fn test_turning_on_threshold() -> Result<(), BrainFailure> {
+        let rt = Runtime::new().unwrap();
+        let (mut io_bundle, mut handle) = new_dummy_io();
+
+        let config: PythonBrainConfig = toml::from_str(r#"
+temp_before_circulate = 30.0
+turning_on_temp_before_circulate = 40.0
+on_temp_before_circulate = 20.0
+"#).expect("Invalid config string");
+
+        handle.send_steady_temps(&[(Sensor::HPRT, 35.0)]);
+
+        let mut mode = TurningOnMode::new(Instant::now());
+        mode.enter(&config, &rt, &mut io_bundle)?;
+        assert!(!expect_available!(io_bundle.heating_control())?.try_get_heat_circulation_pump()?,
+            "CP should wait for HPRT to reach the turning on threshold");
+
+        let mut info_cache = InfoCache::create(
+            HeatingState::ON,
+            WorkingRange::from_temp_only(WorkingTemperatureRange::from_min_max(40.0, 50.0)),
+        );
+        mode.update(&rt, &config, &mut info_cache, &mut io_bundle, &RealTimeProvider::default())?;
+        assert!(!expect_available!(io_bundle.heating_control())?.try_get_heat_circulation_pump()?,
+            "HPRT is above the On and fallback thresholds, but not the turning on threshold");
+
+        handle.send_temp(Sensor::HPRT, 41.0);
+        info_cache.reset_cache();
+        mode.update(&rt, &config, &mut info_cache, &mut io_bundle, &RealTimeProvider::default())?;
+        assert!(expect_available!(io_bundle.heating_control())?.try_get_heat_circulation_pump()?,
+            "CP should be on after reaching the turning on threshold");
+
+        Ok(())
+    }
+
+    /// Turn on with HPRT at 35, then finish turning on with HPRT at the given temperature.
+    fn finish_turning_on(config: &PythonBrainConfig, hprt: f32) -> Result<Intention, BrainFailure> {
+        let rt = Runtime::new().unwrap();
+        let (mut io_bundle, mut handle) = new_dummy_io();
+
+        handle.send_steady_temps(&[(Sensor::HPRT, 35.0)]);
+
+        let mut mode = TurningOnMode::new(Instant::now());
+        mode.enter(config, &rt, &mut io_bundle)?;
+        let mut info_cache = InfoCache::create(
+            HeatingState::ON,
+            WorkingRange::from_temp_only(WorkingTemperatureRange::from_min_max(40.0, 50.0)),
+        );
+        let intention = mode.update(&rt, config, &mut info_cache, &mut io_bundle, &RealTimeProvider::default())?;
+        assert_eq!(intention, Intention::KeepState);
+        assert_eq!(mode.start_hprt, Some(35.0));
+
+        mode.started = Instant::now() - config.hp_enable_time - std::time::Duration::from_secs(1);
+        handle.send_temp(Sensor::HPRT, hprt);
+        info_cache.reset_cache();
+        mode.update(&rt, config, &mut info_cache, &mut io_bundle, &RealTimeProvider::default())
+    }
+
+    #[test]
+    fn test_hprt_rise() -> Result<(), BrainFailure> {
+        let config: PythonBrainConfig = toml::from_str("turning_on_min_hprt_rise = 2.0")
+            .expect("Invalid config string");
+
+        assert_eq!(finish_turning_on(&config, 38.0)?, Intention::Finish);
+        assert_eq!(finish_turning_on(&config, 37.0)?, Intention::Finish);
+        assert_eq!(
+            finish_turning_on(&config, 36.0)?,
+            Intention::off_now().because("HPRT didn't rise while turning on")
+        );
+        assert_eq!(
+            finish_turning_on(&config, 35.0)?,
+            Intention::off_now().because("HPRT didn't rise while turning on")
+        );
+
+        // Not checked unless configured.
+        assert_eq!(finish_turning_on(&PythonBrainConfig::default(), 35.0)?, Intention::Finish);
+        Ok(())
+    }
+
+    /// Test that after the heat pump fails to start, Off doesn't go straight back into TurningOn
+    /// until the backoff has passed.
+    #[test]
+    fn test_fault_lockout() -> Result<(), BrainFailure> {
+        let rt = Runtime::new().unwrap();
+        let (mut io_bundle, mut handle) = new_dummy_io();
+        let mut config: PythonBrainConfig = toml::from_str("turning_on_min_hprt_rise = 2.0")
+            .expect("Invalid config string");
+        let range = WorkingTemperatureRange::from_min_max(40.0, 50.0);
+        let mut shared_data = SharedData::new(FallbackWorkingRange::new(range.clone()));
+        let new_info_cache = || InfoCache::create(HeatingState::ON, WorkingRange::from_temp_only(range.clone()));
+
+        for sensor in [Sensor::TKBT, Sensor::HXIF, Sensor::HXIR, Sensor::HXOF, Sensor::HXOR, Sensor::TKFL, Sensor::HPFL, Sensor::HPRT] {
+            handle.send_temp(sensor, 20.0);
+        }
+
+        let mut mode = HeatingMode::TurningOn(TurningOnMode {
+            started: Instant::now() - config.hp_enable_time - std::time::Duration::from_secs(1),
+            start_hprt: Some(20.0),
+            hprt_fault: false,
+        });
+        mode.enter(&config, &rt, &mut io_bundle)?;
+        let next = mode.update(&mut shared_data, &rt, &config, &mut io_bundle, &mut new_info_cache(), &RealTimeProvider::default())?;
+        assert_eq!(next, Some(HeatingMode::off()), "HPRT didn't rise");
+
+        let mut off = HeatingMode::off();
+        mode.transition_to(HeatingMode::off(), &config, &rt, &mut io_bundle)?;
+        for _ in 0..3 {
+            let mut info_cache = new_info_cache();
+            let next = off.update(&mut shared_data, &rt, &config, &mut io_bundle, &mut info_cache, &RealTimeProvider::default())?;
+            assert_eq!(next, Some(HeatingMode::off()), "Should stay off within the backoff");
+            assert_eq!(info_cache.get_mode_reason(), Some("Heat pump failed to start recently, staying off"));
+        }
+
+        config.turning_on_fault_backoff = std::time::Duration::ZERO;
+        let next = off.update(&mut shared_data, &rt, &config, &mut io_bundle, &mut new_info_cache(), &RealTimeProvider::default())?;
+        assert!(matches!(next, Some(HeatingMode::TurningOn(_))), "Should turn on again after the backoff, got {:?}", next);
+        Ok(())
+    }
+}
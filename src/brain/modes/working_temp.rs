@@ -1,11 +1,15 @@
 use crate::brain::{modes::heating_mode::PossibleTemperatureContainer, python_like::config::overrun_config::DhwBap};
 use crate::brain::python_like::config::heat_pump_circulation::HeatPumpCirculationConfig;
+use crate::brain::python_like::config::unnamed_rooms::UnnamedRoomPolicy;
 use crate::brain::python_like::config::working_temp_model::WorkingTempModelConfig;
 use crate::io::temperatures::Sensor;
 use crate::io::wiser::hub::WiserRoomData;
+use crate::log_rate_limit::rate_limited;
 use crate::python_like::FallbackWorkingRange;
+use crate::temp_format::fmt_temp;
 use crate::wiser::hub::RetrieveDataError;
-use log::{debug, error, info};
+use itertools::Itertools;
+use log::{debug, error, info, warn};
 use serde::Deserialize;
 use std::fmt::{Debug, Display, Formatter};
 
@@ -55,18 +59,18 @@ impl Display for WorkingRange {
         match &self.room {
             None => write!(f, "N/A: ",)?,
             Some(room) => {
-                write!(f, "{} (diff: {:.1}", room.name, room.difference)?;
+                write!(f, "{} (diff: {}", room.name, fmt_temp(room.difference))?;
                 if room.capped_difference != room.difference {
-                    write!(f, ", cap: {:.1}", room.capped_difference)?;
+                    write!(f, ", cap: {}", fmt_temp(room.capped_difference))?;
                 }
                 write!(f, "); ")?;
             }
         }
         write!(
             f,
-            "Working Range {:.2}-{:.2}",
-            self.get_min(),
-            self.get_max()
+            "Working Range {}-{}",
+            fmt_temp(self.get_min()),
+            fmt_temp(self.get_max())
         )?;
         Ok(())
     }
@@ -88,6 +92,10 @@ impl Room {
         }
     }
 
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
     pub fn get_difference(&self) -> f32 {
         self.capped_difference
     }
@@ -112,21 +120,29 @@ impl WorkingTemperatureRange {
         assert!(max > min, "Max should be greater than min.");
         WorkingTemperatureRange { max, min }
     }
+
+    /// The same range, moved down by the given amount.
+    pub fn lowered_by(&self, amount: f32) -> Self {
+        WorkingTemperatureRange {
+            max: self.max - amount,
+            min: self.min - amount,
+        }
+    }
 }
 
 impl Debug for WorkingTemperatureRange {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "WorkingTemperatureRange {{ min: {:.2} max: {:.2} }}",
-            self.min, self.max
+            "WorkingTemperatureRange {{ min: {} max: {} }}",
+            fmt_temp(self.min), fmt_temp(self.max)
         )
     }
 }
 
 impl Display for WorkingTemperatureRange {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:.2}-{:.2}", self.min, self.max)
+        write!(f, "{}-{}", fmt_temp(self.min), fmt_temp(self.max))
     }
 }
 
@@ -134,26 +150,44 @@ impl Display for WorkingTemperatureRange {
 // increase just the time rather than the temperature
 const MAX_ROOM_TEMP: f32 = 21.0;
 
+/// Find the working range from the room with the biggest difference. If the model has outdoor
+/// compensation and the outdoor temperature is known, the compensation is added onto the max
+/// from the room difference curve (see OutdoorCompensationConfig), the min is left as it is.
 fn get_working_temperature(
     data: &[WiserRoomData],
     working_temp_config: &WorkingTempModelConfig,
+    unnamed_rooms: UnnamedRoomPolicy,
+    outdoor_temp: Option<f32>,
 ) -> WorkingRange {
     let difference = data
         .iter()
-        .filter(|room| room.get_temperature() > -10.0) // Low battery or something.
-        .map(|room| {
-            (
-                room.get_name().unwrap_or(UNKNOWN_ROOM),
-                room.get_set_point().min(MAX_ROOM_TEMP) - room.get_temperature(),
-            )
+        .filter(|room| !(working_temp_config.ignore_away_rooms && room.is_away()))
+        .filter_map(|room| {
+            // Rooms that are off or have no valid temperature (low battery or something)
+            // would only give a misleading difference.
+            let set_point = room.get_active_set_point()?;
+            let temp = room.get_valid_temperature(working_temp_config.min_valid_room_temp)?;
+            Some((
+                unnamed_rooms.get_room_name(room)?,
+                set_point.min(MAX_ROOM_TEMP) - temp,
+            ))
         })
+        .filter(|(name, _difference)| !working_temp_config.ignore_rooms.iter().any(|ignored| ignored == name))
         .max_by(|a, b| a.1.total_cmp(&b.1))
-        .unwrap_or((UNKNOWN_ROOM, 0.0));
+        .unwrap_or((UNKNOWN_ROOM.into(), 0.0));
 
-    let (range, capped_difference) =
+    let (mut range, capped_difference) =
         get_working_temperature_from_max_difference(difference.1, working_temp_config);
 
-    let room = Room::of(difference.0.to_owned(), difference.1, capped_difference);
+    if let (Some(compensation), Some(outdoor_temp)) = (&working_temp_config.outdoor_compensation, outdoor_temp) {
+        let max = compensation.compensate_max(range.max, outdoor_temp);
+        if max != range.max {
+            debug!("Outdoor temperature {} raised the max working temperature from {} to {}", fmt_temp(outdoor_temp), fmt_temp(range.max), fmt_temp(max));
+            range = WorkingTemperatureRange::from_min_max(range.min, max);
+        }
+    }
+
+    let room = Room::of(difference.0.into_owned(), difference.1, capped_difference);
 
     WorkingRange::from_wiser(range, room)
 }
@@ -162,48 +196,105 @@ fn get_working_temperature_from_max_difference(
     difference: f32,
     config: &WorkingTempModelConfig,
 ) -> (WorkingTemperatureRange, f32) {
+    let mut min = config.min.get_temp_from_room_diff(difference);
+    let mut max = config.max.get_temp_from_room_diff(difference);
+    if max - min < config.min_band_width {
+        let middle = (min + max) / 2.0;
+        let widened_min = middle - config.min_band_width / 2.0;
+        let widened_max = middle + config.min_band_width / 2.0;
+        rate_limited!(info, "working_range_widened", "Working range {}-{} narrower than {}, widened to {}-{}",
+            fmt_temp(min), fmt_temp(max), fmt_temp(config.min_band_width), fmt_temp(widened_min), fmt_temp(widened_max));
+        min = widened_min;
+        max = widened_max;
+    }
     (
-        WorkingTemperatureRange::from_min_max(
-            config.min.get_temp_from_room_diff(difference),
-            config.max.get_temp_from_room_diff(difference)
-        ),
+        WorkingTemperatureRange::from_min_max(min, max),
         difference,
     )
 }
 
+/// A tab separated table of the working range for each room difference from `from` to `to`
+/// (inclusive) in steps of `step`, to see the shape of the curve when tuning the model.
+pub fn working_range_table(config: &WorkingTempModelConfig, from: f32, to: f32, step: f32) -> Result<String, String> {
+    if step <= 0.0 || from > to {
+        return Err(format!("Invalid range: {} to {} step {}", from, to, step));
+    }
+    // Count steps rather than repeatedly adding to avoid the error building up.
+    let steps = ((to - from) / step + 1e-4).floor() as usize;
+    let mut table = "difference\tmin\tmax\n".to_owned();
+    for i in 0..=steps {
+        let difference = from + step * i as f32;
+        let (range, _) = get_working_temperature_from_max_difference(difference, config);
+        table.push_str(&format!("{:.2}\t{:.2}\t{:.2}\n", difference, range.min, range.max));
+    }
+    Ok(table)
+}
+
 pub fn get_working_temperature_range_from_wiser_data(
     fallback: &mut FallbackWorkingRange,
     result: Result<Vec<WiserRoomData>, RetrieveDataError>,
     working_temp_config: &WorkingTempModelConfig,
+    unnamed_rooms: UnnamedRoomPolicy,
+    outdoor_temp: Option<f32>,
 ) -> WorkingRange {
     result
         .ok()
-        .filter(|data| {
-            let good_data = data.iter().any(|r| r.get_temperature() > -10.0);
-            if !good_data {
-                error!(target: "wiser", "Bad data detected: no rooms with sensible temperatures");
-                error!(target: "wiser", "{:?}", data);
-            }
-            good_data
-        })
+        .and_then(|data| keep_valid_rooms(data, working_temp_config.min_valid_room_temp))
         .map(|data| {
-            let working_range = get_working_temperature(&data, working_temp_config);
+            let working_range = get_working_temperature(&data, working_temp_config, unnamed_rooms, outdoor_temp);
             fallback.update(working_range.get_temperature_range().clone());
             working_range
         })
         .unwrap_or_else(|| WorkingRange::from_temp_only(fallback.get_fallback().clone()))
 }
 
+/// Drop the rooms that aren't reporting a sensible temperature, keeping the rest.
+/// Returns None if no rooms are reporting a sensible temperature, as then the data is no use at all.
+fn keep_valid_rooms(data: Vec<WiserRoomData>, min_valid: f32) -> Option<Vec<WiserRoomData>> {
+    let (good, bad): (Vec<_>, Vec<_>) = data
+        .into_iter()
+        .partition(|room| room.has_valid_temperature(min_valid));
+
+    if good.is_empty() {
+        error!(target: "wiser", "Bad data detected: no rooms with sensible temperatures");
+        error!(target: "wiser", "{:?}", bad);
+        return None;
+    }
+    if !bad.is_empty() {
+        let names = bad.iter()
+            .map(|room| room.get_name().map_or_else(|| room.get_id().to_string(), |name| name.to_owned()))
+            .join(", ");
+        warn!(target: "wiser", "Ignoring rooms without a sensible temperature (at or below {}): {names}", fmt_temp(min_valid));
+    }
+    Some(good)
+}
+
 /// Which way we are currently travelling within the working range.
+#[derive(Debug, PartialEq)]
 pub enum CurrentHeatDirection {
-    /// Just started up. Fine to go either up or down.
+    /// Starting from rest, e.g. after being idle. Fine to go either up or down.
     None,
+    /// Starting from rest after being idle for a long time. Like None, but using
+    /// cold_start_above_percent.
+    ColdStart,
     /// Already climbing / temperature rising, only start circulating once we hit the top.
     Climbing,
     /// Already falling (circulating already), only stop circulating once we hit the bottom.
     Falling,
 }
 
+impl CurrentHeatDirection {
+    /// How far within the working range the forecast needs to be to cool rather than heat,
+    /// if starting from rest in this direction.
+    fn start_above_percent(&self, config: &HeatPumpCirculationConfig) -> Option<f32> {
+        match self {
+            CurrentHeatDirection::None => Some(config.get_forecast_start_above_percent()),
+            CurrentHeatDirection::ColdStart => Some(config.get_cold_start_above_percent()),
+            CurrentHeatDirection::Climbing | CurrentHeatDirection::Falling => None,
+        }
+    }
+}
+
 /// What to do about the working temp in order to stay within the required range.
 #[derive(PartialEq, Debug)]
 pub enum WorkingTempAction {
@@ -235,6 +326,9 @@ pub fn find_working_temp_action(
     mixed_state:    Option<MixedState>,
     dhw_slot:       Option<&DhwBap>,
 ) -> Result<WorkingTempAction, Sensor> {
+    let biased_range = apply_bias(range, config.get_bias());
+    let range = &biased_range;
+
     let hx_pct = forecast_hx_pct(temps, config, &heat_direction, range)?;
 
     // Only cause 1 log if needed.
@@ -246,26 +340,26 @@ pub fn find_working_temp_action(
         Ok(tk_pct_cached.unwrap())
     };
 
-    let should_cool = match heat_direction {
-        CurrentHeatDirection::Falling => hx_pct >= 0.0,
-        CurrentHeatDirection::Climbing => hx_pct >= 1.0,
-        CurrentHeatDirection::None => {
+    let required_pct = heat_direction.start_above_percent(config);
+    let should_cool = match required_pct {
+        // Starting from rest, so fine to go either way.
+        Some(required_pct) => {
             let tk_pct = get_tk_pct()?;
 
             // Happy to circulate first
-            let hx_above_req = hx_pct >= config.forecast_start_above_percent;
+            let hx_above_req = hx_pct >= required_pct;
             // Happy to drain from tank first
-            let tk_above_req = tk_pct >= config.forecast_start_above_percent;
+            let tk_above_req = tk_pct >= required_pct;
 
-            hx_above_req || tk_above_req
+            config.start_forecast.should_cool(hx_above_req, tk_above_req)
         }
+        None => match heat_direction {
+            CurrentHeatDirection::Falling => hx_pct >= 0.0,
+            _ => hx_pct >= 1.0,
+        },
     };
 
-    let (required_pct, used_tk) = match heat_direction {
-        CurrentHeatDirection::None => (Some(config.forecast_start_above_percent), true),
-        _ => (None, false),
-    };
-    if should_cool || used_tk {
+    if should_cool || required_pct.is_some() {
         info!(
             "HX Forecast ({}), TK Forecast ({})",
             format_pct(hx_pct, required_pct),
@@ -284,6 +378,60 @@ pub fn find_working_temp_action(
     })
 }
 
+/// How far (in degrees) the working range is moved at full bias.
+const BIAS_RANGE_SHIFT: f32 = 1.0;
+/// The proportion by which the width of the working range is changed at full bias.
+const BIAS_RANGE_WIDTH_PROPORTION: f32 = 0.25;
+
+/// Adjust the working range by the comfort vs efficiency bias b (-1.0..=1.0):
+/// * min' = min + b * (BIAS_RANGE_SHIFT - BIAS_RANGE_WIDTH_PROPORTION * width / 2)
+/// * max' = max + b * (BIAS_RANGE_SHIFT + BIAS_RANGE_WIDTH_PROPORTION * width / 2)
+///
+/// So the middle of the range moves up by b * BIAS_RANGE_SHIFT and the width of the range
+/// (the hysteresis between heating and circulating) is scaled by 1 + b * BIAS_RANGE_WIDTH_PROPORTION,
+/// which never goes below 75% of the original width.
+fn apply_bias(range: &WorkingRange, bias: f32) -> WorkingRange {
+    if bias == 0.0 {
+        return range.clone();
+    }
+    let half_width_change = BIAS_RANGE_WIDTH_PROPORTION * (range.get_max() - range.get_min()) / 2.0;
+    let temp_range = WorkingTemperatureRange::from_min_max(
+        range.get_min() + bias * (BIAS_RANGE_SHIFT - half_width_change),
+        range.get_max() + bias * (BIAS_RANGE_SHIFT + half_width_change),
+    );
+    debug!("Working range {} biased by {bias:.2} to {temp_range}", range.get_temperature_range());
+    WorkingRange {
+        temp_range,
+        room: range.room.clone(),
+    }
+}
+
+/// Whether TKBT is far enough above the bottom of the working range for draining the tank
+/// into the heating to be worthwhile.
+/// Returns the sensor that was missing if it couldn't be decided.
+pub fn tank_warm_enough_to_drain(
+    temps:  &impl PossibleTemperatureContainer,
+    range:  &WorkingRange,
+    config: &HeatPumpCirculationConfig,
+) -> Result<bool, Sensor> {
+    let tkbt = temps.get_sensor_temp(&Sensor::TKBT).ok_or(Sensor::TKBT)?;
+    let score = circulation_score(*tkbt, range, config);
+    let threshold = config.circulation_cost.threshold;
+    if score <= threshold {
+        info!("TKBT {} gives a circulation score of {score:.2}, not above {threshold:.2}, not worth draining the tank.", fmt_temp(*tkbt));
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+/// How worthwhile it is to drain the tank into the heating, see CirculationCostConfig.
+pub fn circulation_score(tkbt: f32, range: &WorkingRange, config: &HeatPumpCirculationConfig) -> f32 {
+    let cost = &config.circulation_cost;
+    let remaining_heat = tkbt - (range.get_min() + config.drain_tank_min_margin);
+    let room_demand = range.get_room().map_or(0.0, |room| room.get_difference());
+    cost.remaining_heat_weight * remaining_heat + cost.room_demand_weight * room_demand
+}
+
 fn get_mixed_state(
     temps:          &impl PossibleTemperatureContainer,
     config:         &HeatPumpCirculationConfig,
@@ -291,6 +439,10 @@ fn get_mixed_state(
     hx_pct:         f32,
     dhw_slot:       Option<&DhwBap>,
 ) -> Result<MixedState, Sensor> {
+    if !config.mixed_enabled {
+        return Ok(MixedState::NotMixed);
+    }
+
     if let Some(mixed_state) = mixed_state {
         // Possible candidate for boosting. This is where the heat pump is on, but the values and pump speeds
         // are such that some the the water from HXRT enters the tank heat exchanger at TKRT, gets heated and
@@ -371,22 +523,49 @@ fn format_pct(pct: f32, required_pct: Option<f32>) -> String {
 
 
 
-fn forecast_hx_pct(
+/// How much HXIA (the average of HXIF and HXIR) is expected to drop by once circulating,
+/// given how far HXOR is below it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HxForecast {
+    pub hxia: f32,
+    pub hxor: f32,
+    pub expected_drop: f32,
+}
+
+impl HxForecast {
+    /// How much more HXIA actually dropped by than expected, given what it is now.
+    /// Negative if it dropped by less than expected.
+    pub fn error(&self, hxia_now: f32) -> f32 {
+        (self.hxia - hxia_now) - self.expected_drop
+    }
+}
+
+pub fn forecast_hx_drop(
     temps: &impl PossibleTemperatureContainer,
     config: &HeatPumpCirculationConfig,
-    heat_direction: &CurrentHeatDirection,
-    range: &WorkingRange,
-) -> Result<f32, Sensor> {
+) -> Result<HxForecast, Sensor> {
     let hxif = temps.get_sensor_temp(&Sensor::HXIF).ok_or(Sensor::HXIF)?;
     let hxir = temps.get_sensor_temp(&Sensor::HXIR).ok_or(Sensor::HXIR)?;
     let hxor = temps.get_sensor_temp(&Sensor::HXOR).ok_or(Sensor::HXOR)?;
-    let hprt = temps.get_sensor_temp(&Sensor::HPRT).ok_or(Sensor::HPRT)?;
 
     let hxia = (hxif + hxir) / 2.0;
-    
+
     let adjusted_difference = (hxia - hxor) - config.forecast_diff_offset;
     let expected_drop = adjusted_difference * config.forecast_diff_proportion;
-    let expected_drop = expected_drop.clamp(0.0, 25.0);
+    let expected_drop = expected_drop.clamp(0.0, config.forecast_max_drop);
+
+    Ok(HxForecast { hxia, hxor: *hxor, expected_drop })
+}
+
+fn forecast_hx_pct(
+    temps: &impl PossibleTemperatureContainer,
+    config: &HeatPumpCirculationConfig,
+    heat_direction: &CurrentHeatDirection,
+    range: &WorkingRange,
+) -> Result<f32, Sensor> {
+    let HxForecast { hxia, hxor, expected_drop } = forecast_hx_drop(temps, config)?;
+    let hprt = temps.get_sensor_temp(&Sensor::HPRT).ok_or(Sensor::HPRT)?;
+
     let hxia_forecast_raw = hxia - expected_drop;
 
     let hxia_forecast = merge_hprt_into_fhxia(hxia_forecast_raw, *hprt);
@@ -395,13 +574,11 @@ fn forecast_hx_pct(
 
     let hx_pct = (hxia_forecast - range.get_min()) / range_width;
 
-    let required_pct = match heat_direction {
-        CurrentHeatDirection::None => Some(config.forecast_start_above_percent),
-        _ => None,
-    };
+    let required_pct = heat_direction.start_above_percent(config);
 
     debug!(
-        "HXIA: {hxia:.2}, HXOR: {hxor:.2} => HXIA forecast: {hxia_forecast_raw:.2}/{hxia_forecast:.2} ({})",
+        "HXIA: {}, HXOR: {} => HXIA forecast: {}/{} ({})",
+        fmt_temp(hxia), fmt_temp(hxor), fmt_temp(hxia_forecast_raw), fmt_temp(hxia_forecast),
         format_pct(hx_pct, required_pct),
     );
 
@@ -420,20 +597,18 @@ fn forecast_tk_pct(
 
     let adjusted_difference = (hxia - hxor) - config.forecast_diff_offset;
     let expected_drop = adjusted_difference * config.forecast_diff_proportion;
-    let expected_drop = expected_drop.clamp(0.0, 25.0);
+    let expected_drop = expected_drop.clamp(0.0, config.forecast_max_drop);
     let hxia_forecast = (hxia - expected_drop).clamp(0.0, 100.0);
 
     let range_width = range.get_max() - range.get_min();
 
     let tk_pct = (hxia_forecast - range.get_min()) / range_width;
 
-    let required_pct = match heat_direction {
-        CurrentHeatDirection::None => Some(config.forecast_start_above_percent),
-        _ => None,
-    };
+    let required_pct = heat_direction.start_above_percent(config);
 
     debug!(
-        "TKBT: {tkbt:.2}, HXOR: {hxor:.2} => HXIA forecast: {hxia_forecast:.2} ({})",
+        "TKBT: {}, HXOR: {} => HXIA forecast: {} ({})",
+        fmt_temp(*tkbt), fmt_temp(*hxor), fmt_temp(hxia_forecast),
         format_pct(tk_pct, required_pct),
     );
 
@@ -465,6 +640,7 @@ fn merge_hprt_into_fhxia(fhxia: f32, hprt: f32) -> f32 {
 #[cfg(test)]
 mod test {
     use crate::brain::python_like::config::PythonBrainConfig;
+    use crate::brain::python_like::config::heat_pump_circulation::StartForecastPolicy;
     
     use super::*;
     use std::{collections::HashMap, ops::Range};
@@ -624,6 +800,38 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_start_forecast_policy() -> Result<(), Sensor> {
+        let range = WorkingRange::from_temp_only(WorkingTemperatureRange::from_min_max(30.0, 40.0));
+        let temps = |hx: f32, tkbt: f32| HashMap::from([
+            (Sensor::HXIF, hx), (Sensor::HXIR, hx), (Sensor::HXOF, hx), (Sensor::HXOR, hx),
+            (Sensor::TKBT, tkbt), (Sensor::TKFL, 20.0), (Sensor::HPFL, 30.0), (Sensor::HPRT, 50.0),
+        ]);
+        let hx_warm = temps(39.5, 20.0);
+        let tk_warm = temps(25.0, 60.0);
+        let both_warm = temps(39.5, 60.0);
+
+        let should_cool = |temps: &HashMap<Sensor, f32>, policy: StartForecastPolicy| -> Result<bool, Sensor> {
+            let config = HeatPumpCirculationConfig { start_forecast: policy, ..Default::default() };
+            let action = find_working_temp_action(temps, &range, &config, CurrentHeatDirection::None, None, None)?;
+            Ok(matches!(action, WorkingTempAction::Cool { .. }))
+        };
+
+        assert!(should_cool(&hx_warm, StartForecastPolicy::Either)?);
+        assert!(should_cool(&tk_warm, StartForecastPolicy::Either)?);
+
+        assert!(!should_cool(&hx_warm, StartForecastPolicy::Both)?);
+        assert!(!should_cool(&tk_warm, StartForecastPolicy::Both)?);
+        assert!(should_cool(&both_warm, StartForecastPolicy::Both)?);
+
+        assert!(should_cool(&hx_warm, StartForecastPolicy::HxOnly)?);
+        assert!(!should_cool(&tk_warm, StartForecastPolicy::HxOnly)?);
+
+        assert!(!should_cool(&hx_warm, StartForecastPolicy::TkOnly)?);
+        assert!(should_cool(&tk_warm, StartForecastPolicy::TkOnly)?);
+        Ok(())
+    }
+
     #[test]
     fn test_cool_using_idle_when_reach_top() -> Result<(), Sensor> {
         let range = WorkingRange::from_temp_only(WorkingTemperatureRange::from_min_max(30.0, 40.0));
@@ -871,4 +1079,400 @@ mod test {
 
         Ok(())
     }
+
+    fn bias_config(bias: f32) -> HeatPumpCirculationConfig {
+        HeatPumpCirculationConfig {
+            bias,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_apply_bias() {
+        let range = WorkingRange::from_temp_only(WorkingTemperatureRange::from_min_max(30.0, 40.0));
+
+        let comfort = apply_bias(&range, 1.0);
+        assert_eq!(comfort.get_min(), 29.75);
+        assert_eq!(comfort.get_max(), 42.25);
+
+        let efficiency = apply_bias(&range, -1.0);
+        assert_eq!(efficiency.get_min(), 30.25);
+        assert_eq!(efficiency.get_max(), 37.75);
+
+        assert_eq!(bias_config(5.0).get_forecast_start_above_percent(), 0.2);
+        assert_eq!(bias_config(-5.0).get_forecast_start_above_percent(), 0.0);
+    }
+
+    #[test]
+    fn test_comfort_bias_heats_sooner() -> Result<(), Sensor> {
+        let range = WorkingRange::from_temp_only(WorkingTemperatureRange::from_min_max(30.0, 40.0));
+        let mut temps = HashMap::new();
+
+        temps.insert(Sensor::HXIF, 31.5);
+        temps.insert(Sensor::HXIR, 31.5);
+        temps.insert(Sensor::HXOF, 31.5);
+        temps.insert(Sensor::HXOR, 31.5);
+        temps.insert(Sensor::TKBT, 20.0);
+        temps.insert(Sensor::HPRT, 50.0);
+
+        let neutral = find_working_temp_action(&temps, &range, &bias_config(0.0), CurrentHeatDirection::None, None, None)?;
+        assert_eq!(WorkingTempAction::Cool { circulate: false }, neutral);
+
+        let comfort = find_working_temp_action(&temps, &range, &bias_config(1.0), CurrentHeatDirection::None, None, None)?;
+        assert_eq!(WorkingTempAction::Heat { mixed_state: MixedState::NotMixed }, comfort);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cold_start_threshold() -> Result<(), Sensor> {
+        let range = WorkingRange::from_temp_only(WorkingTemperatureRange::from_min_max(30.0, 40.0));
+        let mut temps = HashMap::new();
+
+        // 15% of the way up the working range.
+        temps.insert(Sensor::HXIF, 31.5);
+        temps.insert(Sensor::HXIR, 31.5);
+        temps.insert(Sensor::HXOF, 31.5);
+        temps.insert(Sensor::HXOR, 31.5);
+        temps.insert(Sensor::TKBT, 20.0);
+        temps.insert(Sensor::HPRT, 50.0);
+
+        let config = HeatPumpCirculationConfig {
+            forecast_start_above_percent: 0.1,
+            cold_start_above_percent: Some(0.2),
+            ..Default::default()
+        };
+
+        let after_idle = find_working_temp_action(&temps, &range, &config, CurrentHeatDirection::None, None, None)?;
+        assert_eq!(WorkingTempAction::Cool { circulate: false }, after_idle);
+
+        let cold_start = find_working_temp_action(&temps, &range, &config, CurrentHeatDirection::ColdStart, None, None)?;
+        assert_eq!(WorkingTempAction::Heat { mixed_state: MixedState::NotMixed }, cold_start);
+
+        let unset = HeatPumpCirculationConfig { cold_start_above_percent: None, ..config };
+        let cold_start = find_working_temp_action(&temps, &range, &unset, CurrentHeatDirection::ColdStart, None, None)?;
+        assert_eq!(WorkingTempAction::Cool { circulate: false }, cold_start, "Should fall back to forecast_start_above_percent");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_efficiency_bias_circulates_sooner() -> Result<(), Sensor> {
+        let range = WorkingRange::from_temp_only(WorkingTemperatureRange::from_min_max(30.0, 40.0));
+        let mut temps = HashMap::new();
+
+        temps.insert(Sensor::HXIF, 39.0);
+        temps.insert(Sensor::HXIR, 39.0);
+        temps.insert(Sensor::HXOF, 39.0);
+        temps.insert(Sensor::HXOR, 39.0);
+        temps.insert(Sensor::TKBT, 20.0);
+        temps.insert(Sensor::HPRT, 50.0);
+
+        let neutral = find_working_temp_action(&temps, &range, &bias_config(0.0), CurrentHeatDirection::Climbing, None, None)?;
+        assert_eq!(WorkingTempAction::Heat { mixed_state: MixedState::NotMixed }, neutral);
+
+        let efficiency = find_working_temp_action(&temps, &range, &bias_config(-1.0), CurrentHeatDirection::Climbing, None, None)?;
+        assert_eq!(WorkingTempAction::Cool { circulate: false }, efficiency);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_forecast_max_drop() -> Result<(), Sensor> {
+        let range = WorkingRange::from_temp_only(WorkingTemperatureRange::from_min_max(30.0, 50.0));
+        let mut temps = HashMap::new();
+
+        // HXIA of 60 and a huge difference to HXOR, so the expected drop is clamped.
+        temps.insert(Sensor::HXIF, 60.0);
+        temps.insert(Sensor::HXIR, 60.0);
+        temps.insert(Sensor::HXOR, 20.0);
+        temps.insert(Sensor::TKBT, 63.0);
+        temps.insert(Sensor::HPRT, 30.0);
+
+        let config_with_max_drop = |forecast_max_drop: f32| HeatPumpCirculationConfig {
+            forecast_diff_proportion: 1.0,
+            forecast_max_drop,
+            ..Default::default()
+        };
+
+        let direction = CurrentHeatDirection::Climbing;
+        let default_config = config_with_max_drop(HeatPumpCirculationConfig::default().forecast_max_drop);
+        // 60 - 25 = 35
+        assert_eq!(forecast_hx_pct(&temps, &default_config, &direction, &range)?, 0.25);
+        assert_eq!(forecast_tk_pct(&temps, &default_config, &direction, &range)?, 0.25);
+
+        // 60 - 10 = 50
+        let config = config_with_max_drop(10.0);
+        assert_eq!(forecast_hx_pct(&temps, &config, &direction, &range)?, 1.0);
+        assert_eq!(forecast_tk_pct(&temps, &config, &direction, &range)?, 1.0);
+
+        // Not clamped, 60 - (40 - 5) = 25
+        let config = config_with_max_drop(40.0);
+        assert_eq!(forecast_hx_pct(&temps, &config, &direction, &range)?, -0.25);
+        assert_eq!(forecast_tk_pct(&temps, &config, &direction, &range)?, -0.25);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_working_range_table() {
+        let table = working_range_table(&WorkingTempModelConfig::default(), 0.0, 2.0, 0.5).unwrap();
+        let expected = "difference\tmin\tmax
+0.00\t29.99\t37.55
+0.50\t35.30\t41.87
+1.00\t40.61\t45.69
+1.50\t44.18\t48.01
+2.00\t45.99\t49.12
+";
+        assert_eq!(table, expected);
+
+        // Shouldn't miss the end because of rounding.
+        let table = working_range_table(&WorkingTempModelConfig::default(), 0.0, 5.0, 0.1).unwrap();
+        assert_eq!(table.lines().count(), 52);
+        assert!(table.ends_with(&format!("5.00\t{:.2}\t{:.2}\n",
+            WorkingTempModelConfig::default().min.get_temp_from_room_diff(5.0),
+            WorkingTempModelConfig::default().max.get_temp_from_room_diff(5.0))));
+
+        assert!(working_range_table(&WorkingTempModelConfig::default(), 0.0, 5.0, 0.0).is_err());
+        assert!(working_range_table(&WorkingTempModelConfig::default(), 5.0, 0.0, 0.1).is_err());
+    }
+
+    #[test]
+    fn test_min_band_width() {
+        let config: WorkingTempModelConfig = toml::from_str(r#"
+min_band_width = 4.0
+[min]
+points = [[0.0, 30.0], [2.0, 46.0]]
+[max]
+points = [[0.0, 40.0], [2.0, 48.0]]
+"#).expect("Invalid config");
+
+        let (range, _) = get_working_temperature_from_max_difference(0.0, &config);
+        assert_eq!((range.min, range.max), (30.0, 40.0), "Wide enough already");
+
+        let (range, _) = get_working_temperature_from_max_difference(1.5, &config);
+        assert_eq!((range.min, range.max), (42.0, 46.0), "Exactly wide enough");
+
+        let (range, _) = get_working_temperature_from_max_difference(2.0, &config);
+        assert_eq!((range.min, range.max), (45.0, 49.0), "46-48 widened either side");
+
+        let model = WorkingTempModelConfig { min_band_width: 0.0, ..config };
+        let (range, _) = get_working_temperature_from_max_difference(2.0, &model);
+        assert_eq!((range.min, range.max), (46.0, 48.0), "Not widened without a minimum");
+    }
+
+    fn room_json(id: usize, name: &str, origin: &str, temp: i32, set_point: i32) -> String {
+        format!(r#"{{
+            "id": {id},
+            "Name": "{name}",
+            "Mode": "Auto",
+            "SetpointOrigin": "{origin}",
+            "CalculatedTemperature": {temp},
+            "CurrentSetPoint": {set_point},
+            "ScheduledSetPoint": {set_point}
+        }}"#)
+    }
+
+    fn away_and_normal_rooms() -> Vec<WiserRoomData> {
+        let json = format!(
+            "[{}, {}, {}]",
+            room_json(1, "Away Room", "FromAwayMode", 150, 210),
+            room_json(2, "Normal Room", "FromSchedule", 180, 190),
+            room_json(3, "Off Room", "FromSchedule", 100, -200),
+        );
+        serde_json::from_str(&json).expect("Invalid room json")
+    }
+
+    #[test]
+    fn test_away_room_ignored() {
+        let config = WorkingTempModelConfig {
+            ignore_away_rooms: true,
+            ..Default::default()
+        };
+
+        let range = get_working_temperature(&away_and_normal_rooms(), &config, UnnamedRoomPolicy::UseId, None);
+        let room = range.get_room().expect("Should have a room");
+        assert_eq!(room.name, "Normal Room");
+        assert_eq!(room.get_difference(), 1.0);
+    }
+
+    #[test]
+    fn test_away_room_used_when_not_ignored() {
+        let config = WorkingTempModelConfig::default();
+
+        let range = get_working_temperature(&away_and_normal_rooms(), &config, UnnamedRoomPolicy::UseId, None);
+        let room = range.get_room().expect("Should have a room");
+        assert_eq!(room.name, "Away Room");
+        assert_eq!(room.get_difference(), 6.0);
+    }
+
+    #[test]
+    fn test_ignored_room() {
+        let config = WorkingTempModelConfig {
+            ignore_rooms: vec!["Away Room".to_owned()],
+            ..Default::default()
+        };
+
+        let ignored = get_working_temperature(&away_and_normal_rooms(), &config, UnnamedRoomPolicy::UseId, None);
+        let room = ignored.get_room().expect("Should have a room");
+        assert_eq!(room.name, "Normal Room");
+        assert_eq!(room.get_difference(), 1.0);
+
+        let used = get_working_temperature(&away_and_normal_rooms(), &WorkingTempModelConfig::default(), UnnamedRoomPolicy::UseId, None);
+        assert!(
+            ignored.get_max() < used.get_max(),
+            "The ignored room's bigger difference shouldn't raise the range: {} vs {}", ignored.get_max(), used.get_max()
+        );
+    }
+
+    fn outdoor_compensated_config() -> WorkingTempModelConfig {
+        toml::from_str(r#"
+[min]
+points = [[0.0, 30.0], [2.0, 40.0]]
+[max]
+points = [[0.0, 40.0], [2.0, 50.0]]
+[outdoor_compensation]
+points = [[-5.0, 6.0], [5.0, 2.0], [15.0, 0.0]]
+max_temp = 55.0
+"#).expect("Invalid config")
+    }
+
+    #[test]
+    fn test_outdoor_compensation() {
+        let config = outdoor_compensated_config();
+        let rooms = away_and_normal_rooms()[1..].to_vec();
+
+        // Normal Room is 1.0 below its set point.
+        let range = get_working_temperature(&rooms, &config, UnnamedRoomPolicy::UseId, None);
+        assert_eq!((range.get_min(), range.get_max()), (35.0, 45.0), "No change without an outdoor temperature");
+
+        let range = get_working_temperature(&rooms, &config, UnnamedRoomPolicy::UseId, Some(20.0));
+        assert_eq!((range.get_min(), range.get_max()), (35.0, 45.0), "No change when it is warm");
+
+        let range = get_working_temperature(&rooms, &config, UnnamedRoomPolicy::UseId, Some(10.0));
+        assert_eq!((range.get_min(), range.get_max()), (35.0, 46.0), "Mild");
+
+        let range = get_working_temperature(&rooms, &config, UnnamedRoomPolicy::UseId, Some(-5.0));
+        assert_eq!((range.get_min(), range.get_max()), (35.0, 51.0), "Cold");
+
+        let range = get_working_temperature(&away_and_normal_rooms(), &config, UnnamedRoomPolicy::UseId, Some(-5.0));
+        assert_eq!((range.get_min(), range.get_max()), (40.0, 55.0), "Capped at max_temp");
+    }
+
+    fn good_and_bad_rooms() -> Vec<WiserRoomData> {
+        vec![
+            WiserRoomData::new(1, None, None, None, "FromSchedule".into(), 180, 190, Some("Good Room".into())),
+            WiserRoomData::new(2, None, None, None, "FromSchedule".into(), -32768, 210, Some("Flat Battery".into())),
+            WiserRoomData::new(3, None, None, None, "FromSchedule".into(), -80, 210, Some("Freezing Room".into())),
+        ]
+    }
+
+    #[test]
+    fn test_keep_valid_rooms() {
+        let rooms = keep_valid_rooms(good_and_bad_rooms(), -10.0).expect("Should have some good rooms");
+        assert_eq!(rooms.iter().map(|room| room.get_id()).collect::<Vec<_>>(), vec![1, 3]);
+
+        let rooms = keep_valid_rooms(good_and_bad_rooms(), -5.0).expect("Should have some good rooms");
+        assert_eq!(rooms.iter().map(|room| room.get_id()).collect::<Vec<_>>(), vec![1]);
+
+        let all_bad = good_and_bad_rooms()[1..].to_vec();
+        assert!(keep_valid_rooms(all_bad, -5.0).is_none());
+    }
+
+    #[test]
+    fn test_partial_room_data() {
+        let mut fallback = FallbackWorkingRange::new(WorkingTemperatureRange::from_min_max(42.0, 45.0));
+        let mut config = WorkingTempModelConfig {
+            min_valid_room_temp: -5.0,
+            ..Default::default()
+        };
+
+        let range = get_working_temperature_range_from_wiser_data(&mut fallback, Ok(good_and_bad_rooms()), &config, UnnamedRoomPolicy::UseId, None);
+        let room = range.get_room().expect("Should use the good room");
+        assert_eq!(room.name, "Good Room");
+        assert_eq!(room.get_difference(), 1.0);
+
+        // With the default, the freezing room is a real reading, and is the furthest from its set point.
+        config.min_valid_room_temp = -10.0;
+        let range = get_working_temperature_range_from_wiser_data(&mut fallback, Ok(good_and_bad_rooms()), &config, UnnamedRoomPolicy::UseId, None);
+        assert_eq!(range.get_room().expect("Should have a room").name, "Freezing Room");
+
+        let all_bad = good_and_bad_rooms()[1..2].to_vec();
+        let range = get_working_temperature_range_from_wiser_data(&mut fallback, Ok(all_bad), &config, UnnamedRoomPolicy::UseId, None);
+        assert!(range.get_room().is_none(), "Should fall back when no rooms are good");
+        assert_eq!(range.get_temperature_range(), fallback.get_fallback());
+    }
+
+    fn unnamed_and_named_rooms() -> Vec<WiserRoomData> {
+        vec![
+            WiserRoomData::new(1, None, None, None, "FromSchedule".into(), 180, 190, Some("Named Room".into())),
+            WiserRoomData::new(7, None, None, None, "FromSchedule".into(), 150, 200, None),
+        ]
+    }
+
+    #[test]
+    fn test_unnamed_room_skipped() {
+        let config = WorkingTempModelConfig::default();
+
+        let range = get_working_temperature(&unnamed_and_named_rooms(), &config, UnnamedRoomPolicy::Skip, None);
+        let room = range.get_room().expect("Should have a room");
+        assert_eq!(room.name, "Named Room");
+        assert_eq!(room.get_difference(), 1.0);
+    }
+
+    #[test]
+    fn test_unnamed_room_uses_id() {
+        let config = WorkingTempModelConfig::default();
+
+        let range = get_working_temperature(&unnamed_and_named_rooms(), &config, UnnamedRoomPolicy::UseId, None);
+        let room = range.get_room().expect("Should have a room");
+        assert_eq!(room.name, "7");
+        assert_eq!(room.get_difference(), 5.0);
+    }
+
+    #[test]
+    fn test_tank_warm_enough_to_drain() -> Result<(), Sensor> {
+        let range = WorkingRange::from_temp_only(WorkingTemperatureRange::from_min_max(30.0, 40.0));
+        let config = HeatPumpCirculationConfig {
+            drain_tank_min_margin: 2.0,
+            ..Default::default()
+        };
+
+        let cold = HashMap::from([(Sensor::TKBT, 31.0)]);
+        assert!(!tank_warm_enough_to_drain(&cold, &range, &config)?);
+
+        let warm = HashMap::from([(Sensor::TKBT, 32.5)]);
+        assert!(tank_warm_enough_to_drain(&warm, &range, &config)?);
+
+        let missing = HashMap::new();
+        assert_eq!(tank_warm_enough_to_drain(&missing, &range, &config), Err(Sensor::TKBT));
+        Ok(())
+    }
+
+    #[test]
+    fn test_circulation_cost() -> Result<(), Sensor> {
+        let range = WorkingRange::from_wiser(
+            WorkingTemperatureRange::from_min_max(30.0, 40.0),
+            Room::of("Lounge".into(), 2.0, 2.0),
+        );
+        let config: HeatPumpCirculationConfig = toml::from_str(r#"
+            circulation_cost = { remaining_heat_weight = 1.0, room_demand_weight = 0.5, threshold = 3.0 }
+        "#).expect("Invalid config string");
+
+        // Lots of heat left in the tank: 8 remaining + 1 demand.
+        let high = HashMap::from([(Sensor::TKBT, 38.0)]);
+        assert_eq!(circulation_score(38.0, &range, &config), 9.0);
+        assert!(tank_warm_enough_to_drain(&high, &range, &config)?);
+
+        // Barely any heat left: 1 remaining + 1 demand.
+        let low = HashMap::from([(Sensor::TKBT, 31.0)]);
+        assert_eq!(circulation_score(31.0, &range, &config), 2.0);
+        assert!(!tank_warm_enough_to_drain(&low, &range, &config)?);
+
+        // The defaults only care whether there is any heat left, whatever the demand.
+        let default_config = HeatPumpCirculationConfig::default();
+        assert!(tank_warm_enough_to_drain(&low, &range, &default_config)?);
+        assert!(!tank_warm_enough_to_drain(&HashMap::from([(Sensor::TKBT, 29.0)]), &range, &default_config)?);
+        Ok(())
+    }
 }
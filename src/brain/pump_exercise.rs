@@ -0,0 +1,284 @@
+use crate::brain::python_like::config::pump_exercise::PumpExerciseConfig;
+use crate::brain::python_like::control::heating_control::{HeatPumpMode, HeatingControl};
+use crate::brain::BrainFailure;
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// When the pumps and valves last moved, as kept in the state file.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct LastMoved {
+    /// The valves (and the extra heating pump), which move whenever the heat pump mode isn't Off.
+    valves: Option<DateTime<Utc>>,
+    circulation_pump: Option<DateTime<Utc>>,
+}
+
+impl LastMoved {
+    fn load(path: &Path) -> Self {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                warn!("Couldn't read pump exercise state from {:?}, assuming everything moved just now: {}", path, e);
+                return Self::default();
+            }
+        };
+        serde_json::from_str(&contents).unwrap_or_else(|e| {
+            error!("Invalid pump exercise state in {:?}, assuming everything moved just now: {}", path, e);
+            Self::default()
+        })
+    }
+
+    fn save(&self, path: &Path) {
+        let result = serde_json::to_string(self)
+            .map_err(|e| e.to_string())
+            .and_then(|json| fs::write(path, json).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            error!("Failed to save pump exercise state to {:?}: {}", path, e);
+        }
+    }
+
+    /// The time the least recently moved of the pumps and valves last moved.
+    fn oldest(&self) -> Option<DateTime<Utc>> {
+        self.valves.into_iter().chain(self.circulation_pump).min()
+    }
+}
+
+/// Keeps track of when the pumps and valves last moved, exercising them if they have
+/// been idle for too long.
+#[derive(Debug, Default)]
+pub struct PumpExercise {
+    state_file: Option<PathBuf>,
+    last_moved: LastMoved,
+    valves_moving: bool,
+    circulation_pump_moving: bool,
+    exercising_since: Option<DateTime<Utc>>,
+}
+
+impl PumpExercise {
+    pub fn load(state_file: Option<&Path>) -> Self {
+        Self {
+            state_file: state_file.map(Path::to_path_buf),
+            last_moved: state_file.map(LastMoved::load).unwrap_or_default(),
+            ..Self::default()
+        }
+    }
+
+    /// Note what is moving now, then exercise whatever has been idle for too long if we are idle
+    /// (in Off mode, and the wiser isn't calling for heat) and within the window, stopping again
+    /// after the configured time.
+    pub fn update(
+        &mut self,
+        config: &PumpExerciseConfig,
+        now: DateTime<Utc>,
+        off_mode: bool,
+        wiser_heating: bool,
+        heating_control: &mut dyn HeatingControl,
+    ) -> Result<(), BrainFailure> {
+        let idle = off_mode && !wiser_heating;
+        if let Some(started) = self.exercising_since {
+            if !off_mode {
+                // The new mode has already set things up how it wants them.
+                info!("No longer in Off mode, stopped exercising the pumps and valves");
+                self.exercising_since = None;
+            } else if !idle || (now - started).to_std().unwrap_or_default() >= config.run_secs {
+                info!("Finished exercising the pumps and valves");
+                heating_control.try_set_heat_pump(HeatPumpMode::Off)?;
+                heating_control.try_set_heat_circulation_pump(false)?;
+                self.exercising_since = None;
+                self.last_moved = LastMoved { valves: Some(now), circulation_pump: Some(now) };
+                self.valves_moving = false;
+                self.circulation_pump_moving = false;
+                self.save();
+                return Ok(());
+            } else {
+                return Ok(());
+            }
+        }
+
+        let valves_moving = heating_control.try_get_heat_pump()? != HeatPumpMode::Off;
+        let circulation_pump_moving = heating_control.try_get_heat_circulation_pump()?;
+        self.record(now, valves_moving, circulation_pump_moving);
+
+        if !idle || !config.window.contains(&now) {
+            return Ok(());
+        }
+        let oldest = self.last_moved.oldest().unwrap_or(now);
+        if now - oldest < config.get_max_idle() {
+            return Ok(());
+        }
+        info!("Pumps and valves haven't all moved since {}, exercising them for {}s", oldest, config.run_secs.as_secs());
+        heating_control.try_set_heat_pump(HeatPumpMode::DrainTank)?;
+        heating_control.try_set_heat_circulation_pump(true)?;
+        self.exercising_since = Some(now);
+        Ok(())
+    }
+
+    /// Stop any exercise in progress, without counting it as having moved anything.
+    /// Whatever was left running must be turned off by the caller.
+    pub fn cancel(&mut self) {
+        if self.exercising_since.take().is_some() {
+            info!("Cancelled exercising the pumps and valves");
+        }
+    }
+
+    /// Update when things last moved, saving it whenever something starts or stops moving.
+    fn record(&mut self, now: DateTime<Utc>, valves_moving: bool, circulation_pump_moving: bool) {
+        let mut changed = valves_moving != self.valves_moving
+            || circulation_pump_moving != self.circulation_pump_moving;
+        for (moving, last_moved) in [
+            (valves_moving, &mut self.last_moved.valves),
+            (circulation_pump_moving, &mut self.last_moved.circulation_pump),
+        ] {
+            if moving || last_moved.is_none() {
+                changed |= last_moved.is_none();
+                *last_moved = Some(now);
+            }
+        }
+        self.valves_moving = valves_moving;
+        self.circulation_pump_moving = circulation_pump_moving;
+        if changed {
+            self.save();
+        }
+    }
+
+    fn save(&self) {
+        if let Some(state_file) = &self.state_file {
+            self.last_moved.save(state_file);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::brain::python_like::control::heating_control::{HeatCirculationPumpControl, HeatPumpControl};
+    use crate::io::dummy::DummyAllOutputs;
+    use crate::time_util::test_utils::utc_datetime;
+    use chrono::Duration;
+
+    fn config() -> PumpExerciseConfig {
+        toml::from_str(r#"
+max_idle_days = 7
+window = { type = "Utc", start = "02:00:00", end = "04:00:00" }
+run_secs = 60
+"#).expect("Should be valid")
+    }
+
+    fn moved_days_ago(now: DateTime<Utc>, days: i64) -> PumpExercise {
+        PumpExercise {
+            last_moved: LastMoved {
+                valves: Some(now - Duration::days(days)),
+                circulation_pump: Some(now - Duration::days(1)),
+            },
+            ..PumpExercise::default()
+        }
+    }
+
+    #[test]
+    fn test_exercises_after_idle() -> Result<(), BrainFailure> {
+        let config = config();
+        let now = utc_datetime(2024, 3, 1, 3, 0, 0);
+        let mut exercise = moved_days_ago(now, 8);
+        let mut control = DummyAllOutputs::default();
+
+        exercise.update(&config, now, true, false, &mut control)?;
+        assert_eq!(control.try_get_heat_pump()?, HeatPumpMode::DrainTank);
+        assert!(control.try_get_heat_circulation_pump()?);
+
+        exercise.update(&config, now + Duration::seconds(30), true, false, &mut control)?;
+        assert_eq!(control.try_get_heat_pump()?, HeatPumpMode::DrainTank, "Still exercising");
+
+        exercise.update(&config, now + Duration::seconds(60), true, false, &mut control)?;
+        assert_eq!(control.try_get_heat_pump()?, HeatPumpMode::Off);
+        assert!(!control.try_get_heat_circulation_pump()?);
+        assert_eq!(exercise.last_moved.oldest(), Some(now + Duration::seconds(60)));
+
+        exercise.update(&config, now + Duration::seconds(90), true, false, &mut control)?;
+        assert_eq!(control.try_get_heat_pump()?, HeatPumpMode::Off, "Shouldn't exercise again straight away");
+        Ok(())
+    }
+
+    #[test]
+    fn test_stops_when_wiser_on() -> Result<(), BrainFailure> {
+        let config = config();
+        let now = utc_datetime(2024, 3, 1, 3, 0, 0);
+        let mut exercise = moved_days_ago(now, 8);
+        let mut control = DummyAllOutputs::default();
+
+        exercise.update(&config, now, true, false, &mut control)?;
+        assert_eq!(control.try_get_heat_pump()?, HeatPumpMode::DrainTank);
+
+        exercise.update(&config, now + Duration::seconds(10), true, true, &mut control)?;
+        assert_eq!(control.try_get_heat_pump()?, HeatPumpMode::Off);
+        assert!(!control.try_get_heat_circulation_pump()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_not_before_idle() -> Result<(), BrainFailure> {
+        let config = config();
+        let now = utc_datetime(2024, 3, 1, 3, 0, 0);
+        let mut control = DummyAllOutputs::default();
+
+        moved_days_ago(now, 6).update(&config, now, true, false, &mut control)?;
+        assert_eq!(control.try_get_heat_pump()?, HeatPumpMode::Off, "Not idle for long enough");
+
+        moved_days_ago(now, 8).update(&config, now, true, true, &mut control)?;
+        assert_eq!(control.try_get_heat_pump()?, HeatPumpMode::Off, "Wiser calling for heat");
+
+        moved_days_ago(now, 8).update(&config, now, false, false, &mut control)?;
+        assert_eq!(control.try_get_heat_pump()?, HeatPumpMode::Off, "Not in Off mode");
+
+        let midday = utc_datetime(2024, 3, 1, 12, 0, 0);
+        moved_days_ago(midday, 8).update(&config, midday, true, false, &mut control)?;
+        assert_eq!(control.try_get_heat_pump()?, HeatPumpMode::Off, "Outside of the window");
+
+        let mut exercise = PumpExercise::default();
+        exercise.update(&config, now, true, false, &mut control)?;
+        assert_eq!(control.try_get_heat_pump()?, HeatPumpMode::Off, "Never seen it move, so start counting from now");
+        Ok(())
+    }
+
+    #[test]
+    fn test_cancel() -> Result<(), BrainFailure> {
+        let config = config();
+        let now = utc_datetime(2024, 3, 1, 3, 0, 0);
+        let mut exercise = moved_days_ago(now, 8);
+        let mut control = DummyAllOutputs::default();
+
+        exercise.update(&config, now, true, false, &mut control)?;
+        assert_eq!(control.try_get_heat_pump()?, HeatPumpMode::DrainTank);
+
+        exercise.cancel();
+        control.try_set_heat_pump(HeatPumpMode::Off)?;
+        control.try_set_heat_circulation_pump(false)?;
+        assert_eq!(exercise.exercising_since, None);
+        assert_eq!(exercise.last_moved.valves, Some(now - Duration::days(8)), "Cancelled, so shouldn't count as moved");
+        Ok(())
+    }
+
+    #[test]
+    fn test_state_file() -> Result<(), BrainFailure> {
+        let path = std::env::temp_dir().join(format!("follow_heating_test_pump_exercise_{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let config = config();
+        let now = utc_datetime(2024, 3, 1, 12, 0, 0);
+        let mut control = DummyAllOutputs::default();
+
+        let mut exercise = PumpExercise::load(Some(&path));
+        control.try_set_heat_circulation_pump(true)?;
+        exercise.update(&config, now - Duration::days(8), true, false, &mut control)?;
+        control.try_set_heat_circulation_pump(false)?;
+        exercise.update(&config, now - Duration::days(5), true, false, &mut control)?;
+
+        let loaded = PumpExercise::load(Some(&path));
+        assert_eq!(loaded.last_moved, LastMoved {
+            valves: Some(now - Duration::days(8)),
+            circulation_pump: Some(now - Duration::days(8)),
+        });
+        fs::remove_file(&path).unwrap();
+        Ok(())
+    }
+}
@@ -0,0 +1,27 @@
+use serde::Deserialize;
+
+/// Lowers the targets while we are away (e.g. on holiday) so only enough heat is used to keep
+/// things from freezing. Turned on and off over the control socket, or held on with `active`.
+#[derive(Clone, Deserialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct AwayConfig {
+    /// Be in away mode regardless of the control socket, until this is removed and reloaded.
+    pub active: bool,
+    /// How much to lower the working range (both min and max) by.
+    pub working_range_reduction: f32,
+    /// How much to lower the hot water overrun temperatures by.
+    pub overrun_reduction: f32,
+    /// How much to lower the temperatures the immersion heater model heats to by.
+    pub immersion_heater_reduction: f32,
+}
+
+impl Default for AwayConfig {
+    fn default() -> Self {
+        Self {
+            active: false,
+            working_range_reduction: 15.0,
+            overrun_reduction: 20.0,
+            immersion_heater_reduction: 20.0,
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use crate::brain::python_like::modes::heating_mode::TargetTemperature;
+use crate::time_util::timeslot::ZonedSlot;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+/// During the slot, keep circulating until the target is reached rather than stopping at
+/// the bottom of the working range. Useful for cooling the tank down ahead of a long hot
+/// water heat up.
+#[derive(Clone, Deserialize, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CirculateCoolTo {
+    pub slot: ZonedSlot,
+    pub target: TargetTemperature,
+}
+
+/// Find the target to cool down to at the given time, if any. The first matching slot wins.
+pub fn find_cool_to_target<'a>(
+    cool_to: &'a [CirculateCoolTo],
+    now: &DateTime<Utc>,
+) -> Option<&'a TargetTemperature> {
+    cool_to
+        .iter()
+        .find(|cool_to| cool_to.slot.contains(now))
+        .map(|cool_to| &cool_to.target)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::io::temperatures::Sensor;
+    use crate::time_util::test_utils::utc_datetime;
+
+    #[derive(Deserialize)]
+    struct Wrapper {
+        circulate_cool_to: Vec<CirculateCoolTo>,
+    }
+
+    #[test]
+    fn test_deserialize_and_find() {
+        let config_str = r#"
+[[circulate_cool_to]]
+slot = { type = "Utc", start = "02:00:00", end = "04:00:00" }
+target = { sensor = "TKBT", temp = 28.0 }
+
+[[circulate_cool_to]]
+slot = { type = "Utc", start = "03:00:00", end = "05:00:00" }
+target = { sensor = "TKTP", temp = 35.0 }
+"#;
+        let config: Wrapper = toml::from_str(config_str).expect("Failed to deserialize");
+        let cool_to = &config.circulate_cool_to;
+
+        assert_eq!(
+            find_cool_to_target(cool_to, &utc_datetime(2024, 1, 1, 3, 30, 0)),
+            Some(&TargetTemperature::new(Sensor::TKBT, 28.0))
+        );
+        assert_eq!(
+            find_cool_to_target(cool_to, &utc_datetime(2024, 1, 1, 4, 30, 0)),
+            Some(&TargetTemperature::new(Sensor::TKTP, 35.0))
+        );
+        assert_eq!(find_cool_to_target(cool_to, &utc_datetime(2024, 1, 1, 12, 0, 0)), None);
+    }
+}
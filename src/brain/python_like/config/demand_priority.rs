@@ -0,0 +1,17 @@
+use serde::Deserialize;
+
+/// What to prioritise when wiser is calling for heat at the same time as a hot water slot
+/// wants the tank heated (i.e. the tank is below the slot's max).
+/// Being below a slot's min always heats the hot water first, whatever the priority.
+#[derive(Clone, Copy, Deserialize, Debug, PartialEq, Eq, Default)]
+pub enum DemandPriority {
+    /// Heat only the rooms, never going into mixed mode. The hot water is heated once
+    /// the heating no longer needs it.
+    HeatingFirst,
+    /// Heat only the hot water until the slot is satisfied, then go back to heating.
+    DhwFirst,
+    /// Heat the rooms, moving into mixed mode to share with the hot water once the
+    /// heating is near the top of the working range.
+    #[default]
+    Balanced,
+}
@@ -22,19 +22,62 @@ pub struct HeatPumpCirculationConfig {
     #[serde_as(as = "DurationSeconds")]
     pub initial_hp_sleep: Duration,
 
+    /// How long (in seconds) to sleep in the first PreCirculate after heating, since there is
+    /// more heat to dissipate then. Falls back to initial_hp_sleep if not set.
+    #[serde_as(as = "Option<DurationSeconds>")]
+    pub first_initial_hp_sleep: Option<Duration>,
+
     /// The temperature required on HXOR to go into pre circulate rather than directly to
     /// circulate.
     pub pre_circulate_temp_required: f32,
 
+    /// The temperature HXOR needs to stay above in order to keep choosing pre circulate over
+    /// circulate when already pre circulating, to stop flipping between the two when HXOR
+    /// is hovering around pre_circulate_temp_required.
+    pub pre_circulate_temp_min: f32,
+
+    /// How fast (in degrees per minute) TKBT can be rising while still going straight into
+    /// circulating. Any faster and we go into pre circulate first, since the tank is still
+    /// heating up and would give less heat to the radiators than it appears.
+    pub circulate_max_tkbt_rise: f32,
+
+    /// Go straight into trying to circulate even when HXOR is hot enough to pre circulate,
+    /// for radiators that get rid of the heat quickly enough that waiting isn't worth it.
+    pub skip_pre_circulate: bool,
+
+    /// How long (in seconds) the bottom of the working range needs to have been reached
+    /// while circulating before ending circulation to turn the heat pump on, so that a brief
+    /// dip in TKBT doesn't turn it straight back on.
+    #[serde_as(as = "DurationSeconds")]
+    pub circulate_finish_confirm: Duration,
+
     /// The amount to subtract from the difference of TKBT and HXOR as the first step.
     pub forecast_diff_offset: f32,
     /// The proportion of the difference between TKBT and HXOR subtract from TKBT to make the
     /// forecasted temperature.
     pub forecast_diff_proportion: f32,
+    /// The most the forecast can expect HXIA to drop by, however big the difference.
+    /// Depends on the heat exchanger.
+    pub forecast_max_drop: f32,
+    /// Log how far off the expected HXIA drop was when deciding to circulate from rest, once
+    /// TryCirculate has finished, to help tune forecast_diff_offset and forecast_diff_proportion.
+    pub forecast_diagnostic: bool,
 
     /// The percentage i.e 0.33 that it needs to be above the bottom when first starting.
     pub forecast_start_above_percent: f32,
 
+    /// The percentage to use instead of forecast_start_above_percent when starting cold, i.e.
+    /// after the heat pump has been off for at least cold_start_after. If not set, there is no
+    /// difference.
+    pub cold_start_above_percent: Option<f32>,
+    /// How long (in seconds) the heat pump needs to have been off for to start cold.
+    #[serde_as(as = "DurationSeconds")]
+    pub cold_start_after: Duration,
+
+    /// Which of the heat exchanger and tank forecasts need to be above the start percentage
+    /// to circulate rather than heat when starting from rest.
+    pub start_forecast: StartForecastPolicy,
+
     /// The steady-state drop between TKBT (Tank Bottom) and HXIA (Heat Exchanger Input Average)
     pub forecast_tkbt_hxia_drop: f32,
 
@@ -42,13 +85,62 @@ pub struct HeatPumpCirculationConfig {
     /// range in order to go into a mixed heating mode (if there is demand for hot water)
     pub mixed_mode: MixedModeConfig,
 
+    /// Whether mixed / boosted heating can be used at all. If disabled, only ever
+    /// heat or circulate.
+    pub mixed_enabled: bool,
+
     /// When to enter boost mode whereby the heat pump is on and the heating is boosted
     /// by taking heat from the hot water tank
     pub boost_mode: BoostModeConfig,
 
+    /// When to nudge some heat into the hot water tank while heating the house,
+    /// to keep it warm without a full hot water only cycle.
+    pub keep_warm: KeepWarmConfig,
+
+    /// How far above the bottom of the working range TKBT needs to be in order to
+    /// bother draining the tank into the heating.
+    pub drain_tank_min_margin: f32,
+
+    /// How to weigh up whether draining the tank into the heating is worth it rather than
+    /// just turning off.
+    pub circulation_cost: CirculationCostConfig,
+
     /// How long to sample draining the tank to see whether it is effective.
     #[serde_as(as = "DurationSeconds")]
     pub sample_tank_time: Duration,
+
+    /// Shifts thresholds towards comfort (positive, up to 1.0: heat sooner, circulate later)
+    /// or efficiency (negative, down to -1.0: circulate sooner, tighter working range).
+    /// 0.0 leaves everything unchanged. With b being the bias clamped to -1.0..=1.0:
+    /// * forecast_start_above_percent becomes forecast_start_above_percent * (1 + b), capped to 0..=1
+    /// * the working range is moved and scaled as described on `working_temp::apply_bias`
+    pub bias: f32,
+}
+
+/// How to combine the heat exchanger (HX) and tank (TK) forecasts when starting from rest.
+#[derive(Clone, Copy, Deserialize, Debug, PartialEq, Default)]
+pub enum StartForecastPolicy {
+    /// Circulate if either is above the start percentage.
+    #[default]
+    Either,
+    /// Circulate only if both are above the start percentage.
+    Both,
+    /// Only go by the heat exchanger.
+    HxOnly,
+    /// Only go by the tank.
+    TkOnly,
+}
+
+impl StartForecastPolicy {
+    /// Whether to circulate, given whether each forecast is above the start percentage.
+    pub fn should_cool(&self, hx_above_req: bool, tk_above_req: bool) -> bool {
+        match self {
+            StartForecastPolicy::Either => hx_above_req || tk_above_req,
+            StartForecastPolicy::Both => hx_above_req && tk_above_req,
+            StartForecastPolicy::HxOnly => hx_above_req,
+            StartForecastPolicy::TkOnly => tk_above_req,
+        }
+    }
 }
 
 #[serde_as]
@@ -90,21 +182,129 @@ pub struct BoostModeConfig {
     pub stop_slot_min_diff:  f32,
 }
 
+/// Scores circulating as the heat remaining in the tank (TKBT minus the working range min
+/// and drain_tank_min_margin) times remaining_heat_weight, plus the difference of the room
+/// the working range is based on times room_demand_weight. Circulates if the score is
+/// above the threshold.
+/// The defaults only consider the remaining heat, i.e. circulate if TKBT is above the
+/// working range min plus drain_tank_min_margin.
+#[derive(Clone, Deserialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CirculationCostConfig {
+    pub remaining_heat_weight: f32,
+    pub room_demand_weight: f32,
+    pub threshold: f32,
+}
+
+impl Default for CirculationCostConfig {
+    fn default() -> Self {
+        Self {
+            remaining_heat_weight: 1.0,
+            room_demand_weight: 0.0,
+            threshold: 0.0,
+        }
+    }
+}
+
+#[serde_as]
+#[derive(Clone, Deserialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeepWarmConfig {
+    /// The TKBT below which to switch to MostlyHotWater while heating, or to stay in mixed mode
+    /// once its hot water slot no longer applies.
+    /// If not set, the hot water is never kept warm this way.
+    pub floor: Option<f32>,
+
+    /// How far above the floor TKBT needs to get before going back to just heating.
+    pub margin: f32,
+
+    /// The longest (in seconds) to keep warm for in one go, before going back to just heating.
+    /// Also how long to wait after that before keeping warm again.
+    #[serde_as(as = "DurationSeconds")]
+    pub max_duration: Duration,
+}
+
+impl Default for KeepWarmConfig {
+    fn default() -> Self {
+        Self {
+            floor: None,
+            margin: 2.0,
+            max_duration: Duration::from_secs(10 * 60),
+        }
+    }
+}
+
+impl HeatPumpCirculationConfig {
+    pub fn diff(&self, new: &Self) -> Vec<String> {
+        diff_fields!(self, new, HeatPumpCirculationConfig {
+            hp_pump_on_time, hp_pump_off_time, initial_hp_sleep, first_initial_hp_sleep,
+            pre_circulate_temp_required, pre_circulate_temp_min, circulate_max_tkbt_rise, skip_pre_circulate,
+            circulate_finish_confirm, forecast_diff_offset, forecast_diff_proportion, forecast_max_drop,
+            forecast_diagnostic, forecast_start_above_percent, cold_start_above_percent, cold_start_after, start_forecast,
+            forecast_tkbt_hxia_drop, mixed_mode, mixed_enabled, boost_mode, keep_warm, drain_tank_min_margin,
+            circulation_cost, sample_tank_time, bias,
+        })
+    }
+
+    /// The comfort vs efficiency bias, clamped to -1.0..=1.0
+    pub fn get_bias(&self) -> f32 {
+        self.bias.clamp(-1.0, 1.0)
+    }
+
+    /// pre_circulate_temp_min, capped so that it is never above pre_circulate_temp_required.
+    pub fn get_pre_circulate_temp_min(&self) -> f32 {
+        self.pre_circulate_temp_min.min(self.pre_circulate_temp_required)
+    }
+
+    /// How long to sleep in PreCirculate, depending on whether it is the first since heating.
+    pub fn get_pre_circulate_sleep(&self, first: bool) -> Duration {
+        match self.first_initial_hp_sleep {
+            Some(sleep) if first => sleep,
+            _ => self.initial_hp_sleep,
+        }
+    }
+
+    /// forecast_start_above_percent adjusted by the bias.
+    pub fn get_forecast_start_above_percent(&self) -> f32 {
+        self.apply_start_bias(self.forecast_start_above_percent)
+    }
+
+    /// cold_start_above_percent (or forecast_start_above_percent if not set) adjusted by the bias.
+    pub fn get_cold_start_above_percent(&self) -> f32 {
+        self.apply_start_bias(self.cold_start_above_percent.unwrap_or(self.forecast_start_above_percent))
+    }
+
+    fn apply_start_bias(&self, percent: f32) -> f32 {
+        (percent * (1.0 + self.get_bias())).clamp(0.0, 1.0)
+    }
+}
+
 impl Default for HeatPumpCirculationConfig {
     fn default() -> Self {
         Self {
             hp_pump_on_time: Duration::from_secs(70),
             hp_pump_off_time: Duration::from_secs(30),
             initial_hp_sleep: Duration::from_secs(5 * 60),
+            first_initial_hp_sleep: None,
             forecast_diff_offset: 5.0,
             forecast_diff_proportion: 0.33,
+            forecast_max_drop: 25.0,
+            forecast_diagnostic: false,
             forecast_start_above_percent: 0.10,
+            cold_start_above_percent: None,
+            cold_start_after: Duration::from_secs(6 * 60 * 60),
+            start_forecast: StartForecastPolicy::default(),
             forecast_tkbt_hxia_drop: 3.0,
             pre_circulate_temp_required: 35.0,
+            pre_circulate_temp_min: 33.0,
+            circulate_max_tkbt_rise: 0.5,
+            skip_pre_circulate: false,
+            circulate_finish_confirm: Duration::ZERO,
             mixed_mode: MixedModeConfig {
                 start_heat_pct: 0.70,
                 stop_heat_pct: 0.30,
             },
+            mixed_enabled: true,
             boost_mode: BoostModeConfig {
                 start_heat_pct:       0.00,
                 stop_heat_pct:        0.10,
@@ -113,7 +313,11 @@ impl Default for HeatPumpCirculationConfig {
                 start_slot_min_diff:  3.5,
                 stop_slot_min_diff:   1.5,
             },
+            keep_warm: KeepWarmConfig::default(),
+            drain_tank_min_margin: 0.0,
+            circulation_cost: CirculationCostConfig::default(),
             sample_tank_time: Duration::from_secs(30),
+            bias: 0.0,
         }
     }
 }
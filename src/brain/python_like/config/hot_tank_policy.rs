@@ -0,0 +1,15 @@
+use serde::Deserialize;
+
+/// What to do when wiser is still calling for heat and the heating has reached the top of the
+/// working range, but TKBT is above the top of the working range.
+#[derive(Clone, Copy, Deserialize, Debug, PartialEq, Eq, Default)]
+pub enum HotTankPolicy {
+    /// Stop heating and decide whether to circulate, via pre circulate if needed.
+    #[default]
+    Circulate,
+    /// Keep the heat pump on but boost the heating from the tank for up to hot_tank_boost_time
+    /// first, keeping the house warm from the stored heat before circulating.
+    /// This is enabled by this policy alone, whether or not mixed heating is, and stops early
+    /// if HXIA goes more than hot_tank_boost_max_margin above the top of the working range.
+    Boost,
+}
@@ -1,12 +1,21 @@
+use crate::brain::modes::heating_mode::PossibleTemperatureContainer;
 use crate::brain::python_like::modes::heating_mode::TargetTemperature;
 use crate::io::temperatures::Sensor;
-use serde::Deserialize;
+use log::warn;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
 use serde_with::serde_as;
 use serde_with::DurationSeconds;
 use std::time::Duration;
 
+/// The lowest safety cut off temperature allowed, any lower and it would barely heat at all.
+const MIN_SAFETY_CUT_OFF_TEMP: f32 = 20.0;
+/// The highest safety cut off temperature allowed, to avoid running the heat pump too hot.
+const MAX_SAFETY_CUT_OFF_TEMP: f32 = 60.0;
+
 #[serde_as]
 #[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
 pub struct MinHeatPumpRuntime {
     /// Duration that the heat pump must stay on for, regardless
     /// of whether overruns / the wiser says it should no longer be on.
@@ -14,10 +23,47 @@ pub struct MinHeatPumpRuntime {
     duration_secs: Duration,
 
     /// A target temperature which if reached will allow the heat pump to turn off
-    /// despite any mini
+    /// despite any minimum runtime. Until then, the tank is heated.
+    #[serde(deserialize_with = "deserialize_safety_cut_off")]
     safety_cut_off: TargetTemperature,
 }
 
+impl MinHeatPumpRuntime {
+    pub fn get_safety_cut_off(&self) -> &TargetTemperature {
+        &self.safety_cut_off
+    }
+
+    /// Whether the heat pump should keep heating the tank even though nothing else wants it to,
+    /// as it hasn't been on for the minimum runtime and hasn't reached the safety cut off.
+    pub fn should_keep_heating(&self, hp_on_for: Duration, temps: &impl PossibleTemperatureContainer) -> bool {
+        if hp_on_for >= self.duration_secs {
+            return false;
+        }
+        match temps.get_sensor_temp(self.safety_cut_off.get_target_sensor()) {
+            Some(temp) => *temp < self.safety_cut_off.get_target_temp(),
+            None => {
+                warn!("Missing {} sensor for the minimum runtime safety cut off, not keeping the heat pump on", self.safety_cut_off.get_target_sensor());
+                false
+            }
+        }
+    }
+}
+
+fn deserialize_safety_cut_off<'de, D>(deserializer: D) -> Result<TargetTemperature, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let target = TargetTemperature::deserialize(deserializer)?;
+    let temp = target.get_target_temp();
+    if !(MIN_SAFETY_CUT_OFF_TEMP..=MAX_SAFETY_CUT_OFF_TEMP).contains(&temp) {
+        return Err(D::Error::custom(format!(
+            "Safety cut off temperature {} must be between {} and {}",
+            temp, MIN_SAFETY_CUT_OFF_TEMP, MAX_SAFETY_CUT_OFF_TEMP
+        )));
+    }
+    Ok(target)
+}
+
 impl Default for MinHeatPumpRuntime {
     fn default() -> Self {
         Self {
@@ -27,3 +73,44 @@ impl Default for MinHeatPumpRuntime {
     }
 }
 
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_safety_cut_off_limits() {
+        let config: MinHeatPumpRuntime = toml::from_str(r#"
+duration_secs = 300
+safety_cut_off = { sensor = "TKBT", temp = 45.0 }
+"#).expect("Should be valid");
+        assert_eq!(config.get_safety_cut_off(), &TargetTemperature::new(Sensor::TKBT, 45.0));
+
+        let result: Result<MinHeatPumpRuntime, _> = toml::from_str(r#"
+duration_secs = 300
+safety_cut_off = { sensor = "HPRT", temp = 75.0 }
+"#);
+        assert!(result.is_err(), "Too hot a cut off should be rejected");
+
+        let result: Result<MinHeatPumpRuntime, _> = toml::from_str(r#"
+duration_secs = 300
+safety_cut_off = { sensor = "HPRT", temp = 5.0 }
+"#);
+        assert!(result.is_err(), "Too cold a cut off should be rejected");
+    }
+
+    #[test]
+    fn test_should_keep_heating() {
+        let config = MinHeatPumpRuntime {
+            duration_secs: Duration::from_secs(300),
+            safety_cut_off: TargetTemperature::new(Sensor::TKBT, 45.0),
+        };
+        let cold = HashMap::from([(Sensor::TKBT, 40.0), (Sensor::HPRT, 55.0)]);
+        let hot = HashMap::from([(Sensor::TKBT, 45.5), (Sensor::HPRT, 30.0)]);
+
+        assert!(config.should_keep_heating(Duration::from_secs(60), &cold));
+        assert!(!config.should_keep_heating(Duration::from_secs(60), &hot), "Reached the safety cut off");
+        assert!(!config.should_keep_heating(Duration::from_secs(300), &cold), "Been on for the minimum runtime");
+        assert!(!config.should_keep_heating(Duration::from_secs(60), &HashMap::new()), "Missing sensor");
+    }
+}
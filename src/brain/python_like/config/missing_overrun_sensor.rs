@@ -0,0 +1,86 @@
+use crate::brain::modes::heating_mode::PossibleTemperatureContainer;
+use crate::io::temperatures::Sensor;
+use crate::log_rate_limit::rate_limited;
+use serde::Deserialize;
+use serde_with::serde_as;
+use serde_with::DurationSeconds;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// What to do during a hot water overrun when the sensor it is heating up is missing.
+/// By default, the overrun is given up on straight away.
+#[serde_as]
+#[derive(Clone, Deserialize, Debug, PartialEq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct MissingOverrunSensorConfig {
+    /// Sensors to use in place of an overrun's sensor when it is missing, for example { TKBT = "TKBTM" }.
+    pub fallbacks: HashMap<Sensor, Sensor>,
+    /// How long (in seconds) to carry on heating while the sensor is missing (with no fallback),
+    /// waiting for it to come back, before giving up.
+    #[serde_as(as = "DurationSeconds")]
+    pub hold_secs: Duration,
+}
+
+impl MissingOverrunSensorConfig {
+    /// Fill in any missing sensors that have a fallback from their fallback.
+    pub fn apply_fallbacks(&self, temps: &mut HashMap<Sensor, f32>) {
+        for (sensor, fallback) in &self.fallbacks {
+            if temps.contains_key(sensor) {
+                continue;
+            }
+            if let Some(temp) = temps.get(fallback).copied() {
+                rate_limited!(warn, "overrun_sensor_fallback", "Missing {}, using {} ({:.1}) instead for the overrun", sensor, fallback, temp);
+                temps.insert(sensor.clone(), temp);
+            }
+        }
+    }
+
+    /// The temperatures to decide on overruns with, using the fallbacks for any missing sensors.
+    pub fn overrun_temps<'a, T: PossibleTemperatureContainer>(&'a self, temps: &'a T) -> OverrunTemps<'a, T> {
+        OverrunTemps { temps, fallbacks: &self.fallbacks }
+    }
+}
+
+pub struct OverrunTemps<'a, T: PossibleTemperatureContainer> {
+    temps: &'a T,
+    fallbacks: &'a HashMap<Sensor, Sensor>,
+}
+
+impl<T: PossibleTemperatureContainer> PossibleTemperatureContainer for OverrunTemps<'_, T> {
+    fn get_sensor_temp(&self, sensor: &Sensor) -> Option<&f32> {
+        self.temps.get_sensor_temp(sensor)
+            .or_else(|| self.temps.get_sensor_temp(self.fallbacks.get(sensor)?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_apply_fallbacks() {
+        let config: MissingOverrunSensorConfig = toml::from_str(r#"
+fallbacks = { TKBT = "TKBTM" }
+hold_secs = 120
+"#).expect("Should be valid");
+        assert_eq!(config.hold_secs, Duration::from_secs(120));
+
+        let mut temps = HashMap::from([(Sensor::from("TKBTM"), 41.0), (Sensor::TKTP, 50.0)]);
+        config.apply_fallbacks(&mut temps);
+        assert_eq!(temps.get(&Sensor::TKBT), Some(&41.0));
+
+        let mut temps = HashMap::from([(Sensor::TKBT, 38.0), (Sensor::from("TKBTM"), 41.0)]);
+        config.apply_fallbacks(&mut temps);
+        assert_eq!(temps.get(&Sensor::TKBT), Some(&38.0), "Not missing");
+
+        let mut temps = HashMap::from([(Sensor::TKTP, 50.0)]);
+        config.apply_fallbacks(&mut temps);
+        assert_eq!(temps.get(&Sensor::TKBT), None, "Fallback missing too");
+
+        let temps = HashMap::from([(Sensor::from("TKBTM"), 41.0), (Sensor::TKTP, 50.0)]);
+        let overrun_temps = config.overrun_temps(&temps);
+        assert_eq!(overrun_temps.get_sensor_temp(&Sensor::TKBT), Some(&41.0));
+        assert_eq!(overrun_temps.get_sensor_temp(&Sensor::TKTP), Some(&50.0));
+        assert_eq!(overrun_temps.get_sensor_temp(&Sensor::HXOR), None);
+    }
+}
@@ -1,24 +1,75 @@
 use crate::brain::boost_active_rooms::config::BoostActiveRoomsConfig;
 use crate::brain::immersion_heater::config::ImmersionHeaterModelConfig;
+use crate::brain::missing_sensors::config::MissingSensorsConfig;
 use crate::brain::modes::working_temp::WorkingTemperatureRange;
 use crate::brain::python_like::config::min_hp_runtime::MinHeatPumpRuntime;
+use crate::brain::python_like::modes::heating_mode::TargetTemperature;
+use crate::io::temperatures::Sensor;
 use crate::python_like::config::overrun_config::OverrunConfig;
+use crate::temp_format::DEFAULT_TEMP_PRECISION;
 use crate::time_util::timeslot::ZonedSlot;
+use away::AwayConfig;
+use chrono::{DateTime, NaiveTime, Utc};
+use circulate_cool_to::CirculateCoolTo;
+use demand_priority::DemandPriority;
 use heat_pump_circulation::HeatPumpCirculationConfig;
+use hot_tank_policy::HotTankPolicy;
+use itertools::Itertools;
 use log::{debug, error, info};
-use serde::Deserialize;
+use missing_overrun_sensor::MissingOverrunSensorConfig;
+use pump_exercise::PumpExerciseConfig;
+use sensor_range::SensorRange;
+use smoothing::SmoothingConfig;
+use crate::brain::modes::heating_mode::HeatingMode;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
 use serde_with::serde_as;
 use serde_with::DurationSeconds;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use std::time::Duration;
+use tracing::level_filters::LevelFilter;
+use unnamed_rooms::UnnamedRoomPolicy;
+use wiser_outage::WiserOutageConfig;
 use working_temp_model::WorkingTempModelConfig;
 
 #[cfg(test)]
 use self::working_temp_model::test::get_working_temp_model_test_data;
 
+/// Compare the given fields of two structs, giving "field: old -> new" for each that differs.
+/// Every field has to be named, either to compare or to skip (to compare separately), so that
+/// newly added fields can't be forgotten.
+macro_rules! diff_fields {
+    ($old:expr, $new:expr, $ty:ident { $($field:ident),* $(,)? } $(skip { $($skip:ident),* $(,)? })?) => {{
+        let $ty { $($field,)* $($($skip: _,)*)? } = $old;
+        let mut changes: Vec<String> = Vec::new();
+        $(
+            if *$field != $new.$field {
+                changes.push(format!("{}: {:?} -> {:?}", stringify!($field), $field, $new.$field));
+            }
+        )*
+        changes
+    }};
+}
+
+/// Prefix each change of a nested config with the name of the field it is in.
+fn nested_diff(field: &str, changes: Vec<String>) -> impl Iterator<Item = String> + '_ {
+    changes.into_iter().map(move |change| format!("{}.{}", field, change))
+}
+
+pub mod away;
+pub mod circulate_cool_to;
+pub mod demand_priority;
 pub mod heat_pump_circulation;
+pub mod hot_tank_policy;
 pub mod min_hp_runtime;
+pub mod missing_overrun_sensor;
 pub mod overrun_config;
+pub mod pump_exercise;
+pub mod sensor_range;
+pub mod smoothing;
+pub mod unnamed_rooms;
+pub mod wiser_outage;
 pub mod working_temp_model;
 
 #[serde_as]
@@ -35,8 +86,36 @@ pub struct PythonBrainConfig {
     /// The minimum HPRT temperature to start circulating through the heating
     pub temp_before_circulate: f32,
 
-    /// TODO: Currently unused
-    min_hp_runtime: MinHeatPumpRuntime,
+    /// The minimum HPRT temperature to start circulating through the heating while turning on.
+    /// If not set, the circulation pump is turned on at the same time as the heat pump.
+    pub turning_on_temp_before_circulate: Option<f32>,
+
+    /// How much HPRT must rise by while turning on (within hp_enable_time). If it doesn't,
+    /// the heat pump probably hasn't started, so turn off rather than carrying on regardless.
+    /// If not set, this isn't checked.
+    pub turning_on_min_hprt_rise: Option<f32>,
+
+    /// How long (in seconds) to stay off after HPRT didn't rise while turning on, rather than
+    /// restarting what is probably a faulty heat pump straight away.
+    #[serde_as(as = "DurationSeconds")]
+    pub turning_on_fault_backoff: Duration,
+
+    /// The minimum HPRT temperature to start circulating through the heating once the
+    /// heat pump is established. Falls back to temp_before_circulate if not set.
+    pub on_temp_before_circulate: Option<f32>,
+
+    /// How long (in seconds) HPRT must stay above on_temp_before_circulate before starting
+    /// the circulation pump, so it isn't started by a brief spike.
+    #[serde_as(as = "DurationSeconds")]
+    pub on_circulate_debounce: Duration,
+
+    /// If set, HPRT must be available and within this range (as well as TKBT) before turning
+    /// the heat pump on, so we don't start it based on incomplete sensor data.
+    pub require_hprt_to_turn_on: Option<SensorRange>,
+
+    /// How long the heat pump must stay on for once turned on, heating the tank up to a safety
+    /// cut off if nothing else wants it. If not set, the heat pump can turn off straight away.
+    min_hp_runtime: Option<MinHeatPumpRuntime>,
 
     /// If we cannot calculate the working range using wiser, we fallback to this,
     /// though this is usually rapidly replaced with the last used (calculated) working temperature range
@@ -44,6 +123,99 @@ pub struct PythonBrainConfig {
 
     pub working_temp_model: WorkingTempModelConfig,
 
+    /// What to do when we can't contact the wiser hub.
+    pub wiser_outage: WiserOutageConfig,
+
+    /// Whether a room with an active override (such as a boost) counts as the wiser calling for heat,
+    /// for setups where boosting a room doesn't turn on the wiser's overall heating.
+    pub boost_counts_as_heating: bool,
+
+    /// What to do with wiser rooms that have no name, both when finding the
+    /// working temperature and when boosting rooms.
+    pub unnamed_rooms: UnnamedRoomPolicy,
+
+    /// Whether to prioritise heating or hot water when both are wanted at the same time.
+    pub demand_priority: DemandPriority,
+
+    /// What to do when the heating is warm enough but the tank is still above the working range.
+    pub hot_tank_policy: HotTankPolicy,
+
+    /// How long (in seconds) to boost the heating from a hot tank for, see HotTankPolicy::Boost.
+    #[serde_as(as = "DurationSeconds")]
+    pub hot_tank_boost_time: Duration,
+
+    /// How far (in degrees) HXIA can go above the top of the working range while boosting the
+    /// heating from a hot tank before the boost is stopped, see HotTankPolicy::Boost.
+    pub hot_tank_boost_max_margin: f32,
+
+    /// When to alert about sensors missing from the readings.
+    missing_sensors: MissingSensorsConfig,
+
+    /// Sensors we can't operate without. If any are missing from the readings,
+    /// everything is turned off until they come back.
+    essential_sensors: Vec<Sensor>,
+
+    /// Smoothing of TKBT before deciding whether to heat or circulate, as it can be noisy
+    /// as the stratification in the tank shifts. If not set, TKBT is used as is.
+    tkbt_smoothing: Option<SmoothingConfig>,
+
+    /// How far (in seconds) the clock can move between loops, beyond how much time really
+    /// passed, before we treat it as the clock having been corrected and re-evaluate the mode.
+    #[serde_as(as = "DurationSeconds")]
+    pub clock_jump_threshold: Duration,
+
+    /// The minimum time (in seconds) between one hot water overrun finishing and another starting,
+    /// so the tank isn't continuously topped up. If not set, overruns can follow straight on.
+    #[serde_as(as = "Option<DurationSeconds>")]
+    min_overrun_gap: Option<Duration>,
+
+    /// How long (in seconds) after circulating to wait before heating the hot water, as circulating
+    /// has just drained the tank's heat into the heating, and heating it straight back up would undo
+    /// that. 0 to not wait.
+    #[serde_as(as = "DurationSeconds")]
+    pub post_circulate_dhw_cooldown: Duration,
+
+    /// What to do when the sensor a hot water overrun is heating up goes missing.
+    pub missing_overrun_sensor: MissingOverrunSensorConfig,
+
+    /// The longest (in seconds) we can heat for continuously (in On or Mixed) before going into
+    /// PreCirculate to re-evaluate, even if still below the top of the working range, to catch
+    /// runaway heating. If not set, there is no limit.
+    #[serde_as(as = "Option<DurationSeconds>")]
+    max_on_session: Option<Duration>,
+
+    /// The time of day (local time) to log a summary of the day's activity at. If not set, no summary is logged.
+    daily_summary_time: Option<NaiveTime>,
+
+    /// If set, exercise the pumps and valves when they haven't moved for a while.
+    pump_exercise: Option<PumpExerciseConfig>,
+
+    /// The sensor that measures the temperature outside, if there is one.
+    outdoor_sensor: Option<Sensor>,
+
+    /// The most verbose level each mode (by name) may log at while updating, for example
+    /// { Off = "info" } to quieten the Off mode. This can only reduce what logging.env allows.
+    #[serde(deserialize_with = "deserialize_mode_log_levels")]
+    mode_log_levels: HashMap<String, LevelFilter>,
+
+    /// Whether each mode (by name) can be interrupted by the wiser turning off before it has
+    /// done the minimum it needs to, for example { TryCirculate = false } to always finish
+    /// the reading. Modes are interruptible unless given here. Only modes with a minimum action
+    /// (currently just TryCirculate) can be given.
+    #[serde(deserialize_with = "deserialize_mode_interruptible")]
+    mode_interruptible: HashMap<String, bool>,
+
+    /// The level to log the intention each mode returns from its update at, so the decision
+    /// made each loop can always be recovered from the logs. "off" to not log it.
+    #[serde(deserialize_with = "deserialize_level_filter")]
+    intention_log_level: LevelFilter,
+
+    /// How many decimal places to show temperatures to in the logs.
+    temp_log_precision: usize,
+
+    /// How much to lower the targets by while in away mode.
+    pub away: AwayConfig,
+
     #[serde(flatten)]
     additive_config: PythonBrainAdditiveConfig,
 }
@@ -60,9 +232,18 @@ pub struct PythonBrainAdditiveConfig {
     boost_active_rooms: BoostActiveRoomsConfig,
     /// Times at which to ignore the wiser heating.
     no_heating: Vec<ZonedSlot>,
+    /// Times at which to circulate down to a target rather than the bottom of the working range.
+    circulate_cool_to: Vec<CirculateCoolTo>,
 }
 
 impl PythonBrainAdditiveConfig {
+    pub fn diff(&self, new: &Self) -> Vec<String> {
+        diff_fields!(self, new, PythonBrainAdditiveConfig {
+            include_config_directories, overrun_during, immersion_heater_model, boost_active_rooms,
+            no_heating, circulate_cool_to,
+        })
+    }
+
     pub fn combine(&mut self, other: Self) {
         self.include_config_directories
             .append(&mut other.include_config_directories.clone());
@@ -71,10 +252,27 @@ impl PythonBrainAdditiveConfig {
             .combine(other.immersion_heater_model);
         self.boost_active_rooms.combine(other.boost_active_rooms);
         self.no_heating.extend(other.no_heating);
+        self.circulate_cool_to.extend(other.circulate_cool_to);
     }
 }
 
 impl PythonBrainConfig {
+    /// Describe each setting that differs in the new config, as "field: old -> new".
+    pub fn diff(&self, new: &Self) -> Vec<String> {
+        let mut changes = diff_fields!(self, new, PythonBrainConfig {
+            hp_enable_time, temp_before_circulate, turning_on_temp_before_circulate, turning_on_min_hprt_rise,
+            turning_on_fault_backoff, on_temp_before_circulate, on_circulate_debounce, require_hprt_to_turn_on, min_hp_runtime,
+            default_working_range, working_temp_model, wiser_outage, boost_counts_as_heating, unnamed_rooms,
+            demand_priority, hot_tank_policy, hot_tank_boost_time, hot_tank_boost_max_margin, missing_sensors, essential_sensors,
+            tkbt_smoothing, clock_jump_threshold, min_overrun_gap, post_circulate_dhw_cooldown,
+            missing_overrun_sensor, max_on_session, daily_summary_time, pump_exercise, outdoor_sensor,
+            mode_log_levels, mode_interruptible, intention_log_level, temp_log_precision, away,
+        } skip { hp_circulation, additive_config });
+        changes.extend(nested_diff("hp_circulation", self.hp_circulation.diff(&new.hp_circulation)));
+        changes.extend(self.additive_config.diff(&new.additive_config));
+        changes
+    }
+
     pub fn get_overrun_during(&self) -> &OverrunConfig {
         &self.additive_config.overrun_during
     }
@@ -91,11 +289,166 @@ impl PythonBrainConfig {
         &self.additive_config.no_heating
     }
 
+    /// The target to circulate down to at the given time, if one is configured.
+    pub fn get_circulate_cool_to(&self, now: &DateTime<Utc>) -> Option<&TargetTemperature> {
+        circulate_cool_to::find_cool_to_target(&self.additive_config.circulate_cool_to, now)
+    }
+
+    pub fn get_min_hp_runtime(&self) -> Option<&MinHeatPumpRuntime> {
+        self.min_hp_runtime.as_ref()
+    }
+
+    pub fn get_missing_sensors(&self) -> &MissingSensorsConfig {
+        &self.missing_sensors
+    }
+
+    pub fn get_essential_sensors(&self) -> &[Sensor] {
+        &self.essential_sensors
+    }
+
+    /// The essential sensors that are missing from the given readings.
+    pub fn find_missing_essential_sensors(&self, temps: &HashMap<Sensor, f32>) -> Vec<&Sensor> {
+        self.essential_sensors.iter()
+            .filter(|sensor| !temps.contains_key(sensor))
+            .collect()
+    }
+
+    pub fn get_tkbt_smoothing(&self) -> Option<&SmoothingConfig> {
+        self.tkbt_smoothing.as_ref()
+    }
+
+    pub fn get_max_on_session(&self) -> Option<&Duration> {
+        self.max_on_session.as_ref()
+    }
+
+    pub fn get_pump_exercise(&self) -> Option<&PumpExerciseConfig> {
+        self.pump_exercise.as_ref()
+    }
+
+    pub fn get_min_overrun_gap(&self) -> Option<&Duration> {
+        self.min_overrun_gap.as_ref()
+    }
+
+    pub fn get_mode_log_level(&self, mode: &str) -> Option<LevelFilter> {
+        self.mode_log_levels.get(mode).copied()
+    }
+
+    pub fn is_mode_interruptible(&self, mode: &str) -> bool {
+        self.mode_interruptible.get(mode).copied().unwrap_or(true)
+    }
+
+    pub fn get_intention_log_level(&self) -> LevelFilter {
+        self.intention_log_level
+    }
+
+    pub fn get_temp_log_precision(&self) -> usize {
+        self.temp_log_precision
+    }
+
+    pub fn get_daily_summary_time(&self) -> Option<NaiveTime> {
+        self.daily_summary_time
+    }
+
+    pub fn get_outdoor_sensor(&self) -> Option<&Sensor> {
+        self.outdoor_sensor.as_ref()
+    }
+
+    /// The outdoor temperature from the given readings, or None if there is no outdoor sensor
+    /// configured or it is missing from the readings.
+    pub fn get_outdoor_temp(&self, temps: &HashMap<Sensor, f32>) -> Option<f32> {
+        self.get_outdoor_sensor()
+            .and_then(|sensor| temps.get(sensor))
+            .copied()
+    }
+
+    /// A copy of this config with the working range, overrun and immersion heater
+    /// targets lowered by the amounts configured for away mode.
+    pub fn with_away_applied(&self) -> Self {
+        let mut config = self.clone();
+        config.default_working_range = self.default_working_range.lowered_by(self.away.working_range_reduction);
+        config.working_temp_model = self.working_temp_model.lowered_by(self.away.working_range_reduction);
+        config.additive_config.overrun_during = self.get_overrun_during().lowered_by(self.away.overrun_reduction);
+        config.additive_config.immersion_heater_model = self.get_immersion_heater_model().lowered_by(self.away.immersion_heater_reduction);
+        config
+    }
+
+    pub fn get_on_temp_before_circulate(&self) -> f32 {
+        self.on_temp_before_circulate
+            .unwrap_or(self.temp_before_circulate)
+    }
+
+    /// Get every sensor that is referenced somewhere in the config.
+    pub fn get_referenced_sensors(&self) -> Vec<&Sensor> {
+        self.get_overrun_during().slots.iter()
+            .map(|bap| &bap.temps.sensor)
+            .chain(self.get_immersion_heater_model().get_sensors())
+            .chain(self.min_hp_runtime.iter().map(|min_runtime| min_runtime.get_safety_cut_off().get_target_sensor()))
+            .chain(self.additive_config.circulate_cool_to.iter().map(|cool_to| cool_to.target.get_target_sensor()))
+            .chain(self.missing_sensors.get_expected())
+            .chain(self.essential_sensors.iter())
+            .chain(self.outdoor_sensor.iter())
+            .unique()
+            .collect()
+    }
+
+    /// Find the sensors referenced in the config that aren't in the available sensors,
+    /// for example because of a typo in the config, in sensor order.
+    pub fn find_unresolved_sensors(&self, available: &HashSet<Sensor>) -> Vec<Sensor> {
+        self.get_referenced_sensors().into_iter()
+            .filter(|sensor| !available.contains(sensor))
+            .cloned()
+            .sorted()
+            .collect()
+    }
+
     pub fn _add_dhw_slot(&mut self, slot: overrun_config::DhwBap) {
         self.additive_config.overrun_during.slots.push(slot);
     }
 }
 
+fn deserialize_mode_interruptible<'de, D>(deserializer: D) -> Result<HashMap<String, bool>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let interruptible = HashMap::<String, bool>::deserialize(deserializer)?;
+    for mode in interruptible.keys() {
+        match HeatingMode::from_name(mode) {
+            None => return Err(D::Error::custom(format!("Unknown mode {:?} in mode_interruptible", mode))),
+            Some(heating_mode) if !heating_mode.has_min_action() => {
+                return Err(D::Error::custom(format!("Mode {:?} in mode_interruptible has no minimum action to finish", mode)));
+            }
+            Some(_) => {}
+        }
+    }
+    Ok(interruptible)
+}
+
+fn deserialize_mode_log_levels<'de, D>(deserializer: D) -> Result<HashMap<String, LevelFilter>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    HashMap::<String, String>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(mode, level)| {
+            if HeatingMode::from_name(&mode).is_none() {
+                return Err(D::Error::custom(format!("Unknown mode {:?} in mode_log_levels", mode)));
+            }
+            let level = level.parse()
+                .map_err(|e| D::Error::custom(format!("Invalid log level {:?} for mode {}: {}", level, mode, e)))?;
+            Ok((mode, level))
+        })
+        .collect()
+}
+
+fn deserialize_level_filter<'de, D>(deserializer: D) -> Result<LevelFilter, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let level = String::deserialize(deserializer)?;
+    level.parse()
+        .map_err(|e| D::Error::custom(format!("Invalid log level {:?}: {}", level, e)))
+}
+
 impl Default for PythonBrainConfig {
     fn default() -> Self {
         PythonBrainConfig {
@@ -103,10 +456,39 @@ impl Default for PythonBrainConfig {
             hp_circulation: HeatPumpCirculationConfig::default(),
             default_working_range: WorkingTemperatureRange::from_min_max(42.0, 45.0),
             working_temp_model: WorkingTempModelConfig::default(),
+            wiser_outage: WiserOutageConfig::default(),
+            boost_counts_as_heating: false,
+            unnamed_rooms: UnnamedRoomPolicy::default(),
+            demand_priority: DemandPriority::default(),
+            hot_tank_policy: HotTankPolicy::default(),
+            hot_tank_boost_time: Duration::from_secs(10 * 60),
+            hot_tank_boost_max_margin: 5.0,
+            missing_sensors: MissingSensorsConfig::default(),
+            essential_sensors: vec![],
+            tkbt_smoothing: None,
+            clock_jump_threshold: Duration::from_secs(15 * 60),
+            min_overrun_gap: None,
+            post_circulate_dhw_cooldown: Duration::ZERO,
+            missing_overrun_sensor: MissingOverrunSensorConfig::default(),
+            max_on_session: None,
+            daily_summary_time: None,
+            pump_exercise: None,
+            outdoor_sensor: None,
+            mode_log_levels: HashMap::new(),
+            mode_interruptible: HashMap::new(),
+            intention_log_level: LevelFilter::DEBUG,
+            temp_log_precision: DEFAULT_TEMP_PRECISION,
+            away: AwayConfig::default(),
             hp_enable_time: Duration::from_secs(70),
             temp_before_circulate: 33.0,
+            turning_on_temp_before_circulate: None,
+            turning_on_min_hprt_rise: None,
+            turning_on_fault_backoff: Duration::from_secs(30 * 60),
+            on_temp_before_circulate: None,
+            on_circulate_debounce: Duration::ZERO,
+            require_hprt_to_turn_on: None,
             additive_config: PythonBrainAdditiveConfig::default(),
-            min_hp_runtime: Default::default(),
+            min_hp_runtime: None,
         }
     }
 }
@@ -129,16 +511,10 @@ impl AsRef<WorkingTempModelConfig> for PythonBrainConfig {
     }
 }
 
-impl AsRef<MinHeatPumpRuntime> for PythonBrainConfig {
-    fn as_ref(&self) -> &MinHeatPumpRuntime {
-        &self.min_hp_runtime
-    }
-}
-
 const PYTHON_BRAIN_CONFIG_FILE: &str = "python_brain.toml";
 
-pub fn try_read_python_brain_config() -> Option<PythonBrainConfig> {
-    try_read_python_brain_config_file(PYTHON_BRAIN_CONFIG_FILE)
+pub fn try_read_python_brain_config(config_dir: &Path) -> Option<PythonBrainConfig> {
+    try_read_python_brain_config_file(config_dir.join(PYTHON_BRAIN_CONFIG_FILE))
 }
 
 const CONFIG_LOG_TARGET: &str = "config";
@@ -256,11 +632,11 @@ pub fn read_additive_config(file: PathBuf) -> Result<PythonBrainAdditiveConfig,
 mod tests {
     use super::*;
     use crate::brain::immersion_heater::config::ImmersionHeaterModelPart;
-    use crate::brain::python_like::config::heat_pump_circulation::{MixedModeConfig, BoostModeConfig};
+    use crate::brain::python_like::config::heat_pump_circulation::{MixedModeConfig, BoostModeConfig, KeepWarmConfig, CirculationCostConfig, StartForecastPolicy};
     use crate::brain::python_like::config::overrun_config::DhwBap;
-    use crate::brain::python_like::config::working_temp_model::WorkingTempCurveConfig;
+    use crate::brain::python_like::config::working_temp_model::{WorkingTempCurve, WorkingTempCurveConfig};
+    use crate::io::temperatures::file::TempsFileData;
     use crate::time_util::test_utils::{local_time_slot, time, utc_time_slot};
-    use crate::Sensor;
 
     #[test]
     fn test_deserialize_config() {
@@ -304,6 +680,100 @@ mod tests {
             toml::from_str(&config_str).expect("Failed to deserialize config");
     }
 
+    #[test]
+    fn test_find_unresolved_sensors() {
+        let config_str = std::fs::read_to_string("test/python_brain/check_sensors/config.toml")
+            .expect("Failed to read config file.");
+        let config: PythonBrainConfig =
+            toml::from_str(&config_str).expect("Failed to deserialize config");
+
+        let temps_str = std::fs::read_to_string("test/python_brain/check_sensors/temps.json")
+            .expect("Failed to read temps file.");
+        let temps: TempsFileData =
+            serde_json::from_str(&temps_str).expect("Failed to deserialize temps");
+        let available: HashSet<Sensor> = temps.get_sensors().cloned().collect();
+
+        assert_eq!(
+            config.find_unresolved_sensors(&available),
+            vec![Sensor::TKEN, Sensor::from("TKBTM")]
+        );
+    }
+
+    #[test]
+    fn test_outdoor_temp() {
+        let config: PythonBrainConfig =
+            toml::from_str("outdoor_sensor = \"outs\"").expect("Failed to deserialize config");
+        let outdoor = Sensor::from("outs");
+        assert_eq!(config.get_outdoor_sensor(), Some(&outdoor));
+        assert!(config.get_referenced_sensors().contains(&&outdoor));
+
+        let temps = HashMap::from([(Sensor::TKBT, 45.0), (outdoor.clone(), 4.5)]);
+        assert_eq!(config.get_outdoor_temp(&temps), Some(4.5));
+
+        let missing = HashMap::from([(Sensor::TKBT, 45.0)]);
+        assert_eq!(config.get_outdoor_temp(&missing), None);
+
+        let unconfigured = PythonBrainConfig::default();
+        assert_eq!(unconfigured.get_outdoor_sensor(), None);
+        assert_eq!(unconfigured.get_outdoor_temp(&temps), None);
+    }
+
+    #[test]
+    fn test_read_from_config_dir() {
+        let config = try_read_python_brain_config(Path::new("test/config_dir")).expect("Should read config from the directory");
+        assert_eq!(config.hp_enable_time, Duration::from_secs(60));
+        assert_eq!(config.default_working_range, WorkingTemperatureRange::from_min_max(40.0, 44.0));
+
+        assert_eq!(try_read_python_brain_config(Path::new("test/missing_dir")), None);
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = PythonBrainConfig::default();
+        assert!(old.diff(&old.clone()).is_empty(), "Nothing changed");
+
+        let new: PythonBrainConfig = toml::from_str("hp_enable_time = 60").unwrap();
+        assert_eq!(old.diff(&new), vec!["hp_enable_time: 70s -> 60s"]);
+
+        let new: PythonBrainConfig = toml::from_str("hp_circulation = { bias = 0.5 }").unwrap();
+        assert_eq!(old.diff(&new), vec!["hp_circulation.bias: 0.0 -> 0.5"]);
+
+        let new: PythonBrainConfig = toml::from_str("include_config_directories = [\"extra\"]").unwrap();
+        assert_eq!(old.diff(&new), vec!["include_config_directories: [] -> [\"extra\"]"]);
+    }
+
+    #[test]
+    fn test_mode_log_levels() {
+        let config: PythonBrainConfig = toml::from_str("mode_log_levels = { Off = \"info\", On = \"trace\" }")
+            .expect("Failed to deserialize config");
+        assert_eq!(config.get_mode_log_level("Off"), Some(LevelFilter::INFO));
+        assert_eq!(config.get_mode_log_level("On"), Some(LevelFilter::TRACE));
+        assert_eq!(config.get_mode_log_level("Circulate"), None);
+
+        let result: Result<PythonBrainConfig, _> = toml::from_str("mode_log_levels = { Of = \"info\" }");
+        assert!(result.is_err(), "Unknown mode should be rejected");
+        let result: Result<PythonBrainConfig, _> = toml::from_str("mode_log_levels = { Off = \"loud\" }");
+        assert!(result.is_err(), "Invalid level should be rejected");
+    }
+
+    #[test]
+    fn test_intention_log_level() {
+        assert_eq!(PythonBrainConfig::default().get_intention_log_level(), LevelFilter::DEBUG);
+        let config: PythonBrainConfig = toml::from_str("intention_log_level = \"off\"")
+            .expect("Failed to deserialize config");
+        assert_eq!(config.get_intention_log_level(), LevelFilter::OFF);
+        let result: Result<PythonBrainConfig, _> = toml::from_str("intention_log_level = \"loud\"");
+        assert!(result.is_err(), "Invalid level should be rejected");
+    }
+
+    #[test]
+    fn test_temp_log_precision() {
+        assert_eq!(PythonBrainConfig::default().get_temp_log_precision(), 2);
+        let config: PythonBrainConfig = toml::from_str("temp_log_precision = 1")
+            .expect("Failed to deserialize config");
+        assert_eq!(config.get_temp_log_precision(), 1);
+    }
+
     #[test]
     fn test_deserialize_included_files() {
         let config =
@@ -315,24 +785,44 @@ mod tests {
                 hp_pump_on_time:  Duration::from_secs(1),
                 hp_pump_off_time: Duration::from_secs(2),
                 initial_hp_sleep: Duration::from_secs(3),
+                first_initial_hp_sleep: None,
                 pre_circulate_temp_required: 4.0,
+                pre_circulate_temp_min: 33.0,
+                circulate_max_tkbt_rise: 0.5,
+                skip_pre_circulate: false,
+                circulate_finish_confirm: Duration::ZERO,
                 forecast_diff_offset: 5.0,
                 forecast_diff_proportion: 6.0,
+                forecast_max_drop: 25.0,
+                forecast_diagnostic: false,
                 forecast_start_above_percent: 7.0,
+                cold_start_above_percent: None,
+                cold_start_after: Duration::from_secs(6 * 60 * 60),
+                start_forecast: StartForecastPolicy::Either,
                 forecast_tkbt_hxia_drop: 8.0,
                 mixed_mode: MixedModeConfig { start_heat_pct: 9.1, stop_heat_pct: 9.2 },
+                mixed_enabled: true,
                 boost_mode: BoostModeConfig {
                     start_heat_pct: 10.1, stop_heat_pct: 10.2,
                     start_tkfl_hpfl_diff: 10.3, stop_tkfl_hpfl_diff: 10.4,
                     start_slot_min_diff: 10.5, stop_slot_min_diff: 10.6,
                 },
+                keep_warm: KeepWarmConfig::default(),
+                drain_tank_min_margin: 0.0,
+                circulation_cost: CirculationCostConfig::default(),
                 sample_tank_time: Duration::from_secs(11),
+                bias: 0.0,
             },
             hp_enable_time: Duration::from_secs(70),
             default_working_range: WorkingTemperatureRange::from_min_max(42.0, 45.0),
             working_temp_model: WorkingTempModelConfig {
-                min: WorkingTempCurveConfig { sharpness: 1.0, turning_point: 2.0, multiplier: 3.0, offset: 4.0 },
-                max: WorkingTempCurveConfig { sharpness: 5.0, turning_point: 6.0, multiplier: 7.0, offset: 8.0 },
+                min: WorkingTempCurve::Sigmoid(WorkingTempCurveConfig { sharpness: 1.0, turning_point: 2.0, multiplier: 3.0, offset: 4.0 }),
+                max: WorkingTempCurve::Sigmoid(WorkingTempCurveConfig { sharpness: 5.0, turning_point: 6.0, multiplier: 7.0, offset: 8.0 }),
+                ignore_away_rooms: false,
+                min_valid_room_temp: -10.0,
+                outdoor_compensation: None,
+                min_band_width: 0.0,
+                ignore_rooms: vec![],
             },
             additive_config: PythonBrainAdditiveConfig {
                 include_config_directories: vec![
@@ -367,6 +857,7 @@ mod tests {
                 ]),
                 boost_active_rooms: Default::default(),
                 no_heating: vec![local_time_slot(04, 15, 00, 04, 30, 00)],
+                circulate_cool_to: vec![],
             },
             ..Default::default()
         };
@@ -377,4 +868,33 @@ mod tests {
             config, expected
         );
     }
+
+    #[test]
+    fn test_with_away_applied() {
+        let mut config: PythonBrainConfig = toml::from_str(r#"
+default_working_range = { min = 40.0, max = 44.0 }
+away = { working_range_reduction = 10.0, overrun_reduction = 20.0, immersion_heater_reduction = 5.0 }
+"#).expect("Failed to deserialize config");
+        config.additive_config.overrun_during = OverrunConfig::new(vec![
+            DhwBap::_new(local_time_slot(01,00,00, 04,30,00), Sensor::TKTP, 40.0, 50.0),
+        ]);
+        config.additive_config.immersion_heater_model = ImmersionHeaterModelConfig::new(vec![
+            ImmersionHeaterModelPart::from_time_points((time(02, 00, 00), 30.0), (time(04, 00, 00), 50.0), Sensor::TKBT),
+        ]);
+
+        let away = config.with_away_applied();
+        assert_eq!(away.default_working_range, WorkingTemperatureRange::from_min_max(30.0, 34.0));
+        let lowered = config.working_temp_model.min.get_temp_from_room_diff(0.5) - away.working_temp_model.min.get_temp_from_room_diff(0.5);
+        assert!((lowered - 10.0).abs() < 0.001, "min lowered by {}", lowered);
+        let lowered = config.working_temp_model.max.get_temp_from_room_diff(0.5) - away.working_temp_model.max.get_temp_from_room_diff(0.5);
+        assert!((lowered - 10.0).abs() < 0.001, "max lowered by {}", lowered);
+        assert_eq!(away.get_overrun_during().slots, vec![
+            DhwBap::_new(local_time_slot(01,00,00, 04,30,00), Sensor::TKTP, 20.0, 30.0),
+        ]);
+
+        let temps = HashMap::from([(Sensor::TKBT, 37.0)]);
+        assert_eq!(config.get_immersion_heater_model().should_be_on(&temps, time(03, 00, 00)), Some((Sensor::TKBT, 40.0)));
+        assert_eq!(away.get_immersion_heater_model().should_be_on(&temps, time(03, 00, 00)), None, "35 recommended, already above");
+        assert_eq!(away.get_immersion_heater_model().should_be_on(&temps, time(04, 00, 00)), Some((Sensor::TKBT, 45.0)));
+    }
 }
@@ -1,5 +1,6 @@
 use crate::python_like::modes::heating_mode::PossibleTemperatureContainer;
 use crate::time_util::timeslot::ZonedSlot;
+use crate::temp_format::fmt_temp;
 use crate::Sensor;
 use chrono::{DateTime, Utc};
 use itertools::Itertools;
@@ -11,99 +12,156 @@ use std::fmt::{Display, Formatter};
 #[derive(Deserialize, Clone, Debug, PartialEq, Default)]
 pub struct OverrunConfig {
     pub slots: Vec<DhwBap>,
+    /// Which slot to use when more than one matches at the same time.
+    /// Only the one in the main config file is used.
+    #[serde(default)]
+    pub selection: OverrunSelection,
+    /// What to do when the slot being heated for ends before reaching its target.
+    /// Only the one in the main config file is used.
+    #[serde(default)]
+    pub at_slot_end: SlotEnd,
+}
+
+/// How to pick between multiple slots that match at the same time, e.g. a TKTP and a TKBT
+/// slot that are both below their minimum. Ties go to whichever comes first in the config.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum OverrunSelection {
+    /// The slot with the highest minimum temperature.
+    #[default]
+    HighestMin,
+    /// The slot with the highest target (max) temperature.
+    HighestTarget,
+    /// The slot whose sensor is furthest below its target (max) temperature.
+    ColdestSensor,
+    /// The first matching slot in the order they are configured, so the config gives the priority.
+    ConfigOrder,
+}
+
+/// What to do when the time of the slot we were heating the hot water for runs out.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum SlotEnd {
+    /// Finish the mode, leaving it to the usual checks to decide what to do next.
+    #[default]
+    Finish,
+    /// Turn off if the wiser is still off, but if the wiser has called for heat in the
+    /// meantime carry straight on heating, rather than turning off just to turn on again.
+    ContinueIfWiser,
+}
+
+impl OverrunSelection {
+    /// How good a choice the slot is, higher being better.
+    fn score(&self, bap: &DhwBap, temp: f32) -> f32 {
+        match self {
+            OverrunSelection::HighestMin => bap.temps.min,
+            OverrunSelection::HighestTarget => bap.temps.max,
+            OverrunSelection::ColdestSensor => bap.temps.max - temp,
+            OverrunSelection::ConfigOrder => 0.0,
+        }
+    }
 }
 
 impl OverrunConfig {
     #[cfg(test)]
     pub fn new(slots: Vec<DhwBap>) -> Self {
-        Self { slots }
+        Self { slots, ..Self::default() }
     }
 
     pub fn combine(&mut self, mut other: OverrunConfig) {
         self.slots.append(&mut other.slots);
     }
 
+    /// The same slots, with all of their temperatures lowered by the given amount.
+    pub fn lowered_by(&self, amount: f32) -> Self {
+        let mut lowered = self.clone();
+        for bap in &mut lowered.slots {
+            bap.temps.min -= amount;
+            bap.temps.max -= amount;
+            bap.temps.extra = bap.temps.extra.map(|extra| extra - amount);
+        }
+        lowered
+    }
+
+    /// The valid slots that apply at the given time, in the order they are configured.
+    fn current_slots(&self, now: &DateTime<Utc>) -> impl Iterator<Item = &DhwBap> + '_ {
+        let now = *now;
+        self.slots.iter()
+            .filter(move |slot| slot.slot.contains(&now))
+            .filter(|slot| slot.is_valid())
+    }
+
+    /// Whether there is a slot for the sensor at the given time.
+    pub fn has_current_slot_for(&self, now: &DateTime<Utc>, sensor: &Sensor) -> bool {
+        self.slots.iter().any(|bap| bap.temps.sensor == *sensor && bap.slot.contains(now))
+    }
+
     fn _get_current_slots<'a>(&'a self, now: &DateTime<Utc>) -> HashMap<Sensor, Vec<&'a DhwBap>> {
         trace!(
             "All slots: {}",
             self.slots.iter().map(|s| format!("{{ {} }}", s)).join(", ")
         );
-        self
-            .slots
-            .iter()
-            .filter(|slot| slot.slot.contains(now))
-            .filter(|slot| {
-                if slot.temps.max <= slot.temps.min {
-                    error!("Invalid slot, slot max temp ({}) must be greater than the slot min temp ({}).", slot.temps.max, slot.temps.min);
-                    return false;
-                }
-                if slot.temps.extra.is_some() && slot.temps.extra <= Some(slot.temps.max) {
-                    error!("Invalid slot, slot extra temp ({:?}) must be greater than the slot max temp ({}).", slot.temps.extra, slot.temps.max);
-                    return false;
-                }
-                return true;
-            })
+        self.current_slots(now)
             .map(|slot| (slot.temps.sensor.clone(), slot))
             .into_group_map()
     }
 
+    /// Find the slot that applies at the given time and matches the temperature of its sensor,
+    /// picking between multiple matches according to the configured selection.
     pub fn find_matching_slot<T: PossibleTemperatureContainer>(&self,
         now:     &DateTime<Utc>,
         temps:   &T,
         matches: impl Fn(&DhwTemps, f32) -> bool,
     ) -> Option<&DhwBap> {
-        let applicable = self._get_current_slots(now);
-
-        debug!("Current overrun time slots: {applicable:?}", );
-
-        let mut result: Option<&DhwBap> = None;
-
-        for (sensor, baps) in &applicable {
-            if let Some(temp) = temps.get_sensor_temp(sensor) {
-                for bap in baps {
-                    debug!(target: OVERRUN_LOG_TARGET, "Checking overrun for {}. Current temp {:.2}. Overrun config: {}", sensor, temp, bap);
-
-                    if let Some(disable_below) = &bap.disable_below {
-                        if let Some(temp) = temps.get_sensor_temp(&Sensor::TKEN) {
-                            if *temp < disable_below.tken {
-                                info!(target: OVERRUN_LOG_TARGET, "Overrun is disabled {bap} due to TKEN of {temp}");
-                                continue;
-                            }
-                        }
-                        else {
-                            error!(target: OVERRUN_LOG_TARGET, "Potentially missing sensor: TKEN");
-                        }
-
-                        if let Some(temp) = temps.get_sensor_temp(&Sensor::TKBT) {
-                            if *temp < disable_below.tkbt {
-                                info!(target: OVERRUN_LOG_TARGET, "Overrun is disabled {bap} due to TKBT of {temp}");
-                                continue;
-                            }
-                        }
-                        else {
-                            error!(target: OVERRUN_LOG_TARGET, "Potentially missing sensor: TKBT");
-                        }
+        debug!("Current overrun time slots: {:?}", self._get_current_slots(now));
+
+        let mut result: Option<(&DhwBap, f32)> = None;
+
+        for bap in self.current_slots(now) {
+            let sensor = &bap.temps.sensor;
+            let Some(temp) = temps.get_sensor_temp(sensor) else {
+                error!(target: OVERRUN_LOG_TARGET, "Potentially missing sensor: {}", sensor);
+                continue;
+            };
+            debug!(target: OVERRUN_LOG_TARGET, "Checking overrun for {}. Current temp {}. Overrun config: {}", sensor, fmt_temp(*temp), bap);
+
+            if let Some(disable_below) = &bap.disable_below {
+                if let Some(temp) = temps.get_sensor_temp(&Sensor::TKEN) {
+                    if *temp < disable_below.tken {
+                        info!(target: OVERRUN_LOG_TARGET, "Overrun is disabled {bap} due to TKEN of {temp}");
+                        continue;
                     }
-                        
-                    if matches(&bap.temps, *temp) {
-                        if let Some(old) = result {
-                            if bap.temps.min > old.temps.min {
-                                info!(target: OVERRUN_LOG_TARGET, "Found better matching overrun {bap} for {sensor}={temp:.2}");
-                                result = Some(*bap);
-                            }
-                        }
-                        else {
-                            info!(target: OVERRUN_LOG_TARGET, "Found matching overrun {bap} for {sensor}={temp:.2}");
-                            result = Some(*bap);
-                        }
+                }
+                else {
+                    error!(target: OVERRUN_LOG_TARGET, "Potentially missing sensor: TKEN");
+                }
+
+                if let Some(temp) = temps.get_sensor_temp(&Sensor::TKBT) {
+                    if *temp < disable_below.tkbt {
+                        info!(target: OVERRUN_LOG_TARGET, "Overrun is disabled {bap} due to TKBT of {temp}");
+                        continue;
+                    }
+                }
+                else {
+                    error!(target: OVERRUN_LOG_TARGET, "Potentially missing sensor: TKBT");
+                }
+            }
+
+            if matches(&bap.temps, *temp) {
+                let score = self.selection.score(bap, *temp);
+                match result {
+                    Some((_, best_score)) if score <= best_score => {}
+                    Some(_) => {
+                        info!(target: OVERRUN_LOG_TARGET, "Found better matching overrun {bap} for {sensor}={} ({:?})", fmt_temp(*temp), self.selection);
+                        result = Some((bap, score));
+                    }
+                    None => {
+                        info!(target: OVERRUN_LOG_TARGET, "Found matching overrun {bap} for {sensor}={}", fmt_temp(*temp));
+                        result = Some((bap, score));
                     }
                 }
-            } else {
-                error!(target: OVERRUN_LOG_TARGET, "Potentially missing sensor: {}", sensor);
             }
         }
 
-        result
+        result.map(|(bap, _)| bap)
     }
 }
 
@@ -168,6 +226,19 @@ pub struct Mixed {
 }
 
 impl DhwBap {
+    /// Whether the temperatures make sense, logging why not if they don't.
+    fn is_valid(&self) -> bool {
+        if self.temps.max <= self.temps.min {
+            error!("Invalid slot, slot max temp ({}) must be greater than the slot min temp ({}).", self.temps.max, self.temps.min);
+            return false;
+        }
+        if self.temps.extra.is_some() && self.temps.extra <= Some(self.temps.max) {
+            error!("Invalid slot, slot extra temp ({:?}) must be greater than the slot max temp ({}).", self.temps.extra, self.temps.max);
+            return false;
+        }
+        true
+    }
+
     #[cfg(test)]
     pub fn _new(slot: ZonedSlot, sensor: Sensor, min_temp: f32, max_temp: f32) -> Self {
         assert!(min_temp < max_temp, "min_temp should be less than max_temp");
@@ -198,6 +269,7 @@ impl Display for DhwBap {
 mod tests {
     use super::*;
     use crate::time_util::test_utils::{date, time};
+    use crate::time_util::timeslot::TimeSlot;
     use chrono::{NaiveDateTime, TimeZone};
 
     #[test]
@@ -327,5 +399,59 @@ mod tests {
 
         assert_eq!(bap, &slot2);
     }
-}
 
+    #[test]
+    fn test_selection() {
+        let datetime = Utc::from_utc_datetime(
+            &Utc,
+            &NaiveDateTime::new(date(2022, 08, 19), time(04, 15, 00)),
+        );
+        let utc_slot: TimeSlot = (time(04, 00, 00)..time(04, 30, 00)).into();
+
+        // All of these match, and each policy picks a different one.
+        let first = DhwBap::_new(ZonedSlot::Utc(utc_slot.clone()), Sensor::TKTP, 20.0, 43.0);
+        let coldest = DhwBap::_new(ZonedSlot::Utc(utc_slot.clone()), Sensor::TKBT, 30.0, 40.0);
+        let highest_min = DhwBap::_new(ZonedSlot::Utc(utc_slot.clone()), Sensor::TKTP, 42.0, 45.0);
+        let highest_target = DhwBap::_new(ZonedSlot::Utc(utc_slot.clone()), Sensor::TKTP, 36.0, 50.0);
+
+        let temps = HashMap::from([(Sensor::TKTP, 41.0), (Sensor::TKBT, 25.0)]);
+        let select = |selection| {
+            let config = OverrunConfig {
+                slots: vec![first.clone(), coldest.clone(), highest_min.clone(), highest_target.clone()],
+                selection,
+                ..OverrunConfig::default()
+            };
+            config.find_matching_slot(&datetime, &temps, |temps, temp| temp < temps.max).cloned()
+        };
+
+        assert_eq!(select(OverrunSelection::HighestMin), Some(highest_min.clone()));
+        assert_eq!(select(OverrunSelection::HighestTarget), Some(highest_target.clone()));
+        assert_eq!(select(OverrunSelection::ColdestSensor), Some(coldest.clone()));
+        assert_eq!(select(OverrunSelection::ConfigOrder), Some(first.clone()));
+
+        let config: OverrunConfig = toml::from_str("selection = \"ColdestSensor\"\nslots = []").expect("Should be valid");
+        assert_eq!(config.selection, OverrunSelection::ColdestSensor);
+        assert_eq!(config.at_slot_end, SlotEnd::Finish);
+    }
+
+    #[test]
+    fn test_selection_tie_uses_config_order() {
+        let datetime = Utc::from_utc_datetime(
+            &Utc,
+            &NaiveDateTime::new(date(2022, 08, 19), time(04, 15, 00)),
+        );
+        let utc_slot: TimeSlot = (time(04, 00, 00)..time(04, 30, 00)).into();
+
+        let tktp = DhwBap::_new(ZonedSlot::Utc(utc_slot.clone()), Sensor::TKTP, 40.0, 45.0);
+        let tkbt = DhwBap::_new(ZonedSlot::Utc(utc_slot.clone()), Sensor::TKBT, 40.0, 45.0);
+        let temps = HashMap::from([(Sensor::TKTP, 35.0), (Sensor::TKBT, 35.0)]);
+
+        for (slots, expected) in [(vec![tktp.clone(), tkbt.clone()], &tktp), (vec![tkbt.clone(), tktp.clone()], &tkbt)] {
+            for selection in [OverrunSelection::HighestMin, OverrunSelection::HighestTarget, OverrunSelection::ColdestSensor, OverrunSelection::ConfigOrder] {
+                let config = OverrunConfig { slots: slots.clone(), selection, ..OverrunConfig::default() };
+                let slot = config.find_matching_slot(&datetime, &temps, |temps, temp| temp < temps.max);
+                assert_eq!(slot, Some(expected), "{:?}", selection);
+            }
+        }
+    }
+}
@@ -0,0 +1,34 @@
+use crate::time_util::timeslot::ZonedSlot;
+use serde::Deserialize;
+use serde_with::serde_as;
+use serde_with::DurationSeconds;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Briefly run the pumps and valves if they haven't moved for a while, so they don't seize.
+#[serde_as]
+#[derive(Clone, Deserialize, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PumpExerciseConfig {
+    /// How many days a pump or valve can go without moving before it is exercised.
+    pub max_idle_days: u32,
+    /// When it is safe to exercise them. The wiser must also not be calling for heat.
+    pub window: ZonedSlot,
+    /// How long (in seconds) to run them for.
+    #[serde_as(as = "DurationSeconds")]
+    #[serde(default = "default_run_secs")]
+    pub run_secs: Duration,
+    /// Where to keep when each of them last moved, so that it isn't forgotten on restart.
+    #[serde(default)]
+    pub state_file: Option<PathBuf>,
+}
+
+fn default_run_secs() -> Duration {
+    Duration::from_secs(60)
+}
+
+impl PumpExerciseConfig {
+    pub fn get_max_idle(&self) -> chrono::Duration {
+        chrono::Duration::days(self.max_idle_days as i64)
+    }
+}
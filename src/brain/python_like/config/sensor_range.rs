@@ -0,0 +1,27 @@
+use serde::Deserialize;
+use std::fmt::{Display, Formatter};
+
+/// The range of temperatures a sensor can sensibly read when it is working properly.
+#[derive(Clone, Deserialize, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SensorRange {
+    min: f32,
+    max: f32,
+}
+
+impl SensorRange {
+    #[cfg(test)]
+    pub fn new(min: f32, max: f32) -> Self {
+        Self { min, max }
+    }
+
+    pub fn contains(&self, temp: f32) -> bool {
+        (self.min..=self.max).contains(&temp)
+    }
+}
+
+impl Display for SensorRange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:.1}-{:.1}", self.min, self.max)
+    }
+}
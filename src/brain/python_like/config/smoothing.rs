@@ -0,0 +1,85 @@
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
+
+/// Exponential smoothing of a noisy sensor, while still following genuine fast changes.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SmoothingConfig {
+    /// How much weight to give the latest reading, above 0 (very smooth) up to 1 (not smoothed at all).
+    #[serde(deserialize_with = "deserialize_alpha")]
+    alpha: f32,
+    /// If a reading is further than this from the smoothed value, it is taken as a genuine
+    /// change and used as is, rather than being smoothed away.
+    max_deviation: f32,
+}
+
+impl SmoothingConfig {
+    #[cfg(test)]
+    pub fn new(alpha: f32, max_deviation: f32) -> Self {
+        Self { alpha, max_deviation }
+    }
+
+    /// The new smoothed value given the previous one (if any) and the latest reading.
+    pub fn smooth(&self, previous: Option<f32>, raw: f32) -> f32 {
+        match previous {
+            Some(previous) if (raw - previous).abs() <= self.max_deviation => {
+                previous + self.alpha * (raw - previous)
+            }
+            _ => raw,
+        }
+    }
+}
+
+fn deserialize_alpha<'de, D>(deserializer: D) -> Result<f32, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let alpha = f32::deserialize(deserializer)?;
+    if alpha <= 0.0 || alpha > 1.0 {
+        return Err(D::Error::custom(format!("Smoothing alpha {} must be above 0 and at most 1", alpha)));
+    }
+    Ok(alpha)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn smooth_series(config: &SmoothingConfig, series: &[f32]) -> Vec<f32> {
+        let mut smoothed = None;
+        series.iter()
+            .map(|raw| *smoothed.insert(config.smooth(smoothed, *raw)))
+            .collect()
+    }
+
+    #[test]
+    fn test_noisy_series() {
+        let config = SmoothingConfig::new(0.25, 2.0);
+        let smoothed = smooth_series(&config, &[40.0, 41.0, 39.0, 41.0, 39.0, 41.0, 39.0, 40.0]);
+
+        assert_eq!(smoothed[0], 40.0, "Nothing to smooth the first reading with");
+        for value in &smoothed {
+            assert!((39.7..=40.3).contains(value), "Should have smoothed out the noise, got {:?}", smoothed);
+        }
+    }
+
+    #[test]
+    fn test_genuine_step_change() {
+        let config = SmoothingConfig::new(0.25, 2.0);
+        let smoothed = smooth_series(&config, &[40.0, 40.0, 45.0, 45.0]);
+
+        assert_eq!(smoothed, vec![40.0, 40.0, 45.0, 45.0], "A step beyond max_deviation should be followed straight away");
+
+        let smoothed = smooth_series(&config, &[40.0, 42.0]);
+        assert_eq!(smoothed, vec![40.0, 40.5], "Within max_deviation is smoothed");
+    }
+
+    #[test]
+    fn test_alpha_limits() {
+        let config: SmoothingConfig = toml::from_str("alpha = 1.0\nmax_deviation = 2.0").expect("Should be valid");
+        assert_eq!(config.smooth(Some(40.0), 41.0), 41.0);
+
+        assert!(toml::from_str::<SmoothingConfig>("alpha = 0.0\nmax_deviation = 2.0").is_err());
+        assert!(toml::from_str::<SmoothingConfig>("alpha = 1.5\nmax_deviation = 2.0").is_err());
+    }
+}
@@ -0,0 +1,40 @@
+use crate::io::wiser::hub::WiserRoomData;
+use serde::Deserialize;
+use std::borrow::Cow;
+
+/// What to do with rooms that wiser doesn't give a name for.
+#[derive(Clone, Copy, Deserialize, Debug, PartialEq, Default)]
+pub enum UnnamedRoomPolicy {
+    /// Leave the room out of the working temperature and boosting.
+    Skip,
+    /// Use the id of the room as its name.
+    #[default]
+    UseId,
+}
+
+impl UnnamedRoomPolicy {
+    /// Get the name to use for the given room, or None if it should be skipped.
+    pub fn get_room_name<'a>(&self, room: &'a WiserRoomData) -> Option<Cow<'a, str>> {
+        match (room.get_name(), self) {
+            (Some(name), _) => Some(Cow::Borrowed(name)),
+            (None, UnnamedRoomPolicy::Skip) => None,
+            (None, UnnamedRoomPolicy::UseId) => Some(Cow::Owned(room.get_id().to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_get_room_name() {
+        let named = WiserRoomData::new(1, None, None, None, "FromSchedule".into(), 180, 200, Some("Kitchen".into()));
+        let unnamed = WiserRoomData::new(7, None, None, None, "FromSchedule".into(), 180, 200, None);
+
+        assert_eq!(UnnamedRoomPolicy::Skip.get_room_name(&named).as_deref(), Some("Kitchen"));
+        assert_eq!(UnnamedRoomPolicy::UseId.get_room_name(&named).as_deref(), Some("Kitchen"));
+        assert_eq!(UnnamedRoomPolicy::Skip.get_room_name(&unnamed), None);
+        assert_eq!(UnnamedRoomPolicy::UseId.get_room_name(&unnamed).as_deref(), Some("7"));
+    }
+}
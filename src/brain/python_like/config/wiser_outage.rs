@@ -0,0 +1,146 @@
+use crate::brain::modes::HeatingState;
+use serde::Deserialize;
+use serde_with::serde_as;
+use serde_with::DurationSeconds;
+use std::time::Duration;
+
+#[serde_as]
+#[derive(Clone, Deserialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct WiserOutageConfig {
+    /// How long (in seconds) to keep using the last known wiser heating state after
+    /// losing contact with the wiser hub.
+    #[serde_as(as = "DurationSeconds")]
+    pub max_duration: Duration,
+
+    /// What to assume the wiser heating state is once contact has been lost for
+    /// longer than max_duration.
+    pub policy: WiserOutagePolicy,
+
+    /// If set, reboot the wiser by cutting its power when contact has been lost for a while.
+    /// Needs the wiser power relay.
+    pub reboot: Option<WiserRebootConfig>,
+}
+
+#[serde_as]
+#[derive(Clone, Deserialize, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct WiserRebootConfig {
+    /// How long (in seconds) without contact before rebooting the wiser.
+    #[serde_as(as = "DurationSeconds")]
+    pub after: Duration,
+    /// How long (in seconds) to cut the power for.
+    #[serde_as(as = "DurationSeconds")]
+    #[serde(default = "default_reboot_off_time")]
+    pub off_time: Duration,
+    /// The minimum time (in seconds) between reboots, so a wiser that stays broken
+    /// isn't rebooted over and over.
+    #[serde_as(as = "DurationSeconds")]
+    #[serde(default = "default_reboot_min_interval")]
+    pub min_interval: Duration,
+}
+
+fn default_reboot_off_time() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_reboot_min_interval() -> Duration {
+    Duration::from_secs(60 * 60)
+}
+
+#[derive(Clone, Copy, Deserialize, Debug, PartialEq)]
+pub enum WiserOutagePolicy {
+    /// Assume the heating is off.
+    AssumeOff,
+    /// Carry on with whatever wiser last told us.
+    MaintainLast,
+}
+
+impl WiserOutageConfig {
+    /// Get the heating state to use when wiser couldn't be contacted,
+    /// given the last known state and how long it has been since wiser was last contacted.
+    pub fn get_assumed_state(
+        &self,
+        last_state: HeatingState,
+        since_last_contact: Duration,
+    ) -> HeatingState {
+        if since_last_contact <= self.max_duration {
+            return last_state;
+        }
+        match self.policy {
+            WiserOutagePolicy::AssumeOff => HeatingState::OFF,
+            WiserOutagePolicy::MaintainLast => last_state,
+        }
+    }
+}
+
+impl Default for WiserOutageConfig {
+    fn default() -> Self {
+        Self {
+            max_duration: Duration::from_secs(60 * 60),
+            policy: WiserOutagePolicy::AssumeOff,
+            reboot: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_within_timeout_keeps_last() {
+        let config = WiserOutageConfig::default();
+        let state = config.get_assumed_state(HeatingState::ON, Duration::from_secs(30 * 60));
+        assert_eq!(state, HeatingState::ON);
+    }
+
+    #[test]
+    fn test_assume_off_past_timeout() {
+        let config = WiserOutageConfig {
+            max_duration: Duration::from_secs(10 * 60),
+            policy: WiserOutagePolicy::AssumeOff,
+            reboot: None,
+        };
+        let state = config.get_assumed_state(HeatingState::ON, Duration::from_secs(11 * 60));
+        assert_eq!(state, HeatingState::OFF);
+    }
+
+    #[test]
+    fn test_maintain_last_past_timeout() {
+        let config = WiserOutageConfig {
+            max_duration: Duration::from_secs(10 * 60),
+            policy: WiserOutagePolicy::MaintainLast,
+            reboot: None,
+        };
+        let state = config.get_assumed_state(HeatingState::ON, Duration::from_secs(11 * 60));
+        assert_eq!(state, HeatingState::ON);
+
+        let state = config.get_assumed_state(HeatingState::OFF, Duration::from_secs(11 * 60));
+        assert_eq!(state, HeatingState::OFF);
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let config: WiserOutageConfig =
+            toml::from_str("max_duration = 600\npolicy = \"MaintainLast\"").unwrap();
+        assert_eq!(
+            config,
+            WiserOutageConfig {
+                max_duration: Duration::from_secs(600),
+                policy: WiserOutagePolicy::MaintainLast,
+                reboot: None,
+            }
+        );
+
+        let config: WiserOutageConfig = toml::from_str("reboot = { after = 1800 }").unwrap();
+        assert_eq!(
+            config.reboot,
+            Some(WiserRebootConfig {
+                after: Duration::from_secs(1800),
+                off_time: Duration::from_secs(30),
+                min_interval: Duration::from_secs(60 * 60),
+            })
+        );
+    }
+}
@@ -1,12 +1,82 @@
-use serde::Deserialize;
+use crate::io::wiser::hub::DEFAULT_MIN_VALID_TEMPERATURE;
+use crate::math::model::{LinearModel, Model};
+use itertools::Itertools;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
 
 /// Parameters for a signmoid temperature curve
 /// See https://docs.google.com/spreadsheets/d/1W-7uisntqJJfkjusxofNv68s1fr1SONU1kiOftu9RHk/edit#gid=1222591046
 #[derive(Deserialize, Clone, Debug, PartialEq)]
 //#[serde(deny_unknown_fields)]
 pub struct WorkingTempModelConfig {
-    pub min: WorkingTempCurveConfig,
-    pub max: WorkingTempCurveConfig,
+    pub min: WorkingTempCurve,
+    pub max: WorkingTempCurve,
+    /// Whether rooms whose set point comes from wiser's away mode should be left
+    /// out when finding the room with the biggest difference.
+    #[serde(default)]
+    pub ignore_away_rooms: bool,
+    /// Rooms reporting a temperature at or below this are ignored as their thermostat isn't
+    /// giving a real reading (e.g. it has a flat battery). If no room has a real reading,
+    /// the fallback working range is used instead.
+    #[serde(default = "default_min_valid_room_temp")]
+    pub min_valid_room_temp: f32,
+    /// How much to raise the max working temperature by when it is cold outside.
+    /// Needs an outdoor sensor to be configured, otherwise the range is left alone.
+    #[serde(default)]
+    pub outdoor_compensation: Option<OutdoorCompensationConfig>,
+    /// The narrowest the working range may be, as a tiny range would have the heat pump
+    /// cycling on and off. Narrower ranges from the model are widened equally either side.
+    #[serde(default)]
+    pub min_band_width: f32,
+    /// Names of rooms (e.g. a rarely used guest room) to leave out when finding the room with the
+    /// biggest difference, so they don't drive the working range. They can still be boosted.
+    #[serde(default)]
+    pub ignore_rooms: Vec<String>,
+}
+
+impl WorkingTempModelConfig {
+    /// The same model, with the min and max curves moved down by the given amount.
+    pub fn lowered_by(&self, amount: f32) -> Self {
+        Self {
+            min: self.min.lowered_by(amount),
+            max: self.max.lowered_by(amount),
+            ..self.clone()
+        }
+    }
+}
+
+fn default_min_valid_room_temp() -> f32 {
+    DEFAULT_MIN_VALID_TEMPERATURE
+}
+
+/// A curve mapping the room temperature difference to a working temperature.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum WorkingTempCurve {
+    Sigmoid(WorkingTempCurveConfig),
+    Interpolated(InterpolatedCurveConfig),
+}
+
+impl WorkingTempCurve {
+    pub fn get_temp_from_room_diff(&self, room_diff: f32) -> f32 {
+        match self {
+            WorkingTempCurve::Sigmoid(curve) => curve.get_temp_from_room_diff(room_diff),
+            WorkingTempCurve::Interpolated(curve) => curve.get_temp_from_room_diff(room_diff),
+        }
+    }
+
+    /// The same curve, moved down by the given amount.
+    pub fn lowered_by(&self, amount: f32) -> Self {
+        match self {
+            WorkingTempCurve::Sigmoid(curve) => WorkingTempCurve::Sigmoid(WorkingTempCurveConfig {
+                offset: curve.offset - amount,
+                ..curve.clone()
+            }),
+            WorkingTempCurve::Interpolated(curve) => WorkingTempCurve::Interpolated(InterpolatedCurveConfig {
+                points: curve.points.iter().map(|(diff, temp)| (*diff, temp - amount)).collect(),
+            }),
+        }
+    }
 }
 
 #[derive(Deserialize, Clone, Debug, PartialEq)]
@@ -25,28 +95,105 @@ impl WorkingTempCurveConfig {
     }
 }
 
+/// A curve made up of (room_diff, temp) points, linearly interpolating between them.
+/// Outside of the points, the temperature of the nearest end point is used.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct InterpolatedCurveConfig {
+    #[serde(deserialize_with = "deserialize_points")]
+    points: Vec<(f32, f32)>,
+}
+
+impl InterpolatedCurveConfig {
+    pub fn get_temp_from_room_diff(&self, room_diff: f32) -> f32 {
+        interpolate(&self.points, room_diff)
+    }
+}
+
+/// Linearly interpolate between the (x, y) points, using the nearest end point outside of them.
+fn interpolate(points: &[(f32, f32)], x: f32) -> f32 {
+    let first = points.first().expect("Should have at least one point");
+    let last = points.last().expect("Should have at least one point");
+    if x <= first.0 {
+        return first.1;
+    }
+    if x >= last.0 {
+        return last.1;
+    }
+    let (lower, upper) = points.iter()
+        .tuple_windows()
+        .find(|(_, upper)| x <= upper.0)
+        .expect("Should be between the first and last points");
+    LinearModel::from_points(*lower, *upper).get(x)
+}
+
+/// A curve made up of (outdoor temperature, increase) points, linearly interpolating between
+/// them, giving how much to add onto the max working temperature.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct OutdoorCompensationConfig {
+    #[serde(deserialize_with = "deserialize_points")]
+    points: Vec<(f32, f32)>,
+    /// The compensation never raises the max working temperature above this.
+    #[serde(default = "default_compensation_max_temp")]
+    max_temp: f32,
+}
+
+fn default_compensation_max_temp() -> f32 {
+    55.0
+}
+
+impl OutdoorCompensationConfig {
+    /// Add the increase for the outdoor temperature onto the max working temperature.
+    /// The increase is never negative, and only goes up to max_temp, so this never lowers the max
+    /// (even if it is already above max_temp).
+    pub fn compensate_max(&self, max: f32, outdoor_temp: f32) -> f32 {
+        let increase = interpolate(&self.points, outdoor_temp).max(0.0);
+        (max + increase).min(self.max_temp.max(max))
+    }
+}
+
+fn deserialize_points<'de, D>(deserializer: D) -> Result<Vec<(f32, f32)>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let points = Vec::<(f32, f32)>::deserialize(deserializer)?;
+    if points.is_empty() {
+        return Err(D::Error::custom("At least one point is required"));
+    }
+    if !points.iter().tuple_windows().all(|(a, b)| a.0 < b.0) {
+        return Err(D::Error::custom("Points must be in increasing order"));
+    }
+    Ok(points)
+}
+
 impl Default for WorkingTempModelConfig {
     fn default() -> Self {
         Self {
-            min: WorkingTempCurveConfig {
+            min: WorkingTempCurve::Sigmoid(WorkingTempCurveConfig {
                 sharpness:     1.90,
                 turning_point: 0.50,
                 multiplier:    24.0,
                 offset:        23.3,
-            },
-            max: WorkingTempCurveConfig {
+            }),
+            max: WorkingTempCurve::Sigmoid(WorkingTempCurveConfig {
                 sharpness:     1.90,
                 turning_point: 0.35,
                 multiplier:    18.7,
                 offset:        31.2,
-            },
+            }),
+            ignore_away_rooms: false,
+            min_valid_room_temp: DEFAULT_MIN_VALID_TEMPERATURE,
+            outdoor_compensation: None,
+            min_band_width: 0.0,
+            ignore_rooms: vec![],
         }
     }
 }
 
 #[cfg(test)]
 pub mod test {
-    use super::WorkingTempModelConfig;
+    use super::*;
 
     #[test]
     fn get_temp_from_room_diff() {
@@ -56,6 +203,56 @@ pub mod test {
         assert_eq!((model.get_temp_from_room_diff(1.0) * 100.0) as u32, 4060);
     }
 
+    #[test]
+    fn test_interpolated() {
+        let config: WorkingTempModelConfig = toml::from_str(r#"
+[min]
+points = [[0.0, 30.0], [1.0, 40.0], [2.0, 45.0]]
+[max]
+sharpness     = 1.9
+turning_point = 0.35
+multiplier    = 18.7
+offset        = 31.2
+"#).expect("Invalid config");
+
+        assert!(matches!(config.min, WorkingTempCurve::Interpolated(_)));
+        assert!(matches!(config.max, WorkingTempCurve::Sigmoid(_)));
+
+        assert_eq!(config.min.get_temp_from_room_diff(-1.0), 30.0);
+        assert_eq!(config.min.get_temp_from_room_diff(0.0), 30.0);
+        assert_eq!(config.min.get_temp_from_room_diff(0.5), 35.0);
+        assert_eq!(config.min.get_temp_from_room_diff(1.0), 40.0);
+        assert_eq!(config.min.get_temp_from_room_diff(1.5), 42.5);
+        assert_eq!(config.min.get_temp_from_room_diff(2.0), 45.0);
+        assert_eq!(config.min.get_temp_from_room_diff(3.0), 45.0);
+    }
+
+    #[test]
+    fn test_interpolated_unordered() {
+        let result: Result<InterpolatedCurveConfig, _> =
+            toml::from_str("points = [[1.0, 40.0], [0.0, 30.0]]");
+        assert!(result.is_err(), "Unordered points should be rejected");
+
+        let result: Result<InterpolatedCurveConfig, _> = toml::from_str("points = []");
+        assert!(result.is_err(), "Empty points should be rejected");
+    }
+
+    #[test]
+    fn test_outdoor_compensation() {
+        let config: OutdoorCompensationConfig = toml::from_str(r#"
+points = [[-5.0, 6.0], [5.0, 2.0], [15.0, 0.0]]
+max_temp = 52.0
+"#).expect("Invalid config");
+
+        assert_eq!(config.compensate_max(45.0, 20.0), 45.0);
+        assert_eq!(config.compensate_max(45.0, 10.0), 46.0);
+        assert_eq!(config.compensate_max(45.0, 5.0), 47.0);
+        assert_eq!(config.compensate_max(45.0, -10.0), 51.0);
+        // Capped, but never lowered.
+        assert_eq!(config.compensate_max(48.0, -10.0), 52.0);
+        assert_eq!(config.compensate_max(53.0, -10.0), 53.0);
+    }
+
     pub fn get_working_temp_model_test_data() -> WorkingTempModelConfig {
         WorkingTempModelConfig::default()
     }
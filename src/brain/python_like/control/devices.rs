@@ -1,4 +1,5 @@
 use std::fmt::Display;
+use std::time::Duration;
 
 use chrono::{Utc, DateTime};
 use serde::Deserialize;
@@ -10,8 +11,8 @@ pub trait ActiveDevices {
     /// Get all devices currently considered active.
     fn get_active_devices(&mut self, time: &DateTime<Utc>) -> Result<Vec<Device>, BrainFailure>;
 
-    /// Get all devices that were active within the last x minutes.
-    fn get_active_devices_within(&mut self, time: &DateTime<Utc>, minutes: usize) -> Result<Vec<Device>, BrainFailure>;
+    /// Get all devices that were active within the given time before now.
+    fn get_active_devices_within(&mut self, time: &DateTime<Utc>, within: Duration) -> Result<Vec<Device>, BrainFailure>;
 }
 
 #[derive(Debug, Deserialize, Hash, PartialEq, Eq, Clone, PartialOrd, Ord)]
@@ -43,6 +43,16 @@ impl HeatPumpMode {
     pub fn is_hp_off(&self) -> bool {
         !self.is_hp_on()
     }
+
+    /// Whether the heat pump is (mostly) heating the hot water tank.
+    pub fn is_heating_tank(&self) -> bool {
+        matches!(self, HeatPumpMode::HotWaterOnly | HeatPumpMode::MostlyHotWater)
+    }
+
+    /// Whether heat is going to the radiators, either from the heat pump or drained from the tank.
+    pub fn is_heating_rooms(&self) -> bool {
+        matches!(self, HeatPumpMode::HeatingOnly | HeatPumpMode::MostlyHotWater | HeatPumpMode::BoostedHeating | HeatPumpMode::DrainTank)
+    }
 }
 
 pub trait HeatPumpControl {
@@ -84,3 +94,18 @@ pub trait HeatingControl: HeatPumpControl + HeatCirculationPumpControl + Send +
 
     fn as_cp(&mut self) -> &mut dyn HeatCirculationPumpControl;
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_is_heating_rooms() {
+        assert!(!HeatPumpMode::HotWaterOnly.is_heating_rooms(), "Only heating the tank");
+        assert!(!HeatPumpMode::Off.is_heating_rooms());
+        assert!(HeatPumpMode::HeatingOnly.is_heating_rooms());
+        assert!(HeatPumpMode::MostlyHotWater.is_heating_rooms());
+        assert!(HeatPumpMode::BoostedHeating.is_heating_rooms());
+        assert!(HeatPumpMode::DrainTank.is_heating_rooms(), "Draining the tank into the heating");
+    }
+}
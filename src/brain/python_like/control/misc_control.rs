@@ -1,4 +1,7 @@
 use crate::brain::BrainFailure;
+use chrono::{DateTime, Utc};
+use log::info;
+use std::time::Duration;
 
 pub trait MiscControls: ImmersionHeaterControl + WiserPowerControl {
 
@@ -15,8 +18,136 @@ pub trait ImmersionHeaterControl {
     fn try_get_immersion_heater(&self) -> Result<bool, BrainFailure>;
 }
 
+/// Control over the wiser hub's power. The wiser is normally powered, and only has its power cut
+/// deliberately, e.g. to reboot it (see WiserPowerCycle). It is always powered again on shutdown
+/// so the heating carries on working without us.
 pub trait WiserPowerControl {
+    /// Give the wiser power (true) or cut it (false).
     fn try_set_wiser_power(&mut self, on: bool) -> Result<(), BrainFailure>;
 
+    /// Whether the wiser currently has power.
     fn try_get_wiser_power(&mut self) -> Result<bool, BrainFailure>;
+}
+
+/// Cuts the wiser's power and restores it after a while, to force the hub to reboot
+/// when it has stopped responding.
+#[derive(Debug, Default)]
+pub struct WiserPowerCycle {
+    /// When the power was cut, if it is currently cut.
+    cut_at: Option<DateTime<Utc>>,
+    /// When the power was last restored.
+    last_restored: Option<DateTime<Utc>>,
+}
+
+impl WiserPowerCycle {
+    pub fn is_cycling(&self) -> bool {
+        self.cut_at.is_some()
+    }
+
+    /// Whether a new power cycle can start, i.e. one isn't in progress and the last finished
+    /// at least min_interval ago.
+    pub fn can_start(&self, now: DateTime<Utc>, min_interval: Duration) -> bool {
+        !self.is_cycling() && self.last_restored
+            .is_none_or(|restored| (now - restored).to_std().unwrap_or_default() >= min_interval)
+    }
+
+    /// Cut the wiser's power, unless a power cycle is already in progress.
+    pub fn start(&mut self, control: &mut dyn WiserPowerControl, now: DateTime<Utc>) -> Result<(), BrainFailure> {
+        if self.is_cycling() {
+            return Ok(());
+        }
+        info!(target: "wiser", "Cutting the wiser's power to reboot it");
+        control.try_set_wiser_power(false)?;
+        self.cut_at = Some(now);
+        Ok(())
+    }
+
+    /// Restore the wiser's power once it has been off for off_time.
+    pub fn update(&mut self, control: &mut dyn WiserPowerControl, now: DateTime<Utc>, off_time: Duration) -> Result<(), BrainFailure> {
+        if let Some(cut_at) = self.cut_at {
+            if (now - cut_at).to_std().unwrap_or_default() >= off_time {
+                info!(target: "wiser", "Restoring the wiser's power");
+                control.try_set_wiser_power(true)?;
+                self.cut_at = None;
+                self.last_restored = Some(now);
+            }
+        }
+        Ok(())
+    }
+
+    /// Restore the wiser's power straight away if it is cut, e.g. because everything is being held off.
+    pub fn restore(&mut self, control: &mut dyn WiserPowerControl, now: DateTime<Utc>) -> Result<(), BrainFailure> {
+        if self.is_cycling() {
+            info!(target: "wiser", "Restoring the wiser's power early");
+            control.try_set_wiser_power(true)?;
+            self.cut_at = None;
+            self.last_restored = Some(now);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::io::dummy::DummyAllOutputs;
+    use crate::time_util::test_utils::utc_datetime;
+    use chrono::Duration as ChronoDuration;
+
+    #[test]
+    fn test_wiser_power_semantics() -> Result<(), BrainFailure> {
+        let mut controls = DummyAllOutputs::default();
+        assert!(controls.try_get_wiser_power()?, "Wiser should have power by default");
+
+        controls.try_set_wiser_power(false)?;
+        assert!(!controls.try_get_wiser_power()?);
+        controls.try_set_wiser_power(true)?;
+        assert!(controls.try_get_wiser_power()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_power_cycle() -> Result<(), BrainFailure> {
+        let mut controls = DummyAllOutputs::default();
+        let mut cycle = WiserPowerCycle::default();
+        let off_time = Duration::from_secs(30);
+        let now = utc_datetime(2024, 3, 1, 12, 0, 0);
+
+        assert!(cycle.can_start(now, Duration::from_secs(60 * 60)));
+        cycle.start(&mut controls, now)?;
+        assert!(cycle.is_cycling());
+        assert!(!cycle.can_start(now, Duration::ZERO), "Already cycling");
+        assert!(!controls.try_get_wiser_power()?, "Power should be cut");
+        cycle.start(&mut controls, now + ChronoDuration::seconds(20))?;
+
+        cycle.update(&mut controls, now + ChronoDuration::seconds(20), off_time)?;
+        assert!(!controls.try_get_wiser_power()?, "Not off for long enough yet");
+
+        cycle.update(&mut controls, now + ChronoDuration::seconds(30), off_time)?;
+        assert!(!cycle.is_cycling());
+        assert!(controls.try_get_wiser_power()?, "Power should be restored, timed from the first start");
+
+        let min_interval = Duration::from_secs(60 * 60);
+        let restored = now + ChronoDuration::seconds(30);
+        assert!(!cycle.can_start(restored + ChronoDuration::minutes(59), min_interval), "Too soon after the last one");
+        assert!(cycle.can_start(restored + ChronoDuration::minutes(60), min_interval));
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore() -> Result<(), BrainFailure> {
+        let mut controls = DummyAllOutputs::default();
+        let mut cycle = WiserPowerCycle::default();
+        let now = utc_datetime(2024, 3, 1, 12, 0, 0);
+
+        cycle.restore(&mut controls, now)?;
+        assert!(controls.try_get_wiser_power()?, "Nothing to restore");
+
+        cycle.start(&mut controls, now)?;
+        cycle.restore(&mut controls, now + ChronoDuration::seconds(5))?;
+        assert!(!cycle.is_cycling());
+        assert!(controls.try_get_wiser_power()?, "Power should be restored before the off time");
+        assert!(!cycle.can_start(now + ChronoDuration::seconds(10), Duration::from_secs(60)), "Counts as the last restore");
+        Ok(())
+    }
 }
\ No newline at end of file
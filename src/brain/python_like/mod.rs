@@ -1,24 +1,39 @@
 use crate::brain::boost_active_rooms::update_boosted_rooms;
 use crate::brain::boost_active_rooms::AppliedBoosts;
+use crate::brain::daily_summary::DailyActivity;
 use crate::brain::immersion_heater::follow_ih_model;
-use crate::brain::modes::heating_mode::{HeatingMode, SharedData};
+use crate::brain::clock_jump::ClockJumpDetector;
+use crate::brain::missing_sensors::MissingSensorTracker;
+use crate::brain::pump_exercise::PumpExercise;
+use crate::brain::trend::TemperatureTrends;
+use crate::brain::modes::heating_mode::{expect_available_fn, HeatingMode, SharedData};
 use crate::brain::modes::intention::Intention;
 use crate::brain::modes::{HeatingState, InfoCache};
 use crate::brain::python_like::control::devices::Device;
+use crate::brain::python_like::control::heating_control::HeatPumpMode;
+use crate::brain::python_like::control::misc_control::WiserPowerCycle;
 use crate::brain::{modes, Brain, BrainFailure};
+use crate::expect_available;
+use crate::io::temperatures::Sensor;
+use crate::io::wiser::WiserManager;
 use crate::io::IOBundle;
+use crate::log_rate_limit::rate_limited;
+use crate::temp_format::{fmt_temp, set_temp_precision};
 use crate::time_util::mytime::TimeProvider;
+use chrono::{DateTime, Utc};
 use config::PythonBrainConfig;
 use itertools::Itertools;
 use log::{debug, error, info, trace, warn};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
 use std::time::{Duration, Instant};
 use tokio::runtime::Runtime;
 
-use super::modes::working_temp::WorkingTemperatureRange;
+use super::modes::working_temp::{WorkingRange, WorkingTemperatureRange};
 
 pub mod config;
 pub mod control;
+pub mod snapshot;
 
 #[cfg(test)]
 mod test;
@@ -57,10 +72,28 @@ impl FallbackWorkingRange {
     pub fn update(&mut self, range: WorkingTemperatureRange) {
         self.previous.replace((range, Instant::now()));
     }
+
+    /// Start again with the given default, forgetting the previous range as that may have
+    /// been worked out from a different config.
+    pub fn reset(&mut self, default: WorkingTemperatureRange) {
+        self.previous = None;
+        self.default = default;
+    }
 }
 
+const MAINTENANCE_REASON: &str = "Maintenance mode";
+const FORCED_REASON: &str = "Forced";
+const MISSING_ESSENTIAL_SENSORS_REASON: &str = "Missing essential sensors";
+/// How long to cut the wiser's power for when rebooting it, unless configured otherwise.
+const WISER_REBOOT_OFF_TIME: Duration = Duration::from_secs(30);
+
 pub struct PythonBrain {
+    /// The config in use, with away mode applied if we are away.
     config: PythonBrainConfig,
+    /// The config as read, before away mode is applied.
+    loaded_config: PythonBrainConfig,
+    /// Whether away mode has been turned on, see AwayConfig.
+    away: bool,
     /// The current state. None if just started and need to figure out what state to be in.
     heating_mode: Option<HeatingMode>,
     shared_data: SharedData,
@@ -68,19 +101,101 @@ pub struct PythonBrain {
     /// Whether we just reloaded / just restarted
     /// This is used to print additional one-time debugging information.
     just_reloaded: bool,
+    /// Whether we are being held in maintenance mode, where everything is kept off.
+    maintenance: bool,
+    /// A mode to switch into on the next run, regardless of what would normally be chosen.
+    forced_mode: Option<HeatingMode>,
+    missing_sensors: MissingSensorTracker,
+    /// Why the current mode was chosen, if known.
+    mode_reason: Option<String>,
+    /// Why we are staying off, while in Off mode.
+    off_reason: Option<String>,
+    /// Recent readings, to tell whether temperatures are rising or falling.
+    trends: TemperatureTrends,
+    clock_jumps: ClockJumpDetector,
+    /// When the immersion heater was last turned on or off by following the model.
+    immersion_heater_last_switch: Option<Instant>,
+    /// What has been running today, for the daily summary.
+    daily_activity: DailyActivity,
+    /// Loaded from the state file the first time it is needed.
+    pump_exercise: Option<PumpExercise>,
+    /// Cutting and restoring the wiser's power to reboot it.
+    wiser_power_cycle: WiserPowerCycle,
+    /// Whether to reboot the wiser on the next run.
+    wiser_reboot_requested: bool,
+    /// The outdoor temperature as of the last loop, if there is an outdoor sensor.
+    outdoor_temp: Option<f32>,
+    /// The readings from the last loop, kept for dumping the state.
+    last_temps: HashMap<Sensor, f32>,
+    /// The working range from the last loop, kept for dumping the state.
+    last_working_range: Option<WorkingRange>,
+    /// Where to read the config from when reloading.
+    config_dir: PathBuf,
 }
 
 impl PythonBrain {
     pub fn new(config: PythonBrainConfig) -> Self {
-        Self {
+        set_temp_precision(config.get_temp_log_precision());
+        let mut brain = Self {
             shared_data: SharedData::new(FallbackWorkingRange::new(
                 config.default_working_range.clone(),
             )),
+            loaded_config: config.clone(),
             config,
+            away: false,
             heating_mode: None,
             applied_boosts: AppliedBoosts::new(),
             just_reloaded: true,
-        }
+            maintenance: false,
+            forced_mode: None,
+            missing_sensors: MissingSensorTracker::default(),
+            mode_reason: None,
+            off_reason: None,
+            trends: TemperatureTrends::default(),
+            clock_jumps: ClockJumpDetector::default(),
+            immersion_heater_last_switch: None,
+            daily_activity: DailyActivity::default(),
+            pump_exercise: None,
+            wiser_power_cycle: WiserPowerCycle::default(),
+            wiser_reboot_requested: false,
+            outdoor_temp: None,
+            last_temps: HashMap::new(),
+            last_working_range: None,
+            config_dir: PathBuf::from("."),
+        };
+        brain.apply_away();
+        brain
+    }
+
+    /// Reload the config from the given directory rather than the working directory.
+    #[must_use]
+    pub fn with_config_dir(mut self, config_dir: PathBuf) -> Self {
+        self.config_dir = config_dir;
+        self
+    }
+
+    /// Whether we are in away mode, either from the control socket or the config.
+    pub fn is_away(&self) -> bool {
+        self.away || self.loaded_config.away.active
+    }
+
+    /// Work out the config to use from the loaded config, lowering the targets if we are away.
+    fn apply_away(&mut self) {
+        self.config = if self.is_away() {
+            self.loaded_config.with_away_applied()
+        } else {
+            self.loaded_config.clone()
+        };
+        self.shared_data.get_fallback_working_range().reset(self.config.default_working_range.clone());
+    }
+
+    pub fn get_heating_mode(&self) -> Option<&HeatingMode> {
+        self.heating_mode.as_ref()
+    }
+
+    /// Why the current heating mode was chosen, if known.
+    pub fn get_mode_reason(&self) -> Option<&str> {
+        self.mode_reason.as_deref()
     }
 
     fn provide_debug_info(
@@ -89,10 +204,10 @@ impl PythonBrain {
         time_provider: &impl TimeProvider,
     ) -> Result<(), BrainFailure> {
         // Provide information on what active devices have actually been seen.
-        const CHECK_MINUTES: usize = 30;
+        const CHECK_MINUTES: u64 = 30;
         let active_devices: HashSet<Device> = io_bundle
             .active_devices()
-            .get_active_devices_within(&time_provider.get_utc_time(), CHECK_MINUTES)?
+            .get_active_devices_within(&time_provider.get_utc_time(), Duration::from_secs(60 * CHECK_MINUTES))?
             .into_iter()
             .collect();
 
@@ -133,10 +248,183 @@ impl PythonBrain {
             unused_devices
         );
 
+        if let Some(mode) = &self.heating_mode {
+            info!(
+                "Current mode: {} ({})",
+                mode.name(),
+                self.get_mode_reason().unwrap_or("no reason given")
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Switch straight into the forced mode, leaving it to decide what to do from the next run.
+    fn enter_forced_mode(
+        &mut self,
+        mut forced: HeatingMode,
+        runtime: &Runtime,
+        io_bundle: &mut IOBundle,
+    ) -> Result<(), BrainFailure> {
+        self.shared_data.notify_next_mode(&mut forced);
+        match &mut self.heating_mode {
+            Some(cur_mode) => {
+                info!("Forcing transition from {:?} to {:?}", cur_mode, forced);
+                cur_mode.transition_to(forced, &self.config, runtime, io_bundle)?;
+            }
+            None => {
+                info!("Forcing mode: {:?}", forced);
+                forced.enter(&self.config, runtime, io_bundle)?;
+                self.heating_mode = Some(forced);
+            }
+        }
+        self.mode_reason = Some(FORCED_REASON.to_owned());
+        self.off_reason = None;
+        self.shared_data.notify_entered_state();
+        Ok(())
+    }
+
+    /// Go into Off (unless already there) and turn off the heat pump, circulation pump and
+    /// immersion heater, giving the reason. The wiser's power is restored if it was cut.
+    fn switch_everything_off(
+        &mut self,
+        reason: &str,
+        runtime: &Runtime,
+        io_bundle: &mut IOBundle,
+        now: DateTime<Utc>,
+    ) -> Result<(), BrainFailure> {
+        match &mut self.heating_mode {
+            Some(HeatingMode::Off(_)) => {}
+            Some(cur_mode) => {
+                info!("{}: transitioning from {:?} to Off", reason, cur_mode);
+                cur_mode.transition_to(HeatingMode::off(), &self.config, runtime, io_bundle)?;
+                self.mode_reason = Some(reason.to_owned());
+                self.shared_data.notify_entered_state();
+            }
+            None => {
+                info!("{}: entering Off", reason);
+                let mut off = HeatingMode::off();
+                off.enter(&self.config, runtime, io_bundle)?;
+                self.heating_mode = Some(off);
+                self.mode_reason = Some(reason.to_owned());
+                self.shared_data.notify_entered_state();
+            }
+        }
+
+        // Staying off for this reason rather than anything the mode logic decided.
+        self.off_reason = None;
+
+        // Off mode may have been left with things running, e.g. by the pump exercise.
+        if let Some(pump_exercise) = &mut self.pump_exercise {
+            pump_exercise.cancel();
+        }
+        let heating = expect_available!(io_bundle.heating_control())?;
+        heating.set_heat_pump(HeatPumpMode::Off, Some("Holding everything off - turning off Heat Pump"))?;
+        heating.set_heat_circulation_pump(false, Some("Holding everything off - turning off Heat Circulation Pump"))?;
+
+        self.wiser_power_cycle.restore(io_bundle.misc_controls().as_wp(), now)?;
+
+        if io_bundle.misc_controls().try_get_immersion_heater()? {
+            info!("{}: turning off immersion heater", reason);
+            io_bundle.misc_controls().try_set_immersion_heater(false)?;
+        }
+        Ok(())
+    }
+
+    /// Record why we are staying off while in Off mode, as decided by the mode logic, logging it
+    /// whenever it changes.
+    fn update_off_reason(&mut self, off_reason: Option<String>) {
+        let off_reason = off_reason.filter(|_| matches!(self.heating_mode, Some(HeatingMode::Off(_))));
+        if off_reason != self.off_reason {
+            if let Some(reason) = &off_reason {
+                info!("Staying off: {}", reason);
+            }
+            self.off_reason = off_reason;
+        }
+    }
+
+    /// Restore the wiser's power once it has been cut for long enough. This is done before anything
+    /// else so the wiser isn't left without power, whatever else is going on.
+    fn restore_wiser_power(&mut self, io_bundle: &mut IOBundle, now: DateTime<Utc>) -> Result<(), BrainFailure> {
+        let off_time = self.config.wiser_outage.reboot.as_ref().map_or(WISER_REBOOT_OFF_TIME, |reboot| reboot.off_time);
+        self.wiser_power_cycle.update(io_bundle.misc_controls().as_wp(), now, off_time)
+    }
+
+    /// Cut the wiser's power if a reboot has been requested, or we haven't been able to contact it
+    /// for long enough (see WiserRebootConfig).
+    fn start_wiser_power_cycle(&mut self, io_bundle: &mut IOBundle, now: DateTime<Utc>) -> Result<(), BrainFailure> {
+        let reboot = self.config.wiser_outage.reboot.as_ref();
+        let wiser_power = io_bundle.misc_controls().as_wp();
+        if std::mem::take(&mut self.wiser_reboot_requested) {
+            self.wiser_power_cycle.start(wiser_power, now)?;
+        }
+
+        if let Some(reboot) = reboot {
+            let since_contact = self.shared_data.last_successful_contact.elapsed();
+            if since_contact >= reboot.after && self.wiser_power_cycle.can_start(now, reboot.min_interval) {
+                warn!(target: "wiser", "No contact with the wiser for {}s, power cycling it", since_contact.as_secs());
+                self.wiser_power_cycle.start(wiser_power, now)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Hold everything off, but carry on retrieving and logging wiser and temperature readings.
+    fn run_maintenance(
+        &mut self,
+        runtime: &Runtime,
+        io_bundle: &mut IOBundle,
+        now: DateTime<Utc>,
+    ) -> Result<(), BrainFailure> {
+        self.switch_everything_off(MAINTENANCE_REASON, runtime, io_bundle, now)?;
+
+        match runtime.block_on(io_bundle.wiser().get_heating_on()) {
+            Ok(on) => info!(target: "wiser", "Maintenance mode: wiser heating is {}", HeatingState::new(on)),
+            Err(_) => error!(target: "wiser", "Maintenance mode: failed to get whether heating was on"),
+        }
+
+        match runtime.block_on(io_bundle.temperature_manager().retrieve_temperatures()) {
+            Ok(temps) => debug!(target: "temps", "{}", format_temps(&temps)),
+            Err(err) => error!("Maintenance mode: error retrieving temperatures: {}", err),
+        }
+
         Ok(())
     }
 }
 
+/// Whether the wiser is calling for heat. If boost_counts_as_heating, a room with an active
+/// override also counts, even if the wiser's overall heating is off. Boosts we applied ourselves
+/// don't count, otherwise boosting while heating would keep the heating on forever.
+async fn get_wiser_heating_on(
+    wiser: &dyn WiserManager,
+    boost_counts_as_heating: bool,
+    our_boosts: &AppliedBoosts,
+    now: DateTime<Utc>,
+) -> Result<bool, ()> {
+    let heating_on = wiser.get_heating_on().await?;
+    if heating_on || !boost_counts_as_heating {
+        return Ok(heating_on);
+    }
+    match wiser.get_wiser_hub().get_room_data().await {
+        Ok(rooms) => {
+            let boosted = rooms.iter()
+                .filter(|room| room.has_active_override(now))
+                .filter(|room| room.get_name().is_none_or(|name| !our_boosts.get_applied_boosts().contains_key(name)))
+                .map(|room| room.get_name().map_or_else(|| room.get_id().to_string(), str::to_owned))
+                .collect_vec();
+            if boosted.is_empty() {
+                return Ok(false);
+            }
+            debug!(target: "wiser", "Wiser heating is off, but treating it as on due to overrides in: {}", boosted.join(", "));
+            Ok(true)
+        }
+        Err(e) => {
+            rate_limited!(warn, "boost_counts_as_heating", "Failed to get wiser room data to check for boosts: {:?}", e);
+            Ok(false)
+        }
+    }
+}
+
 fn prettify_devices(list: impl IntoIterator<Item = Device>) -> Vec<String> {
     list.into_iter()
         .sorted()
@@ -144,6 +432,15 @@ fn prettify_devices(list: impl IntoIterator<Item = Device>) -> Vec<String> {
         .collect_vec()
 }
 
+/// Formats all sensor temperatures on a single line, in sensor order.
+fn format_temps(temps: &HashMap<Sensor, f32>) -> String {
+    temps
+        .iter()
+        .sorted_by_key(|&(sensor, _)| sensor)
+        .map(|(sensor, temp)| format!("{}: {}", sensor, fmt_temp(*temp)))
+        .join(", ")
+}
+
 impl Default for PythonBrain {
     fn default() -> Self {
         PythonBrain::new(PythonBrainConfig::default())
@@ -157,14 +454,68 @@ impl Brain for PythonBrain {
         io_bundle: &mut IOBundle,
         time_provider: &impl TimeProvider,
     ) -> Result<(), BrainFailure> {
+        self.restore_wiser_power(io_bundle, time_provider.get_utc_time())?;
+
         if self.just_reloaded {
             self.provide_debug_info(io_bundle, time_provider)?;
             self.just_reloaded = false;
         }
 
+        if self.maintenance {
+            return self.run_maintenance(runtime, io_bundle, time_provider.get_utc_time());
+        }
+
+        // Read once for the whole loop, through the InfoCache.
+        let temps = runtime.block_on(io_bundle.temperature_manager().retrieve_temperatures());
+        if !self.config.get_essential_sensors().is_empty() {
+            let missing = match &temps {
+                Ok(temps) => self.config.find_missing_essential_sensors(temps),
+                Err(e) => {
+                    error!("Error retrieving temperatures to check for essential sensors: {}", e);
+                    self.config.find_missing_essential_sensors(&HashMap::new())
+                }
+            };
+            if !missing.is_empty() {
+                error!("Missing essential sensors: {}, keeping everything off", missing.iter().join(", "));
+                if let Some(forced) = self.forced_mode.take() {
+                    warn!("Not forcing {:?} while essential sensors are missing", forced);
+                }
+                return self.switch_everything_off(MISSING_ESSENTIAL_SENSORS_REASON, runtime, io_bundle, time_provider.get_utc_time());
+            }
+        }
+
+        if let Some(forced) = self.forced_mode.take() {
+            return self.enter_forced_mode(forced, runtime, io_bundle);
+        }
+
+        if self.is_away() {
+            rate_limited!(info, "away", "Away mode is on - working range lowered by {}, overruns by {} and the immersion heater by {}",
+                self.config.away.working_range_reduction, self.config.away.overrun_reduction, self.config.away.immersion_heater_reduction);
+        }
+
+        let clock_jump = self.clock_jumps.check(
+            Instant::now(),
+            time_provider.get_utc_time(),
+            &self.config.clock_jump_threshold,
+        );
+        if let Some(jump) = clock_jump {
+            warn!(
+                "Clock jumped by {}s since the last loop, now {} - re-evaluating the current mode",
+                jump.num_seconds(),
+                time_provider.get_utc_time()
+            );
+            // The readings so far are against the wrong times.
+            self.trends = TemperatureTrends::default();
+        }
+
         // Update our value of wiser's state if possible.
         match runtime
-            .block_on(io_bundle.wiser().get_heating_on())
+            .block_on(get_wiser_heating_on(
+                io_bundle.wiser(),
+                self.config.boost_counts_as_heating,
+                &self.applied_boosts,
+                time_provider.get_utc_time(),
+            ))
             .map(HeatingState::new)
         {
             Ok(wiser_heating_on_new) => {
@@ -175,22 +526,28 @@ impl Brain for PythonBrain {
                 }
             }
             Err(_) => {
-                // The wiser hub often doesn't respond. If this happens, carry on heating for a maximum of 1 hour.
+                // The wiser hub often doesn't respond. If this happens, carry on with the old value for a while.
                 error!(target: "wiser", "Failed to get whether heating was on. Using old value");
-                if Instant::now() - self.shared_data.last_successful_contact
-                    > Duration::from_secs(60 * 60)
-                {
-                    error!(target: "wiser", "Saying off - last successful contact too long ago: {}s ago", self.shared_data.last_successful_contact.elapsed().as_secs());
-                    self.shared_data.last_wiser_state = HeatingState::OFF;
+                let since_contact = self.shared_data.last_successful_contact.elapsed();
+                let assumed_state = self
+                    .config
+                    .wiser_outage
+                    .get_assumed_state(self.shared_data.last_wiser_state, since_contact);
+                if assumed_state != self.shared_data.last_wiser_state {
+                    error!(target: "wiser", "Saying {} - last successful contact too long ago: {}s ago", assumed_state, since_contact.as_secs());
+                    self.shared_data.last_wiser_state = assumed_state;
                 }
             }
         }
 
+        self.start_wiser_power_cycle(io_bundle, time_provider.get_utc_time())?;
+
         let working_temp_range = modes::heating_mode::get_working_temp_fn(
             self.shared_data.get_fallback_working_range(),
             io_bundle.wiser(),
             &self.config,
             runtime,
+            self.outdoor_temp,
         );
         let mut wiser_heating_state = self.shared_data.last_wiser_state;
 
@@ -200,12 +557,31 @@ impl Brain for PythonBrain {
             .iter()
             .find(|slot| slot.contains(&time_provider.get_utc_time()));
 
+        let mut ignored_wiser_reason = None;
         if let Some(slot) = ignore_wiser_heating_slot {
             debug!("Ignoring wiser heating due to slot: {slot}. Pretending its off. It was actually: {wiser_heating_state}");
+            if wiser_heating_state.is_on() {
+                ignored_wiser_reason = Some(format!("In no heating slot {}, ignoring the wiser calling for heat", slot));
+            }
             wiser_heating_state = HeatingState::OFF;
         }
 
-        let mut info_cache = InfoCache::create(wiser_heating_state, working_temp_range);
+        self.last_working_range = Some(working_temp_range.clone());
+        let mut info_cache = InfoCache::create(wiser_heating_state, working_temp_range)
+            .with_temps(temps)
+            .with_trends(self.trends.clone())
+            .with_last_overrun_finished(self.shared_data.get_last_overrun_finished())
+            .with_last_circulate_finished(self.shared_data.get_last_circulate_finished());
+
+        if let Some(smoothing) = self.config.get_tkbt_smoothing() {
+            if let Ok(temps) = runtime.block_on(info_cache.get_temps(io_bundle.temperature_manager())) {
+                let raw = temps.get(&Sensor::TKBT).copied();
+                if let (Some(raw), Some(smoothed)) = (raw, self.shared_data.smooth_tkbt(smoothing, raw)) {
+                    debug!(target: "temps", "TKBT: {} raw, {} smoothed", fmt_temp(raw), fmt_temp(smoothed));
+                    info_cache.set_smoothed_tkbt(smoothed);
+                }
+            }
+        }
 
         // Heating mode switches
         match &mut self.heating_mode {
@@ -214,6 +590,7 @@ impl Brain for PythonBrain {
                 let intention = Intention::finish();
                 let new_state = modes::heating_mode::handle_intention(
                     intention,
+                    None,
                     &mut info_cache,
                     io_bundle,
                     &self.config,
@@ -227,24 +604,57 @@ impl Brain for PythonBrain {
                     }
                     Some(mode) => mode,
                 };
-                info!("Entering mode: {:?}", new_mode);
+                if self.config.hp_circulation.forecast_diagnostic {
+                    if let Ok(temps) = runtime.block_on(info_cache.get_temps(io_bundle.temperature_manager())) {
+                        self.shared_data.check_hx_forecast(None, &new_mode, &temps, &self.config.hp_circulation);
+                    }
+                }
+                self.mode_reason = info_cache.get_mode_reason().map(str::to_owned);
+                info!("Entering mode: {:?} ({})", new_mode, self.mode_reason.as_deref().unwrap_or("no reason given"));
                 new_mode.enter(&self.config, runtime, io_bundle)?;
                 self.heating_mode = Some(new_mode);
                 self.shared_data.notify_entered_state();
             }
             Some(cur_mode) => {
                 trace!("Current mode: {:?}", cur_mode);
-                let next_mode = cur_mode.update(
-                    &mut self.shared_data,
-                    runtime,
-                    &self.config,
-                    io_bundle,
-                    &mut info_cache,
-                    time_provider,
-                )?;
-                if let Some(next_mode) = next_mode {
+                let next_mode = if clock_jump.is_some() {
+                    // The mode may only be in place because of the time we thought it was.
+                    let next_mode = modes::heating_mode::handle_intention(
+                        Intention::finish(),
+                        Some(cur_mode),
+                        &mut info_cache,
+                        io_bundle,
+                        &self.config,
+                        runtime,
+                        &time_provider.get_utc_time(),
+                    )?;
+                    self.shared_data.apply_turning_on_lockout(next_mode, self.config.turning_on_fault_backoff, &mut info_cache, Instant::now())
+                } else {
+                    cur_mode.update(
+                        &mut self.shared_data,
+                        runtime,
+                        &self.config,
+                        io_bundle,
+                        &mut info_cache,
+                        time_provider,
+                    )?
+                };
+                if let Some(mut next_mode) = next_mode {
                     if &next_mode != cur_mode {
-                        info!("Transitioning from {:?} to {:?}", cur_mode, next_mode);
+                        if self.config.hp_circulation.forecast_diagnostic {
+                            if let Ok(temps) = runtime.block_on(info_cache.get_temps(io_bundle.temperature_manager())) {
+                                self.shared_data.check_hx_forecast(Some(cur_mode), &next_mode, &temps, &self.config.hp_circulation);
+                            }
+                        }
+                        self.shared_data.notify_leaving_mode(cur_mode, time_provider.get_utc_time());
+                        self.shared_data.notify_next_mode(&mut next_mode);
+                        self.mode_reason = info_cache.get_mode_reason().map(str::to_owned);
+                        info!(
+                            "Transitioning from {:?} to {:?} ({})",
+                            cur_mode,
+                            next_mode,
+                            self.mode_reason.as_deref().unwrap_or("no reason given")
+                        );
                         cur_mode.transition_to(next_mode, &self.config, runtime, io_bundle)?;
                         self.shared_data.notify_entered_state();
                     } else {
@@ -254,13 +664,28 @@ impl Brain for PythonBrain {
             }
         }
 
+        let off_reason = match info_cache.get_off_reason() {
+            Some(modes::heating_mode::WISER_OFF_REASON) if ignored_wiser_reason.is_some() => ignored_wiser_reason,
+            off_reason => off_reason.map(str::to_owned),
+        };
+        self.update_off_reason(off_reason);
+
+        if let (Some(config), Some(heating_control)) = (self.config.get_pump_exercise(), expect_available_fn(io_bundle.heating_control())) {
+            let pump_exercise = self.pump_exercise
+                .get_or_insert_with(|| PumpExercise::load(config.state_file.as_deref()));
+            pump_exercise.update(
+                config,
+                time_provider.get_utc_time(),
+                matches!(self.heating_mode, Some(HeatingMode::Off(_))),
+                info_cache.heating_on(),
+                heating_control,
+            )?;
+        }
+
         // Immersion heater
         let temps = runtime.block_on(info_cache.get_temps(io_bundle.temperature_manager()));
-        if temps.is_err() {
-            error!(
-                "Error retrieving temperatures: {}",
-                temps.as_ref().unwrap_err()
-            );
+        if let Err(e) = &temps {
+            rate_limited!(error, "immersion_heater_temps", "Error retrieving temperatures: {}", e);
             if io_bundle.misc_controls().try_get_immersion_heater()? {
                 error!("Turning off immersion heater since we didn't get temperatures");
                 io_bundle.misc_controls().try_set_immersion_heater(false)?;
@@ -268,13 +693,54 @@ impl Brain for PythonBrain {
             return Ok(());
         }
         let temps = temps.ok().unwrap();
+        debug!(target: "temps", "{}", format_temps(&temps));
+        self.missing_sensors.update(&temps, self.config.get_missing_sensors());
+        self.trends.update(time_provider.get_utc_time(), &temps);
+        self.outdoor_temp = self.config.get_outdoor_temp(&temps);
+        self.last_temps = temps.clone();
+        if let Some(sensor) = self.config.get_outdoor_sensor() {
+            match self.outdoor_temp {
+                Some(temp) => debug!(target: "temps", "Outdoor ({}): {}", sensor, fmt_temp(temp)),
+                None => rate_limited!(warn, "outdoor_sensor", "No reading for outdoor sensor {}", sensor),
+            }
+        }
+        // Only needed for these, so not worth failing over.
+        let wants_heat_pump_mode = self.config.get_immersion_heater_model().suppress_while_heating_tank()
+            || self.config.get_daily_summary_time().is_some()
+            || self.config.get_boost_active_rooms().only_while_heating();
+        let heat_pump_mode = match expect_available_fn(io_bundle.heating_control()) {
+            Some(heating_control) if wants_heat_pump_mode => match heating_control.try_get_heat_pump() {
+                Ok(mode) => Some(mode),
+                Err(e) => {
+                    rate_limited!(warn, "heat_pump_mode", "Failed to get the heat pump mode, carrying on without it: {}", e);
+                    None
+                }
+            },
+            _ => None,
+        };
+        let heat_pump_on = heat_pump_mode.as_ref().is_some_and(HeatPumpMode::is_hp_on);
+        let heating = info_cache.heating_on() || heat_pump_mode.as_ref().is_some_and(HeatPumpMode::is_heating_rooms);
         follow_ih_model(
             time_provider,
             &temps,
             io_bundle.misc_controls().as_ih(),
+            heat_pump_mode,
             self.config.get_immersion_heater_model(),
+            &mut self.immersion_heater_last_switch,
+            Instant::now(),
         )?;
 
+        let summary = self.daily_activity.record(
+            time_provider.get_utc_time(),
+            self.heating_mode.as_ref().map(HeatingMode::name),
+            heat_pump_on,
+            io_bundle.misc_controls().try_get_immersion_heater()?,
+            self.config.get_daily_summary_time(),
+        );
+        if let Some(summary) = summary {
+            info!("{}", summary);
+        }
+
         // Active device/room boosting.
         match io_bundle
             .active_devices()
@@ -284,8 +750,11 @@ impl Brain for PythonBrain {
                 match runtime.block_on(update_boosted_rooms(
                     &mut self.applied_boosts,
                     self.config.get_boost_active_rooms(),
+                    self.config.unnamed_rooms,
                     devices,
+                    heating,
                     io_bundle.wiser(),
+                    time_provider,
                 )) {
                     Ok(_) => {}
                     Err(error) => {
@@ -300,13 +769,74 @@ impl Brain for PythonBrain {
     }
 
     fn reload_config(&mut self) {
-        match config::try_read_python_brain_config() {
+        match config::try_read_python_brain_config(&self.config_dir) {
             None => error!("Failed to read python brain config, keeping previous config"),
             Some(config) => {
-                self.config = config;
+                set_temp_precision(config.get_temp_log_precision());
+                let changes = self.loaded_config.diff(&config);
+                self.loaded_config = config;
+                self.apply_away();
                 self.just_reloaded = true;
-                info!("Reloaded config");
+                if changes.is_empty() {
+                    info!("Reloaded config, nothing changed");
+                } else {
+                    info!("Reloaded config, {} setting(s) changed:", changes.len());
+                    for change in changes {
+                        info!("  {}", change);
+                    }
+                }
             }
         }
     }
+
+    fn set_away(&mut self, away: bool) {
+        if self.away == away {
+            info!("Away mode already {}", if away { "on" } else { "off" });
+            return;
+        }
+        self.away = away;
+        self.apply_away();
+        if away {
+            info!("Entering away mode - lowering targets until turned off");
+        } else if self.is_away() {
+            warn!("Turned off away mode, but it is still active in the config");
+        } else {
+            info!("Left away mode - targets back to normal");
+        }
+    }
+
+    fn reboot_wiser(&mut self) {
+        info!(target: "wiser", "Will reboot the wiser on the next run");
+        self.wiser_reboot_requested = true;
+    }
+
+    fn force_mode(&mut self, mode: &str) -> Result<(), String> {
+        let mode = HeatingMode::from_name(mode)
+            .ok_or_else(|| format!("Cannot force unknown mode {:?}", mode))?;
+        info!("Will force mode {} on the next run", mode.name());
+        self.forced_mode = Some(mode);
+        Ok(())
+    }
+
+    fn dump_state(&self, now: DateTime<Utc>) -> Result<String, String> {
+        serde_json::to_string_pretty(&self.snapshot(now))
+            .map_err(|e| format!("Failed to serialize state: {}", e))
+    }
+
+    fn toggle_maintenance(&mut self) {
+        self.set_maintenance(!self.maintenance);
+    }
+
+    fn set_maintenance(&mut self, maintenance: bool) {
+        if self.maintenance == maintenance {
+            info!("Maintenance mode already {}", if maintenance { "on" } else { "off" });
+            return;
+        }
+        self.maintenance = maintenance;
+        if self.maintenance {
+            info!("Entering maintenance mode - holding everything off until released");
+        } else {
+            info!("Released from maintenance mode - resuming normal operation");
+        }
+    }
 }
@@ -0,0 +1,131 @@
+use crate::brain::modes::heating_mode::HeatingMode;
+use crate::brain::python_like::PythonBrain;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// The internal state of the brain at a point in time, to be dumped out for debugging.
+#[derive(Serialize, Debug)]
+pub struct BrainSnapshot {
+    pub time: DateTime<Utc>,
+    /// The name of the current mode, if there is one yet.
+    pub mode: Option<String>,
+    /// The full state of the current mode.
+    pub mode_state: Option<String>,
+    pub mode_reason: Option<String>,
+    /// Why we are staying off, while in Off mode.
+    pub off_reason: Option<String>,
+    pub maintenance: bool,
+    pub away: bool,
+    pub wiser_heating: String,
+    pub seconds_in_mode: u64,
+    pub seconds_since_wiser_contact: u64,
+    pub working_range: Option<WorkingRangeSnapshot>,
+    /// The readings from the last loop.
+    pub temps: BTreeMap<String, f32>,
+    pub outdoor_temp: Option<f32>,
+    /// The boosts we have applied, by room.
+    pub applied_boosts: BTreeMap<String, String>,
+    pub seconds_since_immersion_heater_switch: Option<u64>,
+    /// How long until the current hot water heat up is expected to finish, if known.
+    pub dhw_minutes_to_target: Option<f32>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct WorkingRangeSnapshot {
+    pub min: f32,
+    pub max: f32,
+    pub room: Option<String>,
+}
+
+impl PythonBrain {
+    /// Take a copy of the current state. This only reads what is already held, so is cheap
+    /// enough to do in the middle of the control loop.
+    pub fn snapshot(&self, now: DateTime<Utc>) -> BrainSnapshot {
+        BrainSnapshot {
+            time: now,
+            mode: self.heating_mode.as_ref().map(|mode| mode.name().to_owned()),
+            mode_state: self.heating_mode.as_ref().map(|mode| format!("{:?}", mode)),
+            mode_reason: self.mode_reason.clone(),
+            off_reason: self.off_reason.clone(),
+            maintenance: self.maintenance,
+            away: self.is_away(),
+            wiser_heating: self.shared_data.last_wiser_state.to_string(),
+            seconds_in_mode: self.shared_data.get_entered_state().elapsed().as_secs(),
+            seconds_since_wiser_contact: self.shared_data.last_successful_contact.elapsed().as_secs(),
+            working_range: self.last_working_range.as_ref().map(|range| WorkingRangeSnapshot {
+                min: range.get_min(),
+                max: range.get_max(),
+                room: range.get_room().map(|room| room.get_name().to_owned()),
+            }),
+            temps: self.last_temps.iter()
+                .map(|(sensor, temp)| (sensor.to_string(), *temp))
+                .collect(),
+            outdoor_temp: self.outdoor_temp,
+            applied_boosts: self.applied_boosts.get_applied_boosts().iter()
+                .map(|(room, boost)| (room.clone(), boost.to_string()))
+                .collect(),
+            seconds_since_immersion_heater_switch: self.immersion_heater_last_switch.map(|last| last.elapsed().as_secs()),
+            dhw_minutes_to_target: match &self.heating_mode {
+                Some(HeatingMode::DhwOnly(mode)) => mode.estimate_minutes_to_target(),
+                _ => None,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::brain::python_like::config::PythonBrainConfig;
+    use crate::brain::{Brain, BrainFailure};
+    use crate::io::dummy_io_bundle::new_dummy_io;
+    use crate::io::temperatures::Sensor;
+    use crate::io::wiser::dummy::ModifyState;
+    use crate::time_util::mytime::DummyTimeProvider;
+    use crate::time_util::test_utils::utc_datetime;
+    use serde_json::Value;
+    use tokio::runtime::Runtime;
+
+    #[test]
+    fn test_snapshot_before_running() {
+        let brain = PythonBrain::new(PythonBrainConfig::default());
+        let json: Value = serde_json::from_str(&brain.dump_state(utc_datetime(2024, 2, 3, 12, 0, 0)).unwrap()).unwrap();
+
+        assert_eq!(json["time"], "2024-02-03T12:00:00Z");
+        assert_eq!(json["mode"], Value::Null);
+        assert_eq!(json["maintenance"], false);
+        assert_eq!(json["working_range"], Value::Null);
+        assert_eq!(json["temps"], serde_json::json!({}));
+        assert_eq!(json["applied_boosts"], serde_json::json!({}));
+        assert_eq!(json["dhw_minutes_to_target"], Value::Null);
+        assert_eq!(json["off_reason"], Value::Null);
+    }
+
+    #[test]
+    fn test_snapshot_serialization() -> Result<(), BrainFailure> {
+        let rt = Runtime::new().expect("Failed to create runtime.");
+        let mut brain = PythonBrain::new(PythonBrainConfig::default());
+        let (mut io_bundle, mut handle) = new_dummy_io();
+        let now = utc_datetime(2024, 2, 3, 12, 0, 0);
+
+        handle.send_wiser(ModifyState::TurnOffHeating);
+        handle.send_temp(Sensor::TKBT, 45.0);
+        handle.send_temp(Sensor::HXOR, 30.5);
+        brain.run(&rt, &mut io_bundle, &DummyTimeProvider::new(now))?;
+
+        let snapshot = brain.snapshot(now);
+        assert_eq!(snapshot.mode.as_deref(), Some("Off"));
+        assert_eq!(snapshot.off_reason.as_deref(), Some("Wiser not calling for heat"));
+        assert_eq!(snapshot.temps, BTreeMap::from([("HXOR".to_owned(), 30.5), ("TKBT".to_owned(), 45.0)]));
+
+        let json: Value = serde_json::from_str(&brain.dump_state(now).unwrap()).unwrap();
+        assert_eq!(json["mode"], "Off");
+        assert_eq!(json["temps"]["TKBT"], 45.0);
+        assert_eq!(json["wiser_heating"], snapshot.wiser_heating);
+        assert!(json["mode_state"].as_str().unwrap().starts_with("Off"));
+        assert!(json["working_range"]["min"].is_number());
+        assert!(json["working_range"]["max"].is_number());
+        Ok(())
+    }
+}
@@ -1,21 +1,29 @@
+use crate::brain::boost_active_rooms::AppliedBoosts;
 use crate::brain::modes::dhw_only::DhwOnlyMode;
 use crate::brain::modes::heating_mode::HeatingMode;
 use crate::brain::modes::on::OnMode;
 use crate::brain::modes::turning_on::TurningOnMode;
 use crate::brain::python_like::config::PythonBrainConfig;
-use crate::brain::python_like::PythonBrain;
+use crate::brain::python_like::{format_temps, get_wiser_heating_on, PythonBrain, FORCED_REASON, MAINTENANCE_REASON, MISSING_ESSENTIAL_SENSORS_REASON};
 use crate::brain::python_like::config::overrun_config::DhwBap;
+use crate::brain::python_like::control::heating_control::HeatPumpMode;
 use crate::brain::{Brain, BrainFailure};
-use crate::io::dummy_io_bundle::new_dummy_io;
+use crate::expect_available;
+use crate::io::dummy_io_bundle::{new_dummy_io, new_dummy_io_with_faults};
+use crate::io::faults::{Fault, Faults};
 use crate::io::temperatures::dummy::ModifyState as TModifyState;
 use crate::io::temperatures::Sensor;
 use crate::io::wiser::dummy::ModifyState as WModifyState;
+use crate::io::wiser::hub::{RetrieveDataError, WiserData, WiserHub, WiserRoomData};
+use crate::io::wiser::WiserManager;
 use crate::time_util::mytime::DummyTimeProvider;
 use crate::time_util::mytime::TimeProvider;
 use crate::time_util::test_utils::{date, time, utc_time_slot};
 use chrono::{DateTime, Duration, TimeZone, Utc};
 use log::info;
+use std::collections::HashMap;
 use std::time::Instant;
+use async_trait::async_trait;
 use tokio::runtime::Runtime;
 
 fn insignificant_time() -> DateTime<Utc> {
@@ -96,6 +104,10 @@ fn test_ignore_wiser_while_off() -> Result<(), BrainFailure> {
 
     brain.run(&rt, &mut io_bundle, &time_provider)?;
     assert_eq!(brain.heating_mode, Some(HeatingMode::off()));
+    assert_eq!(
+        brain.off_reason,
+        Some(format!("In no heating slot {}, ignoring the wiser calling for heat", brain.config.get_no_heating()[0])),
+    );
 
     Ok(())
 }
@@ -194,3 +206,550 @@ fn test_ignore_wiser_into_overrun() -> Result<(), BrainFailure> {
 
     Ok(())
 }
+
+#[test]
+fn test_format_temps() {
+    let temps = HashMap::from([
+        (Sensor::TKBT, 35.0),
+        (Sensor::HPRT, 50.123),
+        (Sensor::from("ZONE1"), 12.5),
+        (Sensor::HXIF, 31.0),
+    ]);
+
+    assert_eq!(
+        format_temps(&temps),
+        "TKBT: 35.00, HPRT: 50.12, HXIF: 31.00, zone1: 12.50"
+    );
+}
+
+/// Test that nothing is turned on while held in maintenance mode, and that normal operation resumes once released.
+#[test_log::test]
+fn test_maintenance_holds_off() -> Result<(), BrainFailure> {
+    let rt = Runtime::new().expect("Failed to create runtime.");
+    let mut brain = PythonBrain::new(PythonBrainConfig::default());
+    let (mut io_bundle, mut handle) = new_dummy_io();
+
+    let fixed_time = insignificant_time();
+
+    handle.send_wiser(WModifyState::SetHeatingOffTime(
+        fixed_time + Duration::seconds(10 * 60),
+    ));
+    handle.send_steady_temps(&[(Sensor::HPRT, 50.0)]);
+
+    let time_provider = DummyTimeProvider::new(fixed_time);
+
+    let started = Instant::now() - time::Duration::minutes(10);
+    brain.heating_mode = Some(HeatingMode::On(OnMode::new(true, started)));
+    expect_available!(io_bundle.heating_control())?.try_set_heat_pump(HeatPumpMode::HeatingOnly)?;
+    expect_available!(io_bundle.heating_control())?.try_set_heat_circulation_pump(true)?;
+    io_bundle.misc_controls().try_set_immersion_heater(true)?;
+
+    brain.toggle_maintenance();
+    for _ in 0..3 {
+        brain.run(&rt, &mut io_bundle, &time_provider)?;
+        assert_eq!(brain.heating_mode, Some(HeatingMode::off()));
+        assert_eq!(brain.get_mode_reason(), Some(MAINTENANCE_REASON));
+        assert_eq!(brain.off_reason, None);
+
+        let heating = expect_available!(io_bundle.heating_control())?;
+        assert_eq!(heating.try_get_heat_pump()?, HeatPumpMode::Off, "HP should be off");
+        assert!(!heating.try_get_heat_circulation_pump()?, "CP should be off");
+        assert!(!io_bundle.misc_controls().try_get_immersion_heater()?, "IH should be off");
+    }
+
+    brain.toggle_maintenance();
+    brain.run(&rt, &mut io_bundle, &time_provider)?;
+    assert!(
+        matches!(brain.heating_mode, Some(HeatingMode::TurningOn(_))),
+        "Should have resumed and started turning on, actually in: {:?}",
+        brain.heating_mode
+    );
+    assert_eq!(
+        brain.get_mode_reason(),
+        Some("Heat pump off, TurningOn based on the current temperatures")
+    );
+
+    Ok(())
+}
+
+/// Test that holding everything off turns off the heat pump and circulation pump even if already
+/// in Off mode, as something (e.g. the pump exercise) may have left them running.
+#[test_log::test]
+fn test_hold_off_from_off_mode() -> Result<(), BrainFailure> {
+    let rt = Runtime::new().expect("Failed to create runtime.");
+    let config: PythonBrainConfig = toml::from_str(r#"essential_sensors = ["TKBT"]"#).expect("Failed to deserialize config");
+    let time_provider = DummyTimeProvider::new(insignificant_time());
+
+    for maintenance in [true, false] {
+        let mut brain = PythonBrain::new(config.clone());
+        let (mut io_bundle, mut handle) = new_dummy_io();
+        handle.send_wiser(WModifyState::TurnOffHeating);
+        if maintenance {
+            handle.send_temp(Sensor::TKBT, 35.0);
+            brain.toggle_maintenance();
+        }
+
+        brain.heating_mode = Some(HeatingMode::off());
+        expect_available!(io_bundle.heating_control())?.try_set_heat_pump(HeatPumpMode::DrainTank)?;
+        expect_available!(io_bundle.heating_control())?.try_set_heat_circulation_pump(true)?;
+
+        brain.run(&rt, &mut io_bundle, &time_provider)?;
+        assert_eq!(brain.heating_mode, Some(HeatingMode::off()));
+        let heating = expect_available!(io_bundle.heating_control())?;
+        assert_eq!(heating.try_get_heat_pump()?, HeatPumpMode::Off, "HP should be off (maintenance: {})", maintenance);
+        assert!(!heating.try_get_heat_circulation_pump()?, "CP should be off (maintenance: {})", maintenance);
+    }
+    Ok(())
+}
+
+/// Test that away mode lowers the targets the brain uses, and that turning it off restores them.
+#[test]
+fn test_away_mode() {
+    let mut config: PythonBrainConfig = toml::from_str(r#"
+[[overrun_during.slots]]
+slot = { type = "Utc", start = "01:00:00", end = "04:30:00" }
+temps = { sensor = "TKTP", min = 40.0, max = 50.0 }
+"#).expect("Failed to deserialize config");
+    let mut brain = PythonBrain::new(config.clone());
+    assert!(!brain.is_away());
+
+    brain.set_away(true);
+    assert!(brain.is_away());
+    assert_eq!(brain.config, config.with_away_applied());
+    assert_eq!(
+        brain.config.get_overrun_during().slots[0].temps.max,
+        50.0 - config.away.overrun_reduction
+    );
+    assert_ne!(brain.config.default_working_range, config.default_working_range);
+    assert_eq!(
+        brain.shared_data.get_fallback_working_range().get_fallback(),
+        &config.with_away_applied().default_working_range,
+        "The fallback should be lowered too"
+    );
+
+    brain.set_away(false);
+    assert!(!brain.is_away());
+    assert_eq!(brain.config, config);
+    assert_eq!(brain.shared_data.get_fallback_working_range().get_fallback(), &config.default_working_range);
+
+    config.away.active = true;
+    let brain = PythonBrain::new(config.clone());
+    assert!(brain.is_away(), "Should be away from the config alone");
+    assert_eq!(brain.config, config.with_away_applied());
+}
+
+/// Test that requesting a wiser reboot cuts its power, then restores it.
+#[test_log::test]
+fn test_reboot_wiser() -> Result<(), BrainFailure> {
+    let rt = Runtime::new().expect("Failed to create runtime.");
+    let mut brain = PythonBrain::new(PythonBrainConfig::default());
+    let (mut io_bundle, _handle) = new_dummy_io();
+    let start = insignificant_time();
+
+    brain.run(&rt, &mut io_bundle, &DummyTimeProvider::new(start))?;
+    assert!(io_bundle.misc_controls().try_get_wiser_power()?, "Should have power normally");
+
+    brain.reboot_wiser();
+    brain.run(&rt, &mut io_bundle, &DummyTimeProvider::new(start))?;
+    assert!(!io_bundle.misc_controls().try_get_wiser_power()?, "Should have cut the wiser's power");
+
+    brain.run(&rt, &mut io_bundle, &DummyTimeProvider::new(start + Duration::seconds(10)))?;
+    assert!(!io_bundle.misc_controls().try_get_wiser_power()?, "Not off for long enough");
+
+    brain.run(&rt, &mut io_bundle, &DummyTimeProvider::new(start + Duration::seconds(30)))?;
+    assert!(io_bundle.misc_controls().try_get_wiser_power()?, "Should have restored the wiser's power");
+    Ok(())
+}
+
+/// Test that the wiser's power is restored on time while a mode is forced, and straight away
+/// when entering maintenance.
+#[test_log::test]
+fn test_wiser_power_restored_when_returning_early() -> Result<(), BrainFailure> {
+    let rt = Runtime::new().expect("Failed to create runtime.");
+    let mut brain = PythonBrain::new(PythonBrainConfig::default());
+    let (mut io_bundle, _handle) = new_dummy_io();
+    let start = insignificant_time();
+
+    brain.reboot_wiser();
+    brain.run(&rt, &mut io_bundle, &DummyTimeProvider::new(start))?;
+    assert!(!io_bundle.misc_controls().try_get_wiser_power()?, "Should have cut the wiser's power");
+
+    brain.force_mode("Off").expect("Should be a valid mode");
+    brain.run(&rt, &mut io_bundle, &DummyTimeProvider::new(start + Duration::seconds(30)))?;
+    assert!(io_bundle.misc_controls().try_get_wiser_power()?, "Should have restored the power despite forcing a mode");
+
+    brain.reboot_wiser();
+    brain.run(&rt, &mut io_bundle, &DummyTimeProvider::new(start + Duration::seconds(60)))?;
+    assert!(!io_bundle.misc_controls().try_get_wiser_power()?, "Should have cut the wiser's power again");
+
+    brain.toggle_maintenance();
+    brain.run(&rt, &mut io_bundle, &DummyTimeProvider::new(start + Duration::seconds(61)))?;
+    assert!(io_bundle.misc_controls().try_get_wiser_power()?, "Maintenance should restore the power straight away");
+    Ok(())
+}
+
+/// Test that the wiser is power cycled exactly once when it stays unresponsive,
+/// until the minimum interval between reboots has passed.
+#[test_log::test]
+fn test_wiser_reboot_on_outage() -> Result<(), BrainFailure> {
+    let rt = Runtime::new().expect("Failed to create runtime.");
+    let config = toml::from_str(r#"
+[wiser_outage.reboot]
+after = 600
+off_time = 30
+min_interval = 3600
+"#).expect("Failed to deserialize config");
+    let mut brain = PythonBrain::new(config);
+    let faults = Faults::default();
+    let (mut io_bundle, _handle) = new_dummy_io_with_faults(&faults);
+    let start = insignificant_time();
+    faults.set(Fault::WiserTimeout, true);
+
+    brain.shared_data.last_successful_contact = Instant::now() - std::time::Duration::from_secs(5 * 60);
+    brain.run(&rt, &mut io_bundle, &DummyTimeProvider::new(start))?;
+    assert!(io_bundle.misc_controls().try_get_wiser_power()?, "Not long enough without contact");
+
+    // Still no contact, so carry on failing every 30s for just under an hour.
+    brain.shared_data.last_successful_contact = Instant::now() - std::time::Duration::from_secs(11 * 60);
+    let mut power_cuts = 0;
+    let mut had_power = true;
+    for i in 0..120 {
+        brain.run(&rt, &mut io_bundle, &DummyTimeProvider::new(start + Duration::seconds(30 * i)))?;
+        let has_power = io_bundle.misc_controls().try_get_wiser_power()?;
+        if had_power && !has_power {
+            power_cuts += 1;
+        }
+        if i == 1 {
+            assert!(has_power, "Should have restored the power after 30s");
+        }
+        had_power = has_power;
+    }
+    assert_eq!(power_cuts, 1, "Should only power cycle once within the minimum interval");
+    assert!(had_power);
+
+    brain.run(&rt, &mut io_bundle, &DummyTimeProvider::new(start + Duration::seconds(30 + 3600)))?;
+    assert!(!io_bundle.misc_controls().try_get_wiser_power()?, "Should power cycle again once the minimum interval has passed");
+    Ok(())
+}
+
+/// Test that everything is kept off while an essential sensor is missing, and that normal operation resumes once it is back.
+#[test_log::test]
+fn test_missing_essential_sensor() -> Result<(), BrainFailure> {
+    let rt = Runtime::new().expect("Failed to create runtime.");
+    let config = toml::from_str(r#"essential_sensors = ["TKBT", "HPRT"]"#).expect("Failed to deserialize config");
+    let mut brain = PythonBrain::new(config);
+    let (mut io_bundle, mut handle) = new_dummy_io();
+
+    let fixed_time = insignificant_time();
+
+    handle.send_wiser(WModifyState::SetHeatingOffTime(
+        fixed_time + Duration::seconds(10 * 60),
+    ));
+    // Missing HPRT, which is essential.
+    handle.send_steady_temps(&[]);
+
+    let time_provider = DummyTimeProvider::new(fixed_time);
+
+    let started = Instant::now() - time::Duration::minutes(10);
+    brain.heating_mode = Some(HeatingMode::On(OnMode::new(true, started)));
+    expect_available!(io_bundle.heating_control())?.try_set_heat_pump(HeatPumpMode::HeatingOnly)?;
+    expect_available!(io_bundle.heating_control())?.try_set_heat_circulation_pump(true)?;
+    io_bundle.misc_controls().try_set_immersion_heater(true)?;
+
+    for _ in 0..2 {
+        brain.run(&rt, &mut io_bundle, &time_provider)?;
+        assert_eq!(brain.heating_mode, Some(HeatingMode::off()));
+        assert_eq!(brain.get_mode_reason(), Some(MISSING_ESSENTIAL_SENSORS_REASON));
+
+        let heating = expect_available!(io_bundle.heating_control())?;
+        assert_eq!(heating.try_get_heat_pump()?, HeatPumpMode::Off, "HP should be off");
+        assert!(!heating.try_get_heat_circulation_pump()?, "CP should be off");
+        assert!(!io_bundle.misc_controls().try_get_immersion_heater()?, "IH should be off");
+    }
+
+    brain.force_mode("On").expect("Should be able to force On");
+    brain.run(&rt, &mut io_bundle, &time_provider)?;
+    assert_eq!(brain.heating_mode, Some(HeatingMode::off()), "Shouldn't force a mode while missing essential sensors");
+    assert_eq!(brain.get_mode_reason(), Some(MISSING_ESSENTIAL_SENSORS_REASON));
+
+    handle.send_temp(Sensor::HPRT, 50.0);
+    brain.run(&rt, &mut io_bundle, &time_provider)?;
+    assert!(
+        matches!(brain.heating_mode, Some(HeatingMode::TurningOn(_))),
+        "Should have resumed and started turning on, actually in: {:?}",
+        brain.heating_mode
+    );
+
+    Ok(())
+}
+
+/// Test that failing to read the temperatures while heating turns everything off, and that heating resumes once they are back.
+#[test_log::test]
+fn test_injected_temperature_read_fault() -> Result<(), BrainFailure> {
+    let rt = Runtime::new().expect("Failed to create runtime.");
+    let mut brain = PythonBrain::new(PythonBrainConfig::default());
+    let faults = Faults::default();
+    let (mut io_bundle, mut handle) = new_dummy_io_with_faults(&faults);
+
+    let fixed_time = insignificant_time();
+
+    handle.send_wiser(WModifyState::SetHeatingOffTime(
+        fixed_time + Duration::seconds(10 * 60),
+    ));
+    handle.send_steady_temps(&[(Sensor::HPRT, 50.0)]);
+
+    let time_provider = DummyTimeProvider::new(fixed_time);
+
+    let started = Instant::now() - time::Duration::minutes(10);
+    brain.heating_mode = Some(HeatingMode::On(OnMode::new(true, started)));
+    expect_available!(io_bundle.heating_control())?.try_set_heat_pump(HeatPumpMode::HeatingOnly)?;
+    expect_available!(io_bundle.heating_control())?.try_set_heat_circulation_pump(true)?;
+    io_bundle.misc_controls().try_set_immersion_heater(true)?;
+
+    faults.set(Fault::TemperatureRead, true);
+    brain.run(&rt, &mut io_bundle, &time_provider)?;
+    assert_eq!(brain.heating_mode, Some(HeatingMode::off()));
+    let heating = expect_available!(io_bundle.heating_control())?;
+    assert_eq!(heating.try_get_heat_pump()?, HeatPumpMode::Off, "HP should be off");
+    assert!(!io_bundle.misc_controls().try_get_immersion_heater()?, "IH should be off");
+
+    faults.set(Fault::TemperatureRead, false);
+    brain.run(&rt, &mut io_bundle, &time_provider)?;
+    assert!(
+        matches!(brain.heating_mode, Some(HeatingMode::TurningOn(_))),
+        "Should have resumed and started turning on, actually in: {:?}",
+        brain.heating_mode
+    );
+
+    Ok(())
+}
+
+/// Test that a jump in the clock (either way) is noticed, throwing away trends measured against the old time.
+#[test_log::test]
+fn test_clock_jump() -> Result<(), BrainFailure> {
+    let rt = Runtime::new().expect("Failed to create runtime.");
+    let mut brain = PythonBrain::new(PythonBrainConfig::default());
+    let (mut io_bundle, mut handle) = new_dummy_io();
+
+    handle.send_wiser(WModifyState::TurnOffHeating);
+    handle.send_temp(Sensor::TKBT, 35.0);
+    let mut time_provider = DummyTimeProvider::new(insignificant_time());
+
+    for _ in 0..3 {
+        brain.run(&rt, &mut io_bundle, &time_provider)?;
+        time_provider.advance(Duration::seconds(10));
+    }
+    assert_eq!(brain.trends.get_trend(&Sensor::TKBT), Some(0.0));
+
+    // Backwards - without noticing, new readings would be ignored until we got back to where we were.
+    time_provider.advance(-Duration::hours(2));
+    brain.run(&rt, &mut io_bundle, &time_provider)?;
+    assert_eq!(brain.heating_mode, Some(HeatingMode::off()));
+    assert_eq!(brain.trends.get_trend(&Sensor::TKBT), None, "Trends should have been reset");
+
+    time_provider.advance(Duration::seconds(10));
+    brain.run(&rt, &mut io_bundle, &time_provider)?;
+    assert_eq!(brain.trends.get_trend(&Sensor::TKBT), Some(0.0), "Should carry on from the new time");
+
+    // Forwards
+    time_provider.advance(Duration::hours(3));
+    brain.run(&rt, &mut io_bundle, &time_provider)?;
+    assert_eq!(brain.heating_mode, Some(HeatingMode::off()));
+    assert_eq!(brain.trends.get_trend(&Sensor::TKBT), None, "Trends should have been reset");
+
+    Ok(())
+}
+
+#[test]
+fn test_force_mode() -> Result<(), BrainFailure> {
+    let rt = Runtime::new().expect("Failed to create runtime.");
+    let mut brain = PythonBrain::new(PythonBrainConfig::default());
+    let (mut io_bundle, mut handle) = new_dummy_io();
+    let time_provider = DummyTimeProvider::new(insignificant_time());
+
+    handle.send_wiser(WModifyState::TurnOffHeating);
+    handle.send_temp(Sensor::TKBT, 45.0);
+    brain.run(&rt, &mut io_bundle, &time_provider)?;
+    assert_eq!(brain.heating_mode, Some(HeatingMode::off()));
+
+    assert!(brain.force_mode("Sideways").is_err());
+    brain.force_mode("DhwOnly").expect("Should be a known mode");
+    brain.run(&rt, &mut io_bundle, &time_provider)?;
+    assert_eq!(brain.heating_mode, Some(HeatingMode::DhwOnly(DhwOnlyMode::new())));
+    assert_eq!(brain.get_mode_reason(), Some(FORCED_REASON));
+    assert_eq!(expect_available!(io_bundle.heating_control())?.try_get_heat_pump()?, HeatPumpMode::HotWaterOnly);
+    Ok(())
+}
+
+const IMMERSION_HEATER_CONFIG_STR: &str = r#"
+[[immersion_heater_model.parts]]
+start = { time = "02:00:00", temp = 40.0 }
+end = { time = "04:00:00", temp = 40.0 }
+sensor = "TKBT"
+"#;
+
+const SUPPRESSED_IMMERSION_HEATER_CONFIG_STR: &str = r#"
+[immersion_heater_model]
+suppress_while_heating_tank = true
+
+[[immersion_heater_model.parts]]
+start = { time = "02:00:00", temp = 40.0 }
+end = { time = "04:00:00", temp = 40.0 }
+sensor = "TKBT"
+"#;
+
+fn immersion_heater_time() -> DateTime<Utc> {
+    Utc.from_utc_datetime(&date(2023, 12, 18).and_time(time(2, 30, 0)))
+}
+
+/// Check the immersion heater follows its model while the heat pump turns on for the heating.
+#[test_log::test]
+fn test_immersion_heater_with_heating() -> Result<(), BrainFailure> {
+    let rt = Runtime::new().expect("Failed to create runtime.");
+    let config = toml::from_str(IMMERSION_HEATER_CONFIG_STR).expect("Failed to deserialize config");
+    let mut brain = PythonBrain::new(config);
+    let (mut io_bundle, mut handle) = new_dummy_io();
+
+    let fixed_time = immersion_heater_time();
+    handle.send_wiser(WModifyState::SetHeatingOffTime(fixed_time + Duration::minutes(10)));
+    handle.send_steady_temps(&[(Sensor::TKTP, 38.0), (Sensor::HPRT, 30.0)]);
+
+    let time_provider = DummyTimeProvider::new(fixed_time);
+    brain.run(&rt, &mut io_bundle, &time_provider)?;
+
+    assert!(matches!(brain.heating_mode, Some(HeatingMode::TurningOn(_))), "Was {:?}", brain.heating_mode);
+    let heating_control = expect_available!(io_bundle.heating_control())?;
+    assert_eq!(heating_control.try_get_heat_pump()?, HeatPumpMode::HeatingOnly);
+    assert!(heating_control.try_get_heat_circulation_pump()?);
+    assert!(io_bundle.misc_controls().try_get_immersion_heater()?, "Immersion heater should be on below the model");
+
+    // Once the tank is hot enough, the immersion heater goes off but the heating carries on.
+    handle.send_temp(Sensor::TKBT, 41.0);
+    brain.run(&rt, &mut io_bundle, &time_provider)?;
+
+    assert!(matches!(brain.heating_mode, Some(HeatingMode::TurningOn(_))), "Was {:?}", brain.heating_mode);
+    let heating_control = expect_available!(io_bundle.heating_control())?;
+    assert_eq!(heating_control.try_get_heat_pump()?, HeatPumpMode::HeatingOnly);
+    assert!(!io_bundle.misc_controls().try_get_immersion_heater()?, "Immersion heater should be off above the model");
+
+    Ok(())
+}
+
+/// Check the immersion heater is kept off while the heat pump heats the tank, if configured to.
+#[test_log::test]
+fn test_immersion_heater_suppressed_by_hot_water() -> Result<(), BrainFailure> {
+    let rt = Runtime::new().expect("Failed to create runtime.");
+    let mut config: PythonBrainConfig = toml::from_str(SUPPRESSED_IMMERSION_HEATER_CONFIG_STR).expect("Failed to deserialize config");
+    config._add_dhw_slot(DhwBap::_new(
+        utc_time_slot(2, 0, 0, 4, 0, 0),
+        Sensor::TKBT, 40.0, 45.0)
+    );
+    let mut brain = PythonBrain::new(config);
+    let (mut io_bundle, mut handle) = new_dummy_io();
+
+    handle.send_wiser(WModifyState::TurnOffHeating);
+    handle.send_steady_temps(&[(Sensor::TKTP, 38.0), (Sensor::HPRT, 30.0)]);
+
+    let time_provider = DummyTimeProvider::new(immersion_heater_time());
+    brain.run(&rt, &mut io_bundle, &time_provider)?;
+
+    assert!(matches!(brain.heating_mode, Some(HeatingMode::DhwOnly(_))), "Was {:?}", brain.heating_mode);
+    let heating_control = expect_available!(io_bundle.heating_control())?;
+    assert_eq!(heating_control.try_get_heat_pump()?, HeatPumpMode::HotWaterOnly);
+    assert!(!heating_control.try_get_heat_circulation_pump()?);
+    assert!(!io_bundle.misc_controls().try_get_immersion_heater()?, "Immersion heater should be suppressed");
+
+    Ok(())
+}
+
+/// Test that TKBT is smoothed for the circulate decisions, but everything else sees the raw reading.
+#[test_log::test]
+fn test_tkbt_smoothing() -> Result<(), BrainFailure> {
+    let rt = Runtime::new().expect("Failed to create runtime.");
+    let config = toml::from_str("tkbt_smoothing = { alpha = 0.5, max_deviation = 3.0 }").expect("Failed to deserialize config");
+    let mut brain = PythonBrain::new(config);
+    let (mut io_bundle, mut handle) = new_dummy_io();
+
+    handle.send_wiser(WModifyState::TurnOffHeating);
+    handle.send_temp(Sensor::TKBT, 40.0);
+    let time_provider = DummyTimeProvider::new(insignificant_time());
+
+    brain.run(&rt, &mut io_bundle, &time_provider)?;
+    assert_eq!(brain.shared_data.get_smoothed_tkbt(), Some(40.0));
+
+    handle.send_temp(Sensor::TKBT, 42.0);
+    brain.run(&rt, &mut io_bundle, &time_provider)?;
+    assert_eq!(brain.shared_data.get_smoothed_tkbt(), Some(41.0), "Should be smoothed");
+    assert_eq!(brain.last_temps.get(&Sensor::TKBT), Some(&42.0), "Everything else should see the raw reading");
+
+    handle.send_temp(Sensor::TKBT, 50.0);
+    brain.run(&rt, &mut io_bundle, &time_provider)?;
+    assert_eq!(brain.shared_data.get_smoothed_tkbt(), Some(50.0), "A genuine change shouldn't be smoothed");
+
+    Ok(())
+}
+
+/// A wiser with its overall heating off, but a room boosted until the given time.
+struct BoostedRoomWiser {
+    rooms: Vec<WiserRoomData>,
+}
+
+impl BoostedRoomWiser {
+    fn new(boost_until: DateTime<Utc>) -> Self {
+        let room = WiserRoomData::new(1, Some("Manual".to_owned()), Some(boost_until.timestamp()), Some(210), "FromBoost".to_owned(), 180, 210, Some("Office".to_owned()));
+        Self { rooms: vec![room] }
+    }
+}
+
+#[async_trait]
+impl WiserManager for BoostedRoomWiser {
+    async fn get_heating_turn_off_time(&self) -> Option<DateTime<Utc>> {
+        None
+    }
+
+    async fn get_heating_on(&self) -> Result<bool, ()> {
+        Ok(false)
+    }
+
+    fn get_wiser_hub(&self) -> &dyn WiserHub {
+        self
+    }
+}
+
+#[async_trait]
+impl WiserHub for BoostedRoomWiser {
+    async fn get_data(&self) -> Result<WiserData, RetrieveDataError> {
+        Err(RetrieveDataError::Other("Not needed".to_owned()))
+    }
+
+    async fn get_room_data(&self) -> Result<Vec<WiserRoomData>, RetrieveDataError> {
+        Ok(self.rooms.clone())
+    }
+
+    async fn cancel_boost(&self, _room_id: usize, _originator: String) -> Result<(), Box<dyn std::error::Error>> {
+        Err("Not needed".into())
+    }
+
+    async fn set_boost(&self, _room_id: usize, _duration_minutes: usize, _temp: f32, _originator: String) -> Result<(f32, DateTime<Utc>), Box<dyn std::error::Error>> {
+        Err("Not needed".into())
+    }
+}
+
+/// Test that a boosted room only counts as the wiser calling for heat when configured to.
+#[test]
+fn test_boost_counts_as_heating() {
+    let rt = Runtime::new().expect("Failed to create runtime.");
+    let now = insignificant_time();
+    let wiser = BoostedRoomWiser::new(now + Duration::minutes(30));
+
+    let mut boosts = AppliedBoosts::new();
+    assert_eq!(rt.block_on(get_wiser_heating_on(&wiser, false, &boosts, now)), Ok(false), "Boosts shouldn't count by default");
+    assert_eq!(rt.block_on(get_wiser_heating_on(&wiser, true, &boosts, now)), Ok(true));
+    assert_eq!(rt.block_on(get_wiser_heating_on(&wiser, true, &boosts, now + Duration::minutes(31))), Ok(false), "Boost has finished");
+
+    boosts.mark_applied("Office".to_owned(), 21.0, now + Duration::minutes(30));
+    assert_eq!(rt.block_on(get_wiser_heating_on(&wiser, true, &boosts, now)), Ok(false), "Our own boosts shouldn't count");
+
+    let config: PythonBrainConfig = toml::from_str("boost_counts_as_heating = true").expect("Failed to deserialize config");
+    assert!(config.boost_counts_as_heating);
+}
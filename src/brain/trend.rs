@@ -0,0 +1,132 @@
+use crate::io::temperatures::Sensor;
+use chrono::{DateTime, Utc};
+use std::collections::{HashMap, VecDeque};
+
+/// How many readings to keep for each sensor by default.
+const DEFAULT_MAX_READINGS: usize = 12;
+
+/// Keeps the last few readings of each sensor so we can tell whether it is rising or falling.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TemperatureTrends {
+    max_readings: usize,
+    readings: HashMap<Sensor, VecDeque<(DateTime<Utc>, f32)>>,
+}
+
+impl Default for TemperatureTrends {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_READINGS)
+    }
+}
+
+impl TemperatureTrends {
+    pub fn new(max_readings: usize) -> Self {
+        Self {
+            max_readings: max_readings.max(2),
+            readings: HashMap::new(),
+        }
+    }
+
+    /// Record the latest readings, dropping the oldest once there are too many.
+    pub fn update(&mut self, time: DateTime<Utc>, temps: &HashMap<Sensor, f32>) {
+        for (sensor, temp) in temps {
+            let readings = self.readings.entry(sensor.clone()).or_default();
+            if readings.back().is_some_and(|(last, _)| *last >= time) {
+                continue;
+            }
+            readings.push_back((time, *temp));
+            while readings.len() > self.max_readings {
+                readings.pop_front();
+            }
+        }
+    }
+
+    /// The most recent reading of the sensor, if there is one.
+    pub fn get_latest(&self, sensor: &Sensor) -> Option<f32> {
+        self.readings.get(sensor)?.back().map(|(_, temp)| *temp)
+    }
+
+    /// The rate of change of the sensor in degrees per minute (least squares fit over the
+    /// readings we have), or None if there aren't enough readings to tell.
+    pub fn get_trend(&self, sensor: &Sensor) -> Option<f32> {
+        let readings = self.readings.get(sensor)?;
+        let (first_time, _) = readings.front()?;
+        let points: Vec<(f64, f64)> = readings
+            .iter()
+            .map(|(time, temp)| {
+                let minutes = (*time - *first_time).num_milliseconds() as f64 / 60_000.0;
+                (minutes, *temp as f64)
+            })
+            .collect();
+        if points.len() < 2 {
+            return None;
+        }
+
+        let n = points.len() as f64;
+        let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / n;
+        let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+        let covariance: f64 = points.iter().map(|(x, y)| (x - mean_x) * (y - mean_y)).sum();
+        let variance: f64 = points.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+        if variance == 0.0 {
+            return None;
+        }
+        Some((covariance / variance) as f32)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::time_util::test_utils::utc_datetime;
+    use chrono::Duration;
+
+    fn update_series(trends: &mut TemperatureTrends, sensor: Sensor, series: &[f32]) {
+        let start = utc_datetime(2023, 11, 14, 12, 0, 0);
+        for (i, temp) in series.iter().enumerate() {
+            let time = start + Duration::seconds(30 * i as i64);
+            trends.update(time, &HashMap::from([(sensor.clone(), *temp)]));
+        }
+    }
+
+    #[test]
+    fn test_rising() {
+        let mut trends = TemperatureTrends::default();
+        // 0.5 degrees every 30 seconds.
+        update_series(&mut trends, Sensor::TKBT, &[40.0, 40.5, 41.0, 41.5, 42.0]);
+
+        let trend = trends.get_trend(&Sensor::TKBT).unwrap();
+        assert!((trend - 1.0).abs() < 0.001, "Expected 1.0/min, got {}", trend);
+    }
+
+    #[test]
+    fn test_noisy_falling() {
+        let mut trends = TemperatureTrends::default();
+        update_series(&mut trends, Sensor::HXOR, &[40.0, 39.0, 39.5, 38.0, 38.5]);
+
+        let trend = trends.get_trend(&Sensor::HXOR).unwrap();
+        assert!((trend - -0.8).abs() < 0.001, "Expected -0.8/min, got {}", trend);
+    }
+
+    #[test]
+    fn test_bounded_history() {
+        let mut trends = TemperatureTrends::new(3);
+        // Only the last 3 (flat) readings should be used.
+        update_series(&mut trends, Sensor::TKBT, &[10.0, 20.0, 45.0, 45.0, 45.0]);
+
+        assert_eq!(trends.get_trend(&Sensor::TKBT), Some(0.0));
+    }
+
+    #[test]
+    fn test_not_enough_readings() {
+        let mut trends = TemperatureTrends::default();
+        assert_eq!(trends.get_trend(&Sensor::TKBT), None);
+
+        update_series(&mut trends, Sensor::TKBT, &[40.0]);
+        assert_eq!(trends.get_trend(&Sensor::TKBT), None);
+
+        // Readings at the same time don't count.
+        let time = utc_datetime(2023, 11, 14, 12, 0, 0);
+        trends.update(time, &HashMap::from([(Sensor::TKBT, 41.0)]));
+        assert_eq!(trends.get_trend(&Sensor::TKBT), None);
+        assert_eq!(trends.get_trend(&Sensor::HXOR), None);
+    }
+}
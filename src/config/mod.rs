@@ -1,11 +1,101 @@
-use serde::Deserialize;
+use crate::brain::python_like::config::sensor_range::SensorRange;
+use crate::io::devices::ArpLogFormat;
+use crate::io::faults::FaultInjectionConfig;
+use crate::io::temperatures::file::TempsFileFormat;
+use crate::io::temperatures::update_db_with_temps::check_table_name;
+use crate::io::temperatures::Sensor;
+use crate::io::wiser::hub::WiserApiVersion;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
 use serde_with::serde_as;
 #[allow(unused_imports)]
 use serde_with::DurationSeconds;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs;
 use std::net::{IpAddr, Ipv4Addr};
-use std::path::PathBuf;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
+const CONFIG_FILE: &str = "follow_heating.toml";
+/// Environment variable to read the config files from another directory.
+pub const CONFIG_DIR_ENV: &str = "FOLLOW_HEATING_CONFIG_DIR";
+/// Argument to read the config files from another directory, overriding [CONFIG_DIR_ENV].
+const CONFIG_DIR_ARG: &str = "--config-dir";
+
+/// Take the directory to read config files from out of the arguments, falling back to the
+/// environment variable, or the working directory if neither are given.
+pub fn take_config_dir(args: &mut Vec<String>, env: Option<OsString>) -> Result<PathBuf, String> {
+    if let Some(i) = args.iter().position(|arg| arg == CONFIG_DIR_ARG) {
+        if i + 1 >= args.len() {
+            return Err(format!("Missing directory after {}", CONFIG_DIR_ARG));
+        }
+        let dir = args.remove(i + 1);
+        args.remove(i);
+        return Ok(PathBuf::from(dir));
+    }
+    Ok(env.map(PathBuf::from).unwrap_or_else(|| PathBuf::from(".")))
+}
+
+/// Environment variable to stop after running the brain this many times, for testing.
+pub const ITERATIONS_ENV: &str = "FOLLOW_HEATING_ITERATIONS";
+/// Argument to stop after running the brain this many times, overriding [ITERATIONS_ENV].
+const ITERATIONS_ARG: &str = "--iterations";
+
+/// Take the number of times to run the brain before stopping out of the arguments, falling back
+/// to the environment variable, or None to keep running until told to stop. It must be at least 1.
+pub fn take_iterations(args: &mut Vec<String>, env: Option<OsString>) -> Result<Option<usize>, String> {
+    let value = if let Some(i) = args.iter().position(|arg| arg == ITERATIONS_ARG) {
+        if i + 1 >= args.len() {
+            return Err(format!("Missing number after {}", ITERATIONS_ARG));
+        }
+        let value = args.remove(i + 1);
+        args.remove(i);
+        value
+    } else {
+        match env {
+            Some(env) => env.to_string_lossy().into_owned(),
+            None => return Ok(None),
+        }
+    };
+    value.parse::<NonZeroUsize>()
+        .map(|iterations| Some(iterations.get()))
+        .map_err(|e| format!("Invalid number of iterations {:?}: {}", value, e))
+}
+
+/// Prefix of a secret to read from an environment variable rather than the config.
+const SECRET_ENV_PREFIX: &str = "env:";
+/// Prefix of a secret to read from a file rather than the config.
+const SECRET_FILE_PREFIX: &str = "file:";
+
+/// Resolve a secret given in the config, which is either the secret itself, or
+/// "env:NAME" to read it from an environment variable (looked up with env), or "file:PATH"
+/// to read it from a file (ignoring any trailing newline).
+pub fn resolve_secret(value: &str, env: impl Fn(&str) -> Option<OsString>) -> Result<String, String> {
+    if let Some(name) = value.strip_prefix(SECRET_ENV_PREFIX) {
+        return env(name)
+            .ok_or_else(|| format!("Unable to read secret from environment variable {}: not set", name))?
+            .into_string()
+            .map_err(|_| format!("Unable to read secret from environment variable {}: not valid unicode", name));
+    }
+    if let Some(path) = value.strip_prefix(SECRET_FILE_PREFIX) {
+        return fs::read_to_string(path)
+            .map(|secret| secret.trim_end_matches(&['\r', '\n'][..]).to_owned())
+            .map_err(|e| format!("Unable to read secret from file {:?}: {}", path, e));
+    }
+    Ok(value.to_owned())
+}
+
+/// Read the main config file from the given directory.
+pub fn read_config(config_dir: &Path) -> Result<Config, String> {
+    let path = config_dir.join(CONFIG_FILE);
+    let config = fs::read_to_string(&path)
+        .map_err(|e| format!("Unable to read config file {:?}: {}", path, e))?;
+    toml::from_str(&config).map_err(|e| format!("Error reading config file {:?}: {}", path, e))
+}
+
+#[serde_as]
 #[derive(Deserialize, Clone)]
 pub struct Config {
     database: DatabaseConfig,
@@ -14,6 +104,30 @@ pub struct Config {
     devices: DevicesFromFileConfig,
     #[serde(default)]
     controls: ControlConfig,
+    /// How often (in seconds) to run the brain.
+    #[serde_as(as = "DurationSeconds")]
+    #[serde(default = "default_loop_interval")]
+    loop_interval_secs: Duration,
+    /// If present, periodically record the temperatures into the database.
+    #[serde(default)]
+    temperature_logging: Option<TemperatureLoggingConfig>,
+    /// If present, listen for line delimited JSON commands on this unix socket.
+    #[serde(default)]
+    control_socket: Option<PathBuf>,
+    /// Make IO calls fail on purpose, to check how the brain copes. Debug builds only.
+    #[serde(default)]
+    fault_injection: FaultInjectionConfig,
+    /// The range of readings each sensor can really give, e.g. TKTP = { min = 0, max = 90 }.
+    /// Readings outside of it are dropped, as the sensor must be broken.
+    #[serde(default)]
+    plausible_ranges: HashMap<Sensor, SensorRange>,
+    /// If present, also write the logs to rotating files, for when there is no journald.
+    #[serde(default)]
+    log_file: Option<LogFileConfig>,
+}
+
+fn default_loop_interval() -> Duration {
+    Duration::from_secs(10)
 }
 
 impl Config {
@@ -30,6 +144,12 @@ impl Config {
             live_data,
             devices,
             controls,
+            loop_interval_secs: default_loop_interval(),
+            temperature_logging: None,
+            control_socket: None,
+            fault_injection: FaultInjectionConfig::default(),
+            plausible_ranges: HashMap::new(),
+            log_file: None,
         }
     }
 
@@ -52,17 +172,139 @@ impl Config {
     pub fn get_control_config(&self) -> &ControlConfig {
         &self.controls
     }
+
+    pub fn get_loop_interval(&self) -> &Duration {
+        &self.loop_interval_secs
+    }
+
+    pub fn get_temperature_logging(&self) -> Option<&TemperatureLoggingConfig> {
+        self.temperature_logging.as_ref()
+    }
+
+    pub fn get_control_socket(&self) -> Option<&PathBuf> {
+        self.control_socket.as_ref()
+    }
+
+    pub fn get_fault_injection(&self) -> &FaultInjectionConfig {
+        &self.fault_injection
+    }
+
+    pub fn get_plausible_ranges(&self) -> &HashMap<Sensor, SensorRange> {
+        &self.plausible_ranges
+    }
+
+    pub fn get_log_file(&self) -> Option<&LogFileConfig> {
+        self.log_file.as_ref()
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct LogFileConfig {
+    /// The directory to write the log files into, which is created if missing.
+    directory: PathBuf,
+    /// The start of each file's name, which is followed by the date (and time) it starts at.
+    #[serde(default = "default_log_file_prefix")]
+    prefix: String,
+    /// How often to start a new file.
+    #[serde(default)]
+    rotation: LogRotation,
+}
+
+fn default_log_file_prefix() -> String {
+    "follow_heating.log".to_owned()
+}
+
+impl LogFileConfig {
+    pub fn get_directory(&self) -> &Path {
+        &self.directory
+    }
+
+    pub fn get_prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub fn get_rotation(&self) -> LogRotation {
+        self.rotation
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum LogRotation {
+    Minutely,
+    Hourly,
+    #[default]
+    Daily,
+    /// Always write to the same file.
+    Never,
+}
+
+#[serde_as]
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TemperatureLoggingConfig {
+    /// How often (in seconds) to record the temperatures.
+    #[serde_as(as = "DurationSeconds")]
+    #[serde(default = "default_temperature_logging_interval")]
+    interval_secs: Duration,
+    /// The table to record temperatures into, which needs sensor and value columns.
+    #[serde(default = "default_temperature_logging_table")]
+    table: String,
+}
+
+fn default_temperature_logging_interval() -> Duration {
+    Duration::from_secs(60)
+}
+
+fn default_temperature_logging_table() -> String {
+    "temperature_reading".to_owned()
+}
+
+impl TemperatureLoggingConfig {
+    pub fn get_interval(&self) -> &Duration {
+        &self.interval_secs
+    }
+
+    pub fn get_table(&self) -> &str {
+        &self.table
+    }
 }
 
 #[derive(Deserialize, Clone)]
 pub struct DatabaseConfig {
+    /// May be read from elsewhere, see [resolve_secret].
     user: String,
+    /// May be read from elsewhere, see [resolve_secret].
     password: String,
+    #[serde(default = "default_db_host")]
+    host: String,
     port: u32,
     database: String,
+    /// A full database url to use instead of building one from the above.
+    #[serde(default)]
+    url: Option<String>,
+    /// Prefix the tables we write to with this (e.g. "test_"), so that running against
+    /// the production config for testing doesn't pollute the real history.
+    #[serde(default)]
+    write_table_prefix: Option<String>,
+    /// Don't write anything to the database, only read from it.
+    #[serde(default)]
+    disable_writes: bool,
+}
+
+fn default_db_host() -> String {
+    "localhost".to_owned()
 }
 
 impl DatabaseConfig {
+    pub fn get_host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn get_url(&self) -> Option<&str> {
+        self.url.as_deref()
+    }
+
     pub fn get_user(&self) -> &str {
         &self.user
     }
@@ -78,12 +320,77 @@ impl DatabaseConfig {
     pub fn get_database(&self) -> &str {
         &self.database
     }
+
+    /// The table to actually write to in place of the given one, or None if we shouldn't be
+    /// writing at all. Errors if the prefix makes it an invalid table name.
+    pub fn get_write_table(&self, table: &str) -> Result<Option<String>, String> {
+        if self.disable_writes {
+            return Ok(None);
+        }
+        let table = match &self.write_table_prefix {
+            Some(prefix) => format!("{}{}", prefix, table),
+            None => table.to_owned(),
+        };
+        check_table_name(&table)?;
+        Ok(Some(table))
+    }
 }
 
 #[derive(Deserialize, Clone)]
 pub struct WiserConfig {
     ip: IpAddr,
+    /// May be read from elsewhere, see [resolve_secret].
     secret: String,
+    /// Read everything (including room data) from the live data file rather than the hub.
+    #[serde(default)]
+    file_only: bool,
+    /// Which version of the hub's api to use, newer firmware needs v2.
+    #[serde(default)]
+    api_version: WiserApiVersion,
+    /// How to wait for the hub to be ready when starting up.
+    #[serde(default)]
+    startup: WiserStartupConfig,
+}
+
+/// After a power cut the hub can take longer than us to come back up, so when starting
+/// wait a while before first asking it for data, and give it a few tries.
+/// The brain starts either way, it just copes without the wiser until it is up.
+#[serde_as]
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct WiserStartupConfig {
+    /// How long to wait before first asking the hub for data.
+    #[serde_as(as = "DurationSeconds")]
+    delay_secs: Duration,
+    /// How many times to ask the hub for data before giving up waiting for it.
+    attempts: u32,
+    /// How long to wait between attempts.
+    #[serde_as(as = "DurationSeconds")]
+    retry_secs: Duration,
+}
+
+impl Default for WiserStartupConfig {
+    fn default() -> Self {
+        Self {
+            delay_secs: Duration::ZERO,
+            attempts: 1,
+            retry_secs: Duration::from_secs(10),
+        }
+    }
+}
+
+impl WiserStartupConfig {
+    pub fn get_delay(&self) -> Duration {
+        self.delay_secs
+    }
+
+    pub fn get_attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    pub fn get_retry_interval(&self) -> Duration {
+        self.retry_secs
+    }
 }
 
 impl WiserConfig {
@@ -91,6 +398,9 @@ impl WiserConfig {
         WiserConfig {
             ip: Ipv4Addr::UNSPECIFIED.into(),
             secret: "".to_owned(),
+            file_only: false,
+            api_version: WiserApiVersion::default(),
+            startup: WiserStartupConfig::default(),
         }
     }
 
@@ -101,15 +411,64 @@ impl WiserConfig {
     pub fn get_secret(&self) -> &str {
         &self.secret
     }
+
+    pub fn is_file_only(&self) -> bool {
+        self.file_only
+    }
+
+    pub fn get_api_version(&self) -> WiserApiVersion {
+        self.api_version
+    }
+
+    pub fn get_startup(&self) -> &WiserStartupConfig {
+        &self.startup
+    }
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Clone)]
 pub struct DevicesFromFileConfig {
     /// The file to read from to obtain the device activity data.
     file: String,
-    /// The maximum number of minutes ago the device must have been detected in order to qualify
-    /// it as being "active"
-    active_within_minutes: usize,
+    /// How long ago the device must have been detected within in order to qualify it as being
+    /// "active". Given as active_within_secs, or active_within_minutes in older configs.
+    active_within: Duration,
+    /// The format of the lines in the file.
+    format: ArpLogFormat,
+}
+
+#[serde_as]
+#[derive(Deserialize)]
+struct DevicesFromFileConfigData {
+    file: String,
+    #[serde_as(as = "Option<DurationSeconds>")]
+    #[serde(default)]
+    active_within_secs: Option<Duration>,
+    #[serde(default)]
+    active_within_minutes: Option<u64>,
+    #[serde(default)]
+    format: ArpLogFormat,
+}
+
+impl<'de> Deserialize<'de> for DevicesFromFileConfig {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let data = DevicesFromFileConfigData::deserialize(deserializer)?;
+        let active_within = match (data.active_within_secs, data.active_within_minutes) {
+            (Some(secs), None) => secs,
+            (None, Some(minutes)) => Duration::from_secs(minutes * 60),
+            (Some(_), Some(_)) => {
+                return Err(D::Error::custom("Only one of active_within_secs and active_within_minutes can be given"))
+            }
+            (None, None) => return Err(D::Error::missing_field("active_within_secs")),
+        };
+        Ok(Self {
+            file: data.file,
+            active_within,
+            format: data.format,
+        })
+    }
 }
 
 impl DevicesFromFileConfig {
@@ -117,8 +476,12 @@ impl DevicesFromFileConfig {
         &self.file
     }
 
-    pub fn get_active_within_minutes(&self) -> usize {
-        self.active_within_minutes
+    pub fn get_active_within(&self) -> Duration {
+        self.active_within
+    }
+
+    pub fn get_format(&self) -> ArpLogFormat {
+        self.format
     }
 }
 
@@ -126,6 +489,9 @@ impl DevicesFromFileConfig {
 pub struct LiveDataConfig {
     wiser_file: PathBuf,
     temps_file: PathBuf,
+    /// The format of the temps file. If not set, this is worked out from the file extension.
+    #[serde(default)]
+    temps_format: Option<TempsFileFormat>,
 }
 
 impl LiveDataConfig {
@@ -136,6 +502,11 @@ impl LiveDataConfig {
     pub fn temps_file(&self) -> &PathBuf {
         &self.temps_file
     }
+
+    pub fn temps_format(&self) -> TempsFileFormat {
+        self.temps_format
+            .unwrap_or_else(|| TempsFileFormat::from_path(&self.temps_file))
+    }
 }
 
 #[serde_as]
@@ -156,6 +527,98 @@ pub struct ControlConfig {
     /// The extra amount of time to wait for water to slow compared to [pump_water_slow_secs]
     #[serde_as(as = "DurationSeconds")]
     extra_heat_pump_water_slow_secs: Duration,
+    /// Input pins that confirm whether valves have actually changed, for valves that have them.
+    #[serde(default)]
+    valve_feedback: ValveFeedbackConfig,
+    /// Whether the wiser hub's power is wired through the wiser power relay. The relay is normally
+    /// closed, so the wiser has power unless we deliberately cut it (e.g. to reboot it).
+    /// If false, the relay is never touched and cutting the wiser's power does nothing.
+    #[serde(default = "default_wiser_power_relay")]
+    wiser_power_relay: bool,
+    /// If present, drive a pin each time the brain runs, so an external watchdog
+    /// (or just an LED) can tell that we haven't hung.
+    #[serde(default)]
+    heartbeat: Option<HeartbeatConfig>,
+}
+
+fn default_wiser_power_relay() -> bool {
+    true
+}
+
+#[serde_as]
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ValveFeedbackConfig {
+    /// The pin that reads the same as the tank valve pin once the tank valve has changed.
+    tank_valve_pin: Option<usize>,
+    /// The pin that reads the same as the heating valve pin once the heating valve has changed.
+    heating_valve_pin: Option<usize>,
+    /// How long (in seconds) to wait for the feedback to confirm the change, after the
+    /// usual valve change wait, before treating the valve as faulty.
+    #[serde_as(as = "DurationSeconds")]
+    timeout_secs: Duration,
+}
+
+impl Default for ValveFeedbackConfig {
+    fn default() -> Self {
+        Self {
+            tank_valve_pin: None,
+            heating_valve_pin: None,
+            timeout_secs: Duration::from_secs(10),
+        }
+    }
+}
+
+impl ValveFeedbackConfig {
+    pub fn get_tank_valve_pin(&self) -> Option<usize> {
+        self.tank_valve_pin
+    }
+
+    pub fn get_heating_valve_pin(&self) -> Option<usize> {
+        self.heating_valve_pin
+    }
+
+    pub fn get_timeout(&self) -> &Duration {
+        &self.timeout_secs
+    }
+
+    #[cfg(test)]
+    pub fn new(tank_valve_pin: Option<usize>, heating_valve_pin: Option<usize>, timeout: Duration) -> Self {
+        Self {
+            tank_valve_pin,
+            heating_valve_pin,
+            timeout_secs: timeout,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct HeartbeatConfig {
+    /// The output pin to drive.
+    pin: usize,
+    #[serde(default)]
+    mode: HeartbeatMode,
+}
+
+impl HeartbeatConfig {
+    pub fn get_pin(&self) -> usize {
+        self.pin
+    }
+
+    pub fn get_mode(&self) -> HeartbeatMode {
+        self.mode
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum HeartbeatMode {
+    /// Toggle the pin each time the brain runs successfully, stopping on failure.
+    #[default]
+    Toggle,
+    /// Hold the pin high while the brain is running successfully, and low on failure or shutdown.
+    /// Unlike Toggle, this can't tell a watchdog that we have hung, as the pin just stays high.
+    Healthy,
 }
 
 impl Default for ControlConfig {
@@ -165,6 +628,9 @@ impl Default for ControlConfig {
             valve_change_secs: Duration::from_secs(3),
             pump_water_slow_secs: Duration::from_secs(2),
             extra_heat_pump_water_slow_secs: Duration::from_secs(3),
+            valve_feedback: ValveFeedbackConfig::default(),
+            wiser_power_relay: true,
+            heartbeat: None,
         }
     }
 }
@@ -185,14 +651,32 @@ impl ControlConfig {
     pub fn get_heat_pump_water_slow_time(&self) -> &Duration {
         &self.extra_heat_pump_water_slow_secs
     }
+
+    pub fn get_valve_feedback(&self) -> &ValveFeedbackConfig {
+        &self.valve_feedback
+    }
+
+    pub fn uses_wiser_power_relay(&self) -> bool {
+        self.wiser_power_relay
+    }
+
+    pub fn get_heartbeat(&self) -> Option<&HeartbeatConfig> {
+        self.heartbeat.as_ref()
+    }
 }
 
 #[cfg(test)]
-mod tests {
+pub mod tests {
     use super::*;
-    use std::fs;
     use std::net::Ipv4Addr;
 
+    /// The test config with the extra config put in front of it.
+    pub fn config_with(extra: &str) -> Config {
+        let config_str = fs::read_to_string("test/testconfig.toml")
+            .expect("Unable to read test config file. Is it missing?");
+        toml::from_str(&format!("{}\n{}", extra, config_str)).expect("Error reading test config file")
+    }
+
     #[test]
     fn test_serialize() {
         let config_str = fs::read_to_string("test/testconfig.toml")
@@ -202,9 +686,14 @@ mod tests {
         assert_eq!(config.database.password, "dbpassword");
         assert_eq!(config.database.port, 3306);
         assert_eq!(config.database.database, "heating");
+        assert_eq!(config.database.host, "localhost");
+        assert_eq!(config.database.url, None);
 
         assert_eq!(config.wiser.ip, Ipv4Addr::new(192, 168, 0, 9));
         assert_eq!(config.wiser.secret, "super-secret-secret");
+        assert!(!config.wiser.file_only);
+        assert_eq!(config.wiser.api_version, WiserApiVersion::V1);
+        assert_eq!(config.wiser.startup, WiserStartupConfig::default());
 
         let mut live_data_path = PathBuf::new();
         live_data_path.push("live_data");
@@ -215,8 +704,149 @@ mod tests {
 
         assert_eq!(config.live_data.temps_file, temps_file);
         assert_eq!(config.live_data.wiser_file, wiser_file);
+        assert_eq!(config.live_data.temps_format(), TempsFileFormat::Json);
 
         assert_eq!(config.devices.file, "x.txt");
-        assert_eq!(config.devices.active_within_minutes, 30);
+        assert_eq!(config.devices.active_within, Duration::from_secs(30 * 60));
+
+        assert_eq!(config.loop_interval_secs, Duration::from_secs(10));
+        assert_eq!(config.temperature_logging, None);
+        assert_eq!(config.control_socket, None);
+        assert_eq!(config.fault_injection, FaultInjectionConfig::default());
+        assert!(config.plausible_ranges.is_empty());
+    }
+
+    #[test]
+    fn test_loop_interval() {
+        let config = config_with("loop_interval_secs = 5");
+        assert_eq!(config.get_loop_interval(), &Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_temperature_logging() {
+        let config = config_with("[temperature_logging]\ninterval_secs = 30");
+        let logging = config.get_temperature_logging().expect("Should have temperature logging");
+        assert_eq!(logging.get_interval(), &Duration::from_secs(30));
+        assert_eq!(logging.get_table(), "temperature_reading");
+    }
+
+    #[test]
+    fn test_control_socket() {
+        let config = config_with("control_socket = \"/run/follow_heating.sock\"");
+        assert_eq!(config.get_control_socket(), Some(&PathBuf::from("/run/follow_heating.sock")));
+    }
+
+    #[test]
+    fn test_plausible_ranges() {
+        let config = config_with("[plausible_ranges]\nTKTP = { min = 0, max = 90 }\nzone1 = { min = -20, max = 40 }");
+        assert_eq!(config.get_plausible_ranges(), &HashMap::from([
+            (Sensor::TKTP, SensorRange::new(0.0, 90.0)),
+            (Sensor::from("zone1"), SensorRange::new(-20.0, 40.0)),
+        ]));
+    }
+
+    #[test]
+    fn test_devices_active_within() {
+        let devices = |active_within: &str| {
+            toml::from_str::<DevicesFromFileConfig>(&format!("file = \"x.txt\"\n{}", active_within))
+                .map(|devices| devices.get_active_within())
+        };
+        assert_eq!(devices("active_within_secs = 45").unwrap(), Duration::from_secs(45));
+        assert_eq!(devices("active_within_minutes = 2").unwrap(), Duration::from_secs(120));
+        assert!(devices("active_within_secs = 45\nactive_within_minutes = 2").is_err(), "Can't have both");
+        assert!(devices("").is_err(), "Need one of them");
+    }
+
+    #[test]
+    fn test_heartbeat() {
+        let config = config_with("");
+        assert_eq!(config.get_control_config().get_heartbeat(), None, "Disabled by default");
+
+        let config = config_with(
+            "[controls]\nvalve_start_open_secs = 5\nvalve_change_secs = 3\npump_water_slow_secs = 2\nextra_heat_pump_water_slow_secs = 3\nheartbeat = { pin = 21 }",
+        );
+        let heartbeat = config.get_control_config().get_heartbeat().expect("Should have a heartbeat");
+        assert_eq!(heartbeat.get_pin(), 21);
+        assert_eq!(heartbeat.get_mode(), HeartbeatMode::Toggle);
+    }
+
+    #[test]
+    fn test_log_file() {
+        assert_eq!(config_with("").get_log_file(), None);
+
+        let config = config_with("[log_file]\ndirectory = \"logs\"");
+        let log_file = config.get_log_file().expect("Should have a log file");
+        assert_eq!(log_file.get_directory(), Path::new("logs"));
+        assert_eq!(log_file.get_prefix(), "follow_heating.log");
+        assert_eq!(log_file.get_rotation(), LogRotation::Daily);
+    }
+
+    #[test]
+    fn test_wiser_startup() {
+        let config = config_with("[wiser.startup]\ndelay_secs = 60\nattempts = 5");
+        let startup = config.get_wiser().get_startup();
+        assert_eq!(startup.get_delay(), Duration::from_secs(60));
+        assert_eq!(startup.get_attempts(), 5);
+        assert_eq!(startup.get_retry_interval(), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_resolve_secret() {
+        let env = |name: &str| (name == "SECRET").then(|| OsString::from("from-env"));
+        assert_eq!(resolve_secret("plain-password", env), Ok("plain-password".to_owned()));
+        assert_eq!(resolve_secret("", env), Ok("".to_owned()));
+
+        assert_eq!(resolve_secret("env:SECRET", env), Ok("from-env".to_owned()));
+        let missing = resolve_secret("env:OTHER_SECRET", env);
+        assert!(missing.is_err(), "{:?}", missing);
+
+        let path = std::env::temp_dir().join(format!("follow_heating_test_secret_{}", std::process::id()));
+        fs::write(&path, "from-file\n").unwrap();
+        assert_eq!(resolve_secret(&format!("file:{}", path.display()), env), Ok("from-file".to_owned()));
+        fs::remove_file(&path).unwrap();
+        let missing = resolve_secret(&format!("file:{}", path.display()), env);
+        assert!(missing.is_err(), "{:?}", missing);
+    }
+
+    #[test]
+    fn test_take_config_dir() {
+        let to_args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+
+        let mut args = to_args(&["follow_heating"]);
+        assert_eq!(take_config_dir(&mut args, None), Ok(PathBuf::from(".")), "Should default to working directory");
+        assert_eq!(take_config_dir(&mut args, Some("/etc/heating".into())), Ok(PathBuf::from("/etc/heating")));
+
+        let mut args = to_args(&["follow_heating", "--config-dir", "/etc/other", "check-config"]);
+        assert_eq!(take_config_dir(&mut args, Some("/etc/heating".into())), Ok(PathBuf::from("/etc/other")));
+        assert_eq!(args, to_args(&["follow_heating", "check-config"]), "Should have been taken out of the args");
+
+        let mut args = to_args(&["follow_heating", "--config-dir"]);
+        assert!(take_config_dir(&mut args, None).is_err());
+    }
+
+    #[test]
+    fn test_take_iterations() {
+        let to_args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+
+        let mut args = to_args(&["follow_heating"]);
+        assert_eq!(take_iterations(&mut args, None), Ok(None), "Should run forever by default");
+        assert_eq!(take_iterations(&mut args, Some("5".into())), Ok(Some(5)));
+        assert!(take_iterations(&mut args, Some("five".into())).is_err());
+        assert!(take_iterations(&mut args, Some("0".into())).is_err(), "Should always run at least once");
+
+        let mut args = to_args(&["follow_heating", "--iterations", "3", "--config-dir", "/etc/heating"]);
+        assert_eq!(take_iterations(&mut args, Some("5".into())), Ok(Some(3)));
+        assert_eq!(args, to_args(&["follow_heating", "--config-dir", "/etc/heating"]), "Should have been taken out of the args");
+
+        let mut args = to_args(&["follow_heating", "--iterations"]);
+        assert!(take_iterations(&mut args, None).is_err());
+    }
+
+    #[test]
+    fn test_read_config_from_dir() {
+        let config = read_config(Path::new("test/config_dir")).expect("Should read config from the directory");
+        assert_eq!(config.database.user, "exampleuser");
+
+        assert!(read_config(Path::new("test/missing_dir")).is_err());
     }
 }
@@ -0,0 +1,370 @@
+use crate::brain::Brain;
+use chrono::{DateTime, Utc};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::{self, Permissions};
+use std::io;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::Path;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::UnixListener;
+use tokio::sync::mpsc::{self, Sender};
+
+/// A command sent as a single line of JSON over the control socket, for example:
+/// {"command": "force-mode", "mode": "Off"}
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "command", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum Command {
+    /// Reload the logging filter and the brain config, same as SIGUSR1.
+    Reload,
+    /// Get the internal state of the brain, same as what SIGQUIT dumps.
+    Status,
+    /// Switch into the given mode (by name) on the next run.
+    ForceMode { mode: String },
+    MaintenanceOn,
+    MaintenanceOff,
+    /// Lower the targets until away-off, see AwayConfig.
+    AwayOn,
+    AwayOff,
+    /// Cut the wiser's power for a while to force it to reboot.
+    RebootWiser,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Response {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Response {
+    pub fn ok(result: Option<Value>) -> Self {
+        Self { ok: true, result, error: None }
+    }
+
+    pub fn error(error: impl Into<String>) -> Self {
+        Self { ok: false, result: None, error: Some(error.into()) }
+    }
+}
+
+/// A command waiting to be handled by the main loop, which must respond to it.
+#[derive(Debug, Clone)]
+pub struct CommandRequest {
+    pub command: Command,
+    reply: Sender<Response>,
+}
+
+impl CommandRequest {
+    pub fn respond(self, response: Response) {
+        if self.reply.try_send(response).is_err() {
+            warn!("Control socket client went away before getting a response");
+        }
+    }
+}
+
+pub fn parse_command(line: &str) -> Result<Command, String> {
+    serde_json::from_str(line).map_err(|e| format!("Invalid command {:?}: {}", line, e))
+}
+
+/// Carry out the command on the brain. Reloading is left to the given function as it
+/// involves more than just the brain.
+pub fn dispatch<B: Brain>(
+    brain: &mut B,
+    command: Command,
+    now: DateTime<Utc>,
+    reload: impl FnOnce(&mut B),
+) -> Response {
+    match command {
+        Command::Reload => {
+            reload(brain);
+            Response::ok(None)
+        }
+        Command::Status => match brain.dump_state(now) {
+            Ok(state) => match serde_json::from_str(&state) {
+                Ok(state) => Response::ok(Some(state)),
+                Err(e) => Response::error(format!("Failed to parse state: {}", e)),
+            },
+            Err(e) => Response::error(e),
+        },
+        Command::ForceMode { mode } => match brain.force_mode(&mode) {
+            Ok(()) => Response::ok(None),
+            Err(e) => Response::error(e),
+        },
+        Command::MaintenanceOn => {
+            brain.set_maintenance(true);
+            Response::ok(None)
+        }
+        Command::MaintenanceOff => {
+            brain.set_maintenance(false);
+            Response::ok(None)
+        }
+        Command::AwayOn => {
+            brain.set_away(true);
+            Response::ok(None)
+        }
+        Command::AwayOff => {
+            brain.set_away(false);
+            Response::ok(None)
+        }
+        Command::RebootWiser => {
+            brain.reboot_wiser();
+            Response::ok(None)
+        }
+    }
+}
+
+/// Bind the control socket so that only our user can connect, replacing any socket left over
+/// from a previous run. Anything else already at the path is left alone and is an error.
+pub fn bind(path: &Path) -> io::Result<UnixListener> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path)?,
+        Ok(_) => return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{:?} already exists and isn't a socket, not replacing it", path),
+        )),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+/// Remove the control socket when shutting down.
+pub fn remove(path: &Path) {
+    if let Err(e) = fs::remove_file(path) {
+        warn!("Failed to remove control socket {:?}: {}", path, e);
+    }
+}
+
+/// Accept connections forever, passing each command on to be handled.
+pub async fn serve(listener: UnixListener, sender: Sender<CommandRequest>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                debug!("Control socket client connected");
+                let sender = sender.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_connection(stream, sender).await {
+                        warn!("Control socket connection failed: {}", e);
+                    }
+                });
+            }
+            Err(e) => {
+                error!("Failed to accept control socket connection: {}", e);
+                return;
+            }
+        }
+    }
+}
+
+/// Read commands a line at a time, replying to each with a line of JSON.
+pub async fn handle_connection<S>(stream: S, sender: Sender<CommandRequest>) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (read, mut write) = tokio::io::split(stream);
+    let mut lines = BufReader::new(read).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match parse_command(&line) {
+            Ok(command) => {
+                info!("Received control command: {:?}", command);
+                let (reply, mut reply_recv) = mpsc::channel(1);
+                if sender.send(CommandRequest { command, reply }).await.is_err() {
+                    return Ok(());
+                }
+                reply_recv.recv().await
+                    .unwrap_or_else(|| Response::error("The command was dropped without a response"))
+            }
+            Err(e) => Response::error(e),
+        };
+        let mut response = serde_json::to_string(&response)?;
+        response.push('\n');
+        write.write_all(response.as_bytes()).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::brain::BrainFailure;
+    use crate::io::IOBundle;
+    use crate::time_util::mytime::TimeProvider;
+    use crate::time_util::test_utils::utc_datetime;
+    use tokio::io::Lines;
+    use tokio::runtime::Runtime;
+
+    #[derive(Default)]
+    struct FakeBrain {
+        maintenance: bool,
+        away: bool,
+        wiser_rebooted: bool,
+        forced: Option<String>,
+        reloaded: bool,
+    }
+
+    impl Brain for FakeBrain {
+        fn run(&mut self, _runtime: &Runtime, _io_bundle: &mut IOBundle, _time_provider: &impl TimeProvider) -> Result<(), BrainFailure> {
+            Ok(())
+        }
+
+        fn reload_config(&mut self) {
+            self.reloaded = true;
+        }
+
+        fn toggle_maintenance(&mut self) {
+            self.maintenance = !self.maintenance;
+        }
+
+        fn set_maintenance(&mut self, maintenance: bool) {
+            self.maintenance = maintenance;
+        }
+
+        fn set_away(&mut self, away: bool) {
+            self.away = away;
+        }
+
+        fn reboot_wiser(&mut self) {
+            self.wiser_rebooted = true;
+        }
+
+        fn force_mode(&mut self, mode: &str) -> Result<(), String> {
+            if mode != "Off" {
+                return Err(format!("Unknown mode {}", mode));
+            }
+            self.forced = Some(mode.to_owned());
+            Ok(())
+        }
+
+        fn dump_state(&self, _now: DateTime<Utc>) -> Result<String, String> {
+            Ok(format!("{{\"maintenance\": {}}}", self.maintenance))
+        }
+    }
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(parse_command(r#"{"command": "reload"}"#), Ok(Command::Reload));
+        assert_eq!(parse_command(r#"{"command": "status"}"#), Ok(Command::Status));
+        assert_eq!(parse_command(r#"{"command": "maintenance-on"}"#), Ok(Command::MaintenanceOn));
+        assert_eq!(parse_command(r#"{"command": "maintenance-off"}"#), Ok(Command::MaintenanceOff));
+        assert_eq!(parse_command(r#"{"command": "away-on"}"#), Ok(Command::AwayOn));
+        assert_eq!(parse_command(r#"{"command": "away-off"}"#), Ok(Command::AwayOff));
+        assert_eq!(parse_command(r#"{"command": "reboot-wiser"}"#), Ok(Command::RebootWiser));
+        assert_eq!(
+            parse_command(r#"{"command": "force-mode", "mode": "Off"}"#),
+            Ok(Command::ForceMode { mode: "Off".to_owned() })
+        );
+
+        assert!(parse_command(r#"{"command": "force-mode"}"#).is_err(), "Missing mode");
+        assert!(parse_command(r#"{"command": "explode"}"#).is_err());
+        assert!(parse_command("reload").is_err());
+    }
+
+    #[test]
+    fn test_dispatch() {
+        let mut brain = FakeBrain::default();
+        let now = utc_datetime(2024, 3, 1, 12, 0, 0);
+
+        assert_eq!(dispatch(&mut brain, Command::MaintenanceOn, now, |_| {}), Response::ok(None));
+        assert!(brain.maintenance);
+        assert_eq!(
+            dispatch(&mut brain, Command::Status, now, |_| {}),
+            Response::ok(Some(serde_json::json!({"maintenance": true})))
+        );
+        assert_eq!(dispatch(&mut brain, Command::MaintenanceOff, now, |_| {}), Response::ok(None));
+        assert!(!brain.maintenance);
+
+        assert_eq!(dispatch(&mut brain, Command::AwayOn, now, |_| {}), Response::ok(None));
+        assert!(brain.away);
+        assert_eq!(dispatch(&mut brain, Command::AwayOff, now, |_| {}), Response::ok(None));
+        assert!(!brain.away);
+
+        assert_eq!(dispatch(&mut brain, Command::RebootWiser, now, |_| {}), Response::ok(None));
+        assert!(brain.wiser_rebooted);
+
+        assert_eq!(dispatch(&mut brain, Command::Reload, now, |brain| brain.reload_config()), Response::ok(None));
+        assert!(brain.reloaded);
+
+        let force = |mode: &str| Command::ForceMode { mode: mode.to_owned() };
+        assert_eq!(dispatch(&mut brain, force("Off"), now, |_| {}), Response::ok(None));
+        assert_eq!(brain.forced.as_deref(), Some("Off"));
+        assert_eq!(dispatch(&mut brain, force("Sideways"), now, |_| {}), Response::error("Unknown mode Sideways"));
+    }
+
+    /// Send a line as the client and wait for the response line.
+    async fn request(
+        write: &mut (impl AsyncWrite + Unpin),
+        lines: &mut Lines<BufReader<impl AsyncRead + Unpin>>,
+        line: &str,
+    ) -> String {
+        write.write_all(format!("{}\n", line).as_bytes()).await.unwrap();
+        lines.next_line().await.unwrap().expect("Should have got a response")
+    }
+
+    #[tokio::test]
+    async fn test_bind() {
+        let dir = std::env::temp_dir().join(format!("follow_heating_test_control_socket_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("control.sock");
+
+        let listener = bind(&path).expect("Should bind");
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600, "Only the owner should be able to connect");
+        drop(listener);
+        bind(&path).expect("Should replace the socket left over");
+        remove(&path);
+        assert!(!path.exists(), "Should have removed the socket");
+
+        fs::write(&path, "important").unwrap();
+        let err = bind(&path).expect_err("Shouldn't replace a file that isn't a socket");
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "important");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_connection() {
+        let (client, server) = tokio::io::duplex(1024);
+        let (sender, mut receiver) = mpsc::channel(5);
+        let connection = tokio::spawn(handle_connection(server, sender));
+
+        // Stands in for the main loop.
+        let main_loop = tokio::spawn(async move {
+            let mut brain = FakeBrain::default();
+            let now = utc_datetime(2024, 3, 1, 12, 0, 0);
+            while let Some(request) = receiver.recv().await {
+                let response = dispatch(&mut brain, request.command.clone(), now, |_| {});
+                request.respond(response);
+            }
+            brain
+        });
+
+        let (read, mut write) = tokio::io::split(client);
+        let mut lines = BufReader::new(read).lines();
+        let response = request(&mut write, &mut lines, r#"{"command": "maintenance-on"}"#).await;
+        assert_eq!(response, r#"{"ok":true}"#);
+
+        let response = request(&mut write, &mut lines, r#"{"command": "status"}"#).await;
+        assert_eq!(response, r#"{"ok":true,"result":{"maintenance":true}}"#);
+
+        let response: Value = serde_json::from_str(&request(&mut write, &mut lines, "nonsense").await).unwrap();
+        assert_eq!(response["ok"], false);
+        assert!(response["error"].as_str().unwrap().starts_with("Invalid command"));
+
+        let response = request(&mut write, &mut lines, r#"{"command": "force-mode", "mode": "Off"}"#).await;
+        assert_eq!(response, r#"{"ok":true}"#);
+
+        write.shutdown().await.unwrap();
+        connection.await.unwrap().unwrap();
+        let brain = main_loop.await.unwrap();
+        assert!(brain.maintenance);
+        assert_eq!(brain.forced.as_deref(), Some("Off"));
+    }
+}
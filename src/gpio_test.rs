@@ -0,0 +1,237 @@
+use crate::brain::BrainFailure;
+use crate::io::controls::{translate_get_gpio, translate_set_gpio};
+use crate::io::gpio::{GPIOError, GPIOManager, GPIOMode};
+use itertools::Itertools;
+use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
+
+const HELP: &str = "Commands: <relay> on | <relay> off | <relay> | list | help | quit";
+
+#[derive(Debug, PartialEq)]
+pub enum GpioTestCommand {
+    /// Turn the relay on or off.
+    Set { relay: String, on: bool },
+    /// Read back the state of the relay.
+    Get { relay: String },
+    List,
+    Help,
+    Quit,
+}
+
+pub fn parse_command(line: &str) -> Result<GpioTestCommand, String> {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    match parts.as_slice() {
+        ["list"] => Ok(GpioTestCommand::List),
+        ["help"] => Ok(GpioTestCommand::Help),
+        ["quit"] | ["exit"] => Ok(GpioTestCommand::Quit),
+        [relay] => Ok(GpioTestCommand::Get { relay: relay.to_string() }),
+        [relay, "on"] => Ok(GpioTestCommand::Set { relay: relay.to_string(), on: true }),
+        [relay, "off"] => Ok(GpioTestCommand::Set { relay: relay.to_string(), on: false }),
+        [_, other] => Err(format!("Expected on or off, got: {}", other)),
+        _ => Err(format!("Unrecognised command: {:?}", line.trim())),
+    }
+}
+
+/// Toggles relays by name, without any of the brain running.
+pub struct GpioTester<G: GPIOManager> {
+    gpio: G,
+    relays: BTreeMap<String, usize>,
+}
+
+impl<G: GPIOManager> GpioTester<G> {
+    pub fn create(mut gpio: G, relays: BTreeMap<String, usize>) -> Result<Self, GPIOError> {
+        for pin in relays.values() {
+            gpio.setup(*pin, &GPIOMode::Output)?;
+        }
+        Ok(Self { gpio, relays })
+    }
+
+    /// Run the command, returning what to print, or None if we should stop.
+    /// Errors if a relay couldn't be set or read.
+    pub fn execute(&mut self, command: GpioTestCommand) -> Result<Option<String>, BrainFailure> {
+        match command {
+            GpioTestCommand::Set { relay, on } => self.set(&relay, on).map(Some),
+            GpioTestCommand::Get { relay } => self.get(&relay).map(Some),
+            GpioTestCommand::List => Ok(Some(
+                self.relays
+                    .iter()
+                    .map(|(name, pin)| format!("{} (pin {})", name, pin))
+                    .join("\n"),
+            )),
+            GpioTestCommand::Help => Ok(Some(HELP.to_owned())),
+            GpioTestCommand::Quit => Ok(None),
+        }
+    }
+
+    /// Turn every relay off, trying them all even if some fail.
+    pub fn all_off(&mut self) -> Result<(), String> {
+        let gpio = &mut self.gpio;
+        let failed = self.relays.iter()
+            .filter(|(_, pin)| translate_set_gpio(**pin, gpio, false, "Failed to turn off relay").is_err())
+            .map(|(name, _)| name)
+            .join(", ");
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("Failed to turn off: {}", failed))
+        }
+    }
+
+    fn set(&mut self, relay: &str, on: bool) -> Result<String, BrainFailure> {
+        let pin = match self.relays.get(relay) {
+            Some(pin) => *pin,
+            None => return Ok(format!("Unknown relay: {}", relay)),
+        };
+        translate_set_gpio(pin, &mut self.gpio, on, "Failed to set relay")?;
+        Ok(format!("{} is now {}", relay, on_off(self.read(pin)?)))
+    }
+
+    fn get(&self, relay: &str) -> Result<String, BrainFailure> {
+        let pin = match self.relays.get(relay) {
+            Some(pin) => *pin,
+            None => return Ok(format!("Unknown relay: {}", relay)),
+        };
+        Ok(format!("{} is {}", relay, on_off(self.read(pin)?)))
+    }
+
+    fn read(&self, pin: usize) -> Result<bool, BrainFailure> {
+        translate_get_gpio(pin, &self.gpio, "Failed to read relay")
+    }
+}
+
+fn on_off(on: bool) -> &'static str {
+    if on {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+/// Read commands from the input until it ends, we are told to quit or a relay fails,
+/// then turn every relay off.
+pub fn run<G: GPIOManager>(
+    tester: &mut GpioTester<G>,
+    input: impl BufRead,
+    mut output: impl Write,
+) -> Result<(), String> {
+    let result = run_commands(tester, input, &mut output);
+    let all_off = tester.all_off();
+    result.and(all_off)
+}
+
+fn run_commands<G: GPIOManager>(
+    tester: &mut GpioTester<G>,
+    input: impl BufRead,
+    mut output: impl Write,
+) -> Result<(), String> {
+    let write_err = |e: std::io::Error| format!("Failed to write output: {}", e);
+    writeln!(output, "{}", HELP).map_err(write_err)?;
+    for line in input.lines() {
+        let line = line.map_err(|e| format!("Failed to read input: {}", e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match parse_command(&line) {
+            Ok(command) => match tester.execute(command).map_err(|e| e.to_string())? {
+                Some(response) => response,
+                None => break,
+            },
+            Err(e) => e,
+        };
+        writeln!(output, "{}", response).map_err(write_err)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::io::gpio::dummy::Dummy;
+    use crate::io::gpio::GPIOState;
+
+    /// Fails to set the given pin on, but otherwise behaves like [Dummy].
+    #[derive(Default)]
+    struct FailOn {
+        pin: usize,
+        dummy: Dummy,
+    }
+
+    impl GPIOManager for FailOn {
+        fn setup(&mut self, pin: usize, mode: &GPIOMode) -> Result<(), GPIOError> {
+            self.dummy.setup(pin, mode)
+        }
+
+        fn set_pin(&mut self, pin_id: usize, state: &GPIOState) -> Result<(), GPIOError> {
+            if pin_id == self.pin && *state == GPIOState::Low {
+                return Err(GPIOError::Other("Broken relay".to_owned()));
+            }
+            self.dummy.set_pin(pin_id, state)
+        }
+
+        fn get_pin(&self, pin: usize) -> Result<GPIOState, GPIOError> {
+            self.dummy.get_pin(pin)
+        }
+    }
+
+    fn relays() -> BTreeMap<String, usize> {
+        BTreeMap::from([
+            ("heat_pump".to_owned(), 26),
+            ("immersion_heater".to_owned(), 6),
+        ])
+    }
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(parse_command("heat_pump on"), Ok(GpioTestCommand::Set { relay: "heat_pump".into(), on: true }));
+        assert_eq!(parse_command("  heat_pump   off "), Ok(GpioTestCommand::Set { relay: "heat_pump".into(), on: false }));
+        assert_eq!(parse_command("heat_pump"), Ok(GpioTestCommand::Get { relay: "heat_pump".into() }));
+        assert_eq!(parse_command("list"), Ok(GpioTestCommand::List));
+        assert_eq!(parse_command("quit"), Ok(GpioTestCommand::Quit));
+        assert!(parse_command("heat_pump maybe").is_err());
+        assert!(parse_command("heat_pump on now").is_err());
+    }
+
+    #[test]
+    fn test_toggle() {
+        let mut tester = GpioTester::create(Dummy::default(), relays()).unwrap();
+
+        assert_eq!(tester.execute(parse_command("heat_pump").unwrap()).unwrap().unwrap(), "heat_pump is off");
+        assert_eq!(tester.execute(parse_command("heat_pump on").unwrap()).unwrap().unwrap(), "heat_pump is now on");
+        assert_eq!(tester.execute(parse_command("heat_pump").unwrap()).unwrap().unwrap(), "heat_pump is on");
+        assert_eq!(tester.execute(parse_command("immersion_heater").unwrap()).unwrap().unwrap(), "immersion_heater is off");
+        assert_eq!(tester.execute(parse_command("heat_pump off").unwrap()).unwrap().unwrap(), "heat_pump is now off");
+        assert_eq!(tester.execute(parse_command("tank_valve on").unwrap()).unwrap().unwrap(), "Unknown relay: tank_valve");
+    }
+
+    #[test]
+    fn test_run() {
+        let mut tester = GpioTester::create(Dummy::default(), relays()).unwrap();
+        let input = "heat_pump on\n\nbad command here\nquit\nheat_pump off\n";
+        let mut output = Vec::new();
+
+        run(&mut tester, input.as_bytes(), &mut output).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines[1], "heat_pump is now on");
+        assert_eq!(lines[2], "Unrecognised command: \"bad command here\"");
+        assert_eq!(lines.len(), 3, "Should stop at quit");
+        assert_eq!(tester.execute(GpioTestCommand::Get { relay: "heat_pump".into() }).unwrap().unwrap(), "heat_pump is off",
+            "Should have turned everything off");
+    }
+
+    #[test]
+    fn test_run_stops_at_first_failure() {
+        let gpio = FailOn { pin: 6, ..Default::default() };
+        let mut tester = GpioTester::create(gpio, relays()).unwrap();
+        let input = "heat_pump on\nimmersion_heater on\nheat_pump on\n";
+        let mut output = Vec::new();
+
+        assert!(run(&mut tester, input.as_bytes(), &mut output).is_err());
+
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output.lines().count(), 2, "Should stop after the failure: {}", output);
+        assert_eq!(tester.execute(GpioTestCommand::Get { relay: "heat_pump".into() }).unwrap().unwrap(), "heat_pump is off",
+            "Should have turned everything off");
+    }
+}
@@ -2,8 +2,8 @@ use std::thread::sleep;
 use std::time::Duration;
 
 use crate::brain::python_like::control::heating_control::HeatPumpMode;
-use crate::brain::BrainFailure;
-use crate::config::ControlConfig;
+use crate::brain::{BrainFailure, CorrectiveActions};
+use crate::config::{ControlConfig, ValveFeedbackConfig};
 use crate::io::controls::{translate_get_gpio, translate_set_gpio};
 use crate::io::gpio::GPIOError;
 use crate::python_like::control::heating_control::{HeatCirculationPumpControl, HeatPumpControl};
@@ -26,6 +26,9 @@ pub struct GPIOPins {
     pub heating_extra_pump: usize,
 }
 
+/// How often to check the valve feedback pins while waiting for them to confirm a change.
+const VALVE_FEEDBACK_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
 #[derive(Debug)]
 enum Valve {
     /// Closing this valve will stop water going through the tank.
@@ -54,6 +57,7 @@ pub struct GPIOHeatingControl<G: GPIOManager> {
     valve_change_time: Duration,
     pump_water_slow_time: Duration,
     extra_heat_pump_water_slow_time: Duration,
+    valve_feedback: ValveFeedbackConfig,
 
     heat_pump_last_changed: DateTime<Utc>,
 }
@@ -69,6 +73,10 @@ impl<G: GPIOManager> GPIOHeatingControl<G> {
         gpio_manager.setup(pins.tank_valve_pin, &GPIOMode::Output)?;
         gpio_manager.setup(pins.heating_valve_pin, &GPIOMode::Output)?;
         gpio_manager.setup(pins.heating_extra_pump, &GPIOMode::Output)?;
+        let valve_feedback = control_config.get_valve_feedback().clone();
+        for pin in [valve_feedback.get_tank_valve_pin(), valve_feedback.get_heating_valve_pin()].iter().flatten() {
+            gpio_manager.setup(*pin, &GPIOMode::Input)?;
+        }
         Ok(Self {
             gpio_manager,
             pins,
@@ -77,6 +85,7 @@ impl<G: GPIOManager> GPIOHeatingControl<G> {
             valve_change_time:               *control_config.get_valve_change_time(),
             pump_water_slow_time:            *control_config.get_pump_water_slow_time(),
             extra_heat_pump_water_slow_time: *control_config.get_heat_pump_water_slow_time(),
+            valve_feedback,
             heat_pump_last_changed:          Utc::now(),
         })
     }
@@ -95,6 +104,13 @@ impl<G: GPIOManager> GPIOHeatingControl<G> {
         }
     }
 
+    fn get_valve_feedback_pin(&self, valve: &Valve) -> Option<usize> {
+        match valve {
+            Valve::Tank => self.valve_feedback.get_tank_valve_pin(),
+            Valve::Heating => self.valve_feedback.get_heating_valve_pin(),
+        }
+    }
+
     fn get_pump_pin(&self, pump: &Pump) -> usize {
         match pump {
             Pump::HeatPump => self.pins.heat_pump_pin,
@@ -212,6 +228,8 @@ impl<G: GPIOManager> GPIOHeatingControl<G> {
         let any_valves_closed = self.update_valves_if_needed(config, false)?;
         if any_valves_opened || any_valves_closed {
             self.wait_for(self.valve_change_time, "Valves to change");
+            self.confirm_valve_feedback(&Valve::Heating, config.heating_valve_open)?;
+            self.confirm_valve_feedback(&Valve::Tank, config.tank_valve_open)?;
         } else {
             debug!("No valves to open or close - not waiting.");
         }
@@ -221,6 +239,36 @@ impl<G: GPIOManager> GPIOHeatingControl<G> {
         Ok(())
     }
 
+    /// If the valve has a feedback pin, wait for it to confirm that the valve is open / closed.
+    /// If it doesn't within the timeout, the valve is probably stuck, so fail.
+    fn confirm_valve_feedback(&self, valve: &Valve, open: bool) -> Result<(), BrainFailure> {
+        let pin = match self.get_valve_feedback_pin(valve) {
+            Some(pin) => pin,
+            None => return Ok(()),
+        };
+        let msg = format!("Failed to get {:?} Valve feedback pin", valve);
+        let attempts = (self.valve_feedback.get_timeout().as_millis() / VALVE_FEEDBACK_POLL_INTERVAL.as_millis()).max(1);
+        for attempt in 0..=attempts {
+            if translate_get_gpio(pin, &self.gpio_manager, &msg)? == open {
+                trace!("{:?} Valve feedback confirmed it is {}", valve, to_valve_state(open));
+                return Ok(());
+            }
+            if attempt < attempts {
+                self.wait_for(VALVE_FEEDBACK_POLL_INTERVAL, &format!("{:?} Valve feedback", valve));
+            }
+        }
+        Err(brain_fail!(
+            format!(
+                "{:?} Valve feedback (GPIO: {}) did not confirm it was {} within {}s",
+                valve,
+                pin,
+                to_valve_state(open),
+                self.valve_feedback.get_timeout().as_secs()
+            ),
+            CorrectiveActions::unknown_heating()
+        ))
+    }
+
     /// Change pumps' state to the given state if they are not already in that state.
     /// To turn on pumps that need turning on, call with to: true
     /// To turn off pumps that need turning off, call with to: false
@@ -417,7 +465,9 @@ mod test {
     use crate::brain::python_like::control::heating_control::{HeatPumpControl, HeatPumpMode};
     use crate::brain::BrainFailure;
     use crate::io::gpio::dummy::Dummy;
-    use crate::io::gpio::{GPIOError, GPIOManager, GPIOState};
+    use crate::config::ValveFeedbackConfig;
+    use crate::io::gpio::{GPIOError, GPIOManager, GPIOMode, GPIOState};
+    use std::time::Duration;
 
     use super::{GPIOHeatingControl, GPIOPins};
 
@@ -619,4 +669,84 @@ mod test {
 
         Ok(())
     }
+
+    const TANK_VALVE_FEEDBACK_PIN: usize = 1005;
+    const HEATING_VALVE_FEEDBACK_PIN: usize = 1006;
+
+    /// Valves whose feedback pins follow the valve pins, unless they are stuck.
+    struct FeedbackGPIO {
+        inner: Dummy,
+        stuck: bool,
+    }
+
+    impl GPIOManager for FeedbackGPIO {
+        fn setup(&mut self, pin: usize, mode: &GPIOMode) -> Result<(), GPIOError> {
+            self.inner.setup(pin, mode)
+        }
+
+        fn set_pin(&mut self, pin_id: usize, state: &GPIOState) -> Result<(), GPIOError> {
+            self.inner.set_pin(pin_id, state)
+        }
+
+        fn get_pin(&self, pin: usize) -> Result<GPIOState, GPIOError> {
+            let valve_pin = match pin {
+                TANK_VALVE_FEEDBACK_PIN => GPIO_PINS.tank_valve_pin,
+                HEATING_VALVE_FEEDBACK_PIN => GPIO_PINS.heating_valve_pin,
+                _ => return self.inner.get_pin(pin),
+            };
+            if self.stuck {
+                // Never moves from closed.
+                return Ok(GPIOState::High);
+            }
+            self.inner.get_pin(valve_pin)
+        }
+    }
+
+    fn create_with_feedback(stuck: bool) -> GPIOHeatingControl<FeedbackGPIO> {
+        let gpio_manager = FeedbackGPIO { inner: Dummy::default(), stuck };
+        let mut controls = GPIOHeatingControl::create_no_sleep(GPIO_PINS.clone(), gpio_manager).unwrap();
+        controls.valve_feedback = ValveFeedbackConfig::new(
+            Some(TANK_VALVE_FEEDBACK_PIN),
+            Some(HEATING_VALVE_FEEDBACK_PIN),
+            Duration::from_secs(5),
+        );
+        controls
+    }
+
+    #[test]
+    fn test_valve_feedback_confirms() -> Result<(), BrainFailure> {
+        let mut controls = create_with_feedback(false);
+
+        controls.try_set_heat_pump(HeatPumpMode::HotWaterOnly)?;
+        controls.try_set_heat_pump(HeatPumpMode::HeatingOnly)?;
+        controls.try_set_heat_pump(HeatPumpMode::Off)?;
+        assert_eq!(controls.try_get_heat_pump()?, HeatPumpMode::Off);
+        Ok(())
+    }
+
+    #[test]
+    fn test_valve_feedback_stuck() -> Result<(), BrainFailure> {
+        let mut controls = create_with_feedback(true);
+
+        assert!(controls.try_set_heat_pump(HeatPumpMode::HotWaterOnly).is_err(), "Tank valve never confirmed it opened");
+        assert_eq!(
+            controls.gpio_manager.get_pin(GPIO_PINS.heat_pump_pin).unwrap(),
+            GPIOState::High,
+            "Heat pump should not have been turned on"
+        );
+
+        // Turning off only closes valves, which the stuck feedback agrees with.
+        controls.try_set_heat_pump(HeatPumpMode::Off)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_valve_feedback() -> Result<(), BrainFailure> {
+        let gpio_manager = FeedbackGPIO { inner: Dummy::default(), stuck: true };
+        let mut controls = GPIOHeatingControl::create_no_sleep(GPIO_PINS.clone(), gpio_manager).unwrap();
+
+        controls.try_set_heat_pump(HeatPumpMode::HotWaterOnly)?;
+        assert_eq!(controls.try_get_heat_pump()?, HeatPumpMode::HotWaterOnly);
+        Ok(())
+    }
 }
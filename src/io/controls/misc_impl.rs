@@ -5,18 +5,22 @@ use crate::python_like::control::misc_control::{ImmersionHeaterControl, WiserPow
 use crate::{GPIOMode, MiscControls, PinUpdate};
 use crate::{GPIOManager, SysFsGPIO};
 use crate::io::gpio::GPIOError;
+use log::warn;
 
 pub struct MiscGPIOControls {
     gpio: SysFsGPIO,
     immersion_heater_pin: usize,
-    wiser_power_pin: usize,
+    /// None if the wiser's power isn't wired through our relay.
+    wiser_power_pin: Option<usize>,
 }
 
 impl MiscGPIOControls {
-    pub fn create(immersion_heater_pin: usize, wiser_power_pin: usize, sender: Sender<PinUpdate>) -> Result<Self, GPIOError> {
+    pub fn create(immersion_heater_pin: usize, wiser_power_pin: Option<usize>, sender: Sender<PinUpdate>) -> Result<Self, GPIOError> {
         let mut gpio = SysFsGPIO::new(sender);
         gpio.setup(immersion_heater_pin, &GPIOMode::Output)?;
-        gpio.setup(wiser_power_pin, &GPIOMode::Output)?;
+        if let Some(wiser_power_pin) = wiser_power_pin {
+            gpio.setup(wiser_power_pin, &GPIOMode::Output)?;
+        }
         Ok(Self {
             gpio,
             immersion_heater_pin,
@@ -46,14 +50,25 @@ impl ImmersionHeaterControl for MiscGPIOControls {
 }
 
 impl WiserPowerControl for MiscGPIOControls {
-    // DEFAULT ON not OFF - So wrong way reported / set.
+    // The relay is normally closed, so the pin is inverted: on (energised) cuts the power.
 
     fn try_set_wiser_power(&mut self, on: bool) -> Result<(), BrainFailure> {
-        translate_set_gpio(self.wiser_power_pin, &mut self.gpio, !on, "Failed to set wiser power pin")
+        match self.wiser_power_pin {
+            Some(pin) => translate_set_gpio(pin, &mut self.gpio, !on, "Failed to set wiser power pin"),
+            None => {
+                if !on {
+                    warn!("Not using the wiser power relay, so can't cut the wiser's power");
+                }
+                Ok(())
+            }
+        }
     }
 
     fn try_get_wiser_power(&mut self) -> Result<bool, BrainFailure> {
-        translate_get_gpio(self.wiser_power_pin, &self.gpio, "Failed to get wiser power pin")
-            .map(|b| !b)
+        match self.wiser_power_pin {
+            Some(pin) => translate_get_gpio(pin, &self.gpio, "Failed to get wiser power pin")
+                .map(|b| !b),
+            None => Ok(true),
+        }
     }
-}
\ No newline at end of file
+}
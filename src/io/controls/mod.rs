@@ -5,7 +5,7 @@ pub mod heating_impl;
 #[cfg(target_family = "unix")]
 pub mod misc_impl;
 
-fn translate_set_gpio(
+pub(crate) fn translate_set_gpio(
     pin: usize,
     gpio: &mut impl GPIOManager,
     on: bool,
@@ -20,7 +20,7 @@ fn translate_set_gpio(
     })
 }
 
-fn translate_get_gpio(
+pub(crate) fn translate_get_gpio(
     pin: usize,
     gpio: &impl GPIOManager,
     msg: &str,
@@ -32,7 +32,7 @@ impl ActiveDevices for DummyActiveDevices {
         Ok((&*guard).clone())
     }
 
-    fn get_active_devices_within(&mut self, time: &DateTime<Utc>, _minutes: usize) -> Result<Vec<Device>, BrainFailure> {
+    fn get_active_devices_within(&mut self, time: &DateTime<Utc>, _within: std::time::Duration) -> Result<Vec<Device>, BrainFailure> {
         self.get_active_devices(time)
     }
 }
@@ -2,6 +2,7 @@ use chrono::{DateTime, Duration, TimeZone, Utc};
 use itertools::Itertools;
 use log::warn;
 use rev_lines::RevLines;
+use serde::Deserialize;
 use std::{collections::HashMap, fs::File, io::BufReader};
 
 use crate::{
@@ -15,36 +16,77 @@ use crate::{
 
 pub mod dummy;
 
+/// The format of the lines in the arp log.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ArpLogFormat {
+    /// Space separated, oldest first:
+    /// 2023-12-14T11:58:24+00:00 58:94:6b:b3:ab:7c 192.168.0.27 PlayroomServer
+    #[default]
+    Spaces,
+    /// The same columns as Spaces but tab separated, so device names may contain spaces.
+    Tabs,
+    /// arpwatch's arp.dat, tab separated with the time in seconds since the epoch and
+    /// in no particular order:
+    /// 58:94:6b:b3:ab:7c\t192.168.0.27\t1702555104\tPlayroomServer
+    Arpwatch,
+}
+
+impl ArpLogFormat {
+    fn separator(&self) -> char {
+        match self {
+            ArpLogFormat::Spaces => ' ',
+            ArpLogFormat::Tabs | ArpLogFormat::Arpwatch => '\t',
+        }
+    }
+
+    /// Whether the lines are in time order, so we can stop reading
+    /// once we get to a line that is too old.
+    fn is_chronological(&self) -> bool {
+        match self {
+            ArpLogFormat::Spaces | ArpLogFormat::Tabs => true,
+            ArpLogFormat::Arpwatch => false,
+        }
+    }
+}
+
 pub struct DevicesFromFile {
     file: String,
-    active_within_minutes: usize,
+    active_within: std::time::Duration,
+    format: ArpLogFormat,
 }
 
 impl DevicesFromFile {
     pub fn create(config: &DevicesFromFileConfig) -> Self {
         Self::new(
             config.get_file().to_owned(),
-            config.get_active_within_minutes(),
+            config.get_active_within(),
         )
+        .with_format(config.get_format())
     }
 
-    pub fn new(file: String, active_within_minutes: usize) -> Self {
+    pub fn new(file: String, active_within: std::time::Duration) -> Self {
         Self {
             file,
-            active_within_minutes,
+            active_within,
+            format: ArpLogFormat::default(),
         }
     }
+
+    pub fn with_format(mut self, format: ArpLogFormat) -> Self {
+        self.format = format;
+        self
+    }
 }
 
 impl ActiveDevices for DevicesFromFile {
     fn get_active_devices(&mut self, time: &DateTime<Utc>) -> Result<Vec<Device>, BrainFailure> {
-        self.get_active_devices_within(time, self.active_within_minutes)
+        self.get_active_devices_within(time, self.active_within)
     }
 
     fn get_active_devices_within(
         &mut self,
         time: &DateTime<Utc>,
-        minutes: usize,
+        within: std::time::Duration,
     ) -> Result<Vec<Device>, BrainFailure> {
         let file = File::open(&self.file).map_err(|err| {
             brain_fail!(format!("Failed to open {} for reading: {}", self.file, err))
@@ -55,16 +97,21 @@ impl ActiveDevices for DevicesFromFile {
 
         let mut device_map: HashMap<Device, DateTime<Utc>> = HashMap::new();
 
-        let cut_off = time.clone() - Duration::seconds(60 * minutes as i64);
+        let within = Duration::from_std(within)
+            .map_err(|err| brain_fail!(format!("Invalid active devices window {:?}: {}", within, err)))?;
+        let cut_off = time.clone() - within;
 
         for line in rev_lines {
-            match parse_line(&line) {
+            match parse_line(&line, &self.format) {
                 Err(msg) => {
                     warn!("Error parsing active device line '{}' => {}", line, msg);
                     continue;
                 }
                 Ok((device, time)) => {
                     if time < cut_off {
+                        if !self.format.is_chronological() {
+                            continue;
+                        }
                         //println!("reached cut off time: {}", cut_off);
                         break;
                     }
@@ -77,30 +124,43 @@ impl ActiveDevices for DevicesFromFile {
     }
 }
 
-/// Parse a arp log line. Currently in the format:
-/// 2023-12-14T11:58:24+00:00 58:94:6b:b3:ab:7c 192.168.0.27 PlayroomServer
-fn parse_line(s: &str) -> Result<(Device, DateTime<Utc>), String> {
-    let mut split = s.split(' ');
+/// Parse a arp log line in the given format, see [ArpLogFormat] for examples.
+fn parse_line(s: &str, format: &ArpLogFormat) -> Result<(Device, DateTime<Utc>), String> {
+    let separator = format.separator();
+    let mut split = s.split(separator);
+    let mut next_column = |what: &str, column: &str| {
+        split.next().ok_or_else(|| {
+            format!("No {} part separated by {:?} ({} column)", what, separator, column)
+        })
+    };
 
-    let time_part = split
-        .next()
-        .ok_or_else(|| "No time part separated by ' ' (1st column)".to_owned())?;
-
-    let time = DateTime::parse_from_str(time_part, "%Y-%m-%dT%H:%M:%S%:z")
-        .map(|dt| Utc.from_utc_datetime(&dt.naive_utc()))
-        .map_err(|err| format!("Invalid date: '{}': {}", time_part, err))?;
-
-    let _mac = split
-        .next()
-        .ok_or_else(|| "No mac addr part separated by ' ' (2nd column)".to_owned())?;
-
-    let _ip = split
-        .next()
-        .ok_or_else(|| "No ip addr part separated by ' ' (3rd column)".to_owned())?;
+    let (time_part, device_name_part) = match format {
+        ArpLogFormat::Spaces | ArpLogFormat::Tabs => {
+            let time_part = next_column("time", "1st")?;
+            let _mac = next_column("mac addr", "2nd")?;
+            let _ip = next_column("ip addr", "3rd")?;
+            (time_part, next_column("device name", "4th")?)
+        }
+        ArpLogFormat::Arpwatch => {
+            let _mac = next_column("mac addr", "1st")?;
+            let _ip = next_column("ip addr", "2nd")?;
+            let time_part = next_column("time", "3rd")?;
+            (time_part, next_column("device name", "4th")?)
+        }
+    };
 
-    let device_name_part = split
-        .next()
-        .ok_or_else(|| "No device name part found separated by ' ' (4th column)".to_owned())?;
+    let time = match format {
+        ArpLogFormat::Spaces | ArpLogFormat::Tabs => {
+            DateTime::parse_from_str(time_part, "%Y-%m-%dT%H:%M:%S%:z")
+                .map(|dt| Utc.from_utc_datetime(&dt.naive_utc()))
+                .map_err(|err| format!("Invalid date: '{}': {}", time_part, err))?
+        }
+        ArpLogFormat::Arpwatch => time_part
+            .parse::<i64>()
+            .ok()
+            .and_then(|secs| Utc.timestamp_opt(secs, 0).single())
+            .ok_or_else(|| format!("Invalid timestamp: '{}'", time_part))?,
+    };
 
     if device_name_part.is_empty() {
         return Err("Device name empty!".to_owned());
@@ -118,13 +178,14 @@ mod test {
     use crate::io::devices::DevicesFromFile;
     use chrono::{NaiveDate, TimeZone, Utc};
     use itertools::Itertools;
+    use std::time::Duration;
 
-    use super::parse_line;
+    use super::{parse_line, ArpLogFormat};
 
     #[test]
     fn test_parse() {
         let s = "2023-02-12T09:59:54+00:00 cc:32:e5:7c:a5:94 192.168.0.17 TP-LINK";
-        let (device, time) = parse_line(s).unwrap();
+        let (device, time) = parse_line(s, &ArpLogFormat::Spaces).unwrap();
         assert_eq!(device, Device::new("TP-LINK".to_owned()));
         let expected_time = Utc.from_utc_datetime(
             &NaiveDate::from_ymd_opt(2023, 02, 12)
@@ -138,7 +199,7 @@ mod test {
     #[test]
     fn test_parse_daylight_savings() {
         let s = "2023-03-26T19:06:44+01:00 58:94:6b:b3:ab:7c 192.168.0.27 PlayroomServer";
-        let (device, time) = parse_line(s).unwrap();
+        let (device, time) = parse_line(s, &ArpLogFormat::Spaces).unwrap();
         assert_eq!(device, Device::new("PlayroomServer".to_owned()));
         let expected_time = Utc.from_utc_datetime(
             &NaiveDate::from_ymd_opt(2023, 03, 26)
@@ -158,7 +219,7 @@ mod test {
                 .unwrap(),
         );
         let mut devices_from_file =
-            DevicesFromFile::new("test/python_brain/active_devices/arp-log.txt".to_owned(), 8);
+            DevicesFromFile::new("test/python_brain/active_devices/arp-log.txt".to_owned(), Duration::from_secs(8 * 60));
         let mut active_devices = devices_from_file
             .get_active_devices(&time)
             .expect("Should work!")
@@ -185,5 +246,105 @@ mod test {
 
         assert_eq!(expected, active_devices);
     }
-}
 
+    #[test]
+    fn test_seconds_window() {
+        let time = Utc.from_utc_datetime(
+            &NaiveDate::from_ymd_opt(2023, 12, 14)
+                .unwrap()
+                .and_hms_opt(12, 58, 29)
+                .unwrap(),
+        );
+        let mut devices_from_file =
+            DevicesFromFile::new("test/python_brain/active_devices/arp-log.txt".to_owned(), Duration::from_secs(20));
+        let active_devices = devices_from_file
+            .get_active_devices(&time)
+            .expect("Should work!")
+            .into_iter()
+            .map(|device| format!("{}", device))
+            .sorted()
+            .collect_vec();
+
+        // LeoPhone was seen 44s ago.
+        let expected: Vec<String> = vec![
+            "OfficeComputer".into(),
+            "PlayroomServer".into(),
+            "TP-LINK".into(),
+            "VirginCableRouter".into(),
+        ];
+
+        assert_eq!(expected, active_devices);
+    }
+
+    #[test]
+    fn test_parse_tabs() {
+        let s = "2023-03-26T19:06:44+01:00\t58:94:6b:b3:ab:7c\t192.168.0.27\tPlayroom Server";
+        let (device, time) = parse_line(s, &ArpLogFormat::Tabs).unwrap();
+        assert_eq!(device, Device::new("Playroom Server".to_owned()));
+        let expected_time = Utc.from_utc_datetime(
+            &NaiveDate::from_ymd_opt(2023, 03, 26)
+                .unwrap()
+                .and_hms_opt(18, 06, 44)
+                .unwrap(),
+        );
+        assert_eq!(time, expected_time);
+    }
+
+    #[test]
+    fn test_parse_arpwatch() {
+        let s = "58:94:6b:b3:ab:7c\t192.168.0.27\t1702555104\tPlayroomServer";
+        let (device, time) = parse_line(s, &ArpLogFormat::Arpwatch).unwrap();
+        assert_eq!(device, Device::new("PlayroomServer".to_owned()));
+        let expected_time = Utc.from_utc_datetime(
+            &NaiveDate::from_ymd_opt(2023, 12, 14)
+                .unwrap()
+                .and_hms_opt(11, 58, 24)
+                .unwrap(),
+        );
+        assert_eq!(time, expected_time);
+    }
+
+    #[test]
+    fn test_parse_malformed() {
+        let spaces = "2023-02-12T09:59:54+00:00 cc:32:e5:7c:a5:94 192.168.0.17 TP-LINK";
+        // Right line, wrong format.
+        assert!(parse_line(spaces, &ArpLogFormat::Tabs).is_err());
+        assert!(parse_line(spaces, &ArpLogFormat::Arpwatch).is_err());
+
+        assert!(parse_line("", &ArpLogFormat::Spaces).is_err());
+        assert!(parse_line("2023-02-12T09:59:54+00:00 cc:32:e5:7c:a5:94 192.168.0.17", &ArpLogFormat::Spaces).is_err());
+        assert!(parse_line("2023-02-12T09:59:54+00:00 cc:32:e5:7c:a5:94 192.168.0.17 ", &ArpLogFormat::Spaces).is_err());
+        assert!(parse_line("yesterday cc:32:e5:7c:a5:94 192.168.0.17 TP-LINK", &ArpLogFormat::Spaces).is_err());
+        assert!(parse_line("cc:32:e5:7c:a5:94\t192.168.0.17\tnot-a-number\tTP-LINK", &ArpLogFormat::Arpwatch).is_err());
+        assert!(parse_line("cc:32:e5:7c:a5:94\t192.168.0.17\t1702555104\t", &ArpLogFormat::Arpwatch).is_err());
+    }
+
+    #[test]
+    fn test_parse_arpwatch_file() {
+        let time = Utc.from_utc_datetime(
+            &NaiveDate::from_ymd_opt(2023, 12, 14)
+                .unwrap()
+                .and_hms_opt(12, 58, 29)
+                .unwrap(),
+        );
+        let mut devices_from_file =
+            DevicesFromFile::new("test/python_brain/active_devices/arp.dat".to_owned(), Duration::from_secs(30 * 60))
+                .with_format(ArpLogFormat::Arpwatch);
+        let active_devices = devices_from_file
+            .get_active_devices(&time)
+            .expect("Should work!")
+            .into_iter()
+            .map(|device| format!("{}", device))
+            .sorted()
+            .collect_vec();
+
+        // Not in time order, so shouldn't stop at the first old line (PI2).
+        let expected: Vec<String> = vec![
+            "James Computer".into(),
+            "LeoPhone".into(),
+            "PlayroomServer".into(),
+        ];
+
+        assert_eq!(expected, active_devices);
+    }
+}
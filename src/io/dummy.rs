@@ -8,6 +8,8 @@ use log::debug;
 use std::sync::mpsc;
 use std::sync::mpsc::{Receiver, Sender, TryRecvError};
 use std::time::Duration;
+#[cfg(test)]
+use std::sync::{Arc, Mutex};
 
 pub trait DummyIO {
     type MessageType;
@@ -140,3 +142,109 @@ impl MiscControls for DummyAllOutputs {
         self
     }
 }
+
+/// A change made through a HeatingControl.
+#[cfg(test)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum HeatingControlEvent {
+    HeatPump(HeatPumpMode),
+    HeatCirculationPump(bool),
+}
+
+/// A change made through a HeatingControl, along with the order and time it happened at.
+#[cfg(test)]
+#[derive(Debug, Clone)]
+pub struct RecordedHeatingControlEvent {
+    pub index: usize,
+    pub time: DateTime<Utc>,
+    pub event: HeatingControlEvent,
+}
+
+/// The events recorded by a RecordingHeatingControl, which can be kept hold of
+/// after the control itself has been given to an IOBundle.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct HeatingControlLog {
+    events: Arc<Mutex<Vec<RecordedHeatingControlEvent>>>,
+}
+
+#[cfg(test)]
+impl HeatingControlLog {
+    fn record(&self, event: HeatingControlEvent) {
+        let mut events = self.events.lock().unwrap();
+        let index = events.len();
+        events.push(RecordedHeatingControlEvent {
+            index,
+            time: Utc::now(),
+            event,
+        });
+    }
+
+    pub fn get_recorded(&self) -> Vec<RecordedHeatingControlEvent> {
+        self.events.lock().unwrap().clone()
+    }
+
+    /// Just the events, in the order they happened.
+    pub fn get_events(&self) -> Vec<HeatingControlEvent> {
+        self.get_recorded().into_iter().map(|recorded| recorded.event).collect()
+    }
+
+    pub fn clear(&self) {
+        self.events.lock().unwrap().clear();
+    }
+}
+
+/// Wraps another HeatingControl, recording every try_set_* call made through it so that
+/// tests can check the order in which things were done.
+#[cfg(test)]
+pub struct RecordingHeatingControl<H: HeatingControl> {
+    inner: H,
+    log: HeatingControlLog,
+}
+
+#[cfg(test)]
+impl<H: HeatingControl> RecordingHeatingControl<H> {
+    pub fn new(inner: H) -> (Self, HeatingControlLog) {
+        let log = HeatingControlLog::default();
+        (Self { inner, log: log.clone() }, log)
+    }
+}
+
+#[cfg(test)]
+impl<H: HeatingControl> HeatPumpControl for RecordingHeatingControl<H> {
+    fn try_set_heat_pump(&mut self, mode: HeatPumpMode) -> Result<(), BrainFailure> {
+        self.log.record(HeatingControlEvent::HeatPump(mode.clone()));
+        self.inner.try_set_heat_pump(mode)
+    }
+
+    fn try_get_heat_pump(&self) -> Result<HeatPumpMode, BrainFailure> {
+        self.inner.try_get_heat_pump()
+    }
+
+    fn get_heat_pump_on_with_time(&self) -> Result<(bool, Duration), BrainFailure> {
+        self.inner.get_heat_pump_on_with_time()
+    }
+}
+
+#[cfg(test)]
+impl<H: HeatingControl> HeatCirculationPumpControl for RecordingHeatingControl<H> {
+    fn try_set_heat_circulation_pump(&mut self, on: bool) -> Result<(), BrainFailure> {
+        self.log.record(HeatingControlEvent::HeatCirculationPump(on));
+        self.inner.try_set_heat_circulation_pump(on)
+    }
+
+    fn try_get_heat_circulation_pump(&self) -> Result<bool, BrainFailure> {
+        self.inner.try_get_heat_circulation_pump()
+    }
+}
+
+#[cfg(test)]
+impl<H: HeatingControl> HeatingControl for RecordingHeatingControl<H> {
+    fn as_hp(&mut self) -> &mut dyn HeatPumpControl {
+        self
+    }
+
+    fn as_cp(&mut self) -> &mut dyn HeatCirculationPumpControl {
+        self
+    }
+}
@@ -1,7 +1,12 @@
 use chrono::{Duration, Utc};
+#[cfg(test)]
+use std::collections::HashMap;
 use std::sync::mpsc::Sender;
 
 use crate::config::WiserConfig;
+#[cfg(test)]
+use crate::io::faults::{Faults, FaultyTemperatureManager, FaultyWiser};
+use crate::HeatingControl;
 
 use super::{
     devices::dummy::{ActiveDevicesMessage, DummyActiveDevices},
@@ -37,13 +42,31 @@ impl DummyIOBundleHandle {
         self.send_temps(temperatures::dummy::ModifyState::SetTemp(sensor, temp))
     }
 
+    /// Replace all the readings with a steady 35.0 for the tank and heat exchanger sensors,
+    /// overridden by the given readings. HPRT is only present if given, as that usually matters.
+    #[cfg(test)]
+    pub fn send_steady_temps(&mut self, temps: &[(Sensor, f32)]) {
+        let mut all_temps: HashMap<Sensor, f32> = [Sensor::TKBT, Sensor::HXIF, Sensor::HXIR, Sensor::HXOR, Sensor::TKFL, Sensor::HPFL]
+            .iter()
+            .map(|sensor| (sensor.clone(), 35.0))
+            .collect();
+        all_temps.extend(temps.iter().cloned());
+        self.send_temps(temperatures::dummy::ModifyState::SetTemps(all_temps))
+    }
+
     pub fn send_devices(&mut self, msg: ActiveDevicesMessage) {
         self.active_devices_handle.send(msg).unwrap();
     }
 }
 
 pub fn new_dummy_io() -> (IOBundle, DummyIOBundleHandle) {
-    let heating_control = DummyAllOutputs::default();
+    new_dummy_io_with_heating_control(DummyAllOutputs::default())
+}
+
+/// As new_dummy_io, but using the given heating control rather than a dummy one.
+pub fn new_dummy_io_with_heating_control(
+    heating_control: impl HeatingControl + 'static,
+) -> (IOBundle, DummyIOBundleHandle) {
     let misc_control = DummyAllOutputs::default();
     let (wiser, wiser_handle) = wiser::dummy::Dummy::create(&WiserConfig::fake());
     let (temp_manager, temp_handle) = temperatures::dummy::Dummy::create(&());
@@ -66,3 +89,26 @@ pub fn new_dummy_io() -> (IOBundle, DummyIOBundleHandle) {
     (io_bundle, handle)
 }
 
+/// As new_dummy_io, but with the temperatures and wiser failing whenever the given faults are active.
+#[cfg(test)]
+pub fn new_dummy_io_with_faults(faults: &Faults) -> (IOBundle, DummyIOBundleHandle) {
+    let (wiser, wiser_handle) = wiser::dummy::Dummy::create(&WiserConfig::fake());
+    let (temp_manager, temp_handle) = temperatures::dummy::Dummy::create(&());
+    let (active_devices, active_devices_handle) = DummyActiveDevices::create(&());
+
+    let io_bundle = IOBundle::new(
+        FaultyTemperatureManager::new(temp_manager, faults.clone()),
+        DummyAllOutputs::default(),
+        DummyAllOutputs::default(),
+        FaultyWiser::new(wiser, faults.clone()),
+        active_devices,
+    );
+
+    let handle = DummyIOBundleHandle {
+        wiser_handle,
+        temp_handle,
+        active_devices_handle,
+    };
+
+    (io_bundle, handle)
+}
@@ -0,0 +1,205 @@
+use crate::io::gpio::{GPIOError, GPIOManager, GPIOMode, GPIOState};
+use crate::io::temperatures::{Sensor, TemperatureManager};
+use crate::io::wiser::WiserManager;
+use crate::WiserHub;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Which IO calls to make fail, for checking how the brain copes.
+/// Only has an effect in debug builds.
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct FaultInjectionConfig {
+    /// Fail reading the temperatures.
+    pub temperature_read: bool,
+    /// Act as if the wiser hub timed out.
+    pub wiser_timeout: bool,
+    /// Fail writing to GPIO pins.
+    pub gpio_write: bool,
+}
+
+impl FaultInjectionConfig {
+    pub fn any(&self) -> bool {
+        self.temperature_read || self.wiser_timeout || self.gpio_write
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fault {
+    TemperatureRead,
+    WiserTimeout,
+    GpioWrite,
+}
+
+/// Which faults are currently being injected, shared between the wrapped IO
+/// so they can be turned on and off while running.
+#[derive(Debug, Clone, Default)]
+pub struct Faults {
+    temperature_read: Arc<AtomicBool>,
+    wiser_timeout: Arc<AtomicBool>,
+    gpio_write: Arc<AtomicBool>,
+}
+
+impl Faults {
+    pub fn from_config(config: &FaultInjectionConfig) -> Self {
+        let faults = Self::default();
+        faults.set(Fault::TemperatureRead, config.temperature_read);
+        faults.set(Fault::WiserTimeout, config.wiser_timeout);
+        faults.set(Fault::GpioWrite, config.gpio_write);
+        faults
+    }
+
+    pub fn set(&self, fault: Fault, active: bool) {
+        self.flag(fault).store(active, Ordering::Relaxed);
+    }
+
+    pub fn is_active(&self, fault: Fault) -> bool {
+        self.flag(fault).load(Ordering::Relaxed)
+    }
+
+    fn flag(&self, fault: Fault) -> &AtomicBool {
+        match fault {
+            Fault::TemperatureRead => &self.temperature_read,
+            Fault::WiserTimeout => &self.wiser_timeout,
+            Fault::GpioWrite => &self.gpio_write,
+        }
+    }
+}
+
+/// A temperature manager that fails to read the temperatures while Fault::TemperatureRead is active.
+pub struct FaultyTemperatureManager<T: TemperatureManager> {
+    inner: T,
+    faults: Faults,
+}
+
+impl<T: TemperatureManager> FaultyTemperatureManager<T> {
+    pub fn new(inner: T, faults: Faults) -> Self {
+        Self { inner, faults }
+    }
+}
+
+#[async_trait]
+impl<T: TemperatureManager + Send + Sync> TemperatureManager for FaultyTemperatureManager<T> {
+    async fn retrieve_sensors(&mut self) -> Result<(), String> {
+        self.inner.retrieve_sensors().await
+    }
+
+    async fn retrieve_temperatures(&self) -> Result<HashMap<Sensor, f32>, String> {
+        if self.faults.is_active(Fault::TemperatureRead) {
+            return Err("Injected temperature read fault".to_owned());
+        }
+        self.inner.retrieve_temperatures().await
+    }
+}
+
+/// A wiser manager that acts as if the hub timed out while Fault::WiserTimeout is active.
+pub struct FaultyWiser<W: WiserManager> {
+    inner: W,
+    faults: Faults,
+}
+
+impl<W: WiserManager> FaultyWiser<W> {
+    pub fn new(inner: W, faults: Faults) -> Self {
+        Self { inner, faults }
+    }
+}
+
+#[async_trait]
+impl<W: WiserManager + Send + Sync> WiserManager for FaultyWiser<W> {
+    async fn get_heating_turn_off_time(&self) -> Option<DateTime<Utc>> {
+        if self.faults.is_active(Fault::WiserTimeout) {
+            return None;
+        }
+        self.inner.get_heating_turn_off_time().await
+    }
+
+    async fn get_heating_on(&self) -> Result<bool, ()> {
+        if self.faults.is_active(Fault::WiserTimeout) {
+            return Err(());
+        }
+        self.inner.get_heating_on().await
+    }
+
+    fn get_wiser_hub(&self) -> &dyn WiserHub {
+        self.inner.get_wiser_hub()
+    }
+}
+
+/// A GPIO manager that fails to set pins while Fault::GpioWrite is active.
+pub struct FaultyGPIO<G: GPIOManager> {
+    inner: G,
+    faults: Faults,
+}
+
+impl<G: GPIOManager> FaultyGPIO<G> {
+    pub fn new(inner: G, faults: Faults) -> Self {
+        Self { inner, faults }
+    }
+}
+
+impl<G: GPIOManager> GPIOManager for FaultyGPIO<G> {
+    fn setup(&mut self, pin: usize, mode: &GPIOMode) -> Result<(), GPIOError> {
+        self.inner.setup(pin, mode)
+    }
+
+    fn set_pin(&mut self, pin_id: usize, state: &GPIOState) -> Result<(), GPIOError> {
+        if self.faults.is_active(Fault::GpioWrite) {
+            return Err(GPIOError::Other(format!("Injected GPIO write fault on pin {}", pin_id)));
+        }
+        self.inner.set_pin(pin_id, state)
+    }
+
+    fn get_pin(&self, pin: usize) -> Result<GPIOState, GPIOError> {
+        self.inner.get_pin(pin)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::io::gpio::dummy::Dummy as DummyGPIO;
+    use crate::io::temperatures::dummy::{Dummy as DummyTemps, ModifyState};
+    use crate::io::dummy::DummyIO;
+    use tokio::runtime::Runtime;
+
+    #[test]
+    fn test_config() {
+        let config: FaultInjectionConfig = toml::from_str("gpio_write = true").expect("Should be valid");
+        assert!(config.any());
+        let faults = Faults::from_config(&config);
+        assert!(faults.is_active(Fault::GpioWrite));
+        assert!(!faults.is_active(Fault::TemperatureRead));
+        assert!(!FaultInjectionConfig::default().any());
+    }
+
+    #[test]
+    fn test_temperature_read_fault() {
+        let rt = Runtime::new().unwrap();
+        let faults = Faults::default();
+        let (temps, handle) = DummyTemps::create(&());
+        let temps = FaultyTemperatureManager::new(temps, faults.clone());
+        handle.send(ModifyState::SetTemp(Sensor::TKBT, 40.0)).unwrap();
+
+        assert_eq!(rt.block_on(temps.retrieve_temperatures()), Ok(HashMap::from([(Sensor::TKBT, 40.0)])));
+        faults.set(Fault::TemperatureRead, true);
+        assert!(rt.block_on(temps.retrieve_temperatures()).is_err());
+        faults.set(Fault::TemperatureRead, false);
+        assert!(rt.block_on(temps.retrieve_temperatures()).is_ok());
+    }
+
+    #[test]
+    fn test_gpio_write_fault() {
+        let faults = Faults::default();
+        let mut gpio = FaultyGPIO::new(DummyGPIO::default(), faults.clone());
+        gpio.setup(1, &GPIOMode::Output).unwrap();
+        gpio.set_pin(1, &GPIOState::Low).unwrap();
+
+        faults.set(Fault::GpioWrite, true);
+        assert!(gpio.set_pin(1, &GPIOState::High).is_err());
+        assert_eq!(gpio.get_pin(1).unwrap(), GPIOState::Low);
+    }
+}
@@ -0,0 +1,101 @@
+use crate::config::{HeartbeatConfig, HeartbeatMode};
+use crate::io::gpio::{GPIOError, GPIOManager, GPIOMode, GPIOState};
+use log::error;
+
+/// Drives a pin each time the brain runs, so that an external watchdog can tell the
+/// process hasn't hung.
+pub struct Heartbeat {
+    pin: usize,
+    mode: HeartbeatMode,
+    gpio: Box<dyn GPIOManager>,
+    state: GPIOState,
+}
+
+impl Heartbeat {
+    pub fn create(config: &HeartbeatConfig, mut gpio: Box<dyn GPIOManager>) -> Result<Self, GPIOError> {
+        let pin = config.get_pin();
+        gpio.setup(pin, &GPIOMode::Output)?;
+        gpio.set_pin(pin, &GPIOState::Low)?;
+        Ok(Self {
+            pin,
+            mode: config.get_mode(),
+            gpio,
+            state: GPIOState::Low,
+        })
+    }
+
+    /// Update the pin after the brain has run, healthy being whether it ran successfully.
+    pub fn beat(&mut self, healthy: bool) {
+        let state = match (self.mode, healthy) {
+            (HeartbeatMode::Toggle, true) => match self.state {
+                GPIOState::High => GPIOState::Low,
+                GPIOState::Low => GPIOState::High,
+            },
+            (HeartbeatMode::Toggle, false) => return,
+            (HeartbeatMode::Healthy, true) => GPIOState::High,
+            (HeartbeatMode::Healthy, false) => GPIOState::Low,
+        };
+        self.set(state);
+    }
+
+    /// Drive the pin low as we are shutting down, so it doesn't look like we are still healthy.
+    pub fn stop(&mut self) {
+        self.set(GPIOState::Low);
+    }
+
+    fn set(&mut self, state: GPIOState) {
+        // Not worth stopping the brain over, the watchdog will notice.
+        match self.gpio.set_pin(self.pin, &state) {
+            Ok(()) => self.state = state,
+            Err(e) => error!("Failed to set heartbeat pin {}: {:?}", self.pin, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::io::gpio::dummy::Dummy;
+
+    fn heartbeat(config: &str) -> Heartbeat {
+        let config: HeartbeatConfig = toml::from_str(config).expect("Should be valid");
+        Heartbeat::create(&config, Box::<Dummy>::default()).expect("Should set up")
+    }
+
+    fn pin_state(heartbeat: &Heartbeat) -> GPIOState {
+        heartbeat.gpio.get_pin(heartbeat.pin).unwrap()
+    }
+
+    #[test]
+    fn test_toggle() {
+        let mut heartbeat = heartbeat("pin = 21");
+        assert_eq!(pin_state(&heartbeat), GPIOState::Low);
+
+        let mut states = vec![];
+        for _ in 0..4 {
+            heartbeat.beat(true);
+            states.push(pin_state(&heartbeat));
+        }
+        assert_eq!(states, vec![GPIOState::High, GPIOState::Low, GPIOState::High, GPIOState::Low]);
+
+        heartbeat.beat(false);
+        assert_eq!(pin_state(&heartbeat), GPIOState::Low, "Should stop toggling on failure");
+        heartbeat.beat(false);
+        assert_eq!(pin_state(&heartbeat), GPIOState::Low, "Should stop toggling on failure");
+    }
+
+    #[test]
+    fn test_healthy() {
+        let mut heartbeat = heartbeat("pin = 21\nmode = \"Healthy\"");
+        heartbeat.beat(true);
+        assert_eq!(pin_state(&heartbeat), GPIOState::High);
+        heartbeat.beat(true);
+        assert_eq!(pin_state(&heartbeat), GPIOState::High, "Should stay high while healthy");
+        heartbeat.beat(false);
+        assert_eq!(pin_state(&heartbeat), GPIOState::Low);
+
+        heartbeat.beat(true);
+        heartbeat.stop();
+        assert_eq!(pin_state(&heartbeat), GPIOState::Low, "Should go low on shutdown");
+    }
+}
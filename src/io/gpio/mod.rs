@@ -1,4 +1,5 @@
 pub mod dummy;
+pub mod heartbeat;
 
 #[cfg(target_family = "unix")]
 pub mod sysfs_gpio;
@@ -34,14 +35,21 @@ pub trait GPIOManager: Send {
     fn get_pin(&self, pin: usize) -> Result<GPIOState, GPIOError>;
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct PinUpdate {
     pin: usize,
     to: GPIOState,
+    /// Whether this is the state the pin was found in at startup, rather than a change,
+    /// so whatever it was before (since the last update) is unknown.
+    startup: bool,
 }
 
 impl PinUpdate {
     pub fn new(pin: usize, to: GPIOState) -> Self {
-        PinUpdate { pin, to }
+        PinUpdate { pin, to, startup: false }
+    }
+
+    pub fn startup_marker(pin: usize, to: GPIOState) -> Self {
+        PinUpdate { pin, to, startup: true }
     }
 }
@@ -16,6 +16,20 @@ impl SysFsGPIO {
             sender,
         }
     }
+
+    /// Read the state of an output pin without setting it up, so nothing about it is changed.
+    /// None if it hasn't been set up as an output (by us or anything else).
+    pub fn read_output_pin(pin_id: usize) -> Result<Option<GPIOState>, GPIOError> {
+        let pin = sysfs_gpio::Pin::new(pin_id as u64);
+        if !pin.is_exported() || pin.get_direction()? == Direction::In {
+            return Ok(None);
+        }
+        match pin.get_value()? {
+            0 => Ok(Some(GPIOState::Low)),
+            1 => Ok(Some(GPIOState::High)),
+            _ => panic!("Breach of api contract / implementation"),
+        }
+    }
 }
 
 impl GPIOManager for SysFsGPIO {
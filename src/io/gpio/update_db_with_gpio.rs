@@ -1,18 +1,149 @@
 use crate::io::gpio::{GPIOState, PinUpdate};
+use crate::io::temperatures::update_db_with_temps::check_table_name;
 use log::{debug, error, info, warn};
 use sqlx::{Executor, MySqlPool, Row};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
 use tokio::sync::mpsc::Receiver;
 
-pub async fn run(conn: MySqlPool, mut receiver: Receiver<PinUpdate>) {
-    info!("Running database GPIO updater.");
-    let mut map: HashMap<u32, u32> = HashMap::new();
+/// The default table to record GPIO changes into.
+pub const GPIO_TABLE: &str = "reading";
 
-    let result = conn
+/// Added to the raw value of a startup marker, so 2 is off and 3 is on at startup,
+/// with the state unknown between the previous row and this one.
+/// Anything reading raw_value as just on/off needs to use raw_value % 2 (see the README).
+pub const STARTUP_MARKER_OFFSET: u16 = 2;
+
+/// Build the insert of a single GPIO change into the table, binding sensor id and value.
+pub fn build_insert(table: &str) -> Result<String, String> {
+    check_table_name(table)?;
+    Ok(format!("INSERT INTO {} (sensor_id, raw_value) VALUES (?,?)", table))
+}
+
+/// Build the query for the last value recorded for a sensor in the table, binding sensor id.
+pub fn build_select_last(table: &str) -> Result<String, String> {
+    check_table_name(table)?;
+    Ok(format!("SELECT raw_value FROM {} WHERE sensor_id=? ORDER BY `id` DESC LIMIT 1", table))
+}
+
+/// The value recorded in the database for the update.
+fn raw_value(pin_update: &PinUpdate) -> u16 {
+    let on_off = match pin_update.to {
+        GPIOState::Low => 1,
+        GPIOState::High => 0,
+    };
+    if pin_update.startup {
+        on_off + STARTUP_MARKER_OFFSET
+    } else {
+        on_off
+    }
+}
+
+/// The startup markers needed for the current pin states, skipping any pin whose last recorded
+/// value is already the marker for its current state so that backfilling twice does nothing.
+pub fn missing_startup_markers(
+    current: &BTreeMap<usize, GPIOState>,
+    last_recorded: &HashMap<usize, u16>,
+) -> Vec<PinUpdate> {
+    current
+        .iter()
+        .map(|(pin, state)| PinUpdate::startup_marker(*pin, state.clone()))
+        .filter(|marker| last_recorded.get(&marker.pin) != Some(&raw_value(marker)))
+        .collect()
+}
+
+/// Map of GPIO pin to the sensor id it is recorded as.
+async fn fetch_sensor_map(conn: &MySqlPool) -> Result<HashMap<u32, u32>, sqlx::Error> {
+    let rows = conn
         .fetch_all(sqlx::query!(
             "SELECT id, channel FROM sensor WHERE type='GPIO'"
         ))
-        .await;
+        .await?;
+
+    let mut map = HashMap::new();
+    for row in rows {
+        let channel: String = row.get("channel");
+        map.insert(channel.parse().unwrap(), row.get("id"));
+    }
+    Ok(map)
+}
+
+/// Insert the update, returning the sensor id it was recorded as, or None if the pin has no sensor.
+async fn record(
+    conn: &MySqlPool,
+    insert: &str,
+    map: &HashMap<u32, u32>,
+    pin_update: &PinUpdate,
+) -> Result<Option<u32>, sqlx::Error> {
+    let sensor_id = match map.get(&(pin_update.pin as u32)) {
+        Some(sensor_id) => *sensor_id,
+        None => return Ok(None),
+    };
+    conn.execute(sqlx::query(insert).bind(sensor_id).bind(raw_value(pin_update)))
+        .await?;
+    Ok(Some(sensor_id))
+}
+
+/// Record the current state of each pin as a startup marker, unless that is already the last
+/// thing recorded for it. Returns the number of markers inserted.
+pub async fn backfill_startup_markers(
+    conn: &MySqlPool,
+    table: &str,
+    current: &BTreeMap<usize, GPIOState>,
+) -> Result<usize, String> {
+    let insert = build_insert(table)?;
+    let select_last = build_select_last(table)?;
+    let map = fetch_sensor_map(conn)
+        .await
+        .map_err(|e| format!("Failed to fetch GPIO sensors from DB: {}", e))?;
+
+    let mut last_recorded = HashMap::new();
+    for pin in current.keys() {
+        let sensor_id = match map.get(&(*pin as u32)) {
+            Some(sensor_id) => sensor_id,
+            None => {
+                warn!("No database entry found for gpio pin: {}, not marking it", pin);
+                continue;
+            }
+        };
+        let row = conn
+            .fetch_optional(sqlx::query(&select_last).bind(sensor_id))
+            .await
+            .map_err(|e| format!("Failed to get last value of pin {}: {}", pin, e))?;
+        let last: Option<i16> = row.and_then(|row| row.get("raw_value"));
+        if let Some(last) = last.and_then(|last| u16::try_from(last).ok()) {
+            last_recorded.insert(*pin, last);
+        }
+    }
+
+    let mut inserted = 0;
+    for marker in missing_startup_markers(current, &last_recorded) {
+        let recorded = record(conn, &insert, &map, &marker)
+            .await
+            .map_err(|e| format!("Failed to record {:?}: {}", marker, e))?;
+        if let Some(sensor_id) = recorded {
+            info!("Marked {}: {} in DB", sensor_id, raw_value(&marker));
+            inserted += 1;
+        }
+    }
+    Ok(inserted)
+}
+
+/// Record GPIO changes into the table, or if None just take them and throw them away.
+pub async fn run(conn: MySqlPool, mut receiver: Receiver<PinUpdate>, table: Option<String>) {
+    let insert = match table.as_deref().map(build_insert) {
+        Some(Ok(insert)) => Some(insert),
+        Some(Err(e)) => {
+            error!("{} - WONT BE ABLE TO RECORD INTO DB", e);
+            return;
+        }
+        None => None,
+    };
+    match &table {
+        Some(table) => info!("Running database GPIO updater into {}.", table),
+        None => info!("Database writes disabled, not recording GPIO changes."),
+    }
+    let result = fetch_sensor_map(&conn).await;
 
     if result.is_err() {
         error!(
@@ -22,12 +153,7 @@ pub async fn run(conn: MySqlPool, mut receiver: Receiver<PinUpdate>) {
         return;
     }
 
-    let rows = result.unwrap();
-    for row in rows {
-        let channel: String = row.get("channel");
-        map.insert(channel.parse().unwrap(), row.get("id"));
-    }
-    let map = map;
+    let map = result.unwrap();
     debug!("Sensor Map: {:?}", map);
 
     loop {
@@ -39,26 +165,62 @@ pub async fn run(conn: MySqlPool, mut receiver: Receiver<PinUpdate>) {
         let pin_update = result.unwrap();
         debug!("Received pin update: {:?}", pin_update);
 
-        let pin = pin_update.pin as u32;
-        if let Some(sensor_id) = map.get(&pin) {
-            let to = gpio_state_to_on_off(&pin_update.to);
-            conn.execute(sqlx::query!(
-                "INSERT INTO reading (sensor_id, raw_value) VALUES (?,?)",
-                sensor_id,
-                to
-            ))
-            .await
-            .unwrap();
-            debug!("Recorded {sensor_id}: {to} in DB");
+        let insert = match &insert {
+            Some(insert) => insert,
+            None => continue,
+        };
+        if let Some(sensor_id) = record(&conn, insert, &map, &pin_update).await.unwrap() {
+            debug!("Recorded {sensor_id}: {} in DB", raw_value(&pin_update));
         } else {
-            error!("No database entry found for gpio pin: {}", pin)
+            error!("No database entry found for gpio pin: {}", pin_update.pin)
         }
     }
+}
 
-    fn gpio_state_to_on_off(state: &GPIOState) -> u16 {
-        match state {
-            GPIOState::Low => 1,
-            GPIOState::High => 0,
-        }
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_build_insert() {
+        assert_eq!(build_insert(GPIO_TABLE), Ok("INSERT INTO reading (sensor_id, raw_value) VALUES (?,?)".to_owned()));
+        assert_eq!(build_insert("test_reading"), Ok("INSERT INTO test_reading (sensor_id, raw_value) VALUES (?,?)".to_owned()));
+        assert!(build_insert("reading; DROP TABLE sensor").is_err());
+    }
+
+    #[test]
+    fn test_raw_value() {
+        assert_eq!(raw_value(&PinUpdate::new(5, GPIOState::High)), 0);
+        assert_eq!(raw_value(&PinUpdate::new(5, GPIOState::Low)), 1);
+        assert_eq!(raw_value(&PinUpdate::startup_marker(5, GPIOState::High)), 2);
+        assert_eq!(raw_value(&PinUpdate::startup_marker(5, GPIOState::Low)), 3);
+    }
+
+    #[test]
+    fn test_missing_startup_markers() {
+        let current = BTreeMap::from([
+            (5, GPIOState::Low),
+            (6, GPIOState::High),
+            (13, GPIOState::High),
+            (26, GPIOState::Low),
+        ]);
+        let last_recorded = HashMap::from([
+            (5, 1),  // On, but not known to be on since.
+            (6, 2),  // Already marked as off.
+            (13, 3), // Marked as on, but has been turned off since.
+            // 26 has never been recorded.
+        ]);
+
+        let markers = missing_startup_markers(&current, &last_recorded);
+        assert_eq!(markers, vec![
+            PinUpdate::startup_marker(5, GPIOState::Low),
+            PinUpdate::startup_marker(13, GPIOState::High),
+            PinUpdate::startup_marker(26, GPIOState::Low),
+        ]);
+
+        let last_recorded: HashMap<usize, u16> = last_recorded.into_iter()
+            .chain(markers.iter().map(|marker| (marker.pin, raw_value(marker))))
+            .collect();
+        assert_eq!(missing_startup_markers(&current, &last_recorded), vec![], "Should be idempotent");
     }
 }
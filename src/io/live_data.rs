@@ -6,6 +6,7 @@ use std::{
 use chrono::{DateTime, Utc};
 use log::warn;
 
+#[derive(Debug)]
 pub struct CheckAgeResult {
     max_age_seconds: i64,
     actual_age_seconds: i64,
@@ -36,7 +37,12 @@ pub enum AgeType {
 }
 
 pub fn check_age(timestamp: DateTime<Utc>, max_age: i64) -> CheckAgeResult {
-    let age_seconds = Utc::now().signed_duration_since(timestamp).num_seconds();
+    check_age_at(timestamp, max_age, Utc::now())
+}
+
+/// Check the age of the timestamp as of the given time.
+pub fn check_age_at(timestamp: DateTime<Utc>, max_age: i64, now: DateTime<Utc>) -> CheckAgeResult {
+    let age_seconds = now.signed_duration_since(timestamp).num_seconds();
 
     let age_type = if age_seconds > max_age {
         AgeType::TooOld
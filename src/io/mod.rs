@@ -2,6 +2,7 @@ pub mod controls;
 pub mod devices;
 pub mod dummy;
 pub mod dummy_io_bundle;
+pub mod faults;
 pub mod gpio;
 pub mod live_data;
 pub mod robbable;
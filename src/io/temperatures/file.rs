@@ -1,10 +1,11 @@
 use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
 
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
-use log::{error, trace, warn};
+use log::{debug, error, trace, warn};
 use serde::Deserialize;
 
 use crate::io::live_data::{check_age, AgeType, CachedPrevious};
@@ -15,29 +16,156 @@ use super::{Sensor, TemperatureManager};
 const MAX_FILE_AGE: i64 = 60;
 /// How old a sensor reading is allowed to be before the reading being considered stale.
 const MAX_READING_AGE: i64 = 90;
+/// How many times to re-read the file if it fails to parse.
+/// The file isn't written atomically, so we can catch it part way through being written.
+const PARSE_RETRIES: usize = 2;
+/// How long to wait before re-reading the file after failing to parse it.
+const PARSE_RETRY_DELAY: Duration = Duration::from_millis(50);
+
+/// The format of the live temperatures file.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TempsFileFormat {
+    /// A timestamped reading for each sensor, as written by the python side.
+    #[default]
+    Json,
+    /// A sensor,value line for each sensor. Having no timestamps, the readings
+    /// are taken to be as old as the file.
+    Csv,
+}
+
+impl TempsFileFormat {
+    /// Guess the format from the file extension, defaulting to JSON.
+    pub fn from_path(file: &Path) -> Self {
+        match file.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("csv") => TempsFileFormat::Csv,
+            _ => TempsFileFormat::Json,
+        }
+    }
+}
 
 pub struct LiveFileTemperatures {
     file: PathBuf,
-    last_data: CachedPrevious<TempsFileData>,
+    format: TempsFileFormat,
+    last_data: CachedPrevious<CachedTempsFile>,
+}
+
+/// The last successfully parsed temps file, along with when it was last modified
+/// so we can avoid re-parsing it if it hasn't changed.
+#[derive(Clone)]
+struct CachedTempsFile {
+    modified: Option<SystemTime>,
+    data: TempsFileData,
 }
 
 impl LiveFileTemperatures {
     pub fn new(file: PathBuf) -> Self {
         Self {
+            format: TempsFileFormat::from_path(&file),
             file,
             last_data: CachedPrevious::none(),
         }
     }
 
-    pub fn read_temps_data(&self) -> Result<TempsFileData, String> {
-        let s = fs::read_to_string(&self.file)
-            .map_err(|e| format!("Failed to read {:?}: {}", self.file, e))?;
+    #[must_use]
+    pub fn with_format(mut self, format: TempsFileFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub async fn read_temps_data(&self) -> Result<TempsFileData, String> {
+        let modified = fs::metadata(&self.file)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        self.read_temps_data_if_modified(modified).await
+    }
+
+    /// Read the temps file, unless it has the same modified time as the last time it was read,
+    /// in which case the previously parsed data is used.
+    async fn read_temps_data_if_modified(&self, modified: Option<SystemTime>) -> Result<TempsFileData, String> {
+        if let (Some(modified), Some(cached)) = (modified, self.last_data.get()) {
+            if cached.modified == Some(modified) {
+                trace!("{:?} unchanged since last read, using cached data", self.file);
+                return Ok(cached.data);
+            }
+        }
+
+        let read = || {
+            fs::read_to_string(&self.file)
+                .map_err(|e| format!("Failed to read {:?}: {}", self.file, e))
+        };
+        let data = match self.format {
+            TempsFileFormat::Json => {
+                parse_with_retry(&self.file, read, |s, _| parse_json(s)).await?
+            }
+            TempsFileFormat::Csv => {
+                let timestamp = modified.map(DateTime::<Utc>::from).unwrap_or_else(Utc::now);
+                parse_with_retry(&self.file, read, |s, last_attempt| parse_csv(&self.file, s, timestamp, last_attempt)).await?
+            }
+        };
 
-        serde_json::from_str(&s)
-            .map_err(|e| format!("Failed to deserialize: {:?}: {}\n{}", self.file, e, s))
+        self.last_data.update(CachedTempsFile {
+            modified,
+            data: data.clone(),
+        });
+        Ok(data)
     }
 }
 
+/// Read and parse the temps data, re-reading it a few times if it is invalid,
+/// in case we caught it while it was being written.
+/// parse is told whether it is the last attempt, so it can be more lenient.
+async fn parse_with_retry(
+    file: &Path,
+    mut read: impl FnMut() -> Result<String, String>,
+    parse: impl Fn(&str, bool) -> Result<TempsFileData, String>,
+) -> Result<TempsFileData, String> {
+    let mut retries = 0;
+    loop {
+        let s = read()?;
+        match parse(&s, retries >= PARSE_RETRIES) {
+            Ok(data) => return Ok(data),
+            Err(e) if retries < PARSE_RETRIES => {
+                retries += 1;
+                debug!("Failed to parse {:?} ({}), retrying ({}/{})", file, e, retries, PARSE_RETRIES);
+                tokio::time::sleep(PARSE_RETRY_DELAY).await;
+            }
+            Err(e) => return Err(format!("Failed to parse: {:?}: {}\n{}", file, e, s)),
+        }
+    }
+}
+
+fn parse_json(s: &str) -> Result<TempsFileData, String> {
+    serde_json::from_str(s).map_err(|e| e.to_string())
+}
+
+/// Parse a sensor,value line for each sensor, all read at the given time.
+/// Blank lines, comments (#) and a header line are ignored. Malformed lines (or no readings at all,
+/// as can be seen part way through the file being written) are an error, unless skip_malformed
+/// in which case the malformed lines are skipped.
+fn parse_csv(file: &Path, s: &str, timestamp: DateTime<Utc>, skip_malformed: bool) -> Result<TempsFileData, String> {
+    let mut temps = HashMap::new();
+    for (i, line) in s.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.eq_ignore_ascii_case("sensor,value") {
+            continue;
+        }
+        let reading = line.split_once(',')
+            .and_then(|(sensor, value)| Some((sensor.trim(), value.trim().parse::<f32>().ok()?)))
+            .filter(|(sensor, _)| !sensor.is_empty());
+        match reading {
+            Some((sensor, value)) => {
+                temps.insert(Sensor::from(sensor), TimestampedTemperature { value, timestamp });
+            }
+            None if skip_malformed => warn!("{:?} line {}: Skipping malformed reading {:?}", file, i + 1, line),
+            None => return Err(format!("Line {}: Malformed reading {:?}", i + 1, line)),
+        }
+    }
+    if temps.is_empty() && !skip_malformed {
+        return Err("No readings".to_owned());
+    }
+    Ok(TempsFileData { timestamp, temps })
+}
+
 #[async_trait]
 impl TemperatureManager for LiveFileTemperatures {
     async fn retrieve_sensors(&mut self) -> Result<(), String> {
@@ -45,13 +173,10 @@ impl TemperatureManager for LiveFileTemperatures {
     }
 
     async fn retrieve_temperatures(&self) -> Result<HashMap<Sensor, f32>, String> {
-        let temps_data = match self.read_temps_data() {
-            Ok(data) => {
-                self.last_data.update(data.clone());
-                data
-            }
+        let temps_data = match self.read_temps_data().await {
+            Ok(data) => data,
             Err(e) => {
-                let previous_data = self.last_data.get().ok_or_else(|| {
+                let previous_data = self.last_data.get().map(|cached| cached.data).ok_or_else(|| {
                     format!(
                         "Failed to get temps ({:?}) and no last was available: {}",
                         self.file, e
@@ -109,6 +234,12 @@ pub struct TempsFileData {
     temps: HashMap<Sensor, TimestampedTemperature>,
 }
 
+impl TempsFileData {
+    pub fn get_sensors(&self) -> impl Iterator<Item = &Sensor> {
+        self.temps.keys()
+    }
+}
+
 #[derive(Deserialize, Debug, PartialEq, Clone)]
 pub struct TimestampedTemperature {
     value: f32,
@@ -193,4 +324,138 @@ mod test {
         };
         assert_eq!(file_data, expected);
     }
+
+    #[tokio::test]
+    async fn test_unchanged_file_cached() {
+        let file = std::env::temp_dir().join(format!("follow_heating_test_temps_{}.json", std::process::id()));
+        fs::write(&file, EXAMPLE_DATA).unwrap();
+
+        let temps = LiveFileTemperatures::new(file.clone());
+        let modified = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1000);
+
+        let first = temps.read_temps_data_if_modified(Some(modified)).await.unwrap();
+        assert_eq!(first.temps.len(), 4);
+
+        // Not valid data, but the modified time is unchanged so it shouldn't get read.
+        fs::write(&file, "not json").unwrap();
+        let cached = temps.read_temps_data_if_modified(Some(modified)).await.unwrap();
+        assert_eq!(cached, first);
+
+        // Modified, so should be read again.
+        let changed_data = EXAMPLE_DATA.replace("14.79", "20.5");
+        fs::write(&file, &changed_data).unwrap();
+        let changed = temps
+            .read_temps_data_if_modified(Some(modified + std::time::Duration::from_secs(1)))
+            .await
+            .unwrap();
+        assert_eq!(changed.temps.get(&Sensor::TKBT).unwrap().value, 20.5);
+
+        // No modified time available, so always read.
+        fs::write(&file, EXAMPLE_DATA).unwrap();
+        let unknown = temps.read_temps_data_if_modified(None).await.unwrap();
+        assert_eq!(unknown, first);
+
+        fs::remove_file(&file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_parse_retries_partial_write() {
+        let partial = &EXAMPLE_DATA[..EXAMPLE_DATA.len() / 2];
+        let mut reads = vec![EXAMPLE_DATA, partial];
+
+        let data = parse_with_retry(Path::new("temps.json"), || Ok(reads.pop().unwrap().to_owned()), |s, _| parse_json(s))
+            .await
+            .expect("Should succeed after retrying");
+
+        assert!(reads.is_empty(), "Should have re-read the file");
+        assert_eq!(data, serde_json::from_str(EXAMPLE_DATA).unwrap());
+    }
+
+    #[test]
+    fn test_parse_csv() {
+        let timestamp = Utc.from_utc_datetime(&date(2024, 1, 3).and_time(time(19, 51, 42)));
+        let csv = "sensor,value\nTKBT,14.79\n TKTP , 51.93 \n\n# comment\nTKBTM,42.0\n";
+
+        let data = parse_csv(Path::new("temps.csv"), csv, timestamp, false).expect("Should be valid");
+
+        assert_eq!(data.timestamp, timestamp);
+        assert_eq!(data.temps.len(), 3);
+        assert_eq!(data.temps.get(&Sensor::TKBT), Some(&TimestampedTemperature { value: 14.79, timestamp }));
+        assert_eq!(data.temps.get(&Sensor::TKTP).unwrap().value, 51.93);
+        assert_eq!(data.temps.get(&Sensor::from("TKBTM")).unwrap().value, 42.0);
+    }
+
+    #[test]
+    fn test_parse_csv_malformed() {
+        let timestamp = Utc.from_utc_datetime(&date(2024, 1, 3).and_time(time(19, 51, 42)));
+        let csv = "TKBT,14.79\nTKTP\nHXOR,warm\n,30.0\nHXIF,30.0,extra\nHPRT,35.5";
+
+        assert!(parse_csv(Path::new("temps.csv"), csv, timestamp, false).is_err(), "Malformed unless skipping");
+        let data = parse_csv(Path::new("temps.csv"), csv, timestamp, true).expect("Should skip malformed lines");
+
+        let sensors: HashMap<Sensor, f32> = data.temps.into_iter().map(|(sensor, reading)| (sensor, reading.value)).collect();
+        assert_eq!(sensors, HashMap::from([(Sensor::TKBT, 14.79), (Sensor::HPRT, 35.5)]));
+    }
+
+    #[tokio::test]
+    async fn test_parse_csv_retries_partial_write() {
+        let timestamp = Utc.from_utc_datetime(&date(2024, 1, 3).and_time(time(19, 51, 42)));
+        let parse = |s: &str, last_attempt| parse_csv(Path::new("temps.csv"), s, timestamp, last_attempt);
+
+        let mut reads = vec!["TKBT,14.79\nTKTP,51.93\n", "TKBT,14.79\nTK", ""];
+        let data = parse_with_retry(Path::new("temps.csv"), || Ok(reads.pop().unwrap().to_owned()), parse)
+            .await
+            .expect("Should succeed after retrying");
+        assert!(reads.is_empty(), "Should have re-read the file");
+        assert_eq!(data.temps.len(), 2);
+
+        // Still malformed on the last attempt, so take what we can.
+        let data = parse_with_retry(Path::new("temps.csv"), || Ok("TKBT,14.79\nTK".to_owned()), parse)
+            .await
+            .expect("Should skip the malformed line in the end");
+        assert_eq!(data.temps.len(), 1);
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(TempsFileFormat::from_path(Path::new("live_data/temps.json")), TempsFileFormat::Json);
+        assert_eq!(TempsFileFormat::from_path(Path::new("live_data/temps.CSV")), TempsFileFormat::Csv);
+        assert_eq!(TempsFileFormat::from_path(Path::new("temps")), TempsFileFormat::Json);
+    }
+
+    #[test]
+    fn test_read_csv_file() {
+        let file = std::env::temp_dir().join(format!("follow_heating_test_temps_{}.txt", std::process::id()));
+        fs::write(&file, "TKBT,14.79\nTKTP,51.93\n").unwrap();
+
+        let temps = LiveFileTemperatures::new(file.clone()).with_format(TempsFileFormat::Csv);
+        let readings = futures::executor::block_on(temps.retrieve_temperatures()).expect("Should be fresh");
+        assert_eq!(readings, HashMap::from([(Sensor::TKBT, 14.79), (Sensor::TKTP, 51.93)]));
+
+        fs::remove_file(&file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_parse_retries_bounded() {
+        let mut reads = 0;
+        let result = parse_with_retry(Path::new("temps.json"), || {
+            reads += 1;
+            Ok("{ \"temps\": ".to_owned())
+        }, |s, _| parse_json(s)).await;
+
+        assert!(result.is_err());
+        assert_eq!(reads, PARSE_RETRIES + 1);
+    }
+
+    #[tokio::test]
+    async fn test_read_error_not_retried() {
+        let mut reads = 0;
+        let result = parse_with_retry(Path::new("temps.json"), || {
+            reads += 1;
+            Err("No such file".to_owned())
+        }, |s, _| parse_json(s)).await;
+
+        assert_eq!(result, Err("No such file".to_owned()));
+        assert_eq!(reads, 1);
+    }
 }
@@ -7,8 +7,11 @@ use std::fmt::{Display, Formatter};
 pub mod database;
 pub mod dummy;
 pub mod file;
+pub mod plausible;
+pub mod update_db_with_temps;
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+/// Sensors are ordered as declared, followed by any others ordered by their id.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
 pub enum Sensor {
     TKTP,
     TKEN,
@@ -71,7 +74,7 @@ impl<'de> Deserialize<'de> for Sensor {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
 pub struct SensorId {
     id: String,
 }
@@ -130,5 +133,25 @@ mod tests {
             );
         }
     }
-}
 
+    #[test]
+    fn test_ordering() {
+        let mut sensors = vec![
+            Sensor::from("zone1"),
+            Sensor::HXIR,
+            Sensor::from("Outside"),
+            Sensor::TKBT,
+            Sensor::TKTP,
+            Sensor::HPRT,
+        ];
+        sensors.sort();
+        assert_eq!(sensors, vec![
+            Sensor::TKTP,
+            Sensor::TKBT,
+            Sensor::HPRT,
+            Sensor::HXIR,
+            Sensor::from("outside"),
+            Sensor::from("zone1"),
+        ]);
+    }
+}
@@ -0,0 +1,83 @@
+use crate::brain::python_like::config::sensor_range::SensorRange;
+use crate::io::temperatures::{Sensor, TemperatureManager};
+use crate::temp_format::fmt_temp;
+use async_trait::async_trait;
+use log::warn;
+use std::collections::HashMap;
+
+/// A temperature manager that drops any reading outside of the plausible range for its sensor,
+/// as it must have come from a broken (e.g. shorted or disconnected) sensor.
+/// Sensors without a range are passed through as they are.
+pub struct PlausibleTemperatureManager<T: TemperatureManager> {
+    inner: T,
+    ranges: HashMap<Sensor, SensorRange>,
+}
+
+impl<T: TemperatureManager> PlausibleTemperatureManager<T> {
+    pub fn new(inner: T, ranges: HashMap<Sensor, SensorRange>) -> Self {
+        Self { inner, ranges }
+    }
+}
+
+#[async_trait]
+impl<T: TemperatureManager + Send + Sync> TemperatureManager for PlausibleTemperatureManager<T> {
+    async fn retrieve_sensors(&mut self) -> Result<(), String> {
+        self.inner.retrieve_sensors().await
+    }
+
+    async fn retrieve_temperatures(&self) -> Result<HashMap<Sensor, f32>, String> {
+        let mut temps = self.inner.retrieve_temperatures().await?;
+        temps.retain(|sensor, temp| match self.ranges.get(sensor) {
+            Some(range) if !range.contains(*temp) => {
+                warn!("Dropping implausible reading for {}: {} is outside of {}", sensor, fmt_temp(*temp), range);
+                false
+            }
+            _ => true,
+        });
+        Ok(temps)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::io::dummy::DummyIO;
+    use crate::io::temperatures::dummy::{Dummy as DummyTemps, ModifyState};
+    use tokio::runtime::Runtime;
+
+    #[test]
+    fn test_drops_implausible() {
+        let rt = Runtime::new().unwrap();
+        let (temps, handle) = DummyTemps::create(&());
+        let ranges = HashMap::from([
+            (Sensor::TKTP, SensorRange::new(0.0, 90.0)),
+            (Sensor::HPRT, SensorRange::new(-10.0, 80.0)),
+        ]);
+        let temps = PlausibleTemperatureManager::new(temps, ranges);
+
+        handle.send(ModifyState::SetTemp(Sensor::TKTP, 45.0)).unwrap();
+        handle.send(ModifyState::SetTemp(Sensor::HPRT, -5.0)).unwrap();
+        handle.send(ModifyState::SetTemp(Sensor::TKBT, 150.0)).unwrap();
+        assert_eq!(
+            rt.block_on(temps.retrieve_temperatures()),
+            Ok(HashMap::from([(Sensor::TKTP, 45.0), (Sensor::HPRT, -5.0), (Sensor::TKBT, 150.0)])),
+            "All in range, or without a range"
+        );
+
+        handle.send(ModifyState::SetTemp(Sensor::TKTP, 127.0)).unwrap();
+        handle.send(ModifyState::SetTemp(Sensor::HPRT, -40.0)).unwrap();
+        assert_eq!(
+            rt.block_on(temps.retrieve_temperatures()),
+            Ok(HashMap::from([(Sensor::TKBT, 150.0)])),
+            "TKTP above its max, HPRT below its min"
+        );
+
+        handle.send(ModifyState::SetTemp(Sensor::TKTP, 90.0)).unwrap();
+        handle.send(ModifyState::SetTemp(Sensor::HPRT, -10.0)).unwrap();
+        assert_eq!(
+            rt.block_on(temps.retrieve_temperatures()),
+            Ok(HashMap::from([(Sensor::TKTP, 90.0), (Sensor::HPRT, -10.0), (Sensor::TKBT, 150.0)])),
+            "The ends of the range are plausible"
+        );
+    }
+}
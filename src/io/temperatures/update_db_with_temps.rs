@@ -0,0 +1,137 @@
+use crate::config::TemperatureLoggingConfig;
+use crate::io::temperatures::{Sensor, TemperatureManager};
+use itertools::Itertools;
+use log::{debug, error, info, warn};
+use sqlx::MySqlPool;
+use std::collections::HashMap;
+use tokio::time::MissedTickBehavior;
+
+/// A single insert of every temperature reading, with the values to bind in order.
+#[derive(Debug, PartialEq)]
+pub struct TemperaturesInsert {
+    pub sql: String,
+    pub values: Vec<(String, f32)>,
+}
+
+/// Check the table name is safe to put straight into a query.
+pub fn check_table_name(table: &str) -> Result<(), String> {
+    if table.is_empty() || !table.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(format!("Invalid table name: {:?}", table));
+    }
+    Ok(())
+}
+
+/// Build a single insert of all the given readings into the table, in sensor order.
+/// Returns None if there is nothing to insert.
+pub fn build_insert(
+    table: &str,
+    temps: &HashMap<Sensor, f32>,
+) -> Result<Option<TemperaturesInsert>, String> {
+    check_table_name(table)?;
+    if temps.is_empty() {
+        return Ok(None);
+    }
+
+    let values: Vec<(String, f32)> = temps
+        .iter()
+        .sorted_by_key(|&(sensor, _)| sensor)
+        .map(|(sensor, temp)| (sensor.to_string(), *temp))
+        .collect();
+
+    let placeholders = vec!["(?,?)"; values.len()].join(",");
+    let sql = format!("INSERT INTO {} (sensor, value) VALUES {}", table, placeholders);
+    Ok(Some(TemperaturesInsert { sql, values }))
+}
+
+async fn insert_temps(
+    conn: &MySqlPool,
+    table: &str,
+    temps: &HashMap<Sensor, f32>,
+) -> Result<usize, String> {
+    let insert = match build_insert(table, temps)? {
+        Some(insert) => insert,
+        None => return Ok(0),
+    };
+
+    let mut query = sqlx::query(&insert.sql);
+    for (sensor, value) in &insert.values {
+        query = query.bind(sensor).bind(value);
+    }
+    query.execute(conn).await.map_err(|e| e.to_string())?;
+    Ok(insert.values.len())
+}
+
+/// Record the temperatures into the table (which may differ from the configured one,
+/// see DatabaseConfig::get_write_table) at the configured interval.
+pub async fn run(
+    conn: MySqlPool,
+    temps: impl TemperatureManager + Send + Sync,
+    config: TemperatureLoggingConfig,
+    table: String,
+) {
+    info!(
+        "Running database temperature recorder into {} every {}s.",
+        table,
+        config.get_interval().as_secs()
+    );
+    let mut interval = tokio::time::interval(*config.get_interval());
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+
+        let readings = match temps.retrieve_temperatures().await {
+            Ok(readings) => readings,
+            Err(e) => {
+                warn!("Failed to retrieve temperatures to record in DB: {}", e);
+                continue;
+            }
+        };
+
+        match insert_temps(&conn, &table, &readings).await {
+            Ok(count) => debug!("Recorded {} temperatures in DB", count),
+            Err(e) => error!("Failed to record temperatures in DB: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_build_insert() {
+        let temps = HashMap::from([
+            (Sensor::TKTP, 50.5),
+            (Sensor::HXOR, 30.0),
+            (Sensor::from("TKBTM"), 42.0),
+        ]);
+
+        let insert = build_insert("temperature_reading", &temps).unwrap().unwrap();
+
+        assert_eq!(
+            insert,
+            TemperaturesInsert {
+                sql: "INSERT INTO temperature_reading (sensor, value) VALUES (?,?),(?,?),(?,?)"
+                    .to_owned(),
+                values: vec![
+                    ("TKTP".to_owned(), 50.5),
+                    ("HXOR".to_owned(), 30.0),
+                    ("tkbtm".to_owned(), 42.0),
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn test_build_insert_empty() {
+        assert_eq!(build_insert("temperature_reading", &HashMap::new()), Ok(None));
+    }
+
+    #[test]
+    fn test_build_insert_invalid_table() {
+        let temps = HashMap::from([(Sensor::TKTP, 50.5)]);
+        assert!(build_insert("reading; DROP TABLE sensor", &temps).is_err());
+        assert!(build_insert("", &temps).is_err());
+    }
+}
@@ -1,11 +1,12 @@
 use std::net::IpAddr;
 use chrono::{DateTime, Utc};
 use sqlx::MySqlPool;
-use crate::io::wiser::hub::{IpWiserHub, WiserRoomData};
+use crate::io::wiser::hub::{IpWiserHub, WiserApiVersion, WiserRoomData};
 use crate::io::wiser::WiserManager;
 use async_trait::async_trait;
 use log::error;
 use crate::wiser::hub::WiserHub;
+use crate::log_rate_limit::rate_limited;
 
 const HEATING_STATE_DB_ID: u32 = 17;
 
@@ -15,9 +16,9 @@ pub struct DBAndHub {
 }
 
 impl DBAndHub {
-    pub fn new(conn: MySqlPool, wiser_ip: IpAddr, wiser_secret: String) -> Self {
+    pub fn new(conn: MySqlPool, wiser_ip: IpAddr, wiser_secret: String, api_version: WiserApiVersion) -> Self {
         DBAndHub {
-            hub: IpWiserHub::new(wiser_ip, wiser_secret),
+            hub: IpWiserHub::new(wiser_ip, wiser_secret, api_version),
             conn,
         }
     }
@@ -28,7 +29,7 @@ impl WiserManager for DBAndHub {
     async fn get_heating_turn_off_time(&self) -> Option<DateTime<Utc>> {
         let data = self.hub.get_room_data().await;
         if let Err(e) = data {
-            error!("Error retrieving hub data: {:?}", e);
+            rate_limited!(error, "dbhub_turn_off_time", "Error retrieving hub data: {:?}", e);
             return None;
         }
         let data = data.unwrap();
@@ -1,14 +1,16 @@
-use std::{fs, net::IpAddr, path::PathBuf};
+use std::fmt::{self, Display, Formatter};
+use std::{fs, io, net::IpAddr, path::PathBuf};
 
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 use log::{error, trace, warn};
 use serde::Deserialize;
 
-use crate::io::live_data::{check_age, AgeType, CachedPrevious};
+use crate::io::live_data::{check_age_at, AgeType, CachedPrevious, CheckAgeResult};
+use crate::log_rate_limit::rate_limited;
 
 use super::{
-    hub::{IpWiserHub, WiserHub, WiserRoomData},
+    hub::{IpWiserHub, WiserApiVersion, WiserHub, WiserRoomData},
     WiserManager,
 };
 
@@ -19,36 +21,103 @@ pub struct FileAndHub {
 }
 
 impl FileAndHub {
-    pub fn new(file: PathBuf, ip: IpAddr, secret: String) -> Self {
+    pub fn new(file: PathBuf, ip: IpAddr, secret: String, api_version: WiserApiVersion) -> Self {
         Self {
             file,
-            hub: IpWiserHub::new(ip, secret),
+            hub: IpWiserHub::new(ip, secret, api_version),
             last_data: CachedPrevious::none(),
         }
     }
 
-    fn retrieve_data(&self) -> Result<WiserFileData, String> {
-        let data = fs::read_to_string(&self.file)
-            .map_err(|e| format!("Error reading {:?}: {}", self.file, e))?;
+    fn retrieve_data(&self) -> Result<WiserFileData, WiserFileError> {
+        let data = fs::read_to_string(&self.file).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => WiserFileError::Missing(e),
+            _ => WiserFileError::Unreadable(e),
+        })?;
 
-        serde_json::from_str(&data)
-            .map_err(|e| format!("Error deserializing {:?}: {}\n{}", self.file, e, data))
+        serde_json::from_str(&data).map_err(|e| WiserFileError::Malformed(e, data))
+    }
+
+    /// Get the latest data from the file, falling back to the last successful read
+    /// if the file is missing or malformed.
+    fn read_data(&self) -> Result<WiserFileData, WiserFileError> {
+        match self.retrieve_data() {
+            Ok(data) => {
+                self.last_data.update(data.clone());
+                Ok(data)
+            }
+            Err(e) => match self.last_data.get() {
+                Some(data) => {
+                    warn!("{:?}: {}, using previous", self.file, e);
+                    Ok(data)
+                }
+                None => Err(e),
+            },
+        }
+    }
+
+    fn check_fresh(
+        &self,
+        what: &'static str,
+        timestamp: DateTime<Utc>,
+        max_age: i64,
+        now: DateTime<Utc>,
+    ) -> Result<(), WiserFileError> {
+        let age = check_age_at(timestamp, max_age, now);
+        match age.age_type() {
+            AgeType::Good => trace!("{} in {:?}: {}", what, self.file, age),
+            AgeType::GettingOld => warn!("{} in {:?}: {}", what, self.file, age),
+            AgeType::TooOld => return Err(WiserFileError::Stale(what, age)),
+        }
+        Ok(())
+    }
+
+    fn get_heating_on_at(&self, now: DateTime<Utc>) -> Result<bool, WiserFileError> {
+        let data = self.read_data()?;
+        self.check_fresh("file", data.timestamp, MAX_FILE_AGE_SECONDS, now)?;
+        let heating = data.wiser.heating;
+        self.check_fresh("heating on", heating.timestamp, MAX_WISER_AGE_SECONDS, now)?;
+        Ok(heating.on)
+    }
+}
+
+/// Why we couldn't get usable data from the wiser file.
+#[derive(Debug)]
+pub enum WiserFileError {
+    /// The file doesn't exist, so whatever writes it probably isn't running.
+    Missing(io::Error),
+    /// The file exists but couldn't be read.
+    Unreadable(io::Error),
+    /// The file isn't valid, along with its contents.
+    Malformed(serde_json::Error, String),
+    /// The file (or the given part of it) hasn't been updated recently enough to be trusted.
+    Stale(&'static str, CheckAgeResult),
+}
+
+impl Display for WiserFileError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            WiserFileError::Missing(e) => write!(f, "Wiser file missing: {}", e),
+            WiserFileError::Unreadable(e) => write!(f, "Error reading wiser file: {}", e),
+            WiserFileError::Malformed(e, data) => write!(f, "Malformed wiser file: {}\n{}", e, data),
+            WiserFileError::Stale(what, age) => write!(f, "Stale wiser file, {} is not up to date: {}", what, age),
+        }
     }
 }
 
 /// How long before we reject the file for being too outdated.
 /// If this is too old then our data collection is broken.
-const MAX_FILE_AGE_SECONDS: i64 = 2 * 60;
+pub(super) const MAX_FILE_AGE_SECONDS: i64 = 2 * 60;
 /// How long before we reject the data within the file for being too outdated
 /// i.e. how long wiser we allow wiser to not respond for before taking action
-const MAX_WISER_AGE_SECONDS: i64 = 10 * 60;
+pub(super) const MAX_WISER_AGE_SECONDS: i64 = 10 * 60;
 
 #[async_trait]
 impl WiserManager for FileAndHub {
     async fn get_heating_turn_off_time(&self) -> Option<DateTime<Utc>> {
         let data = self.hub.get_room_data().await;
         if let Err(e) = data {
-            error!("Error retrieving hub data: {:?}", e);
+            rate_limited!(error, "filehub_turn_off_time", "Error retrieving hub data: {:?}", e);
             return None;
         }
         let data = data.unwrap();
@@ -56,53 +125,9 @@ impl WiserManager for FileAndHub {
     }
 
     async fn get_heating_on(&self) -> Result<bool, ()> {
-        let wiser_file_data = match self.retrieve_data() {
-            Ok(data) => {
-                self.last_data.update(data.clone());
-                data
-            }
-            Err(e) => match self.last_data.get() {
-                Some(data) => {
-                    warn!("Failed to get current wiser data: {}, using previous", e);
-                    data
-                }
-                None => {
-                    error!(
-                        "Failed to get current wiser data: {}, and no previous available.",
-                        e
-                    );
-                    return Err(());
-                }
-            },
-        };
-
-        let file_age = check_age(wiser_file_data.timestamp, MAX_FILE_AGE_SECONDS);
-        match file_age.age_type() {
-            AgeType::Good => {
-                trace!("{:?} {}", self.file, file_age)
-            }
-            AgeType::GettingOld => warn!("{:?}: {}", self.file, file_age),
-            AgeType::TooOld => {
-                error!("{:?}: {} - file is not up to date", self.file, file_age);
-                return Err(());
-            }
-        };
-
-        let wiser_heating_age = check_age(
-            wiser_file_data.wiser.heating.timestamp,
-            MAX_WISER_AGE_SECONDS,
-        );
-        match wiser_heating_age.age_type() {
-            AgeType::Good => {
-                trace!("heating on in: {:?}: {}", self.file, wiser_heating_age);
-            }
-            AgeType::GettingOld => warn!("heating on in: {:?}: {}", self.file, wiser_heating_age),
-            AgeType::TooOld => {
-                error!("heating on in {:?} {}", self.file, wiser_heating_age);
-                return Err(());
-            }
-        }
-        return Ok(wiser_file_data.wiser.heating.on);
+        self.get_heating_on_at(Utc::now()).map_err(|e| {
+            error!("{:?}: {}", self.file, e);
+        })
     }
 
     fn get_wiser_hub(&self) -> &dyn WiserHub {
@@ -110,7 +135,7 @@ impl WiserManager for FileAndHub {
     }
 }
 
-fn get_turn_off_time(data: &[WiserRoomData]) -> Option<DateTime<Utc>> {
+pub(super) fn get_turn_off_time(data: &[WiserRoomData]) -> Option<DateTime<Utc>> {
     data.iter()
         .filter_map(|room| room.get_override_timeout())
         .max()
@@ -129,7 +154,7 @@ struct WiserData {
 }
 
 #[derive(Deserialize, Debug, PartialEq, Clone)]
-struct TimestampedOnValue {
+pub(super) struct TimestampedOnValue {
     pub on: bool,
     pub timestamp: DateTime<Utc>,
 }
@@ -177,4 +202,59 @@ mod test {
 
         assert_eq!(actual, expected);
     }
+
+    const EXAMPLE_FILE: &str = "test/wiser/filehub.json";
+
+    fn file_time() -> DateTime<Utc> {
+        Utc.from_utc_datetime(&date(2024, 1, 3).and_time(time(15, 35, 32)))
+    }
+
+    fn file_and_hub(file: &str) -> FileAndHub {
+        FileAndHub::new(file.into(), IpAddr::from([127, 0, 0, 1]), String::new(), WiserApiVersion::V1)
+    }
+
+    #[test]
+    fn test_valid_file() {
+        let wiser = file_and_hub(EXAMPLE_FILE);
+        assert!(wiser.get_heating_on_at(file_time() + chrono::Duration::seconds(30)).unwrap());
+    }
+
+    #[test]
+    fn test_missing_file() {
+        let wiser = file_and_hub("test/wiser/missing.json");
+        let result = wiser.get_heating_on_at(file_time());
+        assert!(matches!(result, Err(WiserFileError::Missing(_))), "{:?}", result);
+    }
+
+    #[test]
+    fn test_malformed_file() {
+        let wiser = file_and_hub("test/wiser/filehub_malformed.json");
+        let result = wiser.get_heating_on_at(file_time());
+        assert!(matches!(result, Err(WiserFileError::Malformed(_, _))), "{:?}", result);
+    }
+
+    #[test]
+    fn test_stale_file() {
+        let wiser = file_and_hub(EXAMPLE_FILE);
+        let result = wiser.get_heating_on_at(file_time() + chrono::Duration::seconds(MAX_FILE_AGE_SECONDS + 1));
+        assert!(matches!(result, Err(WiserFileError::Stale("file", _))), "{:?}", result);
+
+        let wiser = file_and_hub("test/wiser/filehub_stale_heating.json");
+        let result = wiser.get_heating_on_at(file_time());
+        assert!(matches!(result, Err(WiserFileError::Stale("heating on", _))), "{:?}", result);
+    }
+
+    #[test]
+    fn test_malformed_file_uses_previous() {
+        let mut wiser = file_and_hub(EXAMPLE_FILE);
+        let now = file_time();
+        assert!(wiser.get_heating_on_at(now).unwrap());
+
+        wiser.file = "test/wiser/filehub_malformed.json".into();
+        assert!(wiser.get_heating_on_at(now).unwrap());
+
+        let later = now + chrono::Duration::seconds(MAX_FILE_AGE_SECONDS + 1);
+        let result = wiser.get_heating_on_at(later);
+        assert!(matches!(result, Err(WiserFileError::Stale("file", _))), "Previous data should still go stale: {:?}", result);
+    }
 }
@@ -0,0 +1,220 @@
+use std::{fs, path::PathBuf};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use log::{error, trace, warn};
+use serde::Deserialize;
+
+use crate::io::live_data::{check_age_at, AgeType, CachedPrevious};
+
+use super::{
+    filehub::{get_turn_off_time, TimestampedOnValue, MAX_FILE_AGE_SECONDS, MAX_WISER_AGE_SECONDS},
+    hub::{RetrieveDataError, WiserData, WiserDataSystem, WiserHub, WiserRoomData},
+    WiserManager,
+};
+
+/// Serves everything from the live data file, without ever talking to the hub.
+/// Useful when something else is polling the hub, or for testing offline.
+/// Boosting is not supported as there is no hub to send it to.
+pub struct FileOnlyWiser {
+    file: PathBuf,
+    last_data: CachedPrevious<WiserFileOnlyData>,
+}
+
+impl FileOnlyWiser {
+    pub fn new(file: PathBuf) -> Self {
+        Self {
+            file,
+            last_data: CachedPrevious::none(),
+        }
+    }
+
+    fn retrieve_data(&self) -> Result<WiserFileOnlyData, String> {
+        let data = fs::read_to_string(&self.file)
+            .map_err(|e| format!("Error reading {:?}: {}", self.file, e))?;
+
+        serde_json::from_str(&data)
+            .map_err(|e| format!("Error deserializing {:?}: {}\n{}", self.file, e, data))
+    }
+
+    /// Get the latest data from the file, falling back to the last successful read.
+    fn read_data(&self) -> Result<WiserFileOnlyData, String> {
+        match self.retrieve_data() {
+            Ok(data) => {
+                self.last_data.update(data.clone());
+                Ok(data)
+            }
+            Err(e) => match self.last_data.get() {
+                Some(data) => {
+                    warn!("Failed to get current wiser data: {}, using previous", e);
+                    Ok(data)
+                }
+                None => Err(format!(
+                    "Failed to get current wiser data: {}, and no previous available.",
+                    e
+                )),
+            },
+        }
+    }
+
+    fn check_fresh(
+        &self,
+        what: &str,
+        timestamp: DateTime<Utc>,
+        max_age: i64,
+        now: DateTime<Utc>,
+    ) -> Result<(), String> {
+        let age = check_age_at(timestamp, max_age, now);
+        match age.age_type() {
+            AgeType::Good => trace!("{} in {:?}: {}", what, self.file, age),
+            AgeType::GettingOld => warn!("{} in {:?}: {}", what, self.file, age),
+            AgeType::TooOld => {
+                return Err(format!("{} in {:?}: {} - not up to date", what, self.file, age))
+            }
+        }
+        Ok(())
+    }
+
+    fn read_fresh_data(&self, now: DateTime<Utc>) -> Result<WiserFileOnlyData, String> {
+        let data = self.read_data()?;
+        self.check_fresh("file", data.timestamp, MAX_FILE_AGE_SECONDS, now)?;
+        Ok(data)
+    }
+
+    fn get_heating_on_at(&self, now: DateTime<Utc>) -> Result<bool, String> {
+        let heating = self.read_fresh_data(now)?.wiser.heating;
+        self.check_fresh("heating on", heating.timestamp, MAX_WISER_AGE_SECONDS, now)?;
+        Ok(heating.on)
+    }
+
+    fn get_rooms_at(&self, now: DateTime<Utc>) -> Result<TimestampedRooms, String> {
+        let rooms = self.read_fresh_data(now)?.wiser.rooms;
+        self.check_fresh("rooms", rooms.timestamp, MAX_WISER_AGE_SECONDS, now)?;
+        Ok(rooms)
+    }
+}
+
+#[async_trait]
+impl WiserManager for FileOnlyWiser {
+    async fn get_heating_turn_off_time(&self) -> Option<DateTime<Utc>> {
+        match self.get_rooms_at(Utc::now()) {
+            Ok(rooms) => get_turn_off_time(&rooms.data),
+            Err(e) => {
+                error!("Error retrieving room data: {}", e);
+                None
+            }
+        }
+    }
+
+    async fn get_heating_on(&self) -> Result<bool, ()> {
+        self.get_heating_on_at(Utc::now()).map_err(|e| {
+            error!("{}", e);
+        })
+    }
+
+    fn get_wiser_hub(&self) -> &dyn WiserHub {
+        self
+    }
+}
+
+#[async_trait]
+impl WiserHub for FileOnlyWiser {
+    async fn get_data(&self) -> Result<WiserData, RetrieveDataError> {
+        let rooms = self.get_rooms_at(Utc::now()).map_err(RetrieveDataError::Other)?;
+        let system = WiserDataSystem::new(rooms.timestamp.timestamp() as u64);
+        Ok(WiserData::new(system, rooms.data))
+    }
+
+    async fn get_room_data(&self) -> Result<Vec<WiserRoomData>, RetrieveDataError> {
+        self.get_rooms_at(Utc::now())
+            .map(|rooms| rooms.data)
+            .map_err(RetrieveDataError::Other)
+    }
+
+    async fn cancel_boost(
+        &self,
+        _room_id: usize,
+        _originator: String,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        Err("Cannot cancel boost: wiser is file only".into())
+    }
+
+    async fn set_boost(
+        &self,
+        _room_id: usize,
+        _duration_minutes: usize,
+        _temp: f32,
+        _originator: String,
+    ) -> Result<(f32, DateTime<Utc>), Box<dyn std::error::Error>> {
+        Err("Cannot set boost: wiser is file only".into())
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct WiserFileOnlyData {
+    pub wiser: WiserFileOnlyWiserData,
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct WiserFileOnlyWiserData {
+    pub heating: TimestampedOnValue,
+    pub rooms: TimestampedRooms,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct TimestampedRooms {
+    pub data: Vec<WiserRoomData>,
+    pub timestamp: DateTime<Utc>,
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, TimeZone};
+
+    use crate::time_util::test_utils::{date, time};
+
+    use super::*;
+
+    const EXAMPLE_FILE: &str = "test/wiser/file_only.json";
+
+    fn file_time() -> DateTime<Utc> {
+        Utc.from_utc_datetime(&date(2024, 1, 3).and_time(time(15, 35, 32)))
+    }
+
+    #[test]
+    fn test_read_example() {
+        let wiser = FileOnlyWiser::new(EXAMPLE_FILE.into());
+        let now = file_time() + Duration::seconds(30);
+
+        assert_eq!(wiser.get_heating_on_at(now), Ok(true));
+
+        let rooms = wiser.get_rooms_at(now).expect("Should have rooms");
+        assert_eq!(rooms.data.len(), 2);
+        assert_eq!(rooms.data[0].get_name(), Some("Office"));
+        assert_eq!(rooms.data[1].get_temperature(), 18.5);
+
+        let turn_off = Utc.from_utc_datetime(&date(2024, 1, 3).and_time(time(16, 30, 0)));
+        assert_eq!(get_turn_off_time(&rooms.data), Some(turn_off));
+    }
+
+    #[test]
+    fn test_stale_file() {
+        let wiser = FileOnlyWiser::new(EXAMPLE_FILE.into());
+        let now = file_time() + Duration::seconds(MAX_FILE_AGE_SECONDS + 1);
+
+        assert!(wiser.get_heating_on_at(now).is_err());
+        assert!(wiser.get_rooms_at(now).is_err());
+    }
+
+    #[test]
+    fn test_missing_file_uses_previous() {
+        let wiser = FileOnlyWiser::new("test/wiser/missing.json".into());
+        let now = file_time();
+        assert!(wiser.get_heating_on_at(now).is_err());
+
+        let previous = FileOnlyWiser::new(EXAMPLE_FILE.into()).retrieve_data().unwrap();
+        wiser.last_data.update(previous);
+        assert_eq!(wiser.get_heating_on_at(now), Ok(true));
+    }
+}
@@ -30,6 +30,29 @@ pub trait WiserHub {
 pub struct IpWiserHub {
     ip: IpAddr,
     secret: String,
+    api_version: WiserApiVersion,
+}
+
+/// Which version of the hub's HTTP API to talk to.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum WiserApiVersion {
+    /// The original api, under /data/domain/
+    #[default]
+    V1,
+    /// The api used by newer firmware, under /data/v2/domain/
+    /// This still authenticates with the hub's secret (token), but doesn't take a trailing slash.
+    V2,
+}
+
+impl WiserApiVersion {
+    /// The full url of the given path within the hub's domain data, e.g "Room/3"
+    fn domain_url(&self, ip: &IpAddr, path: &str) -> String {
+        match self {
+            WiserApiVersion::V1 => format!("http://{}/data/domain/{}/", ip, path),
+            WiserApiVersion::V2 => format!("http://{}/data/v2/domain/{}", ip, path),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -52,8 +75,12 @@ impl Display for RetrieveDataError {
 impl std::error::Error for RetrieveDataError {}
 
 impl IpWiserHub {
-    pub fn new(ip: IpAddr, secret: String) -> Self {
-        IpWiserHub { ip, secret }
+    pub fn new(ip: IpAddr, secret: String, api_version: WiserApiVersion) -> Self {
+        IpWiserHub {
+            ip,
+            secret,
+            api_version,
+        }
     }
 }
 
@@ -85,7 +112,7 @@ impl WiserHub for IpWiserHub {
         let mut request = self.new_request(
             &client,
             Method::PATCH,
-            &format!("data/domain/Room/{}", room_id),
+            &format!("Room/{}", room_id),
         )?;
         *request.body_mut() = Some(request_payload.into());
 
@@ -120,7 +147,7 @@ impl WiserHub for IpWiserHub {
         let mut request = self.new_request(
             &client,
             Method::PATCH,
-            &format!("data/domain/Room/{}", room_id),
+            &format!("Room/{}", room_id),
         )?;
         *request.body_mut() = Some(request_payload.into());
 
@@ -160,10 +187,10 @@ impl IpWiserHub {
         &self,
         client: &Client,
         method: Method,
-        location: &str,
+        path: &str,
     ) -> Result<Request, reqwest::Error> {
         client
-            .request(method, format!("http://{}/{}/", self.ip, location))
+            .request(method, self.api_version.domain_url(&self.ip, path))
             .header("SECRET", &self.secret)
             .header("Content-Type", "application/json;charset=UTF-8")
             .timeout(Duration::from_secs(3))
@@ -173,15 +200,13 @@ impl IpWiserHub {
     async fn get_data_raw(&self, select: GrabData) -> Result<String, reqwest::Error> {
         let client = Client::new();
 
-        let extension = match select {
+        let path = match select {
             GrabData::All => "",
             GrabData::System => "System/",
             GrabData::Room => "Room/",
         };
 
-        let s = format!("data/domain/{}", extension);
-
-        let request = self.new_request(&client, Method::GET, &s)?;
+        let request = self.new_request(&client, Method::GET, path)?;
 
         return client.execute(request).await?.text().await;
     }
@@ -238,6 +263,14 @@ impl WiserDataSystem {
 }
 
 pub const FROM_SCHEDULE_ORIGIN: &str = "FromSchedule";
+pub const FROM_AWAY_MODE_ORIGIN: &str = "FromAwayMode";
+pub const OFF_MODE: &str = "Off";
+
+/// The set point (in 10x Celsius) that wiser reports for a room that is turned off.
+const OFF_SET_POINT: i32 = -200;
+/// By default, any calculated temperature at or below this is not a real reading,
+/// e.g. the thermostat has a flat battery and reports -3276.8
+pub const DEFAULT_MIN_VALID_TEMPERATURE: f32 = -10.0;
 
 #[allow(dead_code)]
 #[derive(Deserialize, Debug, Clone)]
@@ -254,6 +287,7 @@ pub struct WiserRoomData {
     current_set_point: i32,
     scheduled_set_point: i32,
     name: Option<String>,
+    mode: Option<String>,
 }
 
 impl WiserRoomData {
@@ -277,6 +311,7 @@ impl WiserRoomData {
             current_set_point,
             scheduled_set_point: current_set_point,
             name,
+            mode: None,
         }
     }
 
@@ -314,6 +349,51 @@ impl WiserRoomData {
     pub fn get_name(&self) -> Option<&str> {
         self.name.as_ref().map(|s| s.as_str())
     }
+
+    pub fn get_mode(&self) -> Option<&str> {
+        self.mode.as_deref()
+    }
+
+    /// Whether the room has been turned off, either by its mode or by being
+    /// scheduled to the "off" set point.
+    pub fn is_off(&self) -> bool {
+        self.get_mode() == Some(OFF_MODE) || self.current_set_point <= OFF_SET_POINT
+    }
+
+    /// Whether the current set point comes from the wiser system being in away mode.
+    pub fn is_away(&self) -> bool {
+        self.setpoint_origin == FROM_AWAY_MODE_ORIGIN
+    }
+
+    /// Whether the room has an override (such as a boost) in effect at the given time.
+    pub fn has_active_override(&self, now: DateTime<Utc>) -> bool {
+        match self.override_type.as_deref() {
+            None | Some("None") => false,
+            Some(_) => self.get_override_timeout().is_none_or(|timeout| timeout > now),
+        }
+    }
+
+    /// Whether the room's thermostat is reporting a sensible temperature,
+    /// i.e. one above the given minimum.
+    pub fn has_valid_temperature(&self, min_valid: f32) -> bool {
+        self.get_temperature() > min_valid
+    }
+
+    /// The set point the room is trying to achieve, or None if the room is off.
+    pub fn get_active_set_point(&self) -> Option<f32> {
+        if self.is_off() {
+            return None;
+        }
+        Some(self.get_set_point())
+    }
+
+    /// The temperature of the room, or None if the thermostat isn't giving a valid reading.
+    pub fn get_valid_temperature(&self, min_valid: f32) -> Option<f32> {
+        if !self.has_valid_temperature(min_valid) {
+            return None;
+        }
+        Some(self.get_temperature())
+    }
 }
 
 // Externally tagged.
@@ -365,5 +445,62 @@ mod tests {
         assert_eq!(data.system.unix_time, 1637331300);
         assert_eq!(data.room.len(), 8);
     }
+
+    #[derive(Deserialize)]
+    struct ExpectedRequest {
+        api_version: WiserApiVersion,
+        method: String,
+        path: String,
+        url: String,
+        headers: Vec<(String, String)>,
+    }
+
+    #[test]
+    pub fn test_request_construction() {
+        let json = fs::read_to_string("test/wiser/requests.json").unwrap();
+        let expected: Vec<ExpectedRequest> = serde_json::from_str(&json).unwrap();
+        assert!(!expected.is_empty());
+
+        let client = Client::new();
+        for expected in expected {
+            let hub = IpWiserHub::new(
+                "192.168.0.9".parse().unwrap(),
+                "super-secret-secret".to_owned(),
+                expected.api_version,
+            );
+            let method = Method::from_bytes(expected.method.as_bytes()).unwrap();
+            let request = hub.new_request(&client, method.clone(), &expected.path).unwrap();
+
+            assert_eq!(request.method(), &method);
+            assert_eq!(request.url().as_str(), expected.url, "{:?} {}", expected.api_version, expected.path);
+            assert_eq!(request.headers().len(), expected.headers.len());
+            for (name, value) in &expected.headers {
+                assert_eq!(request.headers().get(name).unwrap(), value.as_str(), "Header {}", name);
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_room_accessors() {
+        let json = fs::read_to_string("test/test_wiser_output.json").unwrap();
+        let data: WiserData = serde_json::from_str(&json).unwrap();
+
+        let office = &data.room[0];
+        assert_eq!(office.get_mode(), Some("Auto"));
+        assert!(!office.is_off());
+        assert!(!office.is_away());
+        assert_eq!(office.get_active_set_point(), Some(18.0));
+        assert_eq!(office.get_valid_temperature(DEFAULT_MIN_VALID_TEMPERATURE), Some(17.7));
+        let boost_timeout = office.get_override_timeout().unwrap();
+        assert!(office.has_active_override(boost_timeout - chrono::Duration::seconds(1)));
+        assert!(!office.has_active_override(boost_timeout));
+        assert!(!data.room[1].has_active_override(boost_timeout), "Sitting room has no override");
+
+        let roof = &data.room[5];
+        assert_eq!(roof.get_name(), Some("Roof"));
+        assert!(roof.is_off());
+        assert_eq!(roof.get_active_set_point(), None);
+        assert_eq!(roof.get_valid_temperature(DEFAULT_MIN_VALID_TEMPERATURE), None);
+    }
 }
 
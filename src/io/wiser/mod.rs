@@ -1,10 +1,14 @@
+use crate::config::WiserStartupConfig;
+use crate::wiser::hub::{RetrieveDataError, WiserData};
 use crate::WiserHub;
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
+use log::{info, warn};
 
 pub mod dbhub;
 pub mod dummy;
 pub mod filehub;
+pub mod fileonly;
 pub mod hub;
 
 #[async_trait]
@@ -16,3 +20,108 @@ pub trait WiserManager {
     fn get_wiser_hub(&self) -> &dyn WiserHub;
 }
 
+/// Wait for the hub to be ready as configured, returning the first data we get from it,
+/// or the last error if it never answered.
+pub async fn wait_for_wiser(hub: &dyn WiserHub, startup: &WiserStartupConfig) -> Result<WiserData, RetrieveDataError> {
+    if !startup.get_delay().is_zero() {
+        info!("Waiting {}s before contacting the wiser hub", startup.get_delay().as_secs());
+        tokio::time::sleep(startup.get_delay()).await;
+    }
+    let attempts = startup.get_attempts().max(1);
+    let mut attempt = 1;
+    loop {
+        match hub.get_data().await {
+            Ok(data) => return Ok(data),
+            Err(e) if attempt >= attempts => return Err(e),
+            Err(e) => {
+                warn!("Wiser hub not ready (attempt {}/{}): {}", attempt, attempts, e);
+                tokio::time::sleep(startup.get_retry_interval()).await;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::wiser::hub::{WiserDataSystem, WiserRoomData};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::{Duration, Instant};
+
+    /// A hub that fails until it has been asked for data enough times.
+    struct BootingHub {
+        ready_after: usize,
+        calls: AtomicUsize,
+    }
+
+    impl BootingHub {
+        fn new(ready_after: usize) -> Self {
+            Self { ready_after, calls: AtomicUsize::new(0) }
+        }
+
+        fn calls(&self) -> usize {
+            self.calls.load(Ordering::SeqCst)
+        }
+    }
+
+    #[async_trait]
+    impl WiserHub for BootingHub {
+        async fn get_data(&self) -> Result<WiserData, RetrieveDataError> {
+            if self.calls.fetch_add(1, Ordering::SeqCst) < self.ready_after {
+                return Err(RetrieveDataError::Other("Still booting".to_owned()));
+            }
+            Ok(WiserData::new(WiserDataSystem::new(0), vec![]))
+        }
+
+        async fn get_room_data(&self) -> Result<Vec<WiserRoomData>, RetrieveDataError> {
+            Ok(vec![])
+        }
+
+        async fn cancel_boost(&self, _room_id: usize, _originator: String) -> Result<(), Box<dyn std::error::Error>> {
+            Ok(())
+        }
+
+        async fn set_boost(&self, _room_id: usize, _duration_minutes: usize, temp: f32, _originator: String) -> Result<(f32, DateTime<Utc>), Box<dyn std::error::Error>> {
+            Ok((temp, Utc::now()))
+        }
+    }
+
+    fn startup(config: &str) -> WiserStartupConfig {
+        toml::from_str(config).expect("Invalid startup config")
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_wiser_retries() {
+        let config = startup("attempts = 3\nretry_secs = 0");
+
+        let hub = BootingHub::new(2);
+        assert!(wait_for_wiser(&hub, &config).await.is_ok());
+        assert_eq!(hub.calls(), 3, "Ready on the last attempt");
+
+        let hub = BootingHub::new(3);
+        assert!(wait_for_wiser(&hub, &config).await.is_err());
+        assert_eq!(hub.calls(), 3, "Should give up after the configured attempts");
+
+        let hub = BootingHub::new(0);
+        assert!(wait_for_wiser(&hub, &config).await.is_ok());
+        assert_eq!(hub.calls(), 1, "Shouldn't ask again once ready");
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_wiser_default() {
+        let hub = BootingHub::new(1);
+        let start = Instant::now();
+        assert!(wait_for_wiser(&hub, &WiserStartupConfig::default()).await.is_err());
+        assert_eq!(hub.calls(), 1, "Only one attempt by default, as before");
+        assert!(start.elapsed() < Duration::from_secs(1), "No delay by default");
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_wiser_delay() {
+        let hub = BootingHub::new(0);
+        let start = Instant::now();
+        assert!(wait_for_wiser(&hub, &startup("delay_secs = 1")).await.is_ok());
+        assert!(start.elapsed() >= Duration::from_secs(1), "Should wait before the first attempt");
+    }
+}
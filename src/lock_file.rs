@@ -0,0 +1,72 @@
+use log::warn;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// A file recording the process id of whatever currently has control of the hardware,
+/// so that two things (i.e. the brain and gpio-test) can't fight over the relays.
+/// The file is removed when this is dropped.
+pub struct LockFile {
+    path: PathBuf,
+}
+
+impl LockFile {
+    /// Take the lock, failing if another process that is still running holds it.
+    /// A lock left behind by a process that has since died is taken over.
+    pub fn acquire(path: impl AsRef<Path>) -> Result<LockFile, String> {
+        let path = path.as_ref().to_path_buf();
+        match fs::read_to_string(&path) {
+            Ok(contents) => {
+                let pid = contents.trim();
+                if is_running(pid) {
+                    return Err(format!(
+                        "Lock file {:?} is held by process {} which is still running",
+                        path, pid
+                    ));
+                }
+                warn!("Taking over stale lock file {:?} from process {}", path, pid);
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(format!("Failed to read lock file {:?}: {}", path, e)),
+        }
+
+        fs::write(&path, std::process::id().to_string())
+            .map_err(|e| format!("Failed to write lock file {:?}: {}", path, e))?;
+        Ok(LockFile { path })
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!("Failed to remove lock file {:?}: {}", self.path, e);
+        }
+    }
+}
+
+fn is_running(pid: &str) -> bool {
+    match pid.parse::<u32>() {
+        Ok(pid) => Path::new("/proc").join(pid.to_string()).exists(),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lock_file() {
+        let path = std::env::temp_dir().join(format!("follow_heating_test_{}.lock", std::process::id()));
+
+        let lock = LockFile::acquire(&path).expect("Should get lock");
+        assert!(LockFile::acquire(&path).is_err(), "Lock should be held");
+        drop(lock);
+        assert!(!path.exists(), "Lock file should be removed");
+
+        // Left behind by a process that isn't running.
+        fs::write(&path, "not a pid").unwrap();
+        let lock = LockFile::acquire(&path).expect("Should take over stale lock");
+        drop(lock);
+    }
+}
@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// How often the known noisy messages (e.g. during a wiser or database outage) may be logged.
+const NOISY_LOG_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Limits how often a message may be logged, keyed by a static string per message.
+pub struct LogRateLimiter {
+    interval: Duration,
+    keys: Mutex<HashMap<&'static str, LastLogged>>,
+}
+
+struct LastLogged {
+    at: Instant,
+    suppressed: usize,
+}
+
+impl LogRateLimiter {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            keys: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether the message with the given key should be logged now.
+    /// Returns the number of times it was suppressed since it was last logged,
+    /// or None if it should not be logged.
+    pub fn check(&self, key: &'static str, now: Instant) -> Option<usize> {
+        let mut keys = self.keys.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        match keys.get_mut(key) {
+            Some(last) if now.saturating_duration_since(last.at) < self.interval => {
+                last.suppressed += 1;
+                None
+            }
+            Some(last) => {
+                let suppressed = last.suppressed;
+                *last = LastLogged { at: now, suppressed: 0 };
+                Some(suppressed)
+            }
+            None => {
+                keys.insert(key, LastLogged { at: now, suppressed: 0 });
+                Some(0)
+            }
+        }
+    }
+}
+
+/// Check the shared limiter for the known noisy messages.
+/// See [LogRateLimiter::check]
+pub fn check_noisy(key: &'static str) -> Option<usize> {
+    static LIMITER: OnceLock<LogRateLimiter> = OnceLock::new();
+    LIMITER
+        .get_or_init(|| LogRateLimiter::new(NOISY_LOG_INTERVAL))
+        .check(key, Instant::now())
+}
+
+/// Log at most once every [NOISY_LOG_INTERVAL] for the given key,
+/// noting how many were suppressed in between.
+macro_rules! rate_limited {
+    ($level:ident, target: $target:expr, $key:expr, $($arg:tt)+) => {
+        if let Some(suppressed) = $crate::log_rate_limit::check_noisy($key) {
+            if suppressed > 0 {
+                log::$level!(target: $target, "{} (suppressed {} similar)", format_args!($($arg)+), suppressed);
+            } else {
+                log::$level!(target: $target, $($arg)+);
+            }
+        }
+    };
+    ($level:ident, $key:expr, $($arg:tt)+) => {
+        $crate::log_rate_limit::rate_limited!($level, target: module_path!(), $key, $($arg)+)
+    };
+}
+
+pub(crate) use rate_limited;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_logs_once_per_window() {
+        let limiter = LogRateLimiter::new(Duration::from_secs(60));
+        let start = Instant::now();
+
+        assert_eq!(limiter.check("wiser", start), Some(0));
+        assert_eq!(limiter.check("wiser", start + Duration::from_secs(1)), None);
+        assert_eq!(limiter.check("wiser", start + Duration::from_secs(59)), None);
+        assert_eq!(limiter.check("temps", start + Duration::from_secs(30)), Some(0), "Other keys are limited separately");
+
+        assert_eq!(limiter.check("wiser", start + Duration::from_secs(60)), Some(2));
+        assert_eq!(limiter.check("wiser", start + Duration::from_secs(61)), None);
+        assert_eq!(limiter.check("wiser", start + Duration::from_secs(125)), Some(1));
+    }
+}
@@ -1,13 +1,25 @@
+use std::cell::Cell;
 use std::fs;
 
+use crate::config::{LogFileConfig, LogRotation};
 use itertools::Itertools;
 use time::UtcOffset;
-use tracing::{Level, Subscriber};
+use tracing::level_filters::LevelFilter;
+use tracing::span::EnteredSpan;
+use tracing::{Level, Metadata, Subscriber};
 use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::filter::{filter_fn, FilterFn};
+use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::{reload::Handle, EnvFilter};
 
-pub fn init_logging() -> Result<LoggingHandle<EnvFilter, impl Subscriber>, String> {
-    init_tracing_logger()
+thread_local! {
+    /// The most verbose level the mode currently being updated on this thread may log at, if limited.
+    static MODE_LOG_LEVEL: Cell<Option<LevelFilter>> = const { Cell::new(None) };
+}
+
+pub fn init_logging(log_file: Option<&LogFileConfig>) -> Result<LoggingHandle<EnvFilter, impl Subscriber>, String> {
+    init_tracing_logger(log_file)
 }
 
 pub enum ReloadLogLevelError {
@@ -30,18 +42,7 @@ pub fn reload_log_level(
     Ok(filter_string)
 }
 
-fn init_tracing_logger() -> Result<LoggingHandle<EnvFilter, impl Subscriber>, String> {
-    let timer = tracing_subscriber::fmt::time::OffsetTime::new(
-        UtcOffset::current_local_offset().unwrap_or_else(|err| {
-            eprintln!("Failed to get timezone: {}", err);
-            UtcOffset::UTC
-        }),
-        time::macros::format_description!(
-            "[year]-[month]-[day] [hour]:[minute]:[second] +[offset_hour]"
-        ),
-    );
-    let (non_blocking, guard) = tracing_appender::non_blocking(std::io::stdout());
-
+fn init_tracing_logger(log_file: Option<&LogFileConfig>) -> Result<LoggingHandle<EnvFilter, impl Subscriber>, String> {
     let env_filter = read_env_filter().unwrap_or_else(|err| {
         eprintln!(
             "Failed to read env filter, using environment variable or default: {}",
@@ -54,7 +55,49 @@ fn init_tracing_logger() -> Result<LoggingHandle<EnvFilter, impl Subscriber>, St
 
     println!("Env Filter: {}", env_filter);
 
-    //let (layer, handle) = reload::Layer::new(env_filter);
+    let (subscriber, handle) = build_subscriber(env_filter, log_file)?;
+
+    tracing::subscriber::set_global_default(subscriber)
+        .map_err(|err| format!("failed to initialize logger: {}", err))?;
+
+    Ok(handle)
+}
+
+/// Build the subscriber that logs to stdout, and to rotating files too if configured,
+/// along with the handle to reload its filter (which applies to both).
+fn build_subscriber(
+    env_filter: EnvFilter,
+    log_file: Option<&LogFileConfig>,
+) -> Result<(impl Subscriber + Send + Sync, LoggingHandle<EnvFilter, impl Subscriber>), String> {
+    let timer = tracing_subscriber::fmt::time::OffsetTime::new(
+        UtcOffset::current_local_offset().unwrap_or_else(|err| {
+            eprintln!("Failed to get timezone: {}", err);
+            UtcOffset::UTC
+        }),
+        time::macros::format_description!(
+            "[year]-[month]-[day] [hour]:[minute]:[second] +[offset_hour]"
+        ),
+    );
+    let (non_blocking, guard) = tracing_appender::non_blocking(std::io::stdout());
+
+    let (file_layer, file_guard) = match log_file {
+        Some(log_file) => {
+            fs::create_dir_all(log_file.get_directory())
+                .map_err(|err| format!("Failed to create log directory {:?}: {}", log_file.get_directory(), err))?;
+            let appender = RollingFileAppender::new(
+                to_rotation(log_file.get_rotation()),
+                log_file.get_directory(),
+                log_file.get_prefix(),
+            );
+            let (non_blocking, guard) = tracing_appender::non_blocking(appender);
+            let layer = tracing_subscriber::fmt::layer()
+                .with_timer(timer.clone())
+                .with_ansi(false)
+                .with_writer(non_blocking);
+            (Some(layer), Some(guard))
+        }
+        None => (None, None),
+    };
 
     let builder = tracing_subscriber::fmt()
         .with_timer(timer)
@@ -62,16 +105,71 @@ fn init_tracing_logger() -> Result<LoggingHandle<EnvFilter, impl Subscriber>, St
         .with_env_filter(env_filter)
         .with_filter_reloading();
 
-    //let layered = subscriber.with(env_filter);
     let handle = builder.reload_handle();
+    let subscriber = builder.finish().with(mode_log_filter()).with(file_layer);
 
-    tracing::subscriber::set_global_default(builder.finish())
-        .map_err(|err| format!("failed to initialize logger: {}", err))?;
-
-    Ok(LoggingHandle {
-        non_blocking_guard: guard,
+    Ok((subscriber, LoggingHandle {
+        _non_blocking_guard: guard,
+        _file_guard: file_guard,
         handle,
-    })
+    }))
+}
+
+fn to_rotation(rotation: LogRotation) -> Rotation {
+    match rotation {
+        LogRotation::Minutely => Rotation::MINUTELY,
+        LogRotation::Hourly => Rotation::HOURLY,
+        LogRotation::Daily => Rotation::DAILY,
+        LogRotation::Never => Rotation::NEVER,
+    }
+}
+
+/// A handle for running things that want one in tests, without touching the global logger.
+#[cfg(test)]
+pub fn test_logging_handle() -> LoggingHandle<EnvFilter, impl Subscriber> {
+    let (non_blocking, guard) = tracing_appender::non_blocking(std::io::sink());
+    let builder = tracing_subscriber::fmt()
+        .with_writer(non_blocking)
+        .with_env_filter(EnvFilter::default())
+        .with_filter_reloading();
+    LoggingHandle {
+        _non_blocking_guard: guard,
+        _file_guard: None,
+        handle: builder.reload_handle(),
+    }
+}
+
+/// Records the level and message of every event that gets through.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct RecordLevels(std::sync::Arc<std::sync::Mutex<Vec<(Level, String)>>>);
+
+#[cfg(test)]
+impl RecordLevels {
+    pub fn levels(&self) -> Vec<Level> {
+        self.0.lock().unwrap().iter().map(|(level, _)| *level).collect()
+    }
+
+    pub fn events(&self) -> Vec<(Level, String)> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+impl<S: Subscriber> tracing_subscriber::Layer<S> for RecordLevels {
+    fn on_event(&self, event: &tracing::Event<'_>, _ctx: tracing_subscriber::layer::Context<'_, S>) {
+        struct MessageVisitor(String);
+        impl tracing::field::Visit for MessageVisitor {
+            fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+                if field.name() == "message" {
+                    self.0 = format!("{:?}", value);
+                }
+            }
+        }
+        let mut visitor = MessageVisitor(String::new());
+        event.record(&mut visitor);
+        self.0.lock().unwrap().push((*event.metadata().level(), visitor.0));
+    }
 }
 
 fn read_env_filter() -> Result<EnvFilter, String> {
@@ -97,15 +195,59 @@ fn parse_env_filter(filter_string: &str) -> Result<EnvFilter, String> {
 }
 
 pub struct LoggingHandle<L, S> {
-    non_blocking_guard: WorkerGuard,
+    // Only held so the log writers keep flushing until dropped.
+    _non_blocking_guard: WorkerGuard,
+    _file_guard: Option<WorkerGuard>,
     handle: Handle<L, S>,
 }
 
+/// Drops events that are more verbose than the current mode's log level.
+/// This only ever restricts further what the [EnvFilter] lets through.
+pub(crate) fn mode_log_filter() -> FilterFn<impl Fn(&Metadata<'_>) -> bool> {
+    filter_fn(|metadata| {
+        if !metadata.is_event() {
+            return true;
+        }
+        MODE_LOG_LEVEL.with(|level| match level.get() {
+            Some(level) => *metadata.level() <= level,
+            None => true,
+        })
+    })
+}
+
+/// Logging for the mode with the given name, until dropped.
+/// Everything logged is within a "mode" span, and limited to the given level if there is one.
+pub struct ModeLogging {
+    _span: EnteredSpan,
+    previous_level: Option<LevelFilter>,
+}
+
+impl ModeLogging {
+    pub fn enter(name: &'static str, level: Option<LevelFilter>) -> Self {
+        let span = tracing::info_span!("mode", name).entered();
+        let previous_level = MODE_LOG_LEVEL.with(|current| current.replace(level));
+        Self {
+            _span: span,
+            previous_level,
+        }
+    }
+}
+
+impl Drop for ModeLogging {
+    fn drop(&mut self) {
+        MODE_LOG_LEVEL.with(|current| current.set(self.previous_level));
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use tracing::Level;
+    use tracing_subscriber::layer::SubscriberExt;
     use tracing_subscriber::EnvFilter;
 
-    use crate::logging::parse_env_filter;
+    use crate::brain::python_like::config::PythonBrainConfig;
+    use crate::config::LogFileConfig;
+    use crate::logging::{build_subscriber, mode_log_filter, parse_env_filter, ModeLogging, RecordLevels};
 
     const FILTER: &str = "info,sqlx=warn,follow_heating::brain::modes=debug,follow_heating::brain::boost_active_rooms=info";
 
@@ -125,10 +267,58 @@ mod tests {
         assert_eq!(format!("{}", actual), format!("{}", expected));
     }
 
+    #[test]
+    fn test_log_file() {
+        let dir = std::env::temp_dir().join(format!("follow_heating_test_logs_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let log_file: LogFileConfig = toml::from_str(&format!("directory = {:?}\nprefix = \"test.log\"\nrotation = \"Never\"", dir))
+            .expect("Should be valid");
+
+        let (subscriber, handle) = build_subscriber(parse_env_filter("info").unwrap(), Some(&log_file))
+            .expect("Should build with the file layer");
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("Logged at info");
+            handle.handle.reload(parse_env_filter("warn").unwrap()).expect("Should still reload");
+            tracing::info!("Filtered out at info");
+            tracing::warn!("Logged at warn");
+        });
+        drop(handle); // Flush
+
+        let contents = std::fs::read_to_string(dir.join("test.log")).expect("Should have written the log file");
+        assert!(contents.contains("Logged at info"), "{}", contents);
+        assert!(!contents.contains("Filtered out"), "Reloaded filter should apply to the file: {}", contents);
+        assert!(contents.contains("Logged at warn"), "{}", contents);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
     #[test]
     fn test_parse_logging_multiline() {
         let actual = parse_env_filter(MULTI_LINE_FILTER).unwrap();
         let expected = EnvFilter::builder().parse(FILTER).unwrap();
         assert_eq!(format!("{}", actual), format!("{}", expected));
     }
+
+    #[test]
+    fn test_mode_log_level() {
+        let config: PythonBrainConfig = toml::from_str("mode_log_levels = { Off = \"info\" }").unwrap();
+        let recorded = RecordLevels::default();
+        let subscriber = tracing_subscriber::registry()
+            .with(mode_log_filter())
+            .with(recorded.clone());
+
+        tracing::subscriber::with_default(subscriber, || {
+            {
+                let _logging = ModeLogging::enter("Off", config.get_mode_log_level("Off"));
+                tracing::debug!("Suppressed");
+                tracing::info!("Off info");
+            }
+            {
+                let _logging = ModeLogging::enter("On", config.get_mode_log_level("On"));
+                tracing::debug!("On debug");
+            }
+            tracing::debug!("Outside of a mode");
+        });
+
+        assert_eq!(recorded.levels(), vec![Level::INFO, Level::DEBUG, Level::DEBUG]);
+    }
 }
@@ -21,6 +21,14 @@ impl LinearModel {
             y_intercept: (gradient * -x1) + y1
         }
     }
+
+    /// The same line, moved down by the given amount.
+    pub fn lowered_by(&self, amount: f32) -> Self {
+        Self {
+            gradient: self.gradient,
+            y_intercept: self.y_intercept - amount,
+        }
+    }
 }
 
 impl Model for LinearModel {
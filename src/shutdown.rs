@@ -0,0 +1,114 @@
+use crate::brain::python_like::control::heating_control::{HeatPumpMode, HeatingControl};
+use crate::brain::python_like::control::misc_control::MiscControls;
+use crate::io::IOBundle;
+use log::error;
+use std::borrow::BorrowMut;
+use std::ops::DerefMut;
+
+/// Turn everything off (and the wiser power back on) using the controls in the IO bundle,
+/// falling back to the backup heating control if the bundle's can't be got hold of.
+pub fn shutdown_io<F, H>(io_bundle: &mut IOBundle, backup_supplier: F)
+where
+    H: HeatingControl,
+    F: FnOnce() -> H,
+{
+    shutdown_misc(io_bundle.misc_controls());
+
+    if let Ok(heating_control) = io_bundle.heating_control().rob_or_get_now() {
+        shutdown_heating(heating_control.deref_mut().borrow_mut());
+        drop(backup_supplier); // Drop backup GPIO to hopefully drop sender.
+    } else {
+        let mut backup = backup_supplier();
+        shutdown_heating(&mut backup);
+    }
+}
+
+pub fn shutdown_misc(misc_controls: &mut dyn MiscControls) {
+    if let Err(e) = misc_controls.try_set_immersion_heater(false) {
+        error!(
+            "FAILED TO SHUTDOWN IMMERSION HEATER: {:?}. It may still be on",
+            e
+        );
+    }
+    if let Err(e) = misc_controls.try_set_wiser_power(true) {
+        error!(
+            "FAILED TO TURN BACK ON WISER POWER: {:?}. It may be off.",
+            e
+        );
+    }
+}
+
+pub fn shutdown_heating(heating_control: &mut dyn HeatingControl) {
+    if let Err(e) = heating_control.try_set_heat_pump(HeatPumpMode::Off) {
+        error!("FAILED TO SHUTDOWN HEAT PUMP: {:?}. It may still be on", e);
+    }
+    if let Err(e) = heating_control.try_set_heat_circulation_pump(false) {
+        error!(
+            "FAILED TO SHUTDOWN HEAT CIRCULATION PUMP: {:?}. It may still be on",
+            e
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::brain::python_like::control::heating_control::{HeatCirculationPumpControl, HeatPumpControl};
+    use crate::brain::python_like::control::misc_control::{ImmersionHeaterControl, WiserPowerControl};
+    use crate::brain::BrainFailure;
+    use crate::expect_available;
+    use crate::io::dummy::DummyAllOutputs;
+    use crate::io::dummy_io_bundle::new_dummy_io;
+
+    /// Turn on everything that can be on, and the wiser power off.
+    fn everything_on(controls: &mut DummyAllOutputs) -> Result<(), BrainFailure> {
+        controls.try_set_heat_pump(HeatPumpMode::MostlyHotWater)?;
+        controls.try_set_heat_circulation_pump(true)?;
+        controls.try_set_immersion_heater(true)?;
+        controls.try_set_wiser_power(false)?;
+        Ok(())
+    }
+
+    fn assert_shutdown(controls: &mut DummyAllOutputs) -> Result<(), BrainFailure> {
+        assert_eq!(controls.try_get_heat_pump()?, HeatPumpMode::Off, "HP should be off");
+        assert!(!controls.try_get_heat_circulation_pump()?, "CP should be off");
+        assert!(!controls.try_get_immersion_heater()?, "IH should be off");
+        assert!(controls.try_get_wiser_power()?, "Wiser power should be on");
+        Ok(())
+    }
+
+    #[test]
+    fn test_shutdown_turns_everything_off() -> Result<(), BrainFailure> {
+        for mode in [HeatPumpMode::HeatingOnly, HeatPumpMode::MostlyHotWater, HeatPumpMode::BoostedHeating, HeatPumpMode::DrainTank] {
+            let mut controls = DummyAllOutputs::default();
+            everything_on(&mut controls)?;
+            controls.try_set_heat_pump(mode)?;
+
+            shutdown_heating(&mut controls);
+            shutdown_misc(&mut controls);
+            assert_shutdown(&mut controls)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_shutdown_io() -> Result<(), BrainFailure> {
+        let (mut io_bundle, _handle) = new_dummy_io();
+        {
+            let heating = expect_available!(io_bundle.heating_control())?;
+            heating.try_set_heat_pump(HeatPumpMode::HeatingOnly)?;
+            heating.try_set_heat_circulation_pump(true)?;
+        }
+        io_bundle.misc_controls().try_set_immersion_heater(true)?;
+        io_bundle.misc_controls().try_set_wiser_power(false)?;
+
+        shutdown_io(&mut io_bundle, || -> DummyAllOutputs { panic!("Shouldn't need the backup") });
+
+        let heating = expect_available!(io_bundle.heating_control())?;
+        assert_eq!(heating.try_get_heat_pump()?, HeatPumpMode::Off, "HP should be off");
+        assert!(!heating.try_get_heat_circulation_pump()?, "CP should be off");
+        assert!(!io_bundle.misc_controls().try_get_immersion_heater()?, "IH should be off");
+        assert!(io_bundle.misc_controls().try_get_wiser_power()?, "Wiser power should be on");
+        Ok(())
+    }
+}
@@ -1,24 +1,22 @@
+use crate::brain::modes::heating_mode::expect_available_fn;
+use crate::brain::python_like::config::PythonBrainConfig;
 use crate::brain::python_like::control::devices::Device;
+use crate::brain::python_like::control::heating_control::HeatPumpMode;
+use crate::brain::{python_like, Brain, BrainFailure};
 use crate::io::devices::dummy::ActiveDevicesMessage;
-use crate::io::dummy::DummyAllOutputs;
 use crate::io::dummy_io_bundle::new_dummy_io;
-use crate::io::temperatures::dummy::ModifyState::SetTemp;
 use crate::io::temperatures::Sensor;
 use crate::io::wiser::dummy::ModifyState;
 use crate::time_util::mytime::{DummyTimeProvider, TimeProvider};
-use crate::{brain, LoggingHandle};
-use chrono::{NaiveDate, NaiveTime, TimeZone, Utc};
-use log::debug;
-use std::time::Duration;
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, TimeZone, Utc};
+use log::{debug, info};
+use serde::Serialize;
+use std::collections::BTreeMap;
 use tokio::runtime::Builder;
-use tracing::Subscriber;
-use tracing_subscriber::EnvFilter;
 
-const SIMULATION_CONFIG: &'static str = r#"[[overrun_during.slots]]
+const SIMULATION_CONFIG: &str = r#"[[overrun_during.slots]]
 slot = { type = "Utc", start="04:00:00", end="15:00:05" }
-sensor = "TKBT"
-temp = 50.0
-min_temp = 30.0
+temps = { sensor = "TKBT", min = 30.0, max = 50.0 }
 
 [[boost_active_rooms.parts]]
 room = "JohnsRoom"
@@ -26,152 +24,209 @@ device = "JohnsPhone"
 increase = 3.0
 "#;
 
-pub fn simulate(logging_handle: LoggingHandle<EnvFilter, impl Subscriber>) {
-    let backup_heating_supplier = || DummyAllOutputs::default();
-    let (io_bundle, mut io_handle) = new_dummy_io();
+/// How much simulated time passes between each run of the brain.
+const STEP_SECONDS: i64 = 10;
 
-    debug!("{}", SIMULATION_CONFIG);
+/// Something that happens to the outside world during a simulation.
+pub enum SimulationEvent {
+    SetTemp(Sensor, f32),
+    SetHeatingOn,
+    TurnOffHeating,
+    SetActiveDevices(Vec<Device>),
+}
+
+/// A summary of what the brain did during a simulation.
+/// Everything but mode_seconds is the same each time a scenario is run.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct SimulationReport {
+    /// How many steps the simulation ran for.
+    pub steps: usize,
+    /// How long (in simulated seconds) was spent in each mode. Modes time themselves with the
+    /// real clock, so those that wait a while (e.g. TurningOn) don't finish within a simulation,
+    /// and this may differ between runs of the same scenario.
+    pub mode_seconds: BTreeMap<String, i64>,
+    /// How many times the heat pump was turned on.
+    pub heat_pump_cycles: usize,
+    /// How many times the circulation pump was turned on.
+    pub circulation_pump_cycles: usize,
+    /// How long (in simulated seconds) the immersion heater was on for.
+    pub immersion_on_seconds: i64,
+    /// The temperatures at the end of the simulation.
+    pub final_temps: BTreeMap<String, f32>,
+}
 
+pub fn simulate() -> SimulationReport {
+    debug!("{}", SIMULATION_CONFIG);
     let config = toml::from_str(SIMULATION_CONFIG).expect("Failed to deserialize config");
 
-    let brain = brain::python_like::PythonBrain::new(config);
+    let start = Utc.from_utc_datetime(
+        &NaiveDate::from_ymd_opt(2022, 5, 19)
+            .unwrap()
+            .and_time(NaiveTime::from_hms_opt(18, 0, 0).unwrap()),
+    );
+
+    let report = run_scenario(config, start, 120, default_events())
+        .expect("Brain failed during simulation");
+
+    info!(
+        "Simulation report: {}",
+        serde_json::to_string_pretty(&report).expect("Failed to serialize report")
+    );
+    report
+}
+
+fn default_events() -> Vec<(usize, SimulationEvent)> {
+    vec![
+        (0, SimulationEvent::SetActiveDevices(vec![
+            Device::new("StevesPhone".into()),
+            Device::new("JohnsPhone".into()),
+        ])),
+        // Set temp to 30C at the bottom.
+        (0, SimulationEvent::SetTemp(Sensor::TKBT, 30.0)),
+        (0, SimulationEvent::SetTemp(Sensor::TKTP, 30.0)),
+        (0, SimulationEvent::SetTemp(Sensor::HPRT, 25.0)),
+        (0, SimulationEvent::SetTemp(Sensor::HXOR, 30.0)),
+        (0, SimulationEvent::SetTemp(Sensor::HXIF, 32.0)),
+        (0, SimulationEvent::SetTemp(Sensor::HXIR, 31.0)),
+        (0, SimulationEvent::SetTemp(Sensor::HXOF, 30.0)),
+        // Tank up to temperature.
+        (5, SimulationEvent::SetTemp(Sensor::TKTP, 50.5)),
+        (5, SimulationEvent::SetTemp(Sensor::TKBT, 50.5)),
+        // Turn on, expect to start heating.
+        (10, SimulationEvent::SetTemp(Sensor::TKBT, 48.0)),
+        (10, SimulationEvent::SetHeatingOn),
+        // Turn off, expect to stop.
+        (30, SimulationEvent::TurnOffHeating),
+        // Heating back on.
+        (40, SimulationEvent::SetTemp(Sensor::HPRT, 31.0)),
+        (40, SimulationEvent::SetHeatingOn),
+        // Heat exchanger above the working range, expect circulation.
+        (60, SimulationEvent::SetTemp(Sensor::HXIF, 60.0)),
+        (60, SimulationEvent::SetTemp(Sensor::HXIR, 60.0)),
+        (60, SimulationEvent::SetTemp(Sensor::HXOR, 60.0)),
+        (60, SimulationEvent::SetTemp(Sensor::TKBT, 61.0)),
+        // Back down.
+        (80, SimulationEvent::SetTemp(Sensor::HXIF, 32.0)),
+        (80, SimulationEvent::SetTemp(Sensor::HXIR, 32.0)),
+        (80, SimulationEvent::SetTemp(Sensor::HXOR, 30.0)),
+        // Hot water below the desired temperature.
+        (100, SimulationEvent::TurnOffHeating),
+        (100, SimulationEvent::SetTemp(Sensor::TKTP, 20.0)),
+        (100, SimulationEvent::SetTemp(Sensor::TKBT, 20.0)),
+    ]
+}
 
-    let rt = Builder::new_multi_thread()
-        .worker_threads(1)
+/// Run the brain for the given number of steps (each STEP_SECONDS long) starting at the given time,
+/// applying each event just before the step it is for.
+pub fn run_scenario(
+    config: PythonBrainConfig,
+    start: DateTime<Utc>,
+    steps: usize,
+    events: Vec<(usize, SimulationEvent)>,
+) -> Result<SimulationReport, BrainFailure> {
+    let rt = Builder::new_current_thread()
         .enable_time()
         .enable_io()
         .build()
         .expect("Expected to be able to make runtime");
 
-    //rt.spawn(io::gpio::update_db_with_gpio::run(pool.clone(), receiver));
-
-    //sender.try_send(PinUpdate::new(1, GPIOState::LOW)).unwrap();
-
-    let overrun_time = Utc.from_utc_datetime(
-        &NaiveDate::from_ymd_opt(2022, 05, 19)
-            .unwrap()
-            .and_time(NaiveTime::from_hms_opt(04, 18, 00).unwrap()),
-    );
-    let no_overrun_time = Utc.from_utc_datetime(
-        &NaiveDate::from_ymd_opt(2022, 05, 19)
-            .unwrap()
-            .and_time(NaiveTime::from_hms_opt(18, 0, 0).unwrap()),
-    );
-
-    let time_provider = DummyTimeProvider::new(no_overrun_time);
-
-    println!("Current time {:?}", time_provider.get_utc_time());
-    io_handle.send_devices(ActiveDevicesMessage::SetActiveDevices(vec![
-        Device::new("StevesPhone".into()),
-        Device::new("JohnsPhone".into()),
-    ]));
-
-    rt.spawn(async move {
-        tokio::time::sleep(Duration::from_secs(5)).await;
-
-        println!("## Set temp to 30C at the bottom.");
-        io_handle.send_temps(SetTemp(Sensor::TKBT, 30.0));
-        io_handle.send_temps(SetTemp(Sensor::TKTP, 30.0));
-        io_handle.send_temps(SetTemp(Sensor::HPRT, 25.0));
-        //tokio::time::sleep(Duration::from_secs(20)).await;
-
-        println!("## Set temp to 50C at TKTP.");
-        io_handle.send_temps(SetTemp(Sensor::TKTP, 50.5));
-        //tokio::time::sleep(Duration::from_secs(30)).await;
-
-        println!("Test TurningOn state");
-        io_handle.send_temps(SetTemp(Sensor::TKBT, 50.5)); // Make sure up to finish any heat ups
-        tokio::time::sleep(Duration::from_secs(10)).await;
-        io_handle.send_temps(SetTemp(Sensor::TKBT, 48.0)); // Then make sure we will turn on.
-        io_handle.send_temps(SetTemp(Sensor::HXOR, 30.0));
-        io_handle.send_temps(SetTemp(Sensor::HXIF, 32.0));
-        io_handle.send_temps(SetTemp(Sensor::HXIR, 31.0));
-        tokio::time::sleep(Duration::from_secs(10)).await;
-        io_handle.send_wiser(ModifyState::SetHeatingOffTime(
-            Utc::now() + chrono::Duration::seconds(1000),
-        ));
-        //tokio::time::sleep(Duration::from_secs(100)).await;
-
-        println!("## Turning off wiser - expect overrun");
-        io_handle.send_wiser(ModifyState::TurnOffHeating);
-        //tokio::time::sleep(Duration::from_secs(60)).await;
-
-        println!("## Turning on fake wiser heating");
-        tokio::time::sleep(Duration::from_secs(10)).await;
-        io_handle.send_temps(SetTemp(Sensor::HPRT, 31.0));
-        io_handle.send_wiser(ModifyState::SetHeatingOffTime(
-            Utc::now() + chrono::Duration::seconds(1000),
-        ));
-        /*tokio::time::sleep(Duration::from_secs(90)).await;
-
-        println!("## Set temp to 55C at HXIF/R, expect circulation");
-        io_handle.send_temps(SetTemp(Sensor::HXIF, 60.0));
-        io_handle.send_temps(SetTemp(Sensor::HXIR, 60.0));
-        io_handle.send_temps(SetTemp(Sensor::HXOR, 45.0));
-        tokio::time::sleep(Duration::from_secs(60)).await;
-        io_handle.send_temps(SetTemp(Sensor::HXIF, 35.0));
-        io_handle.send_temps(SetTemp(Sensor::HXIR, 33.0));
-        io_handle.send_temps(SetTemp(Sensor::HXOR, 20.0));
-
-        println!("## Turning off fake wiser heating");
-        io_handle.send_wiser(ModifyState::TurnOffHeating);
-        tokio::time::sleep(Duration::from_secs(60)).await;
-
-        println!("## Turning on fake wiser heating");
-        io_handle.send_wiser(ModifyState::SetHeatingOffTime(
-            Utc::now() + chrono::Duration::seconds(1000),
-        ));
-        tokio::time::sleep(Duration::from_secs(30)).await;
-        io_handle.send_temps(SetTemp(Sensor::TKBT, 47.0));
-        io_handle.send_temps(SetTemp(Sensor::TKTP, 47.0));
-        tokio::time::sleep(Duration::from_secs(60)).await;*/
-
-        println!("## Setting HXIF/R to above the turn off temp.");
-        io_handle.send_temps(SetTemp(Sensor::HXIF, 60.0));
-        io_handle.send_temps(SetTemp(Sensor::HXIR, 60.0));
-        io_handle.send_temps(SetTemp(Sensor::HXOR, 60.0));
-        io_handle.send_temps(SetTemp(Sensor::TKBT, 61.0));
-        tokio::time::sleep(Duration::from_secs(60 * 5 + 30)).await;
-        println!("## Now turning back down.");
-        io_handle.send_temps(SetTemp(Sensor::HXIF, 32.0));
-        io_handle.send_temps(SetTemp(Sensor::HXIR, 32.0));
-        tokio::time::sleep(Duration::from_secs(30)).await;
-
-        println!("## Testing Off -> Circulate");
-        io_handle.send_wiser(ModifyState::TurnOffHeating);
-        tokio::time::sleep(Duration::from_secs(10)).await;
-        io_handle.send_temps(SetTemp(Sensor::TKBT, 55.0));
-        io_handle.send_wiser(ModifyState::SetHeatingOffTime(
-            Utc::now() + chrono::Duration::seconds(1000),
-        ));
-        tokio::time::sleep(Duration::from_secs(200)).await;
-        println!("## Setting HXIF/R to above turn off temp and setting TKBT too low to circulate");
-        io_handle.send_temps(SetTemp(Sensor::HXIF, 60.0));
-        io_handle.send_temps(SetTemp(Sensor::HXIR, 60.0));
-        io_handle.send_temps(SetTemp(Sensor::HXOR, 60.0));
-        io_handle.send_temps(SetTemp(Sensor::TKBT, 15.0));
-        tokio::time::sleep(Duration::from_secs(60 * 10 + 30)).await;
-
-        println!("## Turning TKTP below desired temp");
-        io_handle.send_temps(SetTemp(Sensor::TKTP, 20.0));
-        tokio::time::sleep(Duration::from_secs(60)).await;
-        println!("## Turning TKTP below desired temp");
-        io_handle.send_temps(SetTemp(Sensor::TKTP, 35.0));
-        tokio::time::sleep(Duration::from_secs(60)).await;
-    });
-
-    let imaginary_handle = rt.spawn(async {});
-    crate::main_loop(
-        brain,
-        io_bundle,
-        rt,
-        backup_heating_supplier,
-        time_provider,
-        logging_handle,
-        imaginary_handle,
-    );
+    let (mut io_bundle, mut io_handle) = new_dummy_io();
+    let mut brain = python_like::PythonBrain::new(config);
+    let mut time_provider = DummyTimeProvider::new(start);
+
+    let mut mode_seconds = BTreeMap::new();
+    let mut heat_pump_cycles = 0;
+    let mut circulation_pump_cycles = 0;
+    let mut immersion_on_seconds = 0;
+    let mut hp_was_on = false;
+    let mut cp_was_on = false;
+
+    for step in 0..steps {
+        for (_, event) in events.iter().filter(|(event_step, _)| *event_step == step) {
+            match event {
+                SimulationEvent::SetTemp(sensor, temp) => io_handle.send_temp(sensor.clone(), *temp),
+                SimulationEvent::SetHeatingOn => io_handle.send_wiser(ModifyState::SetHeatingOffTime(
+                    time_provider.get_utc_time() + Duration::days(1),
+                )),
+                SimulationEvent::TurnOffHeating => io_handle.send_wiser(ModifyState::TurnOffHeating),
+                SimulationEvent::SetActiveDevices(devices) => io_handle
+                    .send_devices(ActiveDevicesMessage::SetActiveDevices(devices.clone())),
+            }
+        }
+
+        brain.run(&rt, &mut io_bundle, &time_provider)?;
+
+        let mode = brain.get_heating_mode().map_or("None", |mode| mode.name());
+        *mode_seconds.entry(mode.to_owned()).or_insert(0) += STEP_SECONDS;
+
+        // Whilst dispatched, the pumps are as they were.
+        if let Some(heating) = expect_available_fn(io_bundle.heating_control()) {
+            let hp_on = heating.try_get_heat_pump()? != HeatPumpMode::Off;
+            if hp_on && !hp_was_on {
+                heat_pump_cycles += 1;
+            }
+            hp_was_on = hp_on;
+
+            let cp_on = heating.try_get_heat_circulation_pump()?;
+            if cp_on && !cp_was_on {
+                circulation_pump_cycles += 1;
+            }
+            cp_was_on = cp_on;
+        }
+
+        if io_bundle.misc_controls().try_get_immersion_heater()? {
+            immersion_on_seconds += STEP_SECONDS;
+        }
+
+        time_provider.advance(Duration::seconds(STEP_SECONDS));
+    }
+
+    let final_temps = rt
+        .block_on(io_bundle.temperature_manager().retrieve_temperatures())
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(sensor, temp)| (sensor.to_string(), temp))
+        .collect();
+
+    Ok(SimulationReport {
+        steps,
+        mode_seconds,
+        heat_pump_cycles,
+        circulation_pump_cycles,
+        immersion_on_seconds,
+        final_temps,
+    })
+}
 
-    //sleep(Duration::from_secs(30));
-    //println!("Turning off heating.");
-    //wiser_handle.send(ModifyState::TurnOffHeating).unwrap();
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// The report without how long was spent in each mode, which isn't repeatable.
+    fn without_mode_seconds(report: SimulationReport) -> SimulationReport {
+        SimulationReport { mode_seconds: BTreeMap::new(), ..report }
+    }
+
+    /// Modes time themselves with the real clock rather than the simulated time, so how long
+    /// is spent in each mode isn't pinned down, only what doesn't depend on that.
+    #[test]
+    fn test_default_scenario_report() {
+        let report = simulate();
+
+        assert_eq!(report.steps, 120);
+        assert_eq!(report.mode_seconds.values().sum::<i64>(), 120 * STEP_SECONDS);
+        assert!(report.mode_seconds.contains_key("Off"), "Got {:?}", report.mode_seconds);
+        assert!(report.mode_seconds.contains_key("TurningOn"), "Should have started heating, got {:?}", report.mode_seconds);
+        assert!(report.heat_pump_cycles >= 1);
+        assert_eq!(report.final_temps, BTreeMap::from([
+            ("HPRT".to_owned(), 31.0),
+            ("HXIF".to_owned(), 32.0),
+            ("HXIR".to_owned(), 32.0),
+            ("HXOF".to_owned(), 30.0),
+            ("HXOR".to_owned(), 30.0),
+            ("TKBT".to_owned(), 20.0),
+            ("TKTP".to_owned(), 20.0),
+        ]));
+        assert_eq!(without_mode_seconds(simulate()), without_mode_seconds(report), "Simulation should be repeatable");
+    }
 }
@@ -0,0 +1,51 @@
+use std::fmt::{Display, Formatter};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// How many decimal places temperatures are shown to by default.
+pub const DEFAULT_TEMP_PRECISION: usize = 2;
+
+/// Shared by everything, including tests running in parallel, so tests should use
+/// [DisplayTemp::with_precision] rather than setting this.
+static TEMP_PRECISION: AtomicUsize = AtomicUsize::new(DEFAULT_TEMP_PRECISION);
+
+/// Set how many decimal places temperatures are shown to in the logs.
+pub fn set_temp_precision(precision: usize) {
+    TEMP_PRECISION.store(precision, Ordering::Relaxed);
+}
+
+/// A temperature to be shown to a fixed number of decimal places, so the logs are consistent.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DisplayTemp {
+    temp: f32,
+    precision: usize,
+}
+
+impl DisplayTemp {
+    pub fn with_precision(temp: f32, precision: usize) -> Self {
+        Self { temp, precision }
+    }
+}
+
+impl Display for DisplayTemp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:.*}", self.precision, self.temp)
+    }
+}
+
+/// Show the temperature to the configured number of decimal places.
+pub fn fmt_temp(temp: f32) -> DisplayTemp {
+    DisplayTemp::with_precision(temp, TEMP_PRECISION.load(Ordering::Relaxed))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_precision() {
+        assert_eq!(DisplayTemp::with_precision(41.256, 2).to_string(), "41.26");
+        assert_eq!(DisplayTemp::with_precision(41.256, 1).to_string(), "41.3");
+        assert_eq!(DisplayTemp::with_precision(41.256, 0).to_string(), "41");
+        assert_eq!(DisplayTemp::with_precision(-3.0, 3).to_string(), "-3.000");
+    }
+}